@@ -1,5 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0
 
+use crate::CommitPhase;
+use std::path::PathBuf;
 use thiserror::Error;
 
 /// Represents the various errors that can occur in the crate.
@@ -8,15 +10,54 @@ use thiserror::Error;
 /// contain those [`Path`] as [`String`] to provide user-friendly error messages.
 #[derive(Error, Debug)]
 pub enum Error {
+	/// The ACL of a file couldn't be read or applied. Contains the path to the affected file and
+	/// the error as [`String`].
+	#[cfg(feature = "acl")]
+	#[error("Handling the ACL of {0} failed with error: {1}.")]
+	Acl(String, String),
 	/// An path is already noted by the rollback.
 	#[error("{0} has been already noted by this rollback.")]
 	AlreadyNoted(String),
 	/// An item couldn't be committed. Contains the path to the affected item and the error as
 	/// [`String`].
-	#[error("Commiting {0} failed with error: {1}.")]
+	#[error("Committing {0} failed with error: {1}.")]
 	Commit(String, String),
+	/// An item couldn't be committed due to an IO error. Contains the path to the affected item,
+	/// the commit phase where it failed and the IO error, which is the error
+	/// [`source`](std::error::Error::source), so its [`kind`](std::io::Error::kind) can be
+	/// inspected. The message doesn't include the IO error, so error reporters walking the
+	/// sources don't print it twice.
+	#[error("Committing {} failed while committing {phase}.", .path.display())]
+	CommitIo { path: PathBuf, phase: CommitPhase, source: std::io::Error },
+	/// A noted file has been modified by someone else since it was noted, so committing it would
+	/// overwrite those modifications.
+	#[error("{0} has been modified since it was noted.")]
+	ConcurrentModification(String),
+	/// Some staged operations depend on each other, so there's not a valid order to commit them
+	/// within their commit phase. Contains the paths involved in the cycle. Staging cannot
+	/// produce such operations, so this is only a safeguard.
+	#[error("The operations staged for {0} depend on each other cyclically.")]
+	CyclicOperations(String),
+	/// A file is exclusively held by another process, so it cannot be modified.
+	#[error("{0} is busy as it's held by another process.")]
+	FileBusy(String),
+	/// A noted file is reachable through other hard links, so committing it would also change the
+	/// content seen through them.
+	#[cfg(unix)]
+	#[error("{0} has several hard links, so committing it would affect all of them.")]
+	HardLinked(String),
 	#[error("IO error: {0}")]
 	IO(#[from] std::io::Error),
+	/// A file system doesn't have enough space available to commit the staged files. Contains a
+	/// path in that file system, the required bytes and the available bytes.
+	#[error("Committing into {0} requires {1} bytes, but only {2} bytes are available.")]
+	InsufficientSpace(String, u64, u64),
+	/// A file cannot be locked as the platform or the file system doesn't support it.
+	#[error("{0} cannot be locked as locking isn't supported.")]
+	LockUnsupported(String),
+	/// The parent dir of a new file doesn't exist, and it isn't registered as a new dir either.
+	#[error("The parent dir of {0} doesn't exist, and it isn't registered as a new dir.")]
+	MissingParentDir(String),
 	/// A path marked as 'new' for this rollback already exists.
 	#[error("{0} already exists and cannot be noted as 'new'.")]
 	NewItemAlreadyExists(String),
@@ -26,10 +67,64 @@ pub enum Error {
 	/// A path doesn't represent a file.
 	#[error("{0} isn't a file.")]
 	NotAFile(String),
+	/// A path doesn't represent a symlink.
+	#[error("{0} isn't a symlink.")]
+	NotASymlink(String),
+	/// Several items failed while they were committed concurrently. Contains every error.
+	#[error(
+		"Several errors occurred: {}",
+		.0.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ")
+	)]
+	Multiple(Vec<Error>),
+	/// A path isn't noted by the rollback.
+	#[error("{0} hasn't been noted by this rollback.")]
+	NotNoted(String),
+	/// A path lands outside the base dir of the rollback (see
+	/// [`Rollback::rooted_at`](crate::Rollback::rooted_at)).
+	#[error("{0} is outside the base dir.")]
+	OutsideBaseDir(String),
+	/// A path resolves to a location outside the root the rollback is confined to (see
+	/// [`Rollback::confined_to`](crate::Rollback::confined_to)).
+	#[error("{0} resolves outside the allowed root.")]
+	OutsideRoot(String),
+	/// A commit failed, and rolling it back failed as well, so the file system may be left
+	/// partially committed. Contains the commit error and the paths to the noted files or dirs
+	/// that couldn't be restored as [`String`].
+	#[error("Committing failed with error: {0}, and rolling back {1} failed as well.")]
+	RollbackFailed(String, String),
 	/// A path has been declared as new dir several times.
 	#[error("The path {0} has been noted several times as new_dir.")]
 	RepeatedNewDir(String),
 	/// A path has been declared as new file several times.
 	#[error("The path {0} has been noted several times as new_file.")]
 	RepeatedNewFile(String),
+	/// A noted file has been vetoed by a file guard while committing.
+	#[error("Committing {0} has been vetoed.")]
+	Vetoed(String),
+}
+
+impl Error {
+	// Whether the error may be transient, so retrying the failed operation makes sense. Only busy
+	// files and IO errors caused by contention are transient: other errors, such as a missing
+	// file or a denied permission, would fail again the same way.
+	pub(crate) fn is_retryable(&self) -> bool {
+		match self {
+			Error::Multiple(errors) => errors.iter().all(Error::is_retryable),
+			Error::CommitIo { source, .. } => is_transient(source),
+			Error::FileBusy(_) => true,
+			_ => false,
+		}
+	}
+}
+
+fn is_transient(err: &std::io::Error) -> bool {
+	match err.kind() {
+		std::io::ErrorKind::Interrupted |
+		std::io::ErrorKind::WouldBlock |
+		std::io::ErrorKind::ResourceBusy => true,
+		#[cfg(unix)]
+		_ => err.raw_os_error() == Some(libc::EBUSY),
+		#[cfg(not(unix))]
+		_ => false,
+	}
 }
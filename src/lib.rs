@@ -40,11 +40,12 @@
 //! // Rollback instance with capacity for the needed paths
 //! let mut rollback = Rollback::with_capacity(1,2,3);
 //! rollback.note_file(&existing_file).unwrap();
-//! rollback.new_file(&new_file1).unwrap();
-//! rollback.new_file(&new_file2).unwrap();
+//! // New files must be registered after their parent dirs, if they don't exist yet
 //! rollback.new_dir(&dir1).unwrap();
 //! rollback.new_dir(&dir2).unwrap();
 //! rollback.new_dir(&dir3).unwrap();
+//! rollback.new_file(&new_file1).unwrap();
+//! rollback.new_file(&new_file2).unwrap();
 //!
 //! // Some operations with the new files and the noted files.
 //! std::fs::write(rollback.get_noted_file(&existing_file).unwrap(),"Happy to commit this").unwrap();
@@ -70,8 +71,8 @@
 //! This example shows that a failed commit rollbacks everything.
 //!
 //! ```
-//! use fs_rollback::{Rollback, Error};
-//! use std::fs::File;
+//! use fs_rollback::{CommitPhase, Rollback, Error};
+//! use std::{fs::File, io::ErrorKind};
 //!     
 //! let tempdir = tempfile::tempdir().unwrap();
 //!
@@ -87,17 +88,19 @@
 //! assert!(!dir1.is_dir());
 //! assert!(!dir2.is_dir());
 //!
-//! // A file that doesn't exist and that cannot be committed due to its parent dir doesn't exist
-//! // and that won't be noted by the rollback. This file will cause that the rollback commit
-//! // fails.
-//! let new_file1 = dir2.join("file1.txt");
+//! // A file that doesn't exist and that cannot be committed due to its parent dir is removed
+//! // before committing. This file will cause that the rollback commit fails.
+//! let removed_dir = tempdir.path().join("removed");
+//! std::fs::create_dir(&removed_dir).unwrap();
+//! let new_file1 = removed_dir.join("file1.txt");
 //! assert!(!new_file1.is_file());
 //!
 //! // Rollback instance with capacity for the needed paths
 //! let mut rollback = Rollback::with_capacity(1,1,2);
 //! rollback.note_file(&existing_file).unwrap();
-//! rollback.new_file(&new_file1).unwrap();
 //! rollback.new_dir(&dir1).unwrap();
+//! rollback.new_file(&new_file1).unwrap();
+//! std::fs::remove_dir(&removed_dir).unwrap();
 //!
 //! // Some operations with the new files and the noted files.
 //! std::fs::write(rollback.get_noted_file(&existing_file).unwrap(),"Happy to commit this").unwrap();
@@ -105,12 +108,13 @@
 //!
 //! // If everything went well, we can commit our changes to the fs
 //! match rollback.commit(){
-//!     Err(Error::Commit(item, err)) => {
-//!         // The error specifies the uncommited file
-//!         assert_eq!(item, format!("{}",new_file1.display()));
-//!         // As the error's originated by a not existing directory, the message also explains
+//!     Err(Error::CommitIo { path, phase, source }) => {
+//!         // The error specifies the uncommited file and the phase where it failed
+//!         assert_eq!(path, new_file1);
+//!         assert_eq!(phase, CommitPhase::NewFiles);
+//!         // As the error's originated by a not existing directory, the IO error also explains
 //!         // that
-//!         assert!(err.contains("No such file or directory"));
+//!         assert_eq!(source.kind(), ErrorKind::NotFound);
 //!     },
 //!     _ => panic!("Unexpected error")
 //! }
@@ -156,4 +160,8 @@ mod rollback;
 pub mod test_builder;
 
 pub use error::Error;
-pub use rollback::Rollback;
+pub use rollback::{
+	BackupGuard, CommitCounts, CommitOrder, CommitOutcome, CommitPhase, CommitReport, FileTiming,
+	LinkPolicy, NotedHandle, ReNotePolicy, Rollback, RollbackBuilder, RollbackParts, Savepoint,
+	StagedKind, VetoPolicy,
+};
@@ -1,14 +1,230 @@
 // SPDX-License-Identifier: GPL-3.0
 
+#[cfg(feature = "acl")]
+mod acl;
+mod atomic;
 mod backup;
+mod backup_guard;
+mod base_dir;
+mod baseline;
+mod builder;
+mod confine;
+mod dir_backup;
 mod ext;
+mod fs_root;
+mod graph;
+mod handle;
+mod journal;
+mod lock;
+#[cfg(unix)]
+mod metadata;
+mod noted;
+mod options;
+#[cfg(unix)]
+mod owner;
+mod phase;
+#[cfg(unix)]
+mod space;
+mod symlink;
+#[cfg(unix)]
+mod umask;
 
 #[cfg(all(test, not(feature = "integration-tests")))]
 mod tests;
 
 use crate::Error;
-use std::{collections::HashMap, path::Path};
-use tempfile::NamedTempFile;
+pub use backup_guard::BackupGuard;
+pub use builder::RollbackBuilder;
+use dir_backup::DirBackup;
+use ext::{resolve_new_item, sanitize_file_name};
+pub use handle::NotedHandle;
+use noted::{NotedFile, Transform};
+use options::{CommitOptions, FileGuard, Progress};
+pub use phase::CommitPhase;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::{
+	borrow::Cow,
+	collections::{HashMap, HashSet},
+	fs::{File, Metadata, OpenOptions},
+	io::{Read, Write},
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Mutex,
+	},
+	time::Duration,
+};
+use tempfile::{NamedTempFile, TempDir};
+
+/// The kind of an operation staged in a [`Rollback`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StagedKind {
+	/// An existing file noted to be modified.
+	NotedFile,
+	/// A file to be created.
+	NewFile,
+	/// A directory to be created.
+	NewDir,
+	/// A symbolic link to be created.
+	NewSymlink,
+	/// An existing file to be renamed, identified by its current path.
+	RenamedFile,
+	/// An existing file to be deleted.
+	DeletedFile,
+	/// An existing directory to be deleted with all its content.
+	DeletedDir,
+	/// An existing directory whose content is restored if the commit fails.
+	NotedDir,
+	/// An existing directory whose metadata is modified, or an existing file whose permissions
+	/// are modified.
+	#[cfg(unix)]
+	DirMetadata,
+}
+
+impl StagedKind {
+	// Whether the operation is staged for a dir, so other operations can be staged inside it.
+	fn is_dir(self) -> bool {
+		#[cfg(unix)]
+		if self == Self::DirMetadata {
+			return true;
+		}
+		matches!(self, Self::NewDir | Self::DeletedDir | Self::NotedDir)
+	}
+
+	// Whether both operations can be staged for the same path.
+	fn can_share_path(self, other: Self) -> bool {
+		#[cfg(unix)]
+		return matches!(
+			(self, other),
+			(Self::NewDir | Self::NotedDir | Self::NotedFile, Self::DirMetadata) |
+				(Self::DirMetadata, Self::NewDir | Self::NotedDir | Self::NotedFile)
+		);
+		#[cfg(not(unix))]
+		false
+	}
+}
+
+/// A marker of the operations staged in a [`Rollback`] at some point, so the operations staged
+/// afterwards can be discarded. See [`Rollback::savepoint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Savepoint(usize);
+
+/// The noted files and new files of a [`Rollback`] mapped to their temporary files, together with
+/// its new dirs. See [`Rollback::into_parts`].
+pub type RollbackParts =
+	(HashMap<PathBuf, NamedTempFile>, HashMap<PathBuf, NamedTempFile>, Vec<PathBuf>);
+
+/// What happens when a file guard vetoes a noted file. See [`Rollback::commit_with_file_guard`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VetoPolicy {
+	/// The vetoed file isn't committed, but the rest of the rollback is.
+	Skip,
+	/// The whole commit fails and everything is rolled-back.
+	Abort,
+}
+
+/// How noted files are written upon commit, which matters if they have several hard links. See
+/// [`Rollback::with_link_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkPolicy {
+	/// The new content is copied into the existing file, so it's seen through all its hard links.
+	/// The copy isn't atomic: the file may be seen half-written while it's committed.
+	PreserveLinks,
+	/// The existing file is replaced at once by a new file with the new content, so only the
+	/// noted path sees the new content, while the other hard links keep the original content.
+	/// The new file keeps the permissions, owner, group and extended attributes of the original.
+	BreakLinks,
+}
+
+/// What happens when a file already noted is noted again. See
+/// [`Rollback::note_file_with_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReNotePolicy {
+	/// The noted file keeps its current content.
+	Keep,
+	/// The noted file content is reset to the current content of the original file.
+	Reset,
+	/// Noting the file fails with [`Error::AlreadyNoted`], as [`Rollback::note_file`] does.
+	Error,
+}
+
+/// The number of operations applied by a commit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommitCounts {
+	/// The number of noted files committed.
+	pub noted_files: usize,
+	/// The number of new files created.
+	pub new_files: usize,
+	/// The number of new directories created.
+	pub new_dirs: usize,
+	/// The number of new symbolic links created.
+	pub new_symlinks: usize,
+	/// The number of files renamed.
+	pub renamed_files: usize,
+	/// The number of files deleted.
+	pub deleted_files: usize,
+	/// The number of directories deleted.
+	pub deleted_dirs: usize,
+	/// The number of directories whose metadata has been modified.
+	#[cfg(unix)]
+	pub dirs_metadata: usize,
+}
+
+/// The outcome of a successful commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitOutcome {
+	/// There wasn't any staged operation, so nothing has been done.
+	Empty,
+	/// The staged operations have been applied.
+	Applied {
+		/// The number of operations applied.
+		counts: CommitCounts,
+	},
+}
+
+/// The time spent copying the content of a file while committing it. See
+/// [`Rollback::commit_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTiming {
+	/// The path of the committed file, as it was staged.
+	pub path: PathBuf,
+	/// The number of bytes copied.
+	pub bytes: u64,
+	/// The time spent copying the file.
+	pub duration: Duration,
+}
+
+/// A detailed report of a successful commit. See [`Rollback::commit_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitReport {
+	/// The outcome of the commit.
+	pub outcome: CommitOutcome,
+	/// The time spent copying each committed noted file and new file, sorted by path.
+	pub timings: Vec<FileTiming>,
+	/// The new directories created, as they were staged, sorted by path.
+	pub new_dirs: Vec<PathBuf>,
+	/// The backups of the committed noted files kept in the archive dir, sorted by path. Empty
+	/// unless an archive dir is configured. See [`Rollback::with_archive_dir`].
+	pub archived: Vec<PathBuf>,
+}
+
+impl CommitReport {
+	/// The total number of bytes copied into the committed files.
+	pub fn total_bytes(&self) -> u64 {
+		self.timings.iter().map(|timing| timing.bytes).sum()
+	}
+}
+
+/// The order in which the phases of a commit are carried out. See [`Rollback::with_commit_order`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommitOrder {
+	/// Noted files are committed first, then new directories are created, followed by new files.
+	#[default]
+	NotedFirst,
+	/// New directories are created first, then noted files are committed, followed by new files.
+	DirsFirst,
+}
 
 /// # Description
 ///
@@ -28,6 +244,13 @@ use tempfile::NamedTempFile;
 /// - Modification of existing files.
 /// - Creation of new files.
 /// - Creation of new directories.
+/// - Creation of new symbolic links.
+/// - Renaming of existing files.
+/// - Deletion of existing files.
+/// - Deletion of existing directories and their content.
+/// - Modification of existing directories' metadata (Unix only).
+/// - Modification of existing files through memory maps (`mmap` feature).
+/// - Preservation of the POSIX ACLs of modified files (`acl` feature, Linux only).
 ///
 /// # Considerations
 ///
@@ -40,34 +263,159 @@ use tempfile::NamedTempFile;
 ///   described [here](https://docs.rs/tempfile/latest/tempfile/) applies for this crate as well.
 ///
 /// - The [`Rollback`] struct is able to detect if two different paths point to the same file when
-///   its added to the rollback to be modified. However, there's not a way to detect if two paths
-///   will resolve to the same new file or new directory, so it will accept both.
+///   its added to the rollback to be modified. Two paths resolving to the same new file or new
+///   directory are detected as well when they're added, as long as their parent dir exists.
+///   Otherwise, there's not a way to detect it until the parent dir exists, so it will accept both.
+///
+///   In the first cases, the rollback simply won't accept the path the second time. In the last
+///   case, however, the rollback will accept the path and **fail** when it's committed, to avoid a
+///   race condition. Note that this mean that all modifications will be rolled-back, so paying
+///   attention to the paths of new files/new dirs whose parent doesn't exist yet is crutial.
 ///
-///   In the first case, the rollback simply won't accept the noted file the second time. In the
-///   second case, however, the rollback will accept the path and **fail** when it's committed, to
-///   avoid a race condition. Note that this mean that all modifications will be rolled-back, so
-///   paying attention to new files/new dirs paths is crutial.
+/// - Paths given to stage operations (e.g. [`Rollback::note_file`]) or to configure the instance
+///   (e.g. [`Rollback::with_journal`]) are copied into the instance, so they may be built on the
+///   fly and dropped right after passing them.
 
 #[derive(Debug)]
 pub struct Rollback<'a> {
-	// Maps original file paths to the temporary file. As the temporary file is included in the
-	// map, it lives as long as the instance does.
-	noted: HashMap<&'a Path, NamedTempFile>,
+	// Maps original file paths to the noted file, which contains the temporary file. As the
+	// temporary file is included in the map, it lives as long as the instance does.
+	noted: HashMap<PathBuf, NotedFile>,
 	// Maps original paths referring files that must be created with its corresponding temporary
 	// file. As the temporary file is included in the map, it lives as long as the instance does.
-	new_files: HashMap<&'a Path, NamedTempFile>,
+	// Temporary files may be shared among new files with the same content.
+	new_files: HashMap<PathBuf, Arc<NamedTempFile>>,
+	// Maps new files to the mode they get upon commit, if it's specified.
+	#[cfg(unix)]
+	new_file_modes: HashMap<PathBuf, u32>,
 	// New dirs added.
-	new_dirs: Vec<&'a Path>,
+	new_dirs: Vec<PathBuf>,
+	// New dirs whose parent dir must exist upon commit, as only their final component is created.
+	strict_new_dirs: HashSet<PathBuf>,
+	// Maps symlinks that must be created to their target.
+	new_symlinks: HashMap<PathBuf, PathBuf>,
+	// Maps existing files that must be renamed to their new path. They're backed up right before
+	// renaming them upon commit, so the backups hold the content they had when they were renamed.
+	renames: HashMap<PathBuf, PathBuf>,
+	// Existing files that must be deleted. They're backed up right before deleting them upon
+	// commit, so the backups hold the content they had when they were deleted.
+	deleted: HashSet<PathBuf>,
+	// Maps existing dirs that must be deleted to the backup of their whole tree, which is taken
+	// when they're registered.
+	deleted_dirs: HashMap<PathBuf, DirBackup>,
+	// Maps existing dirs whose content must be restored if the commit fails to the backup of their
+	// whole tree, which is taken when they're noted.
+	noted_dirs: HashMap<PathBuf, DirBackup>,
+	// The staged operations in the order they were staged, so the operations staged after a
+	// savepoint can be discarded.
+	history: Vec<(PathBuf, StagedKind)>,
+	// A directory keeping the hashes of the files committed by the instance, if any.
+	baseline_dir: Option<PathBuf>,
+	// Maps existing directories, and files whose permissions are noted, to their original metadata
+	// and the staged changes.
+	#[cfg(unix)]
+	dirs_metadata: HashMap<PathBuf, metadata::DirMetadata>,
+	// The umask applied while committing, if any.
+	#[cfg(unix)]
+	umask: Option<u32>,
+	// The order in which the commit phases are carried out.
+	commit_order: CommitOrder,
+	// A file where the commit progress is recorded, if any.
+	journal: Option<PathBuf>,
+	// Whether noted files are checked to not be busy when noted and before committing.
+	busy_check: bool,
+	// Whether noted files are checked to not be modified by someone else between noting and
+	// committing them.
+	modification_check: bool,
+	// How noted files are written upon commit, if configured.
+	link_policy: Option<LinkPolicy>,
+	// The maximum number of threads committing noted files concurrently, if capped.
+	max_threads: Option<usize>,
+	// The directory where the temporary files of noted files and new files are created, if it
+	// isn't the default temp dir.
+	temp_dir: Option<PathBuf>,
+	// The prefix of the temporary files of noted files and new files, if it isn't the default one.
+	temp_file_prefix: Option<&'a str>,
+	// Whether the names of the temporary files of noted files and new files embed the name of
+	// their targets.
+	temp_file_target_names: bool,
+	// A directory where the backups of noted files are persisted while committing, if any.
+	backup_dir: Option<PathBuf>,
+	// A directory where the backups of noted files are archived once the commit succeeds, if any.
+	archive_dir: Option<PathBuf>,
+	// Whether noted files are checked to not have several hard links.
+	#[cfg(unix)]
+	hard_link_check: bool,
+	// Maps content hashes to the temporary file holding that content, if the content of new files
+	// is deduplicated.
+	deduplicated_contents: Option<HashMap<String, Arc<NamedTempFile>>>,
+	// Whether new files are fully written into staging dirs before any of them is created.
+	stage_new_files: bool,
+	// Whether new files are written next to their final location and renamed into place.
+	atomic_new_files: bool,
+	// A file created once the commit completes, if any.
+	completion_marker: Option<PathBuf>,
+	// A directory standing in for the file system root, if any.
+	fs_root: Option<PathBuf>,
+	// The dir relative paths are staged under, if any.
+	base_dir: Option<PathBuf>,
+	// The dir staged paths must resolve under, if any.
+	confined_root: Option<PathBuf>,
+	// Whether the ACLs of noted files are preserved.
+	#[cfg(feature = "acl")]
+	preserve_acls: bool,
+	// Whether the instance has been committed or explicitly discarded, so dropping it doesn't
+	// report its staged changes as discarded.
+	#[cfg(feature = "tracing")]
+	settled: AtomicBool,
 }
 
 impl Default for Rollback<'_> {
 	/// Creates a new, empty instance
 	fn default() -> Self {
-		Self { noted: HashMap::new(), new_files: HashMap::new(), new_dirs: Vec::new() }
+		// Rollback implements Drop if tracing is enabled, which rules out the struct update
+		// syntax in `with_capacity`, so the fields are initialized there instead. Zero capacities
+		// don't allocate.
+		Self::with_capacity(0, 0, 0)
+	}
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Rollback<'_> {
+	// Reports the staged changes discarded by dropping an instance that hasn't been committed,
+	// which helps finding code paths that build a transaction and never commit it.
+	fn drop(&mut self) {
+		if self.settled.load(Ordering::Relaxed) {
+			return;
+		}
+		let other_operations = self.new_symlinks.len() +
+			self.renames.len() +
+			self.deleted.len() +
+			self.deleted_dirs.len() +
+			self.noted_dirs.len();
+		#[cfg(unix)]
+		let other_operations = other_operations + self.dirs_metadata.len();
+		let (noted_files, new_files, new_dirs) =
+			(self.noted.len(), self.new_files.len(), self.new_dirs.len());
+		if noted_files + new_files + new_dirs + other_operations > 0 {
+			tracing::warn!(
+				noted_files,
+				new_files,
+				new_dirs,
+				other_operations,
+				"rollback dropped without committing, discarding its staged changes"
+			);
+		}
 	}
 }
 
 impl<'a> Rollback<'a> {
+	/// Creates a builder configuring a new instance through named methods, which is less error
+	/// prone than passing positional capacities to [`Rollback::with_capacity`].
+	pub fn builder() -> RollbackBuilder<'a> {
+		RollbackBuilder::default()
+	}
+
 	/// Creates a new, empty instance with pre allocated memory for noted files, new files and new
 	/// directories.
 	pub fn with_capacity(
@@ -78,138 +426,2465 @@ impl<'a> Rollback<'a> {
 		Self {
 			noted: HashMap::with_capacity(note_capacity),
 			new_files: HashMap::with_capacity(new_files_capacity),
+			#[cfg(unix)]
+			new_file_modes: HashMap::new(),
 			new_dirs: Vec::with_capacity(new_dirs_capacity),
+			strict_new_dirs: HashSet::new(),
+			new_symlinks: HashMap::new(),
+			renames: HashMap::new(),
+			deleted: HashSet::new(),
+			deleted_dirs: HashMap::new(),
+			noted_dirs: HashMap::new(),
+			#[cfg(unix)]
+			dirs_metadata: HashMap::new(),
+			history: Vec::new(),
+			baseline_dir: None,
+			#[cfg(unix)]
+			umask: None,
+			commit_order: CommitOrder::default(),
+			journal: None,
+			busy_check: false,
+			modification_check: false,
+			#[cfg(unix)]
+			hard_link_check: false,
+			link_policy: None,
+			max_threads: None,
+			temp_dir: None,
+			temp_file_prefix: None,
+			temp_file_target_names: false,
+			backup_dir: None,
+			archive_dir: None,
+			deduplicated_contents: None,
+			stage_new_files: false,
+			atomic_new_files: false,
+			completion_marker: None,
+			fs_root: None,
+			base_dir: None,
+			confined_root: None,
+			#[cfg(feature = "acl")]
+			preserve_acls: false,
+			#[cfg(feature = "tracing")]
+			settled: AtomicBool::new(false),
 		}
 	}
 
+	/// Configures the order in which the phases of the commit are carried out. By default, noted
+	/// files are committed before creating new directories and new files
+	/// ([`CommitOrder::NotedFirst`]).
+	///
+	/// Operations depending on an operation of a later phase are committed afterwards anyway (see
+	/// [`Rollback::commit`]). If the commit fails, the committed phases are rolled-back in the
+	/// reverse order.
+	pub fn with_commit_order(mut self, order: CommitOrder) -> Self {
+		self.commit_order = order;
+		self
+	}
+
+	/// Configures a journal file where the commit progress is recorded: each operation is recorded
+	/// before it's carried out, together with its backup if any, and once it's done. Entries are
+	/// flushed to disk before each destructive step, so if the process crashes in the middle of a
+	/// commit, the journal and the backups it points to allow to finish or undo the transaction
+	/// manually.
+	///
+	/// The journal is append-only and it's removed once the commit succeeds. If the commit fails,
+	/// the journal is kept, recording the failure and the rollback.
+	pub fn with_journal<P: Into<PathBuf>>(mut self, journal: P) -> Self {
+		self.journal = Some(journal.into());
+		self
+	}
+
+	/// Enables checking that noted files aren't busy, ie, exclusively held by another process.
+	/// Files are checked when they're noted and right before committing, failing early with
+	/// [`Error::FileBusy`] instead of failing in the middle of the commit with an obscure OS error.
+	///
+	/// On Unix, a file is busy if another process holds an exclusive advisory lock on it. On
+	/// Windows, a file is busy if another process opened it without sharing access. The check is
+	/// disabled by default.
+	pub fn with_busy_check(mut self) -> Self {
+		self.busy_check = true;
+		self
+	}
+
+	/// Enables checking that noted files haven't been modified by someone else since they were
+	/// noted, so committing them doesn't silently overwrite those modifications. The modification
+	/// time and the size of each original file are recorded when it's noted, or when it's reset
+	/// (see [`Rollback::note_file_with_policy`]), and compared right before committing it. If
+	/// they differ, the commit fails with [`Error::ConcurrentModification`] and everything is
+	/// rolled-back. The check is disabled by default.
+	///
+	/// This protects cooperating writers against lost updates, but modifications that keep both
+	/// the modification time and the size aren't detected. Files noted with
+	/// [`Rollback::note_file_lazy`] aren't checked, as their content is computed from the original
+	/// upon commit, nor are the symlinks noted with [`Rollback::note_symlink`].
+	pub fn with_modification_check(mut self) -> Self {
+		self.modification_check = true;
+		self
+	}
+
+	/// Enables checking that noted files don't have several hard links, failing with
+	/// [`Error::HardLinked`] when they're noted. The check is disabled by default.
+	///
+	/// Noting the same file through different hard links already fails with
+	/// [`Error::AlreadyNoted`], and new files and dirs cannot exist before the commit, so staged
+	/// operations never alias each other. However, a noted file may be reachable through names
+	/// unknown to the rollback, which would be affected by the commit as well. See
+	/// [`Rollback::note_file`] for the hard link semantics.
+	#[cfg(unix)]
+	pub fn with_hard_link_check(mut self) -> Self {
+		self.hard_link_check = true;
+		self
+	}
+
+	/// Enables deduplicating the content of new files staged with
+	/// [`Rollback::new_file_from_reader`]: new files with identical content share a single
+	/// temporary file, which saves temporary space and file descriptors when many new files
+	/// replicate the same content. Committing still creates an independent copy of the content at
+	/// each new file.
+	///
+	/// As shared temporary files back several new files, they must not be modified through
+	/// [`Rollback::get_new_file`], and [`Rollback::get_writer`] doesn't hand them out. New files
+	/// staged with
+	/// [`Rollback::new_file`] aren't
+	/// deduplicated, as their content isn't known when they're staged.
+	pub fn with_deduplicated_new_files(mut self) -> Self {
+		self.deduplicated_contents = Some(HashMap::new());
+		self
+	}
+
+	/// Enables staging new files before creating them. By default, each new file is created and
+	/// written in turn, so watchers may observe a new file while it's empty or partially written,
+	/// or some new files while the next ones aren't created yet; if the commit fails, the files
+	/// created so far are removed, but they may have been observed in the meantime.
+	///
+	/// When this mode is enabled, every new file is fully written, including its permissions, into
+	/// a hidden staging directory next to its final location. Only once all of them are written
+	/// they're hard linked into place, one after another, and the staging directories are removed.
+	/// So new files never appear partially written, and if something fails while writing them,
+	/// none of them appears at all. Note that linking each file is atomic, but linking all of them
+	/// isn't a single atomic step, so watchers may still observe the links being created.
+	///
+	/// The file system must support hard links.
+	pub fn with_staged_new_files(mut self) -> Self {
+		self.stage_new_files = true;
+		self
+	}
+
+	/// Enables creating each new file atomically. By default, a new file is created empty and
+	/// then filled, so watchers may observe it while it's empty or partially written. When this
+	/// mode is enabled, each new file is fully written, including its permissions, into a hidden
+	/// temporary file next to its final location, which is then renamed into place, as backups
+	/// are when noted files are restored. Renaming fails if the new file exists, so existing
+	/// files aren't overwritten.
+	///
+	/// Unlike [`Rollback::with_staged_new_files`], new files still appear one after another as
+	/// they're written. If both modes are enabled, new files are staged.
+	pub fn with_atomic_new_files(mut self) -> Self {
+		self.atomic_new_files = true;
+		self
+	}
+
+	/// Configures how noted files are written upon commit, which matters if they have several
+	/// hard links (see [`LinkPolicy`]). If it isn't configured, noted files are replaced
+	/// atomically as with [`LinkPolicy::BreakLinks`], so they're never seen half-written. As this
+	/// default breaks the links of files with several hard links,
+	/// [`Rollback::hard_linked_files`] reports them so they can be handled explicitly.
+	///
+	/// Replaced files keep their permissions, and on Unix their owner, group and extended
+	/// attributes, such as SELinux labels, as far as the process is allowed to set them. Keeping
+	/// the owner of a file owned by another user requires privileges, so if the process lacks
+	/// them, the file is written in place as with [`LinkPolicy::PreserveLinks`].
+	pub fn with_link_policy(mut self, policy: LinkPolicy) -> Self {
+		self.link_policy = Some(policy);
+		self
+	}
+
+	/// Caps the number of threads committing noted files concurrently. By default, each noted
+	/// file is committed in its own thread, which may thrash the scheduler for large transactions.
+	/// Once capped, the noted files are shared out among the threads as they finish committing
+	/// the previous ones. At least one thread is always used.
+	pub fn with_max_threads(mut self, max_threads: usize) -> Self {
+		self.max_threads = Some(max_threads);
+		self
+	}
+
+	/// Configures the directory where the temporary files of noted files and new files are
+	/// created, instead of the default temp dir. Placing them in the same file system as the
+	/// targets avoids staging big files in a different mount (e.g. a size-limited tmpfs), and
+	/// turns the copies carried out upon commit into same-file-system copies. The directory must
+	/// exist.
+	pub fn with_temp_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+		self.temp_dir = Some(dir.into());
+		self
+	}
+
+	/// Configures the prefix of the names of the temporary files of noted files and new files
+	/// (e.g. `fs_rollback_`), so they can be told apart from other temporary files while
+	/// debugging or looking for leaked files. By default, `tempfile`'s prefix (`.tmp`) is used.
+	/// The rest of the name is still random, so the names don't collide.
+	pub fn with_temp_file_prefix(mut self, prefix: &'a str) -> Self {
+		self.temp_file_prefix = Some(prefix);
+		self
+	}
+
+	/// Enables embedding the file name of the target into the names of the temporary files of
+	/// noted files and new files, after the random part, so each temporary file can be matched
+	/// with its target while debugging, e.g. `.tmpAbC123-config.toml`. The file name is
+	/// sanitized, keeping only ASCII alphanumeric characters, `.`, `-` and `_`, and it's
+	/// truncated to keep the names short.
+	pub fn with_temp_file_target_names(mut self) -> Self {
+		self.temp_file_target_names = true;
+		self
+	}
+
+	/// Configures a directory where the backups of noted files are persisted while committing,
+	/// instead of next to the originals. Each backup is accompanied by a record pointing to its
+	/// original, so if the process dies before the commit finishes, the originals can be restored
+	/// on the next startup with [`Rollback::recover`]. The backups and records are removed once
+	/// the commit finishes, whether it succeeds or not.
+	///
+	/// The directory must exist, and it should be dedicated to this purpose. Backups living in
+	/// another file system than their originals are restored by copying them. Only noted files are
+	/// covered: renamed and deleted files keep their backups next to them, while the progress of
+	/// the other operations can be recorded with [`Rollback::with_journal`].
+	pub fn with_backup_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+		self.backup_dir = Some(dir.into());
+		self
+	}
+
+	/// Configures a directory where the backups of noted files are archived once the commit
+	/// succeeds, instead of discarding them. This allows undoing a commit afterwards by copying the
+	/// archived backups over the committed files. Each archived backup is named after its original
+	/// file, prefixed with the archiving time in nanoseconds since the Unix epoch and a counter
+	/// keeping the names unique, e.g. `1700000000000000000-0-file.txt`. Existing files in the
+	/// archive are never replaced. The paths of the archived backups
+	/// are returned by [`Rollback::commit_report`].
+	///
+	/// The directory must exist. Archiving is the last step before the completion marker (see
+	/// [`Rollback::with_completion_marker`]), and failing to archive a backup rolls back the whole
+	/// commit. The archive is never cleaned up by this crate, so retention is up to the caller.
+	/// Only noted files are covered, as the rest of operations don't overwrite existing content.
+	pub fn with_archive_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+		self.archive_dir = Some(dir.into());
+		self
+	}
+
+	/// Configures a marker file created as the final step of the commit, signaling watchers that
+	/// the transaction completed. The marker is created atomically once every other phase
+	/// succeeded, and failing to create it rolls back the whole commit, so its absence means the
+	/// transaction didn't fully complete. A marker left by a previous commit is removed when the
+	/// commit starts. Empty commits don't touch the marker.
+	pub fn with_completion_marker<P: Into<PathBuf>>(mut self, marker: P) -> Self {
+		self.completion_marker = Some(marker.into());
+		self
+	}
+
+	/// Configures a directory standing in for the file system root, so absolute staged paths are
+	/// interpreted relative to it, both when they're staged and when they're committed: e.g.
+	/// noting `/etc/foo` notes `<root>/etc/foo`. Relative paths aren't affected. This allows to
+	/// test deployment logic against a fake system root without touching the real one.
+	///
+	/// Paths keep being reported as they were staged, while the journal records the rebased paths.
+	/// The journal, the baseline directory and the completion marker aren't rebased.
+	///
+	/// This is a testing and staging aid, not a security boundary: it's pure path joining, so
+	/// paths containing `..` components or symlinks may still reach anything outside the root.
+	pub fn with_fs_root<P: Into<PathBuf>>(mut self, root: P) -> Self {
+		self.fs_root = Some(root.into());
+		self
+	}
+
+	/// Roots the rollback at a base dir, so relative staged paths are interpreted relative to it:
+	/// e.g. `new_file("foo.txt")` stages `<base>/foo.txt`. The joined paths are the ones stored and
+	/// reported, and lookups accept both forms. A relative base is made absolute against the
+	/// current dir.
+	///
+	/// Unlike [`Rollback::with_fs_root`], this guards against staging outside the base dir by
+	/// mistake: staging methods reject paths containing `..` components and absolute paths not
+	/// under the base dir with [`Error::OutsideBaseDir`]. Symlinks inside the base dir aren't
+	/// resolved, so they may still lead outside it.
+	pub fn rooted_at<P: Into<PathBuf>>(mut self, base: P) -> Self {
+		let base = base.into();
+		self.base_dir = Some(std::path::absolute(&base).unwrap_or(base));
+		self
+	}
+
+	/// Confines the rollback to a root dir, so it never touches anything outside of it: staging
+	/// methods fail with [`Error::OutsideRoot`] if the path resolves outside the root, either
+	/// through `..` components, symlinked parent dirs or the path itself being a symlink, as
+	/// noted symlinks are written through. The parent dir of the path is canonicalized up to its
+	/// deepest existing ancestor, and symlinks are followed even if they're dangling.
+	/// If a file system root is configured (see [`Rollback::with_fs_root`]), the rebased paths
+	/// are the ones checked.
+	///
+	/// The paths are checked when they're staged, so the file system shouldn't be changed
+	/// afterwards to lead them outside the root before committing.
+	pub fn confined_to<P: Into<PathBuf>>(mut self, root: P) -> Self {
+		self.confined_root = Some(root.into());
+		self
+	}
+
+	/// Enables preserving the POSIX ACLs of noted files. Copying the new content to a noted file
+	/// updates its permissions, which also changes its ACL mask, so the access granted by the ACL
+	/// may be silently loosened or tightened. When this mode is enabled, the ACL of each file is
+	/// read when it's noted and re-applied after the file is committed, and also if the file is
+	/// rolled-back.
+	///
+	/// Failing to read an ACL when noting a file results in [`Error::Acl`], while failing to apply
+	/// it upon commit is reported as any other commit failure of the file.
+	#[cfg(feature = "acl")]
+	pub fn with_preserved_acls(mut self) -> Self {
+		self.preserve_acls = true;
+		self
+	}
+
+	/// Configures a directory where the hashes of the files committed by this instance are stored
+	/// upon commit. Once a baseline is configured, [`Rollback::note_file`] reports if the noted
+	/// file differs from the version committed the last time, which is useful for incremental
+	/// tooling. The directory is created when committing if it doesn't exist yet.
+	///
+	/// The baseline is an optional layer: nothing is hashed nor stored unless it's configured.
+	pub fn with_baseline_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+		self.baseline_dir = Some(dir.into());
+		self
+	}
+
+	/// Configures an explicit umask applied for the duration of the commit, so created files and
+	/// directories get predictable permissions regardless of the process umask: new directories
+	/// are created with mode `0o777 & !umask`, while new files end up with mode `0o666 & !umask`.
+	/// The previous umask is restored as soon as the commit finishes, either successfully or not.
+	///
+	/// Note that the umask is a process-wide setting, so other threads creating files while the
+	/// commit is running are affected as well.
+	#[cfg(unix)]
+	pub fn with_umask(mut self, umask: u32) -> Self {
+		self.umask = Some(umask);
+		self
+	}
+
+	/// Reserves capacity for at least `additional` more noted files, as [`HashMap::reserve`] does.
+	pub fn reserve_noted(&mut self, additional: usize) {
+		self.noted.reserve(additional);
+	}
+
+	/// Reserves capacity for at least `additional` more new files, as [`HashMap::reserve`] does.
+	pub fn reserve_new_files(&mut self, additional: usize) {
+		self.new_files.reserve(additional);
+	}
+
+	/// Reserves capacity for at least `additional` more new directories, as [`Vec::reserve`] does.
+	pub fn reserve_new_dirs(&mut self, additional: usize) {
+		self.new_dirs.reserve(additional);
+	}
+
 	/// Registers an existing file as 'to be modified', creating a temporary file that will be
 	/// committed to the existing file upon commit.
+	///
+	/// Committing a noted file writes the new content next to the existing file and then renames
+	/// it over the existing file, so the file is replaced atomically and it's never seen
+	/// half-written. As the file is replaced by a new one, if it has several hard links, the other
+	/// links keep the original content. Configure [`Rollback::with_link_policy`] to write the new
+	/// content into the existing file instead, or enable [`Rollback::with_hard_link_check`] to
+	/// reject files with several hard links.
+	///
+	/// Symlinks are followed: noting a symlink notes the file it points to, which is modified upon
+	/// commit, while the link is kept. As that file may live anywhere,
+	/// [`Rollback::symlinked_files`] reports the files noted through symlinks, and
+	/// [`Rollback::note_symlink`] allows replacing the link itself instead.
+	///
+	/// Returns `true` if the file content differs from the one recorded in the baseline (see
+	/// [`Rollback::with_baseline_dir`]), `false` otherwise. If there's not a baseline configured,
+	/// or if the file isn't part of it, the file is considered as changed.
 	/// ## Errors:
 	/// - If the file is already noted, either using exactly the same [`Path`] or a different
 	///   representation of it.
 	/// - If the original path isn't a file.
 	/// - If the temporary file cannot be created.
 	/// - If the temporary file cannot be writen.
-	pub fn note_file(&mut self, original: &'a Path) -> Result<(), Error> {
-		if !original.is_file() {
+	/// - If the baseline is configured and the file content cannot be compared against it.
+	/// - If the busy check is enabled (see [`Rollback::with_busy_check`]) and the file is busy.
+	/// - If the hard link check is enabled (see [`Rollback::with_hard_link_check`]) and the file
+	///   has several hard links.
+	/// - If the ACLs are preserved (see [`Rollback::with_preserved_acls`]) and the file ACL cannot
+	///   be read.
+	pub fn note_file<P: AsRef<Path>>(&mut self, original: P) -> Result<bool, Error> {
+		self.note(&self.anchored(original.as_ref())?, None, true)
+	}
+
+	/// Registers a file as 'to be modified' as [`Rollback::note_file`] does if it exists, or does
+	/// nothing if it doesn't, which is handy for optional files. Note that the file may be
+	/// removed or created after checking it, so it's better to handle that when committing.
+	///
+	/// Returns `true` if the file has been noted, `false` if it doesn't exist. Whether the noted
+	/// file differs from the baseline isn't reported.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::note_file`] if the path exists, e.g. [`Error::NotAFile`] if
+	///   it's a directory.
+	pub fn note_file_if_exists<P: AsRef<Path>>(&mut self, original: P) -> Result<bool, Error> {
+		let original = self.anchored(original.as_ref())?;
+		let original = original.as_ref();
+		if !self.fs_path(original).exists() {
+			return Ok(false);
+		}
+		self.note(original, None, true)?;
+		Ok(true)
+	}
+
+	/// Registers an existing symlink as 'to be replaced' by a regular file upon commit, creating
+	/// a temporary file with the content of the file it points to, or an empty one if it doesn't
+	/// point to a file (e.g. it's dangling). The temporary file is modified as the one of a noted
+	/// file (see [`Rollback::get_noted_file`]), and upon commit the link is replaced by it at
+	/// once, leaving the file it pointed to untouched. The new file gets the permissions of the
+	/// file the link pointed to when it was noted, or the ones of the temporary file otherwise.
+	///
+	/// If the commit fails, the link is created again pointing to the same target.
+	/// ## Errors:
+	/// - [`Error::NotASymlink`] if the path isn't a symlink.
+	/// - If the symlink, or the file it points to, is already noted, either using exactly the same
+	///   [`Path`] or a different representation of it.
+	/// - If the temporary file cannot be created or the content cannot be copied into it.
+	pub fn note_symlink<P: AsRef<Path>>(&mut self, link: P) -> Result<(), Error> {
+		let link = self.anchored(link.as_ref())?;
+		let link = link.as_ref();
+		let fs_link = self.fs_path(link);
+		if !fs_link.symlink_metadata().is_ok_and(|metadata| metadata.is_symlink()) {
+			return Err(Error::NotASymlink(format!("{}", link.display())));
+		} else if self.is_tracked_file(link) {
+			return Err(Error::AlreadyNoted(format!("{}", link.display())));
+		}
+
+		let target = std::fs::read_link(&fs_link)?;
+		let temp_file = self.new_temp_file(link)?;
+		let permissions = match std::fs::metadata(&fs_link) {
+			Ok(metadata) if metadata.is_file() => {
+				std::fs::copy(&fs_link, &temp_file)?;
+				metadata.permissions()
+			},
+			_ => temp_file.as_file().metadata()?.permissions(),
+		};
+
+		let mut noted = NotedFile::new(temp_file, permissions);
+		noted.link_target = Some(target);
+		self.noted.insert(link.to_path_buf(), noted);
+		self.history.push((link.to_path_buf(), StagedKind::NotedFile));
+		Ok(())
+	}
+
+	/// Registers several existing files as 'to be modified' as [`Rollback::note_file`] does for
+	/// each of them, in order. Noting stops at the first file that cannot be noted, in which case
+	/// the files noted by this call are discarded, so either every file is noted or none is.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::note_file`] for the first file that cannot be noted. IO
+	///   errors include the failing path in their message.
+	pub fn note_files<I>(&mut self, originals: I) -> Result<(), Error>
+	where
+		I: IntoIterator,
+		I::Item: AsRef<Path>,
+	{
+		self.stage_all(originals, |rollback, original| rollback.note_file(original).map(|_| ()))
+	}
+
+	/// Registers an existing file as 'to be modified' as [`Rollback::note_file`] does, but
+	/// deciding what happens if the file is already noted, either using exactly the same [`Path`]
+	/// or a different representation of it, according to `policy`:
+	///
+	/// - [`ReNotePolicy::Keep`]: the changes applied so far to the noted file are kept.
+	/// - [`ReNotePolicy::Reset`]: the changes applied so far are discarded, and the noted file is
+	///   reset to the current content of the original file. This is useful to restart the edits if
+	///   the original file changed. Files noted with [`Rollback::note_file_lazy`] are read upon
+	///   commit, so there's nothing to reset for them.
+	/// - [`ReNotePolicy::Error`]: the same as [`Rollback::note_file`].
+	///
+	/// Returns the same as [`Rollback::note_file`].
+	/// ## Errors:
+	/// - The same errors as [`Rollback::note_file`], except if the file is already noted and the
+	///   policy isn't [`ReNotePolicy::Error`].
+	/// - If the noted file cannot be reset.
+	pub fn note_file_with_policy<P: AsRef<Path>>(
+		&mut self,
+		original: P,
+		policy: ReNotePolicy,
+	) -> Result<bool, Error> {
+		let original = self.anchored(original.as_ref())?;
+		let original = original.as_ref();
+		let Some(key) = self.noted_key(original).map(Path::to_path_buf) else {
+			return self.note_file(original);
+		};
+
+		let fs_key = self.fs_path(&key);
+		match policy {
+			ReNotePolicy::Keep => (),
+			ReNotePolicy::Reset => self
+				.noted
+				.get_mut(&key)
+				.expect("The key belongs to the map; qed;")
+				.reset(&fs_key)?,
+			ReNotePolicy::Error =>
+				return Err(Error::AlreadyNoted(format!("{}", original.display()))),
+		}
+
+		match self.baseline_dir.as_deref() {
+			Some(baseline_dir) => baseline::differs(baseline_dir, &fs_key),
+			None => Ok(true),
+		}
+	}
+
+	/// Registers an existing file as 'to be modified' as [`Rollback::note_file`] does, and memory
+	/// maps its temporary file, so it can be edited in place through
+	/// [`Rollback::get_noted_file_mmap`]. This avoids loading big files into memory when only a
+	/// small region needs to be modified. Note that the map has the size of the file when it's
+	/// noted, so the file cannot grow or shrink through it.
+	///
+	/// Upon commit, the map is flushed and the original file is replaced atomically by the
+	/// modified copy.
+	///
+	/// # Safety considerations
+	///
+	/// The map points to the temporary file, which is owned by the rollback, so the original file
+	/// isn't mapped at all. However, if any other process or thread modifies or truncates the
+	/// temporary file (eg, through the path returned by [`Rollback::get_noted_file`]) while it's
+	/// mapped, the mapped content may change unexpectedly or accessing it may even crash the
+	/// process. Don't touch the temporary file other than through the map.
+	///
+	/// Returns the same as [`Rollback::note_file`].
+	/// ## Errors:
+	/// - The same errors as [`Rollback::note_file`].
+	/// - If the temporary file cannot be mapped.
+	#[cfg(feature = "mmap")]
+	pub fn note_file_mmap<P: AsRef<Path>>(&mut self, original: P) -> Result<bool, Error> {
+		let original = self.anchored(original.as_ref())?;
+		let original = original.as_ref();
+		let savepoint = self.savepoint();
+		let changed = self.note_file(original)?;
+		let noted = self.noted.get_mut(original).expect("The file has just been noted; qed;");
+		// SAFETY: The temporary file is owned by the rollback and isn't modified by the crate
+		// while it's mapped. External modifications are documented as a caller concern.
+		match unsafe { memmap2::MmapMut::map_mut(noted.temp_file.as_file()) } {
+			Ok(mmap) => {
+				noted.mmap = Some(mmap);
+				Ok(changed)
+			},
+			Err(err) => {
+				self.rollback_to(savepoint);
+				Err(err.into())
+			},
+		}
+	}
+
+	/// Registers an existing file as 'to be modified' as [`Rollback::note_file`] does, but without
+	/// backing it up upon commit. This saves the IO needed to back up big files whose original
+	/// content is worthless, such as regenerable caches.
+	///
+	/// This reduces the guarantees of the commit for this file: if the commit fails, the rest of
+	/// the transaction is rolled-back as usual, but this file isn't restored, so it may keep its
+	/// new content, or even be partially written if committing it is what failed.
+	///
+	/// Returns the same as [`Rollback::note_file`].
+	/// ## Errors:
+	/// - The same errors as [`Rollback::note_file`].
+	pub fn note_file_no_backup<P: AsRef<Path>>(&mut self, original: P) -> Result<bool, Error> {
+		let original = self.anchored(original.as_ref())?;
+		let original = original.as_ref();
+		let changed = self.note_file(original)?;
+		self.noted
+			.get_mut(original)
+			.expect("The file has just been noted; qed;")
+			.disposable = true;
+		Ok(changed)
+	}
+
+	/// Registers an existing file as 'to be modified' as [`Rollback::note_file`] does, but without
+	/// copying its content into a temporary file. Instead, upon commit the original file is read,
+	/// `transform` is applied to its content, and the result is committed to the file. This way,
+	/// the transformation always sees the freshest content of the file, and the content isn't
+	/// materialized until it's really needed.
+	///
+	/// As there's no temporary file to modify, [`Rollback::get_noted_file`] returns `None` for
+	/// files noted this way. The transformation may be called from a commit thread, and it's called
+	/// once per commit attempt. If it panics, the commit fails and everything is rolled-back.
+	///
+	/// Returns the same as [`Rollback::note_file`].
+	/// ## Errors:
+	/// - The same errors as [`Rollback::note_file`].
+	pub fn note_file_lazy<P: AsRef<Path>>(
+		&mut self,
+		original: P,
+		transform: Box<dyn Fn(Vec<u8>) -> Vec<u8> + Send>,
+	) -> Result<bool, Error> {
+		self.note(&self.anchored(original.as_ref())?, Some(transform), false)
+	}
+
+	/// Registers an existing file as 'to be modified' as [`Rollback::note_file`] does, but
+	/// starting from an empty temporary file instead of a copy of the original. This avoids an
+	/// expensive copy when the file is going to be fully overwritten anyway.
+	///
+	/// The original content is still backed up upon commit, so the file is restored if the commit
+	/// fails. Note that committing an untouched temporary file leaves the original file empty.
+	///
+	/// Returns the same as [`Rollback::note_file`].
+	/// ## Errors:
+	/// - The same errors as [`Rollback::note_file`].
+	pub fn note_file_truncate<P: AsRef<Path>>(&mut self, original: P) -> Result<bool, Error> {
+		self.note(&self.anchored(original.as_ref())?, None, false)
+	}
+
+	fn note(
+		&mut self,
+		original: &Path,
+		transform: Option<Transform>,
+		copy_content: bool,
+	) -> Result<bool, Error> {
+		let fs_original = self.fs_path(original);
+		if !fs_original.is_file() {
 			return Err(Error::NotAFile(format!("{}", original.display())));
-		} else if self
-			.noted
-			.keys()
-			.any(|path| same_file::is_same_file(original, path).unwrap_or(false))
-		{
+		} else if self.is_tracked_file(original) {
 			return Err(Error::AlreadyNoted(format!("{}", original.display())));
+		} else if self.busy_check && lock::is_busy(&fs_original)? {
+			return Err(Error::FileBusy(format!("{}", original.display())));
+		}
+
+		let metadata = std::fs::metadata(&fs_original)?;
+		#[cfg(unix)]
+		let nlink = metadata.nlink();
+		#[cfg(unix)]
+		if self.hard_link_check && nlink > 1 {
+			return Err(Error::HardLinked(format!("{}", original.display())));
 		}
 
 		// Committing the noted files cannot just persist the temp files as they live inside the
 		// Rollback instance, so moving them out isn't possible, but copying its content is.
-		// Hence, the tempfile can be created in the default temp dir.
-		let temp_file = NamedTempFile::new()?;
-		std::fs::copy(original, &temp_file)?;
-		self.noted.insert(original, temp_file);
-		Ok(())
+		// Hence, the tempfile can be created in the default temp dir, unless another one is
+		// configured.
+		let temp_file = self.new_temp_file(original)?;
+		// Lazily noted files get their content upon commit, while truncated files don't need it.
+		if copy_content {
+			std::fs::copy(&fs_original, &temp_file)?;
+		}
+
+		let changed = match self.baseline_dir.as_deref() {
+			Some(baseline_dir) => baseline::differs(baseline_dir, &fs_original)?,
+			None => true,
+		};
+
+		let mut noted = NotedFile::new(temp_file, metadata.permissions());
+		noted.stamp = noted::stamp(&metadata);
+		noted.transform = transform.map(Mutex::new);
+		noted.symlinked = fs_original.symlink_metadata()?.is_symlink();
+		#[cfg(unix)]
+		{
+			noted.nlink = nlink;
+		}
+		#[cfg(feature = "acl")]
+		if self.preserve_acls {
+			noted.acl = acl::read(&fs_original)?;
+		}
+		self.noted.insert(original.to_path_buf(), noted);
+		self.history.push((original.to_path_buf(), StagedKind::NotedFile));
+		#[cfg(feature = "tracing")]
+		tracing::debug!(path = %original.display(), changed, "file noted");
+		Ok(changed)
+	}
+
+	/// Registers an existing file as 'to be modified' as [`Rollback::note_file`] does, but
+	/// acquiring an advisory shared lock on the original before copying it. The lock is held until
+	/// the instance is committed or dropped, which narrows the window where another cooperating
+	/// process modifies the original between noting and committing it. When committing, the lock
+	/// is upgraded to an exclusive lock while the file is written. As the lock belongs to the
+	/// original file, the new content is copied into it instead of replacing it, as
+	/// [`LinkPolicy::PreserveLinks`] does regardless of the configured link policy, so other
+	/// processes locking the path keep waiting for the lock until the commit finishes.
+	///
+	/// The lock is advisory (`flock` on Unix), so it only protects against processes that also
+	/// lock the file. Acquiring the lock waits until other processes release their exclusive
+	/// locks, and upgrading it waits until they release their shared locks.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::note_file`].
+	/// - If the platform or the file system doesn't support file locking.
+	/// - If the lock cannot be acquired.
+	pub fn note_file_locked<P: AsRef<Path>>(&mut self, original: P) -> Result<bool, Error> {
+		let original = self.anchored(original.as_ref())?;
+		let original = original.as_ref();
+		let fs_original = self.fs_path(original);
+		if !fs_original.is_file() {
+			return Err(Error::NotAFile(format!("{}", original.display())));
+		}
+
+		let file = File::open(&fs_original)?;
+		lock::lock(&file, original, lock::LockKind::Shared)?;
+
+		let changed = self.note_file(original)?;
+		self.noted.get_mut(original).expect("The file has just been noted; qed;").lock = Some(file);
+		Ok(changed)
 	}
 
 	/// Registers a valid file path as 'to be created', creating a temporary file that will be
 	/// committed to this new file. The actual new file isn't created until the Rollback instance
 	/// is committed, so trying to access it would lead to errors.
 	/// ## Considerations:
-	/// - If creating a file whose parent dir doesn't exist, consider adding that path to the
-	///   instance as well using the `new_dir` method. Otherwise, the rollback wouldn't be able to
-	///   commit the new file.
+	/// - If creating a file whose parent dir doesn't exist, add that dir to the instance first
+	///   using the `new_dir` method, or use [`Rollback::new_file_with_parents`] instead.
 	///
 	/// ## Errors:
 	/// - If the specified path already exists.
 	/// - If the path is already noted.
-	/// - If the path isn't a valid file path.
+	/// - If the path cannot name a file: it's empty, its last component is `..`, or it ends with a
+	///   separator. Paths without extension (e.g. `Makefile` or `.gitignore`) are valid files.
+	/// - [`Error::MissingParentDir`] if the parent dir doesn't exist and it isn't created by any of
+	///   the new dirs registered so far.
+	/// - If the path resolves to the same file as another new file (e.g. `dir/file.txt` and
+	///   `dir/../dir/file.txt`) and its parent dir exists. If it doesn't, the collision is detected
+	///   upon commit.
 	/// - If the temporary file cannot be created.
-	pub fn new_file(&mut self, path: &'a Path) -> Result<(), Error> {
-		if path.exists() {
+	pub fn new_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+		let path = self.anchored(path.as_ref())?;
+		let path = path.as_ref();
+		if self.fs_path(path).exists() {
 			return Err(Error::NewItemAlreadyExists(format!("{}", path.display())));
-		} else if self.new_files.contains_key(path) {
+		} else if self.new_files.contains_key(path) || self.new_symlinks.contains_key(path) {
 			return Err(Error::AlreadyNoted(format!("{}", path.display())));
-		} else if path.extension().is_none() {
+		} else if path.file_name().is_none() ||
+			path.as_os_str().to_string_lossy().ends_with(std::path::is_separator)
+		{
 			return Err(Error::NotAFile(format!("{}", path.display())));
+		} else if !self.has_parent_dir(path) {
+			return Err(Error::MissingParentDir(format!("{}", path.display())));
+		} else if self.is_repeated_new_item(self.new_files.keys().map(PathBuf::as_path), path) {
+			return Err(Error::RepeatedNewFile(format!("{}", path.display())));
 		}
 
 		// Committing the new files cannot just persist the temp files as they live inside the
 		// Rollback instance, so moving them out isn't possible, but copying its content is.
-		// Hence, the tempfile can be created in the default temp dir.
-		self.new_files.insert(path, NamedTempFile::new()?);
+		// Hence, the tempfile can be created in the default temp dir, unless another one is
+		// configured.
+		self.new_files.insert(path.to_path_buf(), Arc::new(self.new_temp_file(path)?));
+		self.history.push((path.to_path_buf(), StagedKind::NewFile));
+		Ok(())
+	}
+
+	/// Registers a valid file path as 'to be created' as [`Rollback::new_file`] does, but also
+	/// registering its parent dir as a new dir if it doesn't exist and it isn't created by any of
+	/// the new dirs registered so far. New dirs are created with their missing parents, so this
+	/// covers the whole chain of missing dirs. If the file cannot be registered, the parent dir
+	/// isn't registered either.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::new_dir`] for the parent dir, if it's registered.
+	/// - The same errors as [`Rollback::new_file`].
+	pub fn new_file_with_parents<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+		let path = self.anchored(path.as_ref())?;
+		let path = path.as_ref();
+		let savepoint = self.savepoint();
+		if !self.has_parent_dir(path) {
+			self.new_dir(path.parent().expect("Paths without parent always have it; qed;"))?;
+		}
+		if let Err(err) = self.new_file(path) {
+			self.rollback_to(savepoint);
+			return Err(err);
+		}
+		Ok(())
+	}
+
+	/// Registers a valid file path as 'to be created' as [`Rollback::new_file`] does, but giving
+	/// the new file the specified `mode` upon commit, e.g. `0o755` for a generated script. The
+	/// mode is applied right after copying the content, before the new file is considered
+	/// committed, and it takes precedence over the umask (see [`Rollback::with_umask`]).
+	/// ## Errors:
+	/// - The same errors as [`Rollback::new_file`].
+	#[cfg(unix)]
+	pub fn new_file_with_mode<P: AsRef<Path>>(&mut self, path: P, mode: u32) -> Result<(), Error> {
+		let path = self.anchored(path.as_ref())?;
+		let path = path.as_ref();
+		self.new_file(path)?;
+		self.new_file_modes.insert(path.to_path_buf(), mode);
+		Ok(())
+	}
+
+	/// Registers a valid file path as 'to be created' as [`Rollback::new_file`] does, filling its
+	/// temporary file with the content of `reader`. The content is streamed into the temporary
+	/// file, so it's never fully loaded into memory.
+	///
+	/// If reading fails, the path isn't registered. If the content of new files is deduplicated
+	/// (see [`Rollback::with_deduplicated_new_files`]) and another new file has the same content,
+	/// both share the same temporary file.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::new_file`].
+	/// - If the reader cannot be read or the temporary file cannot be written.
+	pub fn new_file_from_reader<P: AsRef<Path>>(
+		&mut self,
+		path: P,
+		reader: &mut impl Read,
+	) -> Result<(), Error> {
+		let path = self.anchored(path.as_ref())?;
+		let path = path.as_ref();
+		let savepoint = self.savepoint();
+		self.new_file(path)?;
+		let temp_file =
+			Arc::clone(self.new_files.get(path).expect("The file has just been noted; qed;"));
+		if let Err(err) = std::io::copy(reader, &mut temp_file.as_file()) {
+			self.rollback_to(savepoint);
+			return Err(err.into());
+		}
+
+		if let Some(deduplicated_contents) = self.deduplicated_contents.as_mut() {
+			let hash = match baseline::content_hash(temp_file.path()) {
+				Ok(hash) => hash,
+				Err(err) => {
+					self.rollback_to(savepoint);
+					return Err(err);
+				},
+			};
+			// If the content was already staged, the fresh temporary file is deleted once it's
+			// replaced by the shared one.
+			let shared = Arc::clone(deduplicated_contents.entry(hash).or_insert(temp_file));
+			self.new_files.insert(path.to_path_buf(), shared);
+		}
 		Ok(())
 	}
 
+	/// Registers `dst` as 'to be created' as [`Rollback::new_file_from_reader`] does, filling its
+	/// temporary file with the content of the existing file `src`. The content is copied right
+	/// away, so later changes to `src` don't affect the new file, which is committed as any other
+	/// new file.
+	/// ## Errors:
+	/// - If `src` isn't a file.
+	/// - The same errors as [`Rollback::new_file_from_reader`] for `dst`.
+	pub fn copy_file<P: AsRef<Path>, Q: AsRef<Path>>(
+		&mut self,
+		src: P,
+		dst: Q,
+	) -> Result<(), Error> {
+		let src = self.rooted(src.as_ref());
+		let src = src.as_ref();
+		let fs_src = self.fs_path(src);
+		if !fs_src.is_file() {
+			return Err(Error::NotAFile(format!("{}", src.display())));
+		}
+
+		let mut file = File::open(&fs_src)?;
+		self.new_file_from_reader(dst, &mut file)
+	}
+
 	/// Registers a valid direcroty path as 'to be created'. The directory isn't created until the
 	/// Rollback instance is committed, so trying to access it would lead to errors.
 	/// ## Errors:
 	/// - If the specified path already exists.
 	/// - If the specified path is already noted.
-	/// - If the path isn't a valid directory path.
-	pub fn new_dir(&mut self, path: &'a Path) -> Result<(), Error> {
-		if path.exists() {
+	/// - If the path is empty. Paths with extension (e.g. `assets.v2`) are valid directories.
+	/// - If the path resolves to the same directory as another new directory and its parent dir
+	///   exists. If it doesn't, the collision is detected upon commit.
+	pub fn new_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+		let path = self.anchored(path.as_ref())?;
+		let path = path.as_ref();
+		if self.fs_path(path).exists() {
 			return Err(Error::NewItemAlreadyExists(format!("{}", path.display())));
-		} else if self.new_dirs.contains(&path) {
+		} else if self.new_dirs.iter().any(|dir| dir == path) ||
+			self.new_symlinks.contains_key(path)
+		{
 			return Err(Error::AlreadyNoted(format!("{}", path.display())));
-		} else if path.as_os_str().is_empty() || path.extension().is_some() {
-			return Err(Error::NotADir(format!("{}", path.display())))
+		} else if path.as_os_str().is_empty() {
+			return Err(Error::NotADir(format!("{}", path.display())));
+		} else if self.is_repeated_new_item(self.new_dirs.iter().map(PathBuf::as_path), path) {
+			return Err(Error::RepeatedNewDir(format!("{}", path.display())));
 		}
-		self.new_dirs.push(path);
+		self.new_dirs.push(path.to_path_buf());
+		self.history.push((path.to_path_buf(), StagedKind::NewDir));
 		Ok(())
 	}
 
-	/// Get the temporary file associated to a noted file.
-	pub fn get_noted_file<P: AsRef<Path>>(&self, original: P) -> Option<&Path> {
-		self.noted.get(original.as_ref()).map_or_else(
-			|| {
-				self.noted.keys().find_map(|path| {
-					same_file::is_same_file(path, original.as_ref())
-						.ok()
-						.filter(|&same| same)
-						.and_then(|_| self.noted.get(path).map(|temp_file| temp_file.path()))
-				})
-			},
-			|temp_file| Some(temp_file.path()),
-		)
+	/// Registers several valid directory paths as 'to be created' as [`Rollback::new_dir`] does
+	/// for each of them, in order. Registering stops at the first path that cannot be registered,
+	/// in which case the dirs registered by this call are discarded, so either every dir is
+	/// registered or none is.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::new_dir`] for the first path that cannot be registered.
+	pub fn new_dirs_bulk<I>(&mut self, paths: I) -> Result<(), Error>
+	where
+		I: IntoIterator,
+		I::Item: AsRef<Path>,
+	{
+		self.stage_all(paths, |rollback, path| rollback.new_dir(path))
 	}
 
-	/// Get the temporary file associated to a new file.
-	pub fn get_new_file<P: AsRef<Path>>(&self, path: P) -> Option<&Path> {
-		self.new_files.get(path.as_ref()).map(|temp_file| temp_file.path())
+	/// Registers a valid directory path as 'to be created' as [`Rollback::new_dir`] does, but
+	/// creating only its final component upon commit, as [`std::fs::create_dir`] does. So instead
+	/// of creating the missing parents, the commit fails if the parent dir doesn't exist by then,
+	/// which catches typos in directory layouts. The parent dir may be another new dir.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::new_dir`].
+	pub fn new_dir_strict<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+		let path = self.anchored(path.as_ref())?;
+		let path = path.as_ref();
+		self.new_dir(path)?;
+		self.strict_new_dirs.insert(path.to_path_buf());
+		Ok(())
 	}
 
-	/// Consume the Rollback and commit the changes. If something goes wrong during the commit step,
-	/// everything is rolled-back, so the file system isn't affected.
+	/// Registers a symbolic link as 'to be created' at `link`, pointing to `target`. The target is
+	/// written into the link as is, so relative targets are resolved from the parent dir of the
+	/// link, and it doesn't need to exist. The link isn't created until the Rollback instance is
+	/// committed, and it's removed if the commit fails afterwards.
+	///
+	/// On Windows, the link is a dir symlink if the target is an existing dir when it's committed,
+	/// and a file symlink otherwise.
+	/// ## Considerations:
+	/// - If the link's parent dir doesn't exist, consider adding it to the instance as well using
+	///   the `new_dir` method. Otherwise, the rollback wouldn't be able to commit the link.
 	///
 	/// ## Errors:
-	/// - If a noted file cannot be committed. This includes a wide range of possibilities: the
-	///   original file doesn't exist anymore, or the proccess doesn't have write permissions on
-	///   it,...
-	/// - If a new dir cannot be created.
-	/// - If a new file cannot be created.
-	pub fn commit(self) -> Result<(), Error> {
-		let mut backups = Vec::with_capacity(self.noted.capacity());
-
-		match self.commit_noted_files(backups) {
-			Ok(computed_backups) => backups = computed_backups,
-			Err((err, backups)) => {
-				backups.into_iter().for_each(|backup| backup.rollback());
-				return Err(err);
-			},
+	/// - If `link` already exists, even if it's a dangling symlink.
+	/// - If `link` is already registered as a new file, a new symlink or the new path of a rename.
+	/// - If `link` cannot name a file: it's empty or its last component is `..`.
+	pub fn new_symlink<P: AsRef<Path>, Q: AsRef<Path>>(
+		&mut self,
+		link: P,
+		target: Q,
+	) -> Result<(), Error> {
+		let link = self.anchored(link.as_ref())?;
+		let (link, target) = (link.as_ref(), target.as_ref());
+		if self.fs_path(link).symlink_metadata().is_ok() {
+			return Err(Error::NewItemAlreadyExists(format!("{}", link.display())));
+		} else if self.is_new_item(link) {
+			return Err(Error::AlreadyNoted(format!("{}", link.display())));
+		} else if link.file_name().is_none() {
+			return Err(Error::NotAFile(format!("{}", link.display())));
 		}
 
-		if let Err(err) = self.commit_new_dirs() {
-			backups.into_iter().for_each(|backup| backup.rollback());
-			self.rollback_new_dirs();
-			return Err(err);
+		self.new_symlinks.insert(link.to_path_buf(), target.to_path_buf());
+		self.history.push((link.to_path_buf(), StagedKind::NewSymlink));
+		Ok(())
+	}
+
+	/// Registers an existing file as 'to be deleted'. The file isn't deleted until the Rollback
+	/// instance is committed, when it's backed up next to the original right before deleting it,
+	/// so the content it has at that point is restored if the commit fails afterwards.
+	/// ## Errors:
+	/// - If the path isn't a file.
+	/// - If the file is already noted or registered as 'to be deleted', either using exactly the
+	///   same [`Path`] or a different representation of it.
+	pub fn delete_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+		let path = self.anchored(path.as_ref())?;
+		let path = path.as_ref();
+		let fs_path = self.fs_path(path);
+		if !fs_path.is_file() {
+			return Err(Error::NotAFile(format!("{}", path.display())));
+		} else if self.is_tracked_file(path) {
+			return Err(Error::AlreadyNoted(format!("{}", path.display())));
 		}
 
-		if let Err(err) = self.commit_new_files() {
-			backups.into_iter().for_each(|backup| backup.rollback());
-			self.rollback_new_files();
-			self.rollback_new_dirs();
-			return Err(err);
+		self.deleted.insert(path.to_path_buf());
+		self.history.push((path.to_path_buf(), StagedKind::DeletedFile));
+		Ok(())
+	}
+
+	/// Registers an existing file as 'to be renamed' to a path that doesn't exist yet. The file
+	/// isn't renamed until the Rollback instance is committed. If the new path is in the same
+	/// file system, the file is renamed at once without copying or backing it up, and it's renamed
+	/// back if the commit fails afterwards. This makes transactions that mostly move files around
+	/// cheap, no matter how big the files are.
+	///
+	/// If the new path is in a different file system, the file is backed up next to the original
+	/// and copied there, and then removed from its current path upon commit. It's restored at its
+	/// current path from the backup if the commit fails afterwards.
+	/// ## Considerations:
+	/// - If the new path's parent dir doesn't exist, consider adding it to the instance as well
+	///   using the `new_dir` method. Otherwise, the rollback wouldn't be able to commit the rename.
+	///
+	/// ## Errors:
+	/// - If `from` isn't a file.
+	/// - If `to` already exists.
+	/// - If `from` is already noted, renamed or registered as 'to be deleted', either using exactly
+	///   the same [`Path`] or a different representation of it.
+	/// - If `to` is already registered as a new file, a new symlink or the new path of another
+	///   rename.
+	pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) -> Result<(), Error> {
+		let (from, to) = (self.anchored(from.as_ref())?, self.anchored(to.as_ref())?);
+		let (from, to) = (from.as_ref(), to.as_ref());
+		let fs_from = self.fs_path(from);
+		if !fs_from.is_file() {
+			return Err(Error::NotAFile(format!("{}", from.display())));
+		} else if self.fs_path(to).symlink_metadata().is_ok() {
+			return Err(Error::NewItemAlreadyExists(format!("{}", to.display())));
+		} else if self.is_tracked_file(from) {
+			return Err(Error::AlreadyNoted(format!("{}", from.display())));
+		} else if self.is_new_item(to) {
+			return Err(Error::AlreadyNoted(format!("{}", to.display())));
+		}
+
+		self.renames.insert(from.to_path_buf(), to.to_path_buf());
+		self.history.push((from.to_path_buf(), StagedKind::RenamedFile));
+		Ok(())
+	}
+
+	/// Registers an existing directory as 'to be deleted' together with all its content. The
+	/// whole tree is backed up right away into a temporary dir, so it's restored if the commit
+	/// fails after deleting it. The directory isn't deleted until the Rollback instance is
+	/// committed.
+	///
+	/// The backup is a full copy of the tree, so registering big trees takes as long and as much
+	/// space as copying them. Symlinks inside the tree are backed up as symlinks, while other
+	/// special files aren't supported.
+	/// ## Errors:
+	/// - If the path isn't a directory. Symlinks to directories aren't directories.
+	/// - If the directory is already registered as 'to be deleted', either using exactly the same
+	///   [`Path`] or a different representation of it.
+	/// - If the backup cannot be created.
+	pub fn delete_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+		let path = self.anchored(path.as_ref())?;
+		let path = path.as_ref();
+		let fs_path = self.fs_path(path);
+		if !fs_path.symlink_metadata().is_ok_and(|metadata| metadata.is_dir()) {
+			return Err(Error::NotADir(format!("{}", path.display())));
+		} else if self.deleted_dir_key(path).is_some() {
+			return Err(Error::AlreadyNoted(format!("{}", path.display())));
+		}
+
+		let backup = DirBackup::new(&fs_path)?;
+		self.deleted_dirs.insert(path.to_path_buf(), backup);
+		self.history.push((path.to_path_buf(), StagedKind::DeletedDir));
+		Ok(())
+	}
+
+	/// Note an existing directory, so its whole tree is restored exactly as it's now if the commit
+	/// fails: the entries removed afterwards are restored, the entries added afterwards are
+	/// removed, and the rest get back their current content. The tree is backed up right away into
+	/// a temporary dir, as [`Rollback::delete_dir`] does. This allows rewriting a tree with the
+	/// rest of operations (e.g. noting, creating and deleting files inside it) while keeping the
+	/// whole tree consistent if the commit fails, even for changes applied to it by other means
+	/// before committing.
+	///
+	/// The directory is only restored once everything else has been rolled-back. Nothing's
+	/// committed for the directory itself, so it isn't included in the commit counts. Symlinks
+	/// inside the tree are backed up as symlinks, while other special files aren't supported.
+	/// ## Errors:
+	/// - If the path isn't a directory. Symlinks to directories aren't directories.
+	/// - If the directory is already noted, either using exactly the same [`Path`] or a different
+	///   representation of it.
+	/// - If the backup cannot be created.
+	pub fn note_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), Error> {
+		let dir = self.anchored(dir.as_ref())?;
+		let dir = dir.as_ref();
+		let fs_dir = self.fs_path(dir);
+		if !fs_dir.symlink_metadata().is_ok_and(|metadata| metadata.is_dir()) {
+			return Err(Error::NotADir(format!("{}", dir.display())));
+		} else if self.noted_dir_key(dir).is_some() {
+			return Err(Error::AlreadyNoted(format!("{}", dir.display())));
 		}
 
+		let backup = DirBackup::new(&fs_dir)?;
+		self.noted_dirs.insert(dir.to_path_buf(), backup);
+		self.history.push((dir.to_path_buf(), StagedKind::NotedDir));
 		Ok(())
 	}
+
+	/// Cancels every operation staged for a path, as if it was never registered, and returns
+	/// whether something was staged for it. Noted files, renamed files (identified by their
+	/// current path), deleted files and deleted or noted directories are matched even if they're
+	/// registered under a different path representation, while new files, new directories and new
+	/// symlinks must be given exactly as they were registered.
+	///
+	/// The temporary files and backups of the canceled operations are dropped, discarding their
+	/// content.
+	pub fn forget<P: AsRef<Path>>(&mut self, path: P) -> bool {
+		let path = self.rooted(path.as_ref());
+		let path = path.as_ref();
+		let mut forgotten = false;
+		if let Some(key) = self.noted_key(path).map(Path::to_path_buf) {
+			forgotten |= self.noted.remove(&key).is_some();
+		}
+		forgotten |= self.new_files.remove(path).is_some();
+		#[cfg(unix)]
+		self.new_file_modes.remove(path);
+		forgotten |= self.new_symlinks.remove(path).is_some();
+		let new_dirs = self.new_dirs.len();
+		self.new_dirs.retain(|dir| *dir != path);
+		self.strict_new_dirs.remove(path);
+		forgotten |= self.new_dirs.len() != new_dirs;
+		if let Some(key) = self.renamed_key(path).map(Path::to_path_buf) {
+			forgotten |= self.renames.remove(&key).is_some();
+		}
+		if let Some(key) = self.deleted_key(path).map(Path::to_path_buf) {
+			forgotten |= self.deleted.remove(&key);
+		}
+		if let Some(key) = self.deleted_dir_key(path).map(Path::to_path_buf) {
+			forgotten |= self.deleted_dirs.remove(&key).is_some();
+		}
+		if let Some(key) = self.noted_dir_key(path).map(Path::to_path_buf) {
+			forgotten |= self.noted_dirs.remove(&key).is_some();
+		}
+		#[cfg(unix)]
+		if let Some(key) = self.dirs_metadata_key(path).map(Path::to_path_buf) {
+			forgotten |= self.dirs_metadata.remove(&key).is_some();
+		}
+		forgotten
+	}
+
+	/// Discard every staged operation, as [`Rollback::forget`] does, so their temporary files and
+	/// backups are removed and the staged data is lost. The configuration of the instance and the
+	/// allocated capacity are kept, so it can be reused for another batch of operations without
+	/// reallocating.
+	pub fn clear(&mut self) {
+		self.noted.clear();
+		self.new_files.clear();
+		#[cfg(unix)]
+		self.new_file_modes.clear();
+		self.new_dirs.clear();
+		self.strict_new_dirs.clear();
+		self.new_symlinks.clear();
+		self.renames.clear();
+		self.deleted.clear();
+		self.deleted_dirs.clear();
+		self.noted_dirs.clear();
+		#[cfg(unix)]
+		self.dirs_metadata.clear();
+		self.history.clear();
+		if let Some(contents) = &mut self.deduplicated_contents {
+			contents.clear();
+		}
+		#[cfg(feature = "tracing")]
+		self.settled.store(false, Ordering::Relaxed);
+	}
+
+	/// Take a savepoint marking the operations staged so far, so the operations staged afterwards
+	/// can be discarded with [`Rollback::rollback_to`], as SQL savepoints do.
+	pub fn savepoint(&self) -> Savepoint {
+		Savepoint(self.history.len())
+	}
+
+	/// Discard every operation staged after the savepoint was taken, as [`Rollback::forget`]
+	/// does, so their temporary files and backups are removed. The operations staged before the
+	/// savepoint are kept as they are, including the changes written into their temporary files
+	/// after the savepoint.
+	///
+	/// Once rolled-back to a savepoint, the savepoints taken after it are meaningless. Rolling
+	/// back to them does nothing until more operations are staged.
+	pub fn rollback_to(&mut self, savepoint: Savepoint) {
+		let discarded: Vec<_> =
+			self.history.drain(savepoint.0.min(self.history.len())..).rev().collect();
+		for (path, kind) in discarded {
+			match kind {
+				StagedKind::NotedFile => {
+					self.noted.remove(&path);
+				},
+				StagedKind::NewFile => {
+					self.new_files.remove(&path);
+					#[cfg(unix)]
+					self.new_file_modes.remove(&path);
+				},
+				StagedKind::NewDir => {
+					self.new_dirs.retain(|dir| *dir != path);
+					self.strict_new_dirs.remove(&path);
+				},
+				StagedKind::NewSymlink => {
+					self.new_symlinks.remove(&path);
+				},
+				StagedKind::RenamedFile => {
+					self.renames.remove(&path);
+				},
+				StagedKind::DeletedFile => {
+					self.deleted.remove(&path);
+				},
+				StagedKind::DeletedDir => {
+					self.deleted_dirs.remove(&path);
+				},
+				StagedKind::NotedDir => {
+					self.noted_dirs.remove(&path);
+				},
+				#[cfg(unix)]
+				StagedKind::DirMetadata => {
+					self.dirs_metadata.remove(&path);
+				},
+			}
+		}
+	}
+
+	/// Move every operation staged in `other` into this instance, so the operations staged by
+	/// different subsystems can be committed at once. The temporary files and backups of `other`
+	/// are moved as well, so they're kept alive. The merged operations are committed following
+	/// the configuration of this instance, while the configuration of `other` is discarded.
+	///
+	/// The operations of `other` are staged after the operations of this instance, so rolling back
+	/// to a savepoint taken before merging discards them (see [`Rollback::rollback_to`]).
+	/// ## Errors:
+	/// If an operation of `other` collides with an operation of this instance, in which case
+	/// nothing is merged:
+	/// - [`Error::AlreadyNoted`] if an existing file or dir is registered by both instances, either
+	///   using exactly the same [`Path`] or a different representation of it, or if a renamed file
+	///   or a new symlink would be created at a path registered by the other instance.
+	/// - [`Error::RepeatedNewFile`] or [`Error::RepeatedNewDir`] if both instances register the
+	///   same new file or new dir. Different representations are detected if the parent dir exists.
+	pub fn merge(&mut self, other: Rollback<'a>) -> Result<(), Error> {
+		let already_noted = |path: &Path| Err(Error::AlreadyNoted(format!("{}", path.display())));
+		for path in other.noted.keys().chain(other.renames.keys()).chain(other.deleted.iter()) {
+			if self.is_tracked_file(path) {
+				return already_noted(path);
+			}
+		}
+		for path in other.renames.values().chain(other.new_symlinks.keys()) {
+			if self.is_new_item(path) {
+				return already_noted(path);
+			}
+		}
+		for path in other.new_files.keys() {
+			if self.rename_source(path).is_some() || self.new_symlinks.contains_key(path) {
+				return already_noted(path);
+			} else if self.is_repeated_new_item(self.new_files.keys().map(PathBuf::as_path), path) {
+				return Err(Error::RepeatedNewFile(format!("{}", path.display())));
+			}
+		}
+		for dir in other.new_dirs.iter() {
+			if self.is_repeated_new_item(self.new_dirs.iter().map(PathBuf::as_path), dir) {
+				return Err(Error::RepeatedNewDir(format!("{}", dir.display())));
+			}
+		}
+		for dir in other.deleted_dirs.keys() {
+			if self.deleted_dir_key(dir).is_some() {
+				return already_noted(dir);
+			}
+		}
+		for dir in other.noted_dirs.keys() {
+			if self.noted_dir_key(dir).is_some() {
+				return already_noted(dir);
+			}
+		}
+		#[cfg(unix)]
+		for dir in other.dirs_metadata.keys() {
+			if self.dirs_metadata_key(dir).is_some() {
+				return already_noted(dir);
+			}
+		}
+
+		let mut other = other;
+		self.noted.extend(std::mem::take(&mut other.noted));
+		self.new_files.extend(std::mem::take(&mut other.new_files));
+		#[cfg(unix)]
+		self.new_file_modes.extend(std::mem::take(&mut other.new_file_modes));
+		self.new_dirs.extend(std::mem::take(&mut other.new_dirs));
+		self.strict_new_dirs.extend(std::mem::take(&mut other.strict_new_dirs));
+		self.new_symlinks.extend(std::mem::take(&mut other.new_symlinks));
+		self.renames.extend(std::mem::take(&mut other.renames));
+		self.deleted.extend(std::mem::take(&mut other.deleted));
+		self.deleted_dirs.extend(std::mem::take(&mut other.deleted_dirs));
+		self.noted_dirs.extend(std::mem::take(&mut other.noted_dirs));
+		#[cfg(unix)]
+		self.dirs_metadata.extend(std::mem::take(&mut other.dirs_metadata));
+		self.history.extend(std::mem::take(&mut other.history));
+		Ok(())
+	}
+
+	/// Registers an existing directory as 'to have its metadata modified', without creating nor
+	/// deleting it. The directory current mode and ownership are recorded, so they can be
+	/// restored if the commit fails. The new metadata is staged with [`Rollback::set_dir_mode`]
+	/// and [`Rollback::set_dir_owner`], and it's applied upon commit.
+	///
+	/// Only available on Unix, as the metadata is described in terms of Unix modes, user ids and
+	/// group ids. Note that changing the owner of a directory usually requires privileges.
+	/// ## Errors:
+	/// - If the directory is already noted, either using exactly the same [`Path`] or a different
+	///   representation of it.
+	/// - If the path isn't a directory.
+	/// - If the directory metadata cannot be read.
+	#[cfg(unix)]
+	pub fn note_dir_metadata<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), Error> {
+		let dir = self.anchored(dir.as_ref())?;
+		let dir = dir.as_ref();
+		let fs_dir = self.fs_path(dir);
+		if !fs_dir.is_dir() {
+			return Err(Error::NotADir(format!("{}", dir.display())));
+		} else if self.dirs_metadata_key(dir).is_some() {
+			return Err(Error::AlreadyNoted(format!("{}", dir.display())));
+		}
+
+		self.dirs_metadata
+			.insert(dir.to_path_buf(), metadata::DirMetadata::new(&fs_dir)?);
+		self.history.push((dir.to_path_buf(), StagedKind::DirMetadata));
+		Ok(())
+	}
+
+	/// Stages a new mode for a directory noted with [`Rollback::note_dir_metadata`].
+	/// ## Errors:
+	/// - If the directory isn't noted.
+	#[cfg(unix)]
+	pub fn set_dir_mode<P: AsRef<Path>>(&mut self, dir: P, mode: u32) -> Result<(), Error> {
+		let dir_metadata = self.get_dir_metadata_mut(&self.rooted(dir.as_ref()))?;
+		dir_metadata.mode = Some(mode);
+		Ok(())
+	}
+
+	/// Stages a new owner and/or group for a directory noted with
+	/// [`Rollback::note_dir_metadata`]. `None` values keep the current owner or group.
+	/// ## Errors:
+	/// - If the directory isn't noted.
+	#[cfg(unix)]
+	pub fn set_dir_owner<P: AsRef<Path>>(
+		&mut self,
+		dir: P,
+		uid: Option<u32>,
+		gid: Option<u32>,
+	) -> Result<(), Error> {
+		let dir_metadata = self.get_dir_metadata_mut(&self.rooted(dir.as_ref()))?;
+		dir_metadata.uid = uid;
+		dir_metadata.gid = gid;
+		Ok(())
+	}
+
+	/// Registers an existing file as 'to have its permissions changed' to `mode`, without
+	/// modifying its content. The file current permissions are recorded, so they can be restored
+	/// if the commit fails. The new mode is applied upon commit, once the content of noted files
+	/// is written, so the file may be noted as well to change both its content and its
+	/// permissions in the same transaction.
+	///
+	/// Only available on Unix, as the permissions are described in terms of Unix modes.
+	/// ## Errors:
+	/// - If the file permissions are already noted, either using exactly the same [`Path`] or a
+	///   different representation of it.
+	/// - If the path isn't a file.
+	/// - If the file metadata cannot be read.
+	#[cfg(unix)]
+	pub fn note_permissions<P: AsRef<Path>>(&mut self, path: P, mode: u32) -> Result<(), Error> {
+		let path = self.anchored(path.as_ref())?;
+		let path = path.as_ref();
+		let fs_path = self.fs_path(path);
+		if !fs_path.is_file() {
+			return Err(Error::NotAFile(format!("{}", path.display())));
+		} else if self.dirs_metadata_key(path).is_some() {
+			return Err(Error::AlreadyNoted(format!("{}", path.display())));
+		}
+
+		let mut metadata = metadata::DirMetadata::new(&fs_path)?;
+		metadata.mode = Some(mode);
+		self.dirs_metadata.insert(path.to_path_buf(), metadata);
+		self.history.push((path.to_path_buf(), StagedKind::DirMetadata));
+		Ok(())
+	}
+
+	/// Get the temporary file associated to a noted file.
+	/// Lazily noted files don't have an associated temporary file (see
+	/// [`Rollback::note_file_lazy`]).
+	pub fn get_noted_file<P: AsRef<Path>>(&self, original: P) -> Option<&Path> {
+		self.noted_key(&self.rooted(original.as_ref()))
+			.and_then(|key| self.noted.get(key))
+			.filter(|noted| noted.transform.is_none())
+			.map(|noted| noted.temp_file.path())
+	}
+
+	/// Get a handle to the temporary file associated to a noted file, which tracks whether it's
+	/// written. Once a handle has been requested for a noted file, the file is only committed if
+	/// something has been written through any of its handles; otherwise it's skipped upon commit:
+	/// it isn't backed up nor copied, so even its modification time is kept. Skipped files aren't
+	/// included in the commit counts.
+	///
+	/// Only writes through the handles are tracked, so changes applied to the temporary file by
+	/// other means (e.g. through the path returned by [`Rollback::get_noted_file`] or through a
+	/// memory map) are ignored for tracked files. Reading or seeking through the handles doesn't
+	/// make the file dirty.
+	/// ## Errors:
+	/// - If the file isn't noted, or it's noted lazily (see [`Rollback::note_file_lazy`]).
+	/// - If the temporary file cannot be opened.
+	pub fn get_noted_handle<P: AsRef<Path>>(&mut self, original: P) -> Result<NotedHandle, Error> {
+		let original = self.rooted(original.as_ref());
+		let original = original.as_ref();
+		let noted = self
+			.noted_key(original)
+			.map(Path::to_path_buf)
+			.and_then(|key| self.noted.get_mut(&key))
+			.filter(|noted| noted.transform.is_none())
+			.ok_or_else(|| Error::NotNoted(format!("{}", original.display())))?;
+
+		let file = noted.temp_file.reopen()?;
+		let dirty = noted.dirty.get_or_insert_with(|| Arc::new(AtomicBool::new(false)));
+		Ok(NotedHandle::new(file, Arc::clone(dirty)))
+	}
+
+	/// Get the memory map of a file noted with [`Rollback::note_file_mmap`], so its content can be
+	/// edited in place.
+	#[cfg(feature = "mmap")]
+	pub fn get_noted_file_mmap<P: AsRef<Path>>(&mut self, original: P) -> Option<&mut [u8]> {
+		let key = self.noted_key(&self.rooted(original.as_ref()))?.to_path_buf();
+		self.noted.get_mut(&key).and_then(|noted| noted.mmap.as_deref_mut())
+	}
+
+	/// Get the temporary file associated to a new file. As with [`Rollback::get_noted_file`], the
+	/// new file is found even if it's registered under a different path representation, as long as
+	/// its parent dir exists. New files whose parent dir doesn't exist yet are only found by
+	/// exactly the same [`Path`].
+	pub fn get_new_file<P: AsRef<Path>>(&self, path: P) -> Option<&Path> {
+		self.new_file_key(&self.rooted(path.as_ref()))
+			.map(|key| self.new_files[key].path())
+	}
+
+	/// Get the new path of a file registered as 'to be renamed', if any.
+	pub fn get_renamed_file<P: AsRef<Path>>(&self, from: P) -> Option<&Path> {
+		self.renamed_key(&self.rooted(from.as_ref()))
+			.map(|key| self.renames[key].as_path())
+	}
+
+	/// Get the target of a symlink registered as 'to be created', if any.
+	pub fn get_new_symlink<P: AsRef<Path>>(&self, link: P) -> Option<&Path> {
+		self.new_symlinks.get(&*self.rooted(link.as_ref())).map(PathBuf::as_path)
+	}
+
+	/// Get the path under which a file is registered as 'to be deleted', which also tells if the
+	/// file is registered at all. The file is found even if it's registered under a different path
+	/// representation. It isn't backed up until it's deleted upon commit, so there's no backup to
+	/// return.
+	pub fn get_deleted_file<P: AsRef<Path>>(&self, path: P) -> Option<&Path> {
+		self.deleted_key(&self.rooted(path.as_ref()))
+	}
+
+	/// Get the backup of a directory registered as 'to be deleted', which also tells if the
+	/// directory is registered at all.
+	pub fn get_deleted_dir<P: AsRef<Path>>(&self, path: P) -> Option<&Path> {
+		self.deleted_dir_key(&self.rooted(path.as_ref()))
+			.map(|key| self.deleted_dirs[key].path())
+	}
+
+	/// Get a writable handle to the temporary file associated to a noted file or to a new file,
+	/// truncated so the new content can be written right away. Noted files are looked up first,
+	/// even if they're noted under a different path representation. The temporary file is
+	/// reopened through its handle, so it cannot be swapped between resolving and opening it.
+	///
+	/// Returns `None` if the path isn't a noted file nor a new file, if the file is noted lazily
+	/// (see [`Rollback::note_file_lazy`]) or through a memory map (see
+	/// [`Rollback::note_file_mmap`]), which truncating would invalidate, if the new file's
+	/// temporary file is shared by deduplication (see [`Rollback::with_deduplicated_new_files`]),
+	/// which truncating would rewrite for every file sharing it, or if the temporary file cannot
+	/// be opened. As with [`Rollback::get_noted_file`], writes through the returned file
+	/// aren't tracked for files with handles (see [`Rollback::get_noted_handle`]).
+	pub fn get_writer<P: AsRef<Path>>(&self, path: P) -> Option<File> {
+		let path = self.rooted(path.as_ref());
+		let path = path.as_ref();
+		let file = match self.noted_key(path).and_then(|key| self.noted.get(key)) {
+			Some(noted) if noted.transform.is_some() || noted.is_mapped() => return None,
+			Some(noted) => noted.temp_file.reopen(),
+			None => {
+				let temp_file = &self.new_files[self.new_file_key(path)?];
+				// Deduplicated temporary files are also held by the deduplicated contents.
+				if Arc::strong_count(temp_file) > 1 {
+					return None;
+				}
+				temp_file.reopen()
+			},
+		};
+		let file = file.ok()?;
+		file.set_len(0).ok()?;
+		Some(file)
+	}
+
+	/// Append some data to the temporary file associated to a noted file. As the temporary file
+	/// starts as a copy of the original file, the data is appended to the original content upon
+	/// commit. Appending counts as a write for files with handles (see
+	/// [`Rollback::get_noted_handle`]).
+	/// ## Errors:
+	/// - If the file isn't noted, or it's noted lazily (see [`Rollback::note_file_lazy`]) or
+	///   through a memory map (see [`Rollback::note_file_mmap`]), which appending would invalidate.
+	/// - If the temporary file cannot be opened or written.
+	pub fn append_to_noted<P: AsRef<Path>>(&self, path: P, data: &[u8]) -> Result<(), Error> {
+		let path = self.rooted(path.as_ref());
+		let path = path.as_ref();
+		let noted = self
+			.noted_key(path)
+			.and_then(|key| self.noted.get(key))
+			.filter(|noted| noted.transform.is_none() && !noted.is_mapped())
+			.ok_or_else(|| Error::NotNoted(format!("{}", path.display())))?;
+
+		let mut file = OpenOptions::new().append(true).open(noted.temp_file.path())?;
+		file.write_all(data)?;
+		if let Some(dirty) = &noted.dirty {
+			dirty.store(true, Ordering::SeqCst);
+		}
+		Ok(())
+	}
+
+	/// Reads the current content of the temporary file associated to a noted file or to a new
+	/// file. Noted files are looked up first, even if they're noted under a different path
+	/// representation, as [`Rollback::get_writer`] does. Unlike [`Rollback::read_overlay`], files
+	/// that aren't staged aren't read from the file system.
+	/// ## Errors:
+	/// - If the path isn't a noted file nor a new file, or the file is noted lazily (see
+	///   [`Rollback::note_file_lazy`]).
+	/// - If the temporary file cannot be read.
+	pub fn read_staged<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>, Error> {
+		let path = self.rooted(path.as_ref());
+		let path = path.as_ref();
+		let temp_file = self
+			.get_noted_file(path)
+			.or_else(|| self.get_new_file(path))
+			.ok_or_else(|| Error::NotNoted(format!("{}", path.display())))?;
+		Ok(std::fs::read(temp_file)?)
+	}
+
+	/// Iterate over the noted files, as they were noted. The order isn't deterministic, use
+	/// [`Rollback::staged_paths_sorted`] for a sorted view of every staged operation.
+	pub fn noted_paths(&self) -> impl Iterator<Item = &Path> {
+		self.noted.keys().map(PathBuf::as_path)
+	}
+
+	/// Iterate over the new files. The order isn't deterministic, use
+	/// [`Rollback::staged_paths_sorted`] for a sorted view of every staged operation.
+	pub fn new_file_paths(&self) -> impl Iterator<Item = &Path> {
+		self.new_files.keys().map(PathBuf::as_path)
+	}
+
+	/// Iterate over the new directories, in the order they were registered.
+	pub fn new_dir_paths(&self) -> impl Iterator<Item = &Path> {
+		self.new_dirs.iter().map(PathBuf::as_path)
+	}
+
+	/// Iterate over the noted files together with their temporary files, as
+	/// [`Rollback::get_noted_file`] returns them, so the staged content can be compared against
+	/// the original. Files noted with [`Rollback::note_file_lazy`] are skipped, as they don't
+	/// have a temporary file. The order isn't deterministic.
+	pub fn noted_entries(&self) -> impl Iterator<Item = (&Path, &Path)> {
+		self.noted
+			.iter()
+			.filter(|(_, noted)| noted.transform.is_none())
+			.map(|(original, noted)| (original.as_path(), noted.temp_file.path()))
+	}
+
+	/// Iterate over the new files together with their temporary files, as
+	/// [`Rollback::get_new_file`] returns them. The order isn't deterministic.
+	pub fn new_file_entries(&self) -> impl Iterator<Item = (&Path, &Path)> {
+		self.new_files
+			.iter()
+			.map(|(path, temp_file)| (path.as_path(), temp_file.path()))
+	}
+
+	/// Get the number of noted files.
+	pub fn noted_len(&self) -> usize {
+		self.noted.len()
+	}
+
+	/// Get the number of new files.
+	pub fn new_files_len(&self) -> usize {
+		self.new_files.len()
+	}
+
+	/// Get the number of new directories.
+	pub fn new_dirs_len(&self) -> usize {
+		self.new_dirs.len()
+	}
+
+	/// Get the new directories sorted by depth, so each dir comes after the new dirs containing
+	/// it, and creating them in order with [`std::fs::create_dir`] doesn't need to create any
+	/// parent dir that's registered as well. Dirs with the same depth are sorted by path, so the
+	/// order is deterministic. The depth is the number of components of the path as it was
+	/// registered, so paths containing a dir should be registered with the same representation.
+	pub fn sorted_new_dirs(&self) -> Vec<&Path> {
+		let mut dirs: Vec<_> = self.new_dirs.iter().map(PathBuf::as_path).collect();
+		dirs.sort_by(|dir, other| {
+			dir.components().count().cmp(&other.components().count()).then(dir.cmp(other))
+		});
+		dirs
+	}
+
+	/// Get the number of staged operations of any kind, which is the number of operations listed
+	/// by [`Rollback::staged_paths_sorted`]. Directories noted with [`Rollback::note_dir`] are
+	/// counted, even though nothing's committed for them.
+	pub fn pending_count(&self) -> usize {
+		let count = self.noted.len() +
+			self.new_files.len() +
+			self.new_dirs.len() +
+			self.new_symlinks.len() +
+			self.renames.len() +
+			self.deleted.len() +
+			self.deleted_dirs.len() +
+			self.noted_dirs.len();
+		#[cfg(unix)]
+		let count = count + self.dirs_metadata.len();
+		count
+	}
+
+	/// Checks if there isn't any staged operation (see [`Rollback::pending_count`]), so committing
+	/// the instance wouldn't do anything (see [`CommitOutcome::Empty`]).
+	pub fn is_empty(&self) -> bool {
+		self.pending_count() == 0
+	}
+
+	/// Checks if a file is noted, either using exactly the same [`Path`] or a different
+	/// representation of it. Paths that cannot be compared (e.g. because they don't exist) are
+	/// considered different.
+	pub fn is_noted<P: AsRef<Path>>(&self, path: P) -> bool {
+		self.noted_key(&self.rooted(path.as_ref())).is_some()
+	}
+
+	/// Checks if a path is registered as 'to be created' as a file. New files don't exist yet, so
+	/// only exactly the same [`Path`] is recognized.
+	pub fn is_new_file<P: AsRef<Path>>(&self, path: P) -> bool {
+		self.new_files.contains_key(&*self.rooted(path.as_ref()))
+	}
+
+	/// Checks if a path is registered as 'to be created' as a directory. New directories don't
+	/// exist yet, so only exactly the same [`Path`] is recognized.
+	pub fn is_new_dir<P: AsRef<Path>>(&self, path: P) -> bool {
+		let path = self.rooted(path.as_ref());
+		self.new_dirs.iter().any(|dir| *dir == path)
+	}
+
+	/// Get the noted files that were symlinks when they were noted, sorted by path. Committing them
+	/// modifies the files they point to, which may live anywhere, so they may deserve a check, or
+	/// to be noted with [`Rollback::note_symlink`] instead. Symlinks noted with
+	/// [`Rollback::note_symlink`] aren't reported.
+	pub fn symlinked_files(&self) -> Vec<&Path> {
+		let mut files: Vec<_> = self
+			.noted
+			.iter()
+			.filter(|(_, noted)| noted.symlinked)
+			.map(|(original, _)| original.as_path())
+			.collect();
+		files.sort();
+		files
+	}
+
+	/// Get the noted files that had several hard links when they were noted, sorted by path. How
+	/// these files are committed affects their other links, so they may deserve an explicit
+	/// [`LinkPolicy`] (see [`Rollback::with_link_policy`]).
+	#[cfg(unix)]
+	pub fn hard_linked_files(&self) -> Vec<&Path> {
+		let mut files: Vec<_> = self
+			.noted
+			.iter()
+			.filter(|(_, noted)| noted.nlink > 1)
+			.map(|(original, _)| original.as_path())
+			.collect();
+		files.sort();
+		files
+	}
+
+	/// Reads a file as it would be after committing the instance, overlaying the staged changes
+	/// over the file system: the content of the temporary file is returned for noted files and
+	/// new files, while other files are read from the file system. This presents a consistent view
+	/// of the future file system to code generating content depending on other staged files.
+	///
+	/// Files noted lazily (see [`Rollback::note_file_lazy`]) are read from the file system and
+	/// transformed, while files that won't be committed because they haven't been written through
+	/// their handles (see [`Rollback::get_noted_handle`]) are read from the file system as they
+	/// are.
+	/// ## Errors:
+	/// - If the file cannot be read.
+	pub fn read_overlay<P: AsRef<Path>>(&self, path: P) -> std::io::Result<Vec<u8>> {
+		let path = self.rooted(path.as_ref());
+		let path = path.as_ref();
+		if let Some(temp_file) = self.get_new_file(path) {
+			return std::fs::read(temp_file);
+		} else if let Some(from) = self.rename_source(path) {
+			return std::fs::read(self.fs_path(from));
+		} else if self.deleted_key(path).is_some() ||
+			self.renamed_key(path).is_some() ||
+			self.deleted_dirs.keys().any(|dir| path.starts_with(dir))
+		{
+			return Err(std::io::ErrorKind::NotFound.into());
+		}
+
+		let Some(key) = self.noted_key(path) else {
+			return std::fs::read(self.fs_path(path));
+		};
+		let noted = self.noted.get(key).expect("The key belongs to the map; qed;");
+		if noted.is_untouched() {
+			return std::fs::read(self.fs_path(key));
+		}
+
+		match &noted.transform {
+			Some(transform) => {
+				let content = std::fs::read(self.fs_path(key))?;
+				// A poisoned mutex means that the transformation panicked before, which doesn't
+				// prevent calling it again.
+				let transform = transform.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+				Ok(transform(content))
+			},
+			None => std::fs::read(noted.temp_file.path()),
+		}
+	}
+
+	/// Dry-runs the commit, creating everything into a throwaway temporary tree instead of the
+	/// actual targets, so the resulting files and structure can be inspected before committing.
+	/// The tree mirrors the file system as if the returned dir was its root (e.g.
+	/// `/some/file.txt` is created at `<dir>/some/file.txt`), while relative paths are made
+	/// absolute first. The dirs containing the staged items are mirrored as well if they exist,
+	/// and noted files are copied into the tree before writing their staged content, as seen by
+	/// [`Rollback::read_overlay`].
+	///
+	/// The instance is left untouched, so it can still be committed. Journals, baselines,
+	/// completion markers and directories metadata aren't part of the dry run. The tree is
+	/// removed when the returned dir is dropped.
+	/// ## Errors:
+	/// - If the temporary tree cannot be created.
+	/// - If a staged item cannot be created in the tree, e.g. a new file whose parent dir neither
+	///   exists nor is a new dir.
+	pub fn dry_run_into_temp(&self) -> std::io::Result<TempDir> {
+		let root = tempfile::tempdir()?;
+		self.commit_into_mirror(root.path())?;
+		Ok(root)
+	}
+
+	/// Commits the instance into a preview tree under `root` instead of the actual targets, so
+	/// the result of the transaction can be inspected without applying it. The tree is built as
+	/// [`Rollback::dry_run_into_temp`] does, so `root` stands for the file system root (e.g.
+	/// `/some/file.txt` is created at `<root>/some/file.txt`), and the actual targets are left
+	/// untouched. `root` and the dirs inside it are created if needed, but items already in the
+	/// tree aren't overwritten, so previewing into a fresh dir is advised.
+	///
+	/// The tree isn't removed if committing into it fails, so it may be left partially built.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::dry_run_into_temp`], as [`Error::IO`].
+	/// - If a new file or a rename target already exists in the tree.
+	pub fn commit_into(self, root: &Path) -> Result<(), Error> {
+		self.commit_into_mirror(root)?;
+		#[cfg(feature = "tracing")]
+		self.settled.store(true, Ordering::Relaxed);
+		Ok(())
+	}
+
+	/// Check whether committing would succeed, without touching the file system, so a commit can
+	/// fail fast in a validation step. The preconditions of every staged operation are checked
+	/// against the current state of the file system and against the items created by the
+	/// operations committed before it:
+	///
+	/// - Noted files still exist and are writable.
+	/// - New files don't exist yet, and their parent dirs exist or are created by new dirs.
+	/// - No two new files or new dirs point to the same item.
+	/// - Renamed files still exist, their new paths don't, and their new parent dirs exist or are
+	///   created by new dirs.
+	/// - Deleted files and dirs still exist.
+	///
+	/// As the file system may change before committing, a successful dry run doesn't guarantee a
+	/// successful commit.
+	/// ## Errors:
+	/// - The errors that [`Rollback::commit`] would return because of a failed precondition, e.g.
+	///   [`Error::RepeatedNewFile`] or [`Error::RepeatedNewDir`]. Missing or read-only items lead
+	///   to an [`Error::Commit`] for them.
+	pub fn dry_run(&self) -> Result<(), Error> {
+		self.validate_commit()
+	}
+
+	/// Get all the staged operations sorted by path, which is useful for reproducible logs and
+	/// snapshots of the transaction, as the order of the internal collections isn't
+	/// deterministic. Operations staged for the same path are sorted by kind.
+	pub fn staged_paths_sorted(&self) -> Vec<(PathBuf, StagedKind)> {
+		let mut staged = Vec::with_capacity(self.pending_count());
+		staged.extend(self.noted.keys().map(|path| (path.to_path_buf(), StagedKind::NotedFile)));
+		staged.extend(self.new_files.keys().map(|path| (path.to_path_buf(), StagedKind::NewFile)));
+		staged.extend(self.new_dirs.iter().map(|path| (path.to_path_buf(), StagedKind::NewDir)));
+		staged.extend(
+			self.new_symlinks
+				.keys()
+				.map(|path| (path.to_path_buf(), StagedKind::NewSymlink)),
+		);
+		staged
+			.extend(self.renames.keys().map(|path| (path.to_path_buf(), StagedKind::RenamedFile)));
+		staged
+			.extend(self.deleted.iter().map(|path| (path.to_path_buf(), StagedKind::DeletedFile)));
+		staged.extend(
+			self.deleted_dirs
+				.keys()
+				.map(|path| (path.to_path_buf(), StagedKind::DeletedDir)),
+		);
+		staged
+			.extend(self.noted_dirs.keys().map(|path| (path.to_path_buf(), StagedKind::NotedDir)));
+		#[cfg(unix)]
+		staged.extend(
+			self.dirs_metadata
+				.keys()
+				.map(|path| (path.to_path_buf(), StagedKind::DirMetadata)),
+		);
+		staged.sort();
+		staged
+	}
+
+	/// Detects staged operations that contradict each other, so a self-inconsistent transaction
+	/// can be reported before committing it. Two operations conflict if:
+	/// - They're staged for the same path in different categories (e.g. a new file and a new dir),
+	///   except metadata modifications of dirs that are created or noted, and permission changes of
+	///   noted files.
+	/// - One of them is staged for a file, including symlinks and rename targets, and the other one
+	///   for a path inside that file (e.g. a new file `a/b` and a new dir `a/b/c`).
+	///
+	/// Paths are compared as absolute paths, without resolving symlinks. Returns the conflicting
+	/// pairs of staged paths sorted, with the shortest path first, or `None` if there's no
+	/// conflict.
+	pub fn contains_conflicts(&self) -> Option<Vec<(PathBuf, PathBuf)>> {
+		let mut staged: Vec<_> = self
+			.staged_paths_sorted()
+			.into_iter()
+			.chain(self.renames.values().map(|to| (to.to_path_buf(), StagedKind::RenamedFile)))
+			.map(|(path, kind)| {
+				let absolute =
+					std::path::absolute(self.fs_path(&path)).unwrap_or_else(|_| path.clone());
+				(absolute, path, kind)
+			})
+			.collect();
+		// The paths inside a path are sorted right after it.
+		staged.sort();
+
+		let mut conflicts = Vec::new();
+		for (index, (absolute, path, kind)) in staged.iter().enumerate() {
+			for (other_absolute, other_path, other_kind) in staged[index + 1..]
+				.iter()
+				.take_while(|(other_absolute, ..)| other_absolute.starts_with(absolute))
+			{
+				let conflict = if absolute == other_absolute {
+					!kind.can_share_path(*other_kind)
+				} else {
+					!kind.is_dir()
+				};
+				if conflict {
+					conflicts.push((path.to_path_buf(), other_path.to_path_buf()));
+				}
+			}
+		}
+		conflicts.sort();
+		conflicts.dedup();
+		(!conflicts.is_empty()).then_some(conflicts)
+	}
+
+	/// Estimate the space required to commit the staged files, in bytes: the size of the content
+	/// copied into each noted file and each new file. Files noted lazily (see
+	/// [`Rollback::note_file_lazy`]) are estimated by the size of their original content, as
+	/// their new content is only computed upon commit.
+	///
+	/// This is a lower bound: the backups of noted files and the temporary files created while
+	/// committing aren't included. Files whose size cannot be read count as empty.
+	pub fn estimate_required_space(&self) -> u64 {
+		self.staged_sizes().map(|(_, size)| size).sum()
+	}
+
+	/// Checks that every file system where the staged files are committed has enough space
+	/// available for them, as estimated by [`Rollback::estimate_required_space`], so a commit
+	/// that would run out of space can be avoided before it starts modifying anything. The
+	/// required space is checked for each file system separately, while new files whose parent
+	/// dir doesn't exist yet are accounted in the file system of their nearest existing ancestor.
+	///
+	/// The space available may change before the commit, so this doesn't guarantee that it
+	/// succeeds. Only available on Unix, where the space is queried with `statvfs`.
+	/// ## Errors:
+	/// - [`Error::InsufficientSpace`] if a file system doesn't have enough space available.
+	/// - If the space available in a file system cannot be queried.
+	#[cfg(unix)]
+	pub fn check_free_space(&self) -> Result<(), Error> {
+		// Maps devices to a path in them and the space required in them.
+		let mut required: std::collections::BTreeMap<u64, (PathBuf, u64)> =
+			std::collections::BTreeMap::new();
+		for (path, size) in self.staged_sizes() {
+			let fs_path = std::path::absolute(self.fs_path(path))?;
+			let Some(ancestor) = space::existing_ancestor(&fs_path) else {
+				continue;
+			};
+			let entry = required
+				.entry(std::fs::metadata(ancestor)?.dev())
+				.or_insert_with(|| (ancestor.to_path_buf(), 0));
+			entry.1 += size;
+		}
+
+		for (path, bytes) in required.into_values() {
+			let available = space::available(&path)?;
+			if available < bytes {
+				return Err(Error::InsufficientSpace(
+					format!("{}", path.display()),
+					bytes,
+					available,
+				));
+			}
+		}
+		Ok(())
+	}
+
+	/// Get a [Graphviz DOT](https://graphviz.org/doc/info/lang.html) representation of the staged
+	/// operations and their dependencies, which is useful to understand the order followed by the
+	/// commit (see [`Rollback::commit`]). Each operation is a node
+	/// labeled with its kind and path, while edges go from an operation to the operations that
+	/// must be committed after it.
+	pub fn to_dot(&self) -> String {
+		graph::Graph::new(self).to_dot()
+	}
+
+	/// Consume the Rollback and commit the changes. If something goes wrong during the commit step,
+	/// everything is rolled-back, so the file system isn't affected.
+	///
+	/// The commit is driven by the dependencies between the staged operations, following a
+	/// deterministic order: e.g. a new dir is created before the new dirs it contains, while nested
+	/// directories metadata is changed before the metadata of the directories containing them.
+	/// Operations are committed in phases following a fixed sequence (see
+	/// [`Rollback::with_commit_order`]) as long as it honors their dependencies. Operations
+	/// depending on an operation of a later phase, e.g. new items inside a new symlink, are
+	/// committed by a further pass over the phases once that operation is committed.
+	///
+	/// An item that cannot be committed because of an IO error is reported as
+	/// [`Error::CommitIo`], together with the [`CommitPhase`] where it failed and the IO error
+	/// itself, so its kind can be inspected. Other failures committing an item are reported as
+	/// [`Error::Commit`].
+	/// ## Errors:
+	/// - If the staged operations depend on each other cyclically. Staging cannot produce such
+	///   operations, so this is only a safeguard.
+	/// - If the busy check is enabled (see [`Rollback::with_busy_check`]) and a noted file is busy.
+	///   No operation is committed in that case.
+	/// - If the modification check is enabled (see [`Rollback::with_modification_check`]) and a
+	///   noted file has been modified since it was noted.
+	/// - If a noted file cannot be committed. As noted files are committed concurrently, several of
+	///   them may fail, in which case every failure is gathered in [`Error::Multiple`].
+	/// - If the ACLs are preserved (see [`Rollback::with_preserved_acls`]) and the ACL of a noted
+	///   file cannot be applied. This includes a wide range of possibilities: the original file
+	///   doesn't exist anymore, or the proccess doesn't have write permissions on it,...
+	/// - If a new dir cannot be created.
+	/// - If a new file cannot be created.
+	/// - If a new file already exists when it's committed, including existing files reached through
+	///   a symlinked parent dir. Existing files are never overwritten nor removed.
+	/// - If a new symlink cannot be created, including if its path already exists when it's
+	///   committed.
+	/// - If a file registered as 'to be renamed' cannot be renamed, including if its new path
+	///   already exists when it's committed.
+	/// - If a file registered as 'to be deleted' cannot be backed up or deleted.
+	/// - If a directory registered as 'to be deleted' cannot be deleted.
+	/// - If the metadata of a noted directory, or the permissions of a noted file, cannot be
+	///   changed.
+	/// - If the baseline is configured and the committed files cannot be recorded in it.
+	/// - If an archive dir is configured and the backups of noted files cannot be archived in it.
+	/// - [`Error::RollbackFailed`] if the commit fails and some noted files or dirs (see
+	///   [`Rollback::note_dir`]) cannot be restored while rolling it back, e.g. because they have
+	///   been replaced by a directory. If a backup dir is configured (see
+	///   [`Rollback::with_backup_dir`]), their backups are kept there.
+	///
+	/// Returns [`CommitOutcome::Empty`] if there wasn't anything staged, or the number of
+	/// operations applied otherwise.
+	pub fn commit(self) -> Result<CommitOutcome, Error> {
+		self.commit_with_options(&CommitOptions::default())
+	}
+
+	/// Consume the Rollback and commit the changes as [`Rollback::commit`] does, but asking
+	/// `guard` before overwriting each noted file. The guard receives the noted file path and its
+	/// current metadata, and returns `false` to veto the file. What happens then depends on
+	/// `policy`:
+	///
+	/// - [`VetoPolicy::Skip`]: the vetoed file isn't committed, leaving the original untouched,
+	///   while the rest of the rollback is committed. Note that this means that the commit isn't
+	///   all-or-nothing anymore: the transaction is committed partially by design. Vetoed files
+	///   aren't included in the returned counts.
+	/// - [`VetoPolicy::Abort`]: the whole commit fails with [`Error::Vetoed`], so everything is
+	///   rolled-back.
+	///
+	/// Noted files are committed concurrently, so the guard may be called from several threads at
+	/// the same time.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`].
+	/// - If a noted file is vetoed and the policy is [`VetoPolicy::Abort`].
+	pub fn commit_with_file_guard<G>(
+		self,
+		policy: VetoPolicy,
+		guard: G,
+	) -> Result<CommitOutcome, Error>
+	where
+		G: Fn(&Path, &Metadata) -> bool + Sync,
+	{
+		self.commit_with_options(&CommitOptions {
+			file_guard: Some(FileGuard { policy, guard: &guard }),
+			..Default::default()
+		})
+	}
+
+	/// Consume the Rollback and commit the changes as [`Rollback::commit`] does, but reading back
+	/// every committed noted file and new file once everything's committed, and comparing it
+	/// against its staged content. A mismatch means that the file has been modified by a
+	/// concurrent writer while committing, or that the data got silently corrupted, so the commit
+	/// fails and everything is rolled-back, restoring the noted files from their backups.
+	///
+	/// Unlike [`Rollback::commit_paranoid`], files aren't flushed to disk nor verified right after
+	/// copying them, so this pass catches changes made at any point of the commit, but it doesn't
+	/// guarantee that the content survives a crash.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`].
+	/// - [`Error::CommitIo`] in the [`CommitPhase::Verification`] phase if a committed file doesn't
+	///   match its staged content, or it cannot be read.
+	pub fn commit_verified(self) -> Result<CommitOutcome, Error> {
+		self.commit_with_options(&CommitOptions { verified: true, ..Default::default() })
+	}
+
+	/// Consume the Rollback and commit the changes as [`Rollback::commit`] does, but verifying
+	/// everything for maximum confidence, which is useful for critical deployments:
+	///
+	/// - Each committed file is flushed to disk, re-read and compared against the staged content.
+	/// - Once everything's committed, each new dir is checked to exist.
+	/// - On Unix, the dirs containing the committed items are flushed to disk as well, so the new
+	///   entries survive a crash.
+	///
+	/// Any discrepancy fails the commit, so everything is rolled-back. This is slower than a
+	/// regular commit, as every committed file is read twice.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`].
+	/// - If a committed file doesn't match its staged content, or it cannot be read or flushed.
+	/// - If a new dir doesn't exist after committing it.
+	/// - If a dir containing committed items cannot be flushed.
+	pub fn commit_paranoid(self) -> Result<CommitOutcome, Error> {
+		self.commit_with_options(&CommitOptions { paranoid: true, ..Default::default() })
+	}
+
+	/// Consume the Rollback and commit the changes as [`Rollback::commit`] does, but flushing
+	/// everything to disk before returning, so the committed data survives a power loss or a
+	/// crash right after the commit:
+	///
+	/// - Each committed file is flushed to disk (`fsync`) right after writing it.
+	/// - On Unix, the dirs containing the committed, renamed or deleted items, as well as the new
+	///   dirs, are flushed to disk once everything's committed, so their entries are persisted.
+	///
+	/// Flushing waits for the storage device, so this is noticeably slower than a regular commit,
+	/// especially for many small files or slow disks. Unlike [`Rollback::commit_paranoid`], the
+	/// committed files aren't re-read and verified.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`].
+	/// - If a committed file or a dir containing committed items cannot be flushed.
+	pub fn commit_durable(self) -> Result<CommitOutcome, Error> {
+		self.commit_with_options(&CommitOptions { durable: true, ..Default::default() })
+	}
+
+	/// Consume the Rollback and commit the changes as [`Rollback::commit`] does, but committing
+	/// the noted files one by one in a single thread, sorted by path, instead of concurrently. As
+	/// the rest of operations are already committed in a deterministic order, the commit stops at
+	/// the first failing item in that order and reports it, which makes failures reproducible.
+	/// This is slower than a regular commit for many noted files.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`].
+	pub fn commit_sequential(self) -> Result<CommitOutcome, Error> {
+		self.commit_with_options(&CommitOptions { sequential: true, ..Default::default() })
+	}
+
+	/// Consume the Rollback and commit the changes as [`Rollback::commit`] does, measuring how
+	/// long it takes to copy each noted file and each new file. This helps to identify the files
+	/// dominating the commit time, e.g. big files over a slow mount. The report also includes the
+	/// size of each committed file and the new directories created, so the commit can be logged
+	/// without inspecting the file system again.
+	///
+	/// Files that aren't committed (e.g. noted files skipped because they're untouched, see
+	/// [`Rollback::get_noted_handle`]) aren't included in the report.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`].
+	pub fn commit_report(self) -> Result<CommitReport, Error> {
+		let timings = Mutex::new(Vec::new());
+		let archived = Mutex::new(Vec::new());
+		let outcome = self.commit_with_options(&CommitOptions {
+			timings: Some(&timings),
+			archived: Some(&archived),
+			..Default::default()
+		})?;
+
+		let mut timings = timings.into_inner().expect("The commit threads cannot panic; qed;");
+		timings.sort_by(|a, b| a.path.cmp(&b.path));
+		let mut new_dirs: Vec<_> = self.new_dirs.iter().map(|dir| dir.to_path_buf()).collect();
+		new_dirs.sort();
+		let mut archived = archived.into_inner().expect("The commit threads cannot panic; qed;");
+		archived.sort();
+		Ok(CommitReport { outcome, timings, new_dirs, archived })
+	}
+
+	/// Consume the Rollback and commit the changes as [`Rollback::commit`] does, calling `progress`
+	/// right after each item is committed with its path, as it was staged, and the kind of
+	/// operation committed. This gives real-time feedback for large transactions, e.g. to drive a
+	/// progress bar.
+	///
+	/// Noted files are committed concurrently, so the callback may be called from several threads,
+	/// but the calls are serialized. Items skipped by the commit (e.g. untouched noted files, see
+	/// [`Rollback::get_noted_handle`]) aren't reported. If the commit fails, the items reported so
+	/// far are rolled-back as usual.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`].
+	pub fn commit_with_progress<F>(self, progress: F) -> Result<CommitOutcome, Error>
+	where
+		F: Fn(&Path, StagedKind) + Sync,
+	{
+		self.commit_with_options(&CommitOptions {
+			progress: Some(Progress::new(&progress)),
+			..Default::default()
+		})
+	}
+
+	/// Consume the Rollback and commit the changes as [`Rollback::commit`] does, but retrying the
+	/// whole transaction if it fails. After a failed attempt everything is rolled-back, then the
+	/// function waits for `backoff` and attempts the commit again, up to `attempts` times in total
+	/// (at least one attempt is always carried out). This is useful to overcome transient failures,
+	/// such as a directory temporarily locked by another process.
+	///
+	/// Only failures that may be transient are retried: a busy file (see
+	/// [`Rollback::with_busy_check`]), or an [`Error::CommitIo`] whose IO error is interrupted,
+	/// would block or reports a busy resource. Any other error (eg, a missing file, a denied
+	/// permission, or the same new item noted twice) would fail again the same way, so it's
+	/// returned straight away.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`]. If every attempt fails, the error of the last
+	///   attempt is returned.
+	pub fn commit_with_transaction_retry(
+		self,
+		attempts: usize,
+		backoff: Duration,
+	) -> Result<CommitOutcome, Error> {
+		let options = CommitOptions::default();
+		let mut attempt = 1;
+		loop {
+			match self.commit_with_options(&options) {
+				Err(err) if err.is_retryable() && attempt < attempts => {
+					attempt += 1;
+					std::thread::sleep(backoff);
+				},
+				result => return result,
+			}
+		}
+	}
+
+	/// Commit the changes as [`Rollback::commit`] does, but without consuming the Rollback. If the
+	/// commit fails, everything is rolled-back and the staged operations are kept as they are, so
+	/// the failing condition (e.g. a full disk) can be fixed and the commit tried again. If it
+	/// succeeds, every staged operation is discarded, while the configuration of the instance is
+	/// kept, so it can be reused for another transaction.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`].
+	pub fn try_commit(&mut self) -> Result<CommitOutcome, Error> {
+		let outcome = self.commit_with_options(&CommitOptions::default())?;
+		self.clear();
+		Ok(outcome)
+	}
+
+	/// Consume the Rollback discarding every staged change, as dropping it does, but removing its
+	/// temporary files and backups explicitly, so failing to remove them can be handled. The
+	/// staged items aren't affected at all, and the locks held on noted files are released.
+	///
+	/// Every temporary file is removed even if removing another one fails.
+	/// ## Errors:
+	/// - If a temporary file or backup cannot be removed. The first failure is returned.
+	pub fn abort(mut self) -> Result<(), Error> {
+		#[cfg(feature = "tracing")]
+		self.settled.store(true, Ordering::Relaxed);
+		let noted = std::mem::take(&mut self.noted);
+		let new_files = std::mem::take(&mut self.new_files);
+		let deduplicated_contents = self.deduplicated_contents.take();
+		let deleted_dirs = std::mem::take(&mut self.deleted_dirs);
+		let noted_dirs = std::mem::take(&mut self.noted_dirs);
+		// Shared temporary files are removed once the last new file using them is closed.
+		drop(deduplicated_contents);
+
+		let mut results = Vec::new();
+		results.extend(noted.into_values().map(NotedFile::close));
+		results.extend(new_files.into_values().filter_map(|temp_file| {
+			Arc::into_inner(temp_file).map(|temp_file| temp_file.close().map_err(Error::from))
+		}));
+		results.extend(deleted_dirs.into_values().map(DirBackup::close));
+		results.extend(noted_dirs.into_values().map(DirBackup::close));
+		results.into_iter().collect()
+	}
+
+	/// Consume the Rollback yielding its noted files, new files and new dirs, so a custom commit
+	/// logic can be driven with them. The noted files and the new files are mapped to the
+	/// temporary files holding their new content, whose ownership is handed over: they're removed
+	/// when dropped unless they're persisted. The new dirs are listed in the order they were
+	/// staged. Nothing is committed, so the file system isn't affected.
+	///
+	/// The content of lazily noted files is computed from the original files (see
+	/// [`Rollback::note_file_lazy`]), and new files sharing a temporary file (see
+	/// [`Rollback::with_deduplicated_new_files`]) get their own copy of it. Every other staged
+	/// operation is discarded, as dropping the Rollback does, and the locks held on noted files
+	/// are released.
+	/// ## Errors:
+	/// - If the content of a lazily noted file cannot be computed.
+	/// - If a shared temporary file cannot be copied.
+	pub fn into_parts(mut self) -> Result<RollbackParts, Error> {
+		#[cfg(feature = "tracing")]
+		self.settled.store(true, Ordering::Relaxed);
+		let noted = std::mem::take(&mut self.noted);
+		let new_files = std::mem::take(&mut self.new_files);
+		let new_dirs = std::mem::take(&mut self.new_dirs);
+		// Temporary files are only shared among new files once the deduplicated contents are
+		// dropped.
+		drop(self.deduplicated_contents.take());
+
+		let noted = noted
+			.into_iter()
+			.map(|(original, noted)| {
+				let temp_file = noted.into_temp_file(&self.fs_path(&original))?;
+				Ok((original, temp_file))
+			})
+			.collect::<Result<_, Error>>()?;
+		let new_files = new_files
+			.into_iter()
+			.map(|(path, temp_file)| match Arc::try_unwrap(temp_file) {
+				Ok(temp_file) => Ok((path, temp_file)),
+				Err(shared) => {
+					let copy = self.new_temp_file(&path)?;
+					std::fs::copy(shared.path(), copy.path())?;
+					Ok((path, copy))
+				},
+			})
+			.collect::<Result<_, Error>>()?;
+
+		Ok((noted, new_files, new_dirs))
+	}
+
+	/// Restores the noted files left modified by a commit that couldn't finish, e.g. because the
+	/// process died in the middle of it, from the backups persisted in `backup_dir` (see
+	/// [`Rollback::with_backup_dir`]). This is meant to be called on startup, before committing
+	/// anything else that uses the same backup dir. The leftover backups are removed, so
+	/// recovering a clean backup dir does nothing.
+	///
+	/// Returns the restored files, sorted by path.
+	/// ## Errors:
+	/// - If the backup dir cannot be read.
+	/// - If a file cannot be restored, in which case its backup is kept, so recovering can be
+	///   retried.
+	pub fn recover<P: AsRef<Path>>(backup_dir: P) -> Result<Vec<PathBuf>, Error> {
+		backup::recover(backup_dir.as_ref())
+	}
+
+	// Stages every path with `stage`, discarding the paths staged so far if one of them fails. IO
+	// errors don't name the path, so it's added to their message.
+	fn stage_all<I, F>(&mut self, paths: I, mut stage: F) -> Result<(), Error>
+	where
+		I: IntoIterator,
+		I::Item: AsRef<Path>,
+		F: FnMut(&mut Self, &Path) -> Result<(), Error>,
+	{
+		let savepoint = self.savepoint();
+		for path in paths {
+			let path = path.as_ref();
+			if let Err(err) = stage(self, path) {
+				self.rollback_to(savepoint);
+				return Err(match err {
+					Error::IO(err) => Error::IO(std::io::Error::new(
+						err.kind(),
+						format!("{}: {}", path.display(), err),
+					)),
+					err => err,
+				});
+			}
+		}
+		Ok(())
+	}
+
+	// The size of the content copied into each noted file and new file upon commit, together with
+	// their staged path.
+	fn staged_sizes(&self) -> impl Iterator<Item = (&Path, u64)> {
+		let size =
+			|path: &Path| std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+		let noted = self.noted.iter().map(move |(original, noted)| match noted.transform {
+			Some(_) => (original.as_path(), size(&self.fs_path(original))),
+			None => (original.as_path(), size(noted.temp_file.path())),
+		});
+		let new_files = self
+			.new_files
+			.iter()
+			.map(move |(path, temp_file)| (path.as_path(), size(temp_file.path())));
+		noted.chain(new_files)
+	}
+
+	// Creates a temporary file for a noted file or a new file in the configured temp dir, named
+	// as configured.
+	fn new_temp_file(&self, target: &Path) -> std::io::Result<NamedTempFile> {
+		let mut builder = tempfile::Builder::new();
+		if let Some(prefix) = self.temp_file_prefix {
+			builder.prefix(prefix);
+		}
+		let suffix = match target.file_name() {
+			Some(name) if self.temp_file_target_names => format!("-{}", sanitize_file_name(name)),
+			_ => String::new(),
+		};
+		builder.suffix(&suffix);
+		match self.temp_dir.as_deref() {
+			Some(dir) => builder.tempfile_in(dir),
+			None => builder.tempfile(),
+		}
+	}
+
+	// The path actually used in the file system for a staged path, rebased onto the file system
+	// root if configured (see `Rollback::with_fs_root`).
+	pub(crate) fn fs_path<'p>(&self, path: &'p Path) -> Cow<'p, Path> {
+		fs_root::rebase(self.fs_root.as_deref(), path)
+	}
+
+	// Anchors a path about to be staged to the base dir, if configured (see
+	// `Rollback::rooted_at`), rejecting the paths that may land outside of it or outside the
+	// confined root (see `Rollback::confined_to`).
+	fn anchored<'p>(&self, path: &'p Path) -> Result<Cow<'p, Path>, Error> {
+		let anchored = base_dir::anchor(self.base_dir.as_deref(), path)?;
+		if let Some(root) = &self.confined_root {
+			confine::check(root, &self.fs_path(&anchored), path)?;
+		}
+		Ok(anchored)
+	}
+
+	// Interprets a looked up path relative to the base dir, if configured.
+	fn rooted<'p>(&self, path: &'p Path) -> Cow<'p, Path> {
+		base_dir::join(self.base_dir.as_deref(), path)
+	}
+
+	// Checks if two staged paths point to the same item in the file system.
+	fn is_same_item(&self, path: &Path, other: &Path) -> bool {
+		same_file::is_same_file(self.fs_path(path), self.fs_path(other)).unwrap_or(false)
+	}
+
+	// Checks if two staged paths point to the same new item. As new items don't exist yet, they're
+	// compared as resolved against their parent dirs, if they exist.
+	fn is_same_new_item(&self, path: &Path, other: &Path) -> bool {
+		path == other ||
+			matches!(
+				(resolve_new_item(&self.fs_path(path)), resolve_new_item(&self.fs_path(other))),
+				(Ok(path), Ok(other)) if path == other
+			)
+	}
+
+	// Checks if a new item is the same new item as any of the staged ones. Items with different
+	// file names cannot be the same, so only the others are resolved.
+	fn is_repeated_new_item<'p>(
+		&self,
+		mut staged: impl Iterator<Item = &'p Path>,
+		path: &Path,
+	) -> bool {
+		staged.any(|item| item.file_name() == path.file_name() && self.is_same_new_item(item, path))
+	}
+
+	// Checks if the parent dir of a new item exists or is created by a new dir, even if the new dir
+	// is registered under a different path representation. Paths without parent live in the
+	// current dir.
+	fn has_parent_dir(&self, path: &Path) -> bool {
+		let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) else {
+			return true;
+		};
+		if self.fs_path(parent).is_dir() || self.new_dirs.iter().any(|dir| dir.starts_with(parent))
+		{
+			return true;
+		}
+
+		let Ok(parent) = resolve_new_item(&self.fs_path(parent)) else {
+			return false;
+		};
+		self.new_dirs.iter().any(|dir| {
+			resolve_new_item(&self.fs_path(dir)).is_ok_and(|dir| dir.starts_with(&parent))
+		})
+	}
+
+	// Checks if a path is registered as a new file, a new symlink or the new path of a rename.
+	fn is_new_item(&self, path: &Path) -> bool {
+		self.new_files.contains_key(path) ||
+			self.new_symlinks.contains_key(path) ||
+			self.rename_source(path).is_some()
+	}
+
+	// Finds the key of a noted file, even if it's noted under a different path representation.
+	fn noted_key(&self, original: &Path) -> Option<&Path> {
+		self.noted.get_key_value(original).map(|(path, _)| path.as_path()).or_else(|| {
+			self.noted
+				.keys()
+				.find(|path| self.is_same_item(path, original))
+				.map(PathBuf::as_path)
+		})
+	}
+
+	// Finds the key of a new file, even if it's registered under a different path representation.
+	// New files are compared as resolved against their parent dirs, so only the keys with the same
+	// file name are resolved.
+	fn new_file_key(&self, path: &Path) -> Option<&Path> {
+		self.new_files.get_key_value(path).map(|(key, _)| key.as_path()).or_else(|| {
+			self.new_files
+				.keys()
+				.find(|key| key.file_name() == path.file_name() && self.is_same_new_item(key, path))
+				.map(PathBuf::as_path)
+		})
+	}
+
+	// Checks if an existing file is already noted, renamed or deleted, even if it's registered
+	// under a different path representation.
+	fn is_tracked_file(&self, path: &Path) -> bool {
+		self.noted_key(path).is_some() ||
+			self.renamed_key(path).is_some() ||
+			self.deleted_key(path).is_some()
+	}
+
+	// Finds the key of a file to be renamed, even if it's registered under a different path
+	// representation.
+	fn renamed_key(&self, from: &Path) -> Option<&Path> {
+		self.renames.get_key_value(from).map(|(key, _)| key.as_path()).or_else(|| {
+			self.renames
+				.keys()
+				.find(|key| self.is_same_item(key, from))
+				.map(PathBuf::as_path)
+		})
+	}
+
+	// Finds the file that will be renamed to the given path, if any.
+	fn rename_source(&self, to: &Path) -> Option<&Path> {
+		self.renames
+			.iter()
+			.find(|(_, new_path)| *new_path == to)
+			.map(|(from, _)| from.as_path())
+	}
+
+	// Finds the key of a file to be deleted, even if it's registered under a different path
+	// representation.
+	fn deleted_key(&self, path: &Path) -> Option<&Path> {
+		self.deleted.get(path).map(PathBuf::as_path).or_else(|| {
+			self.deleted
+				.iter()
+				.find(|key| self.is_same_item(key, path))
+				.map(PathBuf::as_path)
+		})
+	}
+
+	// Finds the key of a dir to be deleted, even if it's registered under a different path
+	// representation.
+	fn deleted_dir_key(&self, dir: &Path) -> Option<&Path> {
+		self.deleted_dirs.get_key_value(dir).map(|(key, _)| key.as_path()).or_else(|| {
+			self.deleted_dirs
+				.keys()
+				.find(|key| self.is_same_item(key, dir))
+				.map(PathBuf::as_path)
+		})
+	}
+
+	// Finds the key of a directory noted with `note_dir`, even if it's noted under a different
+	// path representation.
+	fn noted_dir_key(&self, dir: &Path) -> Option<&Path> {
+		self.noted_dirs.get_key_value(dir).map(|(key, _)| key.as_path()).or_else(|| {
+			self.noted_dirs
+				.keys()
+				.find(|key| self.is_same_item(key, dir))
+				.map(PathBuf::as_path)
+		})
+	}
+
+	// Finds the key of a noted directory, even if it's noted under a different path
+	// representation.
+	#[cfg(unix)]
+	fn dirs_metadata_key(&self, dir: &Path) -> Option<&Path> {
+		self.dirs_metadata
+			.get_key_value(dir)
+			.map(|(path, _)| path.as_path())
+			.or_else(|| {
+				self.dirs_metadata
+					.keys()
+					.find(|path| self.is_same_item(path, dir))
+					.map(PathBuf::as_path)
+			})
+	}
+
+	#[cfg(unix)]
+	fn get_dir_metadata_mut(&mut self, dir: &Path) -> Result<&mut metadata::DirMetadata, Error> {
+		let key = self
+			.dirs_metadata_key(dir)
+			.map(Path::to_path_buf)
+			.ok_or_else(|| Error::NotNoted(format!("{}", dir.display())))?;
+
+		Ok(self.dirs_metadata.get_mut(&key).expect("The key belongs to the map; qed;"))
+	}
+}
+
+#[cfg(feature = "tokio")]
+impl Rollback<'static> {
+	/// Consume the Rollback and commit the changes as [`Rollback::commit`] does, but running the
+	/// commit in tokio's blocking thread pool, so it can be awaited from async code without
+	/// blocking the executor. The commit is carried out as a whole in the pool, so it's rolled-back
+	/// on failure exactly as [`Rollback::commit`] is.
+	///
+	/// The commit is moved to another thread, so the paths configured in the Rollback (e.g. a
+	/// backup dir) must be `'static`. This function must be called within a tokio runtime.
+	/// ## Errors:
+	/// - The same errors as [`Rollback::commit`].
+	/// - If the runtime shuts down before the commit runs, an [`Error::IO`] is returned, and
+	///   nothing is committed.
+	pub async fn commit_async(self) -> Result<CommitOutcome, Error> {
+		match tokio::task::spawn_blocking(move || self.commit()).await {
+			Ok(result) => result,
+			// Panics are propagated as if the commit was called in the current thread.
+			Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+			Err(err) => Err(Error::IO(std::io::Error::other(err))),
+		}
+	}
 }
@@ -0,0 +1,27 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::Error;
+use std::path::Path;
+
+// POSIX ACLs are stored by Linux in this extended attribute, so copying it around preserves the
+// ACL as is.
+const ACL_XATTR: &str = "system.posix_acl_access";
+
+// Reads the ACL of a file, if it has one. File systems without extended attributes support cannot
+// hold ACLs, so files living there don't have an ACL.
+pub(crate) fn read(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+	match xattr::get(path, ACL_XATTR) {
+		Ok(acl) => Ok(acl),
+		Err(err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(None),
+		Err(err) => Err(Error::Acl(format!("{}", path.display()), format!("{}", err))),
+	}
+}
+
+// Applies an ACL previously read by `read` to a file.
+pub(crate) fn apply(path: &Path, acl: &[u8]) -> Result<(), Error> {
+	xattr::set(path, ACL_XATTR, acl)
+		.map_err(|err| Error::Acl(format!("{}", path.display()), format!("{}", err)))
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::TestBuilder;
+
+// A raw ACL granting read access to the user with uid 1000, besides the usual entries.
+fn raw_acl() -> Vec<u8> {
+	const UNDEFINED_ID: u32 = u32::MAX;
+	let entries: [(u16, u16, u32); 5] = [
+		(0x01, 6, UNDEFINED_ID), // Owner
+		(0x02, 4, 1000),         // Named user
+		(0x04, 4, UNDEFINED_ID), // Owning group
+		(0x10, 4, UNDEFINED_ID), // Mask
+		(0x20, 4, UNDEFINED_ID), // Others
+	];
+	let mut acl = 2u32.to_le_bytes().to_vec();
+	for (tag, permissions, id) in entries {
+		acl.extend(tag.to_le_bytes());
+		acl.extend(permissions.to_le_bytes());
+		acl.extend(id.to_le_bytes());
+	}
+	acl
+}
+
+#[test]
+fn read_works_if_file_doesnt_have_acl() {
+	let builder = TestBuilder::new(Some(1));
+
+	assert_eq!(read(builder.existing_files()[0]).expect("The file exists; qed;"), None);
+}
+
+#[test]
+fn apply_and_read_work() {
+	let builder = TestBuilder::new(Some(1));
+	let path = builder.existing_files()[0];
+
+	assert!(apply(path, &raw_acl()).is_ok());
+
+	assert_eq!(read(path).expect("The file exists; qed;"), Some(raw_acl()));
+}
+
+#[test]
+fn read_fails_if_file_doesnt_exist() {
+	assert!(matches!(read(Path::new("some/unexisting/path/file.txt")), Err(Error::Acl(..))));
+}
+
+#[test]
+fn apply_fails_if_file_doesnt_exist() {
+	assert!(matches!(
+		apply(Path::new("some/unexisting/path/file.txt"), &raw_acl()),
+		Err(Error::Acl(..))
+	));
+}
@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+#[cfg(unix)]
+use crate::rollback::owner;
+use crate::Error;
+use std::{
+	fs::{File, OpenOptions, Permissions},
+	io,
+	path::Path,
+};
+use tempfile::NamedTempFile;
+
+// The Windows error returned when a file cannot be moved to a different disk drive.
+#[cfg(windows)]
+const ERROR_NOT_SAME_DEVICE: i32 = 17;
+
+// Checks if the error comes from renaming a file across file systems.
+fn crosses_devices(err: &io::Error) -> bool {
+	#[cfg(unix)]
+	return err.raw_os_error() == Some(libc::EXDEV);
+	#[cfg(windows)]
+	return err.raw_os_error() == Some(ERROR_NOT_SAME_DEVICE);
+	#[cfg(not(any(unix, windows)))]
+	false
+}
+
+// A temporary file created next to the given path, so it can be renamed to that path.
+fn sibling_temp_file(path: &Path) -> Result<NamedTempFile, Error> {
+	let prefixed_path = rustilities::paths::prefix_with_current_dir(path);
+	let parent_dir = prefixed_path.parent().expect("The path is a file and is prefixed; qed;");
+	Ok(NamedTempFile::new_in(parent_dir)?)
+}
+
+// Moves a file, replacing the destination at once if it exists. Renaming isn't possible across
+// file systems, so in that case the file is copied next to the destination, renamed over it and
+// then removed from its original location. Every rename-based operation must use this function,
+// so they all share the fallback.
+pub(crate) fn atomic_move_or_copy(from: &Path, to: &Path) -> Result<(), Error> {
+	match std::fs::rename(from, to) {
+		Ok(_) => Ok(()),
+		Err(err) if crosses_devices(&err) => {
+			let copy = sibling_temp_file(to)?;
+			// The copy is streamed, so the file isn't loaded into memory.
+			std::fs::copy(from, &copy)?;
+			copy.persist(to).map_err(|err| err.error)?;
+			std::fs::remove_file(from)?;
+			Ok(())
+		},
+		Err(err) => Err(err.into()),
+	}
+}
+
+// Renames a file if both paths are in the same file system, replacing the destination at once if
+// it exists. Returns whether the file has been renamed: if the paths are in different file
+// systems nothing is touched, so the caller can fall back to copying the file.
+pub(crate) fn rename_within_fs(from: &Path, to: &Path) -> Result<bool, Error> {
+	match std::fs::rename(from, to) {
+		Ok(_) => Ok(true),
+		Err(err) if crosses_devices(&err) => Ok(false),
+		Err(err) => Err(err.into()),
+	}
+}
+
+// Links a file to a new path, copying it if linking isn't possible across file systems. Neither
+// linking nor copying replace the destination if it exists, failing with `AlreadyExists` instead.
+pub(crate) fn link_or_copy_new(from: &Path, to: &Path) -> Result<(), Error> {
+	match std::fs::hard_link(from, to) {
+		Ok(_) => Ok(()),
+		Err(err) if crosses_devices(&err) => {
+			let mut source = File::open(from)?;
+			let mut copy = OpenOptions::new().write(true).create_new(true).open(to)?;
+			let result = io::copy(&mut source, &mut copy)
+				.and_then(|_| copy.set_permissions(source.metadata()?.permissions()));
+			if let Err(err) = result {
+				// The copy is new, so it can be removed without losing anything.
+				let _ = std::fs::remove_file(to);
+				return Err(err.into());
+			}
+			Ok(())
+		},
+		Err(err) => Err(err.into()),
+	}
+}
+
+// Creates an empty file at the given path at once, replacing it if it exists.
+pub(crate) fn create_atomically(path: &Path) -> Result<(), Error> {
+	let file = sibling_temp_file(path)?;
+	file.persist(path).map_err(|err| err.error)?;
+	Ok(())
+}
+
+// Replaces the original file by a copy of the source. The copy is created next to the original
+// and then moved over it, so the original is replaced at once. The copy keeps the permissions of
+// the original, and on Unix its owner, group and extended attributes too. Changing the owner
+// requires privileges, so if the process cannot keep it, the source is copied into the original
+// instead, which isn't atomic but doesn't hand the file over to the process owner.
+pub(crate) fn replace_atomically(source: &Path, original: &Path) -> Result<(), Error> {
+	let original_file = File::open(original)?;
+	let metadata = original_file.metadata()?;
+	let replacement = sibling_temp_file(original)?;
+	std::fs::copy(source, &replacement)?;
+	#[cfg(unix)]
+	match owner::keep_owner(&metadata, replacement.as_file()) {
+		Ok(()) => owner::copy_xattrs(&original_file, replacement.as_file())?,
+		Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+			drop(replacement);
+			std::fs::copy(source, original)?;
+			std::fs::set_permissions(original, metadata.permissions())?;
+			return Ok(());
+		},
+		Err(err) => return Err(err.into()),
+	}
+	// Changing the owner clears the setuid and setgid bits, so the permissions go last. The copy
+	// carries the source permissions, but the replaced file should keep its own ones.
+	std::fs::set_permissions(&replacement, metadata.permissions())?;
+	// If moving the replacement fails, the temporary path removes it when dropped.
+	atomic_move_or_copy(&replacement.into_temp_path(), original)
+}
+
+// Replaces the original item, which may be a symlink, by a copy of the source with the given
+// permissions, as `replace_atomically` does.
+pub(crate) fn replace_with_permissions(
+	source: &Path,
+	original: &Path,
+	permissions: Permissions,
+) -> Result<(), Error> {
+	let replacement = sibling_temp_file(original)?;
+	std::fs::copy(source, &replacement)?;
+	std::fs::set_permissions(&replacement, permissions)?;
+	// If moving the replacement fails, the temporary path removes it when dropped.
+	atomic_move_or_copy(&replacement.into_temp_path(), original)
+}
@@ -0,0 +1,311 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::{
+	TestBuilder, MODIFIED_BUILDER_FILE_CONTENT, ORIGINAL_BUILDER_FILE_CONTENT,
+};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+#[test]
+fn atomic_move_or_copy_works() {
+	let builder = TestBuilder::new(Some(1));
+	let from = builder.existing_files()[0];
+	let to = builder.get_temp_dir_path().join("moved.txt");
+
+	assert!(atomic_move_or_copy(from, &to).is_ok());
+
+	assert!(!from.exists());
+	assert_eq!(
+		std::fs::read_to_string(&to).expect("The file exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+}
+
+#[test]
+fn atomic_move_or_copy_replaces_the_destination() {
+	let builder = TestBuilder::new(Some(2));
+	let from = builder.existing_files()[0];
+	let to = builder.existing_files()[1];
+	std::fs::write(from, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+	assert!(atomic_move_or_copy(from, to).is_ok());
+
+	assert!(!from.exists());
+	assert_eq!(
+		std::fs::read_to_string(to).expect("The file exists; qed;"),
+		MODIFIED_BUILDER_FILE_CONTENT
+	);
+}
+
+#[test]
+fn atomic_move_or_copy_works_across_file_systems() {
+	// /dev/shm is usually a tmpfs, while the crate lives in a regular file system.
+	let Ok(from_dir) = tempfile::tempdir_in("/dev/shm") else {
+		return;
+	};
+	let to_dir = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR"))
+		.expect("A tempdir can be created in the crate dir; qed;");
+	let device = |path: &Path| std::fs::metadata(path).expect("The dir exists; qed;").dev();
+	if device(from_dir.path()) == device(to_dir.path()) {
+		// Both dirs are in the same file system, so there's nothing to check.
+		return;
+	}
+
+	let from = from_dir.path().join("file.txt");
+	let to = to_dir.path().join("file.txt");
+	std::fs::write(&from, ORIGINAL_BUILDER_FILE_CONTENT).expect("The file can be written; qed;");
+
+	// As renaming, linking fails across file systems
+	assert!(crosses_devices(
+		&std::fs::hard_link(&from, &to).expect_err("Cannot link across file systems; qed;")
+	));
+
+	assert!(atomic_move_or_copy(&from, &to).is_ok());
+
+	assert!(!from.exists());
+	assert_eq!(
+		std::fs::read_to_string(&to).expect("The file exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+	// No temporary copy is left behind
+	assert_eq!(std::fs::read_dir(to_dir.path()).expect("The dir exists; qed;").count(), 1);
+}
+
+#[test]
+fn rename_within_fs_works() {
+	let builder = TestBuilder::new(Some(1));
+	let from = builder.existing_files()[0];
+	let to = builder.get_temp_dir_path().join("renamed.txt");
+	let inode = std::fs::metadata(from).expect("The file exists; qed;").ino();
+
+	assert!(rename_within_fs(from, &to).expect("The file can be renamed; qed;"));
+
+	assert!(!from.exists());
+	// The file itself is renamed, not copied
+	assert_eq!(std::fs::metadata(&to).expect("The file exists; qed;").ino(), inode);
+}
+
+#[test]
+fn rename_within_fs_doesnt_touch_anything_across_file_systems() {
+	// /dev/shm is usually a tmpfs, while the crate lives in a regular file system.
+	let Ok(from_dir) = tempfile::tempdir_in("/dev/shm") else {
+		return;
+	};
+	let to_dir = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR"))
+		.expect("A tempdir can be created in the crate dir; qed;");
+	let device = |path: &Path| std::fs::metadata(path).expect("The dir exists; qed;").dev();
+	if device(from_dir.path()) == device(to_dir.path()) {
+		// Both dirs are in the same file system, so there's nothing to check.
+		return;
+	}
+
+	let from = from_dir.path().join("file.txt");
+	let to = to_dir.path().join("file.txt");
+	std::fs::write(&from, ORIGINAL_BUILDER_FILE_CONTENT).expect("The file can be written; qed;");
+
+	assert!(!rename_within_fs(&from, &to).expect("Crossing file systems isn't an error; qed;"));
+
+	assert!(from.exists());
+	assert!(!to.exists());
+}
+
+#[test]
+fn atomic_move_or_copy_fails_if_source_doesnt_exist() {
+	let builder = TestBuilder::new(Some(0));
+	let from = builder.get_temp_dir_path().join("missing.txt");
+	let to = builder.get_temp_dir_path().join("moved.txt");
+
+	assert!(matches!(
+		atomic_move_or_copy(&from, &to),
+		Err(Error::IO(err)) if err.kind() == io::ErrorKind::NotFound
+	));
+}
+
+#[test]
+fn link_or_copy_new_works() {
+	let builder = TestBuilder::new(Some(1));
+	let from = builder.existing_files()[0];
+	let to = builder.get_temp_dir_path().join("linked.txt");
+
+	assert!(link_or_copy_new(from, &to).is_ok());
+
+	assert!(from.exists());
+	assert_eq!(
+		std::fs::read_to_string(&to).expect("The file exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+}
+
+#[test]
+fn link_or_copy_new_doesnt_replace_the_destination() {
+	let builder = TestBuilder::new(Some(2));
+	let from = builder.existing_files()[0];
+	let to = builder.existing_files()[1];
+	std::fs::write(to, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+	assert!(matches!(
+		link_or_copy_new(from, to),
+		Err(Error::IO(err)) if err.kind() == io::ErrorKind::AlreadyExists
+	));
+
+	assert_eq!(
+		std::fs::read_to_string(to).expect("The file exists; qed;"),
+		MODIFIED_BUILDER_FILE_CONTENT
+	);
+}
+
+#[test]
+fn link_or_copy_new_copies_across_file_systems() {
+	// /dev/shm is usually a tmpfs, while the crate lives in a regular file system.
+	let Ok(from_dir) = tempfile::tempdir_in("/dev/shm") else {
+		return;
+	};
+	let to_dir = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR"))
+		.expect("A tempdir can be created in the crate dir; qed;");
+	let device = |path: &Path| std::fs::metadata(path).expect("The dir exists; qed;").dev();
+	if device(from_dir.path()) == device(to_dir.path()) {
+		// Both dirs are in the same file system, so there's nothing to check.
+		return;
+	}
+
+	let from = from_dir.path().join("file.txt");
+	let to = to_dir.path().join("file.txt");
+	std::fs::write(&from, ORIGINAL_BUILDER_FILE_CONTENT).expect("The file can be written; qed;");
+
+	assert!(link_or_copy_new(&from, &to).is_ok());
+
+	assert!(from.exists());
+	assert_eq!(
+		std::fs::read_to_string(&to).expect("The file exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+	// The copy doesn't replace an existing destination either
+	assert!(matches!(
+		link_or_copy_new(&from, &to),
+		Err(Error::IO(err)) if err.kind() == io::ErrorKind::AlreadyExists
+	));
+}
+
+#[test]
+fn replace_atomically_works() {
+	let builder = TestBuilder::new(Some(2));
+	let source = builder.existing_files()[0];
+	let original = builder.existing_files()[1];
+	let original_inode = std::fs::metadata(original).expect("The file exists; qed;").ino();
+	std::fs::write(source, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+	assert!(replace_atomically(source, original).is_ok());
+
+	// The source is kept while the original is replaced
+	assert!(source.exists());
+	assert_eq!(
+		std::fs::read_to_string(original).expect("The file exists; qed;"),
+		MODIFIED_BUILDER_FILE_CONTENT
+	);
+	assert_ne!(std::fs::metadata(original).expect("The file exists; qed;").ino(), original_inode);
+	// No temporary copy is left behind
+	assert_eq!(
+		std::fs::read_dir(builder.get_temp_dir_path())
+			.expect("The dir exists; qed;")
+			.count(),
+		2
+	);
+}
+
+#[test]
+fn replace_atomically_keeps_original_permissions() {
+	let builder = TestBuilder::new(Some(2));
+	let source = builder.existing_files()[0];
+	let original = builder.existing_files()[1];
+	std::fs::set_permissions(source, std::fs::Permissions::from_mode(0o600))
+		.expect("The file exists; qed;");
+	std::fs::set_permissions(original, std::fs::Permissions::from_mode(0o644))
+		.expect("The file exists; qed;");
+
+	assert!(replace_atomically(source, original).is_ok());
+
+	assert_eq!(
+		std::fs::metadata(original).expect("The file exists; qed;").permissions().mode() & 0o777,
+		0o644
+	);
+}
+
+#[test]
+fn replace_atomically_keeps_original_owner() {
+	let builder = TestBuilder::new(Some(2));
+	let source = builder.existing_files()[0];
+	let original = builder.existing_files()[1];
+	// Giving files away requires privileges
+	if std::os::unix::fs::chown(original, Some(65534), Some(65534)).is_err() {
+		return;
+	}
+	let original_inode = std::fs::metadata(original).expect("The file exists; qed;").ino();
+	std::fs::write(source, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+	assert!(replace_atomically(source, original).is_ok());
+
+	let metadata = std::fs::metadata(original).expect("The file exists; qed;");
+	assert_ne!(metadata.ino(), original_inode);
+	assert_eq!((metadata.uid(), metadata.gid()), (65534, 65534));
+	assert_eq!(
+		std::fs::read_to_string(original).expect("The file exists; qed;"),
+		MODIFIED_BUILDER_FILE_CONTENT
+	);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn replace_atomically_keeps_original_extended_attributes() {
+	use std::{ffi::CString, os::fd::AsRawFd};
+
+	let builder = TestBuilder::new(Some(2));
+	let source = builder.existing_files()[0];
+	let original = builder.existing_files()[1];
+	let name = CString::new("user.fs_rollback").expect("The name has no nul bytes; qed;");
+	let file = File::open(original).expect("The file exists; qed;");
+	// SAFETY: The file descriptor is valid, the name is a valid C string and the value pointer
+	// is valid for its length.
+	if unsafe { libc::fsetxattr(file.as_raw_fd(), name.as_ptr(), b"value".as_ptr().cast(), 5, 0) } !=
+		0
+	{
+		// The file system doesn't support user extended attributes.
+		return;
+	}
+
+	assert!(replace_atomically(source, original).is_ok());
+
+	let file = File::open(original).expect("The file exists; qed;");
+	let mut value = [0u8; 5];
+	// SAFETY: The file descriptor is valid, the name is a valid C string and the buffer is valid
+	// for its length.
+	let read = unsafe {
+		libc::fgetxattr(file.as_raw_fd(), name.as_ptr(), value.as_mut_ptr().cast(), value.len())
+	};
+	assert_eq!(read, 5);
+	assert_eq!(&value, b"value");
+}
+
+#[test]
+fn replace_with_permissions_replaces_symlinks() {
+	let builder = TestBuilder::new(Some(2));
+	let source = builder.existing_files()[0];
+	let target = builder.existing_files()[1];
+	let link = builder.get_temp_dir_path().join("link.txt");
+	std::os::unix::fs::symlink(target, &link).expect("The link can be created; qed;");
+	std::fs::write(source, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+	assert!(replace_with_permissions(source, &link, Permissions::from_mode(0o640)).is_ok());
+
+	// The link is replaced by a file, while its target is untouched
+	let metadata = link.symlink_metadata().expect("The file exists; qed;");
+	assert!(metadata.is_file());
+	assert_eq!(metadata.mode() & 0o777, 0o640);
+	assert_eq!(
+		std::fs::read_to_string(&link).expect("The file exists; qed;"),
+		MODIFIED_BUILDER_FILE_CONTENT
+	);
+	assert_eq!(
+		std::fs::read_to_string(target).expect("The file exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+}
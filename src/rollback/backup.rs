@@ -4,11 +4,22 @@
 mod tests;
 
 use crate::Error;
-use std::path::{Path, PathBuf};
+use std::{
+	io::Write,
+	path::{Path, PathBuf},
+};
 use tempfile::NamedTempFile;
 
+const PREFIX: &str = ".fs_rollback-backup";
+const BACKUP_SUFFIX: &str = ".backup";
+const RECORD_SUFFIX: &str = ".record";
+
 // A useful struct to create temporary backups and rollback them if needed
+#[derive(Debug)]
 pub(crate) struct Backup {
+	// A record pointing to the original, if the backup is persisted in a backup dir. It's declared
+	// first, so it's removed before the backup when both are dropped.
+	record: Option<NamedTempFile>,
 	backup: NamedTempFile,
 	original: PathBuf,
 }
@@ -20,13 +31,155 @@ impl Backup {
 			prefixed_path.parent().expect("The path is a file and is prefixed; qed;");
 		// Create the backup in the same directory as the original, so we can persist the backup
 		let backup = NamedTempFile::new_in(original_parent_dir)?;
-		std::fs::copy(original, &backup)?;
-		Ok(Self { backup, original: original.to_path_buf() })
+		copy_original(original, &backup)?;
+		Ok(Self { record: None, backup, original: original.to_path_buf() })
+	}
+
+	// Creates the backup in a persistent backup dir, together with a record pointing to the
+	// original, so the original can be restored by `recover` if the process dies before the backup
+	// is rolled-back or removed. The backup is flushed to disk before the record is written, so
+	// complete records always point to complete backups.
+	pub(crate) fn new_in(original: &Path, dir: &Path) -> Result<Self, Error> {
+		let backup =
+			tempfile::Builder::new().prefix(PREFIX).suffix(BACKUP_SUFFIX).tempfile_in(dir)?;
+		copy_original(original, &backup)?;
+		backup.as_file().sync_all()?;
+
+		// The record may be read from another current dir.
+		let absolute_original = std::path::absolute(original)?;
+		let backup_name = backup.path().file_name().expect("The backup is a file; qed;");
+		let mut record =
+			tempfile::Builder::new().prefix(PREFIX).suffix(RECORD_SUFFIX).tempfile_in(dir)?;
+		record.write_all(
+			&[
+				backup_name.as_encoded_bytes(),
+				b"\n",
+				absolute_original.as_os_str().as_encoded_bytes(),
+				b"\n",
+			]
+			.concat(),
+		)?;
+		record.as_file().sync_all()?;
+		#[cfg(unix)]
+		std::fs::File::open(dir)?.sync_all()?;
+
+		Ok(Self { record: Some(record), backup, original: original.to_path_buf() })
+	}
+
+	pub(crate) fn original(&self) -> &Path {
+		&self.original
 	}
 
-	pub(crate) fn rollback(self) {
-		self.backup
-            .persist(&self.original)
-            .expect("Generated backups guarantee that both original and backup exist in the same file system, so persisting the tempfile should be possible; qed;");
+	pub(crate) fn path(&self) -> &Path {
+		self.backup.path()
 	}
+
+	// Restores a removed original from the backup, keeping the backup so the original can be
+	// restored again. Restoring fails if the original exists, so it's never overwritten.
+	pub(crate) fn restore(&self) -> Result<(), Error> {
+		std::fs::hard_link(self.backup.path(), &self.original)?;
+		Ok(())
+	}
+
+	// Removes the backup without restoring it.
+	pub(crate) fn close(self) -> Result<(), Error> {
+		self.backup.close()?;
+		Ok(())
+	}
+
+	// Moves the backup over the original. If that isn't possible (e.g. the original has been
+	// replaced by a directory), the backup is deleted anyway, so it never lingers on disk, unless
+	// it's persisted in a backup dir: then it's kept together with its record, so the original
+	// can be restored later by `recover`. The record, if any, is removed once the original is
+	// restored.
+	pub(crate) fn rollback(self) -> Result<(), Error> {
+		let Self { record, backup, original } = self;
+		// Dropping the returned temporary file deletes it.
+		match (backup.persist(&original), record) {
+			(Ok(_), _) => Ok(()),
+			// Persisted backups may live in another file system, so they're copied instead.
+			(Err(err), Some(record)) => match std::fs::copy(err.file.path(), &original) {
+				Ok(_) => Ok(()),
+				Err(copy_err) => {
+					// Keeping them is best effort, there's nothing else to do if it fails.
+					let _ = err.file.keep();
+					let _ = record.keep();
+					Err(copy_err.into())
+				},
+			},
+			(Err(err), None) => Err(err.error.into()),
+		}
+	}
+}
+
+// Copies the original into the backup keeping its modification time, so restoring the backup
+// restores the modification time as well.
+fn copy_original(original: &Path, backup: &NamedTempFile) -> Result<(), Error> {
+	std::fs::copy(original, backup)?;
+	if let Ok(modified) = std::fs::metadata(original).and_then(|metadata| metadata.modified()) {
+		backup.as_file().set_modified(modified)?;
+	}
+	Ok(())
+}
+
+// Restores the originals of the backups left in a backup dir by a commit that couldn't finish,
+// removing the backups and their records. Returns the restored originals, sorted by path. If an
+// original cannot be restored, its backup and record are kept, so recovering can be retried.
+pub(crate) fn recover(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+	let entries = std::fs::read_dir(dir)?
+		.map(|entry| entry.map(|entry| entry.path()))
+		.collect::<std::io::Result<Vec<_>>>()?;
+	let is_leftover = |path: &Path, suffix: &str| {
+		path.file_name().is_some_and(|name| {
+			let name = name.to_string_lossy();
+			name.starts_with(PREFIX) && name.ends_with(suffix)
+		})
+	};
+
+	let mut restored = Vec::new();
+	for record in entries.iter().filter(|path| is_leftover(path, RECORD_SUFFIX)) {
+		let content = std::fs::read(record)?;
+		// Records are complete once they end with a new line. Incomplete records were being
+		// written before their originals were modified, so there's nothing to restore.
+		let parts = content.strip_suffix(b"\n").and_then(|content| {
+			let split = content.iter().position(|byte| *byte == b'\n')?;
+			Some((&content[..split], &content[split + 1..]))
+		});
+		if let Some((backup_name, original)) = parts {
+			let backup = dir.join(path_from_bytes(backup_name)?);
+			let original = path_from_bytes(original)?;
+			if backup.is_file() {
+				if std::fs::rename(&backup, &original).is_err() {
+					std::fs::copy(&backup, &original)?;
+					std::fs::remove_file(&backup)?;
+				}
+				restored.push(original);
+			}
+		}
+		std::fs::remove_file(record)?;
+	}
+
+	// Backups without a record were never used to modify their originals.
+	for backup in entries.iter().filter(|path| is_leftover(path, BACKUP_SUFFIX)) {
+		if backup.exists() {
+			std::fs::remove_file(backup)?;
+		}
+	}
+
+	restored.sort();
+	Ok(restored)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, Error> {
+	use std::os::unix::ffi::OsStrExt;
+	Ok(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+}
+
+// Paths are recorded in WTF-8, which is UTF-8 for every valid Unicode path.
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, Error> {
+	let path = std::str::from_utf8(bytes)
+		.map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+	Ok(PathBuf::from(path))
 }
@@ -13,6 +13,8 @@ fn new_backup_works() {
 	let backup = Backup::new(file_path).expect("The backup should be created; qed;");
 
 	assert_eq!(backup.original, file_path);
+	// The backup lives next to the original
+	assert_eq!(backup.path().parent(), file_path.parent());
 	assert_eq!(
 		std::fs::read_to_string(backup.backup).expect("The backup should be readable; qed;"),
 		ORIGINAL_BUILDER_FILE_CONTENT
@@ -53,10 +55,163 @@ fn backup_rollback_works() {
 		MODIFIED_BUILDER_FILE_CONTENT
 	);
 
-	backup.rollback();
+	assert!(backup.rollback().is_ok());
 
 	assert_eq!(
 		std::fs::read_to_string(file_path).expect("File should be readable; qed;"),
 		ORIGINAL_BUILDER_FILE_CONTENT
 	);
 }
+
+#[test]
+fn backup_rollback_restores_the_modification_time() {
+	let builder = TestBuilder::new(Some(1));
+	let file_path = builder.existing_files()[0];
+	let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+	std::fs::File::options()
+		.write(true)
+		.open(file_path)
+		.and_then(|file| file.set_modified(modified))
+		.expect("The file should be writable; qed;");
+	let backup = Backup::new(file_path).expect("The backup should be created; qed;");
+
+	std::fs::write(file_path, MODIFIED_BUILDER_FILE_CONTENT)
+		.expect("The file path should be writable; qed;");
+
+	assert!(backup.rollback().is_ok());
+
+	assert_eq!(
+		std::fs::metadata(file_path)
+			.and_then(|metadata| metadata.modified())
+			.expect("File should have a modification time; qed;"),
+		modified
+	);
+}
+
+#[test]
+fn backup_restore_works() {
+	let builder = TestBuilder::new(Some(1));
+	let file_path = builder.existing_files()[0];
+	let backup = Backup::new(file_path).expect("The backup should be created; qed;");
+
+	// The original cannot be overwritten
+	assert!(
+		matches!(backup.restore(), Err(Error::IO(err)) if err.kind() == ErrorKind::AlreadyExists)
+	);
+
+	std::fs::remove_file(file_path).expect("The file exists; qed;");
+	assert!(backup.restore().is_ok());
+
+	assert_eq!(
+		std::fs::read_to_string(file_path).expect("File should be readable; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+	// The backup is kept
+	assert!(backup.path().exists());
+}
+
+#[test]
+fn backup_rollback_deletes_the_backup_if_it_cannot_be_persisted() {
+	let builder = TestBuilder::new(Some(1));
+	let file_path = builder.existing_files()[0];
+	let backup = Backup::new(file_path).expect("The backup should be created; qed;");
+	let backup_path = backup.path().to_path_buf();
+
+	// A file cannot be moved over a dir
+	std::fs::remove_file(file_path).expect("The file exists; qed;");
+	std::fs::create_dir(file_path).expect("The dir can be created; qed;");
+
+	assert!(matches!(backup.rollback(), Err(Error::IO(_))));
+	assert!(!backup_path.exists());
+}
+
+#[test]
+fn backup_rollback_keeps_the_persisted_backup_if_it_cannot_be_restored() {
+	let builder = TestBuilder::new(Some(1));
+	let file_path = builder.existing_files()[0];
+	let dir = tempfile::tempdir().expect("The dir should be created; qed;");
+	let backup = Backup::new_in(file_path, dir.path()).expect("The backup should be created; qed;");
+
+	// A file cannot be moved nor copied over a dir
+	std::fs::remove_file(file_path).expect("The file exists; qed;");
+	std::fs::create_dir(file_path).expect("The dir can be created; qed;");
+
+	assert!(matches!(backup.rollback(), Err(Error::IO(_))));
+	// The backup and its record are kept, so the original can be recovered later
+	assert_eq!(std::fs::read_dir(dir.path()).expect("The dir exists; qed;").count(), 2);
+	std::fs::remove_dir(file_path).expect("The dir exists; qed;");
+	assert_eq!(recover(dir.path()).expect("The backups can be recovered; qed;"), [file_path]);
+	assert_eq!(
+		std::fs::read_to_string(file_path).expect("File should be readable; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+}
+
+#[test]
+fn new_in_backup_works() {
+	let builder = TestBuilder::new(Some(1));
+	let file_path = builder.existing_files()[0];
+	let dir = tempfile::tempdir().expect("The dir should be created; qed;");
+	let backup = Backup::new_in(file_path, dir.path()).expect("The backup should be created; qed;");
+
+	assert_eq!(backup.path().parent(), Some(dir.path()));
+	assert_eq!(
+		std::fs::read_to_string(backup.path()).expect("The backup should be readable; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+	let record = backup.record.as_ref().expect("The backup is persisted; qed;");
+	assert_eq!(
+		std::fs::read_to_string(record.path()).expect("The record should be readable; qed;"),
+		format!(
+			"{}\n{}\n",
+			backup.path().file_name().expect("The backup is a file; qed;").to_string_lossy(),
+			file_path.display()
+		)
+	);
+
+	// Both the backup and the record are removed when dropped
+	drop(backup);
+	assert_eq!(std::fs::read_dir(dir.path()).expect("The dir exists; qed;").count(), 0);
+}
+
+#[test]
+fn recover_restores_leftover_backups() {
+	let builder = TestBuilder::new(Some(1));
+	let file_path = builder.existing_files()[0];
+	let dir = tempfile::tempdir().expect("The dir should be created; qed;");
+	let backup = Backup::new_in(file_path, dir.path()).expect("The backup should be created; qed;");
+	std::fs::write(file_path, MODIFIED_BUILDER_FILE_CONTENT)
+		.expect("The file path should be writable; qed;");
+	// Simulate that the process dies before the backup is rolled-back or removed
+	std::mem::forget(backup);
+
+	assert_eq!(recover(dir.path()).expect("The backups can be recovered; qed;"), [file_path]);
+	assert_eq!(
+		std::fs::read_to_string(file_path).expect("File should be readable; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+	assert_eq!(std::fs::read_dir(dir.path()).expect("The dir exists; qed;").count(), 0);
+
+	// Recovering a clean dir does nothing
+	assert!(recover(dir.path()).expect("The dir can be read; qed;").is_empty());
+}
+
+#[test]
+fn recover_ignores_incomplete_records() {
+	let builder = TestBuilder::new(Some(1));
+	let file_path = builder.existing_files()[0];
+	let dir = tempfile::tempdir().expect("The dir should be created; qed;");
+	let backup = Backup::new_in(file_path, dir.path()).expect("The backup should be created; qed;");
+	let record = backup.record.as_ref().expect("The backup is persisted; qed;");
+	std::fs::write(record.path(), "truncated").expect("The record should be writable; qed;");
+	std::fs::write(file_path, MODIFIED_BUILDER_FILE_CONTENT)
+		.expect("The file path should be writable; qed;");
+	std::mem::forget(backup);
+
+	assert!(recover(dir.path()).expect("The dir can be read; qed;").is_empty());
+	assert_eq!(
+		std::fs::read_to_string(file_path).expect("File should be readable; qed;"),
+		MODIFIED_BUILDER_FILE_CONTENT
+	);
+	assert_eq!(std::fs::read_dir(dir.path()).expect("The dir exists; qed;").count(), 0);
+}
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::{rollback::backup::Backup, Error};
+use std::path::Path;
+
+/// A guard backing up a single file, so it can be modified in place without staging the change
+/// in a whole [`Rollback`](crate::Rollback). The backup is created next to the file when the guard
+/// is created, and the file is restored from it when the guard is dropped, unless the guard is
+/// committed first with [`BackupGuard::commit`].
+///
+/// Restoring upon drop is best effort, as failures cannot be reported from [`Drop`]. Use
+/// [`BackupGuard::restore`] to handle them.
+#[derive(Debug)]
+pub struct BackupGuard {
+	// The backup, until the guard is committed or restored.
+	backup: Option<Backup>,
+}
+
+impl BackupGuard {
+	/// Backs up an existing file, returning the guard restoring it.
+	/// ## Errors:
+	/// - [`Error::NotAFile`] if the path doesn't point to an existing file.
+	/// - If the backup cannot be created.
+	pub fn new<P: AsRef<Path>>(original: P) -> Result<Self, Error> {
+		let original = original.as_ref();
+		if !original.is_file() {
+			return Err(Error::NotAFile(format!("{}", original.display())));
+		}
+		Ok(Self { backup: Some(Backup::new(original)?) })
+	}
+
+	/// Get the path of the backed up file.
+	pub fn original(&self) -> &Path {
+		self.backup().original()
+	}
+
+	/// Get the path of the backup, which holds the content of the file when the guard was
+	/// created.
+	pub fn backup_path(&self) -> &Path {
+		self.backup().path()
+	}
+
+	/// Consume the guard keeping the file as it is, removing the backup.
+	/// ## Errors:
+	/// - If the backup cannot be removed.
+	pub fn commit(mut self) -> Result<(), Error> {
+		self.take_backup().close()
+	}
+
+	/// Consume the guard restoring the file from the backup, as dropping it does, but reporting
+	/// failures.
+	/// ## Errors:
+	/// - If the backup cannot be moved over the file, e.g. because the file has been replaced by a
+	///   directory. The backup is removed anyway.
+	pub fn restore(mut self) -> Result<(), Error> {
+		self.take_backup().rollback()
+	}
+
+	fn backup(&self) -> &Backup {
+		self.backup
+			.as_ref()
+			.expect("The backup is only taken when consuming the guard; qed;")
+	}
+
+	fn take_backup(&mut self) -> Backup {
+		self.backup
+			.take()
+			.expect("The backup is only taken when consuming the guard; qed;")
+	}
+}
+
+impl Drop for BackupGuard {
+	fn drop(&mut self) {
+		if let Some(backup) = self.backup.take() {
+			// Failures cannot be reported while dropping, see `BackupGuard::restore`.
+			let _ = backup.rollback();
+		}
+	}
+}
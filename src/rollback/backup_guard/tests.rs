@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::{TestBuilder, ORIGINAL_BUILDER_FILE_CONTENT};
+
+#[test]
+fn new_works() {
+	let builder = TestBuilder::new(Some(1));
+	let path = builder.existing_files()[0];
+
+	let guard = BackupGuard::new(path).expect("The file can be backed up; qed;");
+
+	assert_eq!(guard.original(), path);
+	assert_eq!(
+		std::fs::read_to_string(guard.backup_path()).expect("The backup exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+}
+
+#[test]
+fn new_fails_if_path_isnt_a_file() {
+	let builder = TestBuilder::new(Some(0));
+
+	assert!(matches!(
+		BackupGuard::new(builder.get_temp_dir_path()),
+		Err(Error::NotAFile(path)) if path == format!("{}", builder.get_temp_dir_path().display())
+	));
+}
+
+#[test]
+fn dropping_the_guard_restores_the_file() {
+	let builder = TestBuilder::new(Some(1));
+	let path = builder.existing_files()[0];
+	let guard = BackupGuard::new(path).expect("The file can be backed up; qed;");
+	let backup_path = guard.backup_path().to_path_buf();
+	std::fs::write(path, "modified").expect("The file is writable; qed;");
+
+	drop(guard);
+
+	assert_eq!(
+		std::fs::read_to_string(path).expect("The file exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+	assert!(!backup_path.exists());
+}
+
+#[test]
+fn restore_works_if_the_file_has_been_removed() {
+	let builder = TestBuilder::new(Some(1));
+	let path = builder.existing_files()[0];
+	let guard = BackupGuard::new(path).expect("The file can be backed up; qed;");
+	std::fs::remove_file(path).expect("The file can be removed; qed;");
+
+	assert!(guard.restore().is_ok());
+	assert_eq!(
+		std::fs::read_to_string(path).expect("The file exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+}
+
+#[test]
+fn restore_fails_if_the_file_has_been_replaced_by_a_dir() {
+	let builder = TestBuilder::new(Some(1));
+	let path = builder.existing_files()[0];
+	let guard = BackupGuard::new(path).expect("The file can be backed up; qed;");
+	let backup_path = guard.backup_path().to_path_buf();
+	std::fs::remove_file(path).expect("The file can be removed; qed;");
+	std::fs::create_dir(path).expect("The dir can be created; qed;");
+	std::fs::write(path.join("file.txt"), "content").expect("The file can be created; qed;");
+
+	assert!(guard.restore().is_err());
+	assert!(path.is_dir());
+	assert!(!backup_path.exists());
+}
+
+#[test]
+fn commit_keeps_the_file_and_removes_the_backup() {
+	let builder = TestBuilder::new(Some(1));
+	let path = builder.existing_files()[0];
+	let guard = BackupGuard::new(path).expect("The file can be backed up; qed;");
+	let backup_path = guard.backup_path().to_path_buf();
+	std::fs::write(path, "modified").expect("The file is writable; qed;");
+
+	assert!(guard.commit().is_ok());
+	assert_eq!(std::fs::read_to_string(path).expect("The file exists; qed;"), "modified");
+	assert!(!backup_path.exists());
+}
@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::Error;
+use std::{
+	borrow::Cow,
+	path::{Component, Path},
+};
+
+// Interprets a path relative to the base dir, so relative paths are joined onto it. Absolute
+// paths, and every path if there's no base dir, are returned untouched.
+pub(crate) fn join<'p>(base: Option<&Path>, path: &'p Path) -> Cow<'p, Path> {
+	match base {
+		Some(base) if path.is_relative() => Cow::Owned(base.join(path)),
+		_ => Cow::Borrowed(path),
+	}
+}
+
+// Like `join`, but rejects the paths that may land outside the base dir: those containing `..`
+// components and absolute paths not under the base dir.
+pub(crate) fn anchor<'p>(base: Option<&Path>, path: &'p Path) -> Result<Cow<'p, Path>, Error> {
+	match base {
+		Some(base)
+			if path.components().any(|component| component == Component::ParentDir) ||
+				(path.is_absolute() && !path.starts_with(base)) =>
+			Err(Error::OutsideBaseDir(path.to_string_lossy().to_string())),
+		_ => Ok(join(base, path)),
+	}
+}
@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+
+#[test]
+fn join_works_with_relative_paths() {
+	assert_eq!(
+		join(Some(Path::new("/tmp/base")), Path::new("foo/bar")),
+		Path::new("/tmp/base/foo/bar")
+	);
+}
+
+#[test]
+fn join_keeps_absolute_paths() {
+	let path = Path::new("/etc/foo");
+
+	assert!(
+		matches!(join(Some(Path::new("/tmp/base")), path), Cow::Borrowed(joined) if joined == path)
+	);
+}
+
+#[test]
+fn join_keeps_paths_without_base() {
+	let path = Path::new("foo");
+
+	assert!(matches!(join(None, path), Cow::Borrowed(joined) if joined == path));
+}
+
+#[test]
+fn anchor_works_with_paths_inside_base() {
+	let base = Path::new("/tmp/base");
+
+	assert_eq!(anchor(Some(base), Path::new("foo")).unwrap(), Path::new("/tmp/base/foo"));
+	assert_eq!(anchor(Some(base), Path::new("/tmp/base/foo")).unwrap(), Path::new("/tmp/base/foo"));
+}
+
+#[test]
+fn anchor_rejects_parent_dir_components() {
+	let base = Path::new("/tmp/base");
+
+	assert!(matches!(
+		anchor(Some(base), Path::new("../foo")),
+		Err(Error::OutsideBaseDir(path)) if path == "../foo"
+	));
+	assert!(matches!(
+		anchor(Some(base), Path::new("/tmp/base/../foo")),
+		Err(Error::OutsideBaseDir(path)) if path == "/tmp/base/../foo"
+	));
+}
+
+#[test]
+fn anchor_rejects_absolute_paths_outside_base() {
+	assert!(matches!(
+		anchor(Some(Path::new("/tmp/base")), Path::new("/tmp/based/foo")),
+		Err(Error::OutsideBaseDir(path)) if path == "/tmp/based/foo"
+	));
+}
+
+#[test]
+fn anchor_keeps_paths_without_base() {
+	let path = Path::new("../foo");
+
+	assert!(matches!(anchor(None, path), Ok(Cow::Borrowed(anchored)) if anchored == path));
+}
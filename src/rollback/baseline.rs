@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::Error;
+use sha2::{Digest, Sha256};
+use std::{
+	fmt::Write,
+	fs::File,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+};
+
+// Hex representation of a digest.
+fn to_hex(digest: &[u8]) -> String {
+	digest.iter().fold(String::with_capacity(2 * digest.len()), |mut hex, byte| {
+		// Writing to a String cannot fail; qed;
+		let _ = write!(hex, "{:02x}", byte);
+		hex
+	})
+}
+
+// Hex representation of the SHA-256 digest of a file content. The file is streamed into the
+// hasher, so big files aren't loaded into memory.
+pub(crate) fn content_hash(path: &Path) -> Result<String, Error> {
+	let mut hasher = Sha256::new();
+	std::io::copy(&mut File::open(path)?, &mut hasher)?;
+	Ok(to_hex(&hasher.finalize()))
+}
+
+// The baseline entry for a file is named after the hash of its canonical path, so different
+// representations of the same file share the entry.
+fn entry_path(baseline_dir: &Path, path: &Path) -> Result<PathBuf, Error> {
+	let canonical = path.canonicalize()?;
+	Ok(baseline_dir.join(to_hex(&Sha256::digest(canonical.as_os_str().as_encoded_bytes()))))
+}
+
+// Checks if the content of the file differs from the one recorded in the baseline. Files without
+// an entry in the baseline are considered as changed.
+pub(crate) fn differs(baseline_dir: &Path, path: &Path) -> Result<bool, Error> {
+	let entry = entry_path(baseline_dir, path)?;
+	if !entry.is_file() {
+		return Ok(true);
+	}
+	Ok(std::fs::read_to_string(entry)? != content_hash(path)?)
+}
+
+// A baseline entry as it was before recording a file, so it can be restored if the commit is
+// rolled back.
+pub(crate) struct PreviousEntry {
+	entry: PathBuf,
+	// The recorded hash, or `None` if the entry didn't exist.
+	hash: Option<String>,
+}
+
+// Records the current content of the file in the baseline. The entry as it was before is pushed
+// into `previous` before it's overwritten.
+pub(crate) fn record(
+	baseline_dir: &Path,
+	path: &Path,
+	previous: &mut Vec<PreviousEntry>,
+) -> Result<(), Error> {
+	std::fs::create_dir_all(baseline_dir)?;
+	let entry = entry_path(baseline_dir, path)?;
+	let hash = content_hash(path)?;
+	let previous_hash = match std::fs::read_to_string(&entry) {
+		Ok(previous_hash) => Some(previous_hash),
+		Err(err) if err.kind() == ErrorKind::NotFound => None,
+		Err(err) => return Err(err.into()),
+	};
+	previous.push(PreviousEntry { entry: entry.clone(), hash: previous_hash });
+	std::fs::write(entry, hash)?;
+	Ok(())
+}
+
+// Restores the baseline entries as they were before being recorded. They're restored in reverse,
+// so an entry recorded several times ends up as it was before the first time. Don't need to
+// handle the results, as there's nothing else to do if restoring an entry fails.
+pub(crate) fn restore(previous: &[PreviousEntry]) {
+	for PreviousEntry { entry, hash } in previous.iter().rev() {
+		let _ = match hash {
+			Some(hash) => std::fs::write(entry, hash),
+			None => std::fs::remove_file(entry),
+		};
+	}
+}
@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::{
+	TestBuilder, MODIFIED_BUILDER_FILE_CONTENT, ORIGINAL_BUILDER_FILE_CONTENT,
+};
+
+#[test]
+fn content_hash_works() {
+	let builder = TestBuilder::new(Some(2));
+	let files = builder.existing_files();
+
+	// Same content => same hash
+	assert_eq!(
+		content_hash(files[0]).expect("The file exists; qed;"),
+		content_hash(files[1]).expect("The file exists; qed;")
+	);
+
+	std::fs::write(files[1], MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+	// Different content => different hash
+	assert_ne!(
+		content_hash(files[0]).expect("The file exists; qed;"),
+		content_hash(files[1]).expect("The file exists; qed;")
+	);
+}
+
+#[test]
+fn differs_is_true_if_the_file_isnt_in_the_baseline() {
+	let builder = TestBuilder::new(Some(1));
+	let baseline_dir = builder.get_temp_dir_path().join("baseline");
+
+	assert!(differs(&baseline_dir, builder.existing_files()[0]).expect("The file exists; qed;"));
+}
+
+#[test]
+fn record_works() {
+	let builder = TestBuilder::new(Some(1));
+	let file = builder.existing_files()[0];
+	let baseline_dir = builder.get_temp_dir_path().join("baseline");
+
+	// The baseline dir is created if needed
+	assert!(record(&baseline_dir, file, &mut Vec::new()).is_ok());
+	assert!(baseline_dir.is_dir());
+
+	assert!(!differs(&baseline_dir, file).expect("The file exists; qed;"));
+
+	std::fs::write(file, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+	assert!(differs(&baseline_dir, file).expect("The file exists; qed;"));
+}
+
+#[test]
+fn record_fails_if_the_file_doesnt_exist() {
+	let builder = TestBuilder::new(Some(1));
+	let baseline_dir = builder.get_temp_dir_path().join("baseline");
+
+	assert!(matches!(
+		record(&baseline_dir, builder.new_files()[0], &mut Vec::new()),
+		Err(Error::IO(err)) if err.kind() == std::io::ErrorKind::NotFound
+	));
+}
+
+#[test]
+fn restore_works() {
+	let builder = TestBuilder::new(Some(2));
+	let files = builder.existing_files();
+	let baseline_dir = builder.get_temp_dir_path().join("baseline");
+	assert!(record(&baseline_dir, files[0], &mut Vec::new()).is_ok());
+	std::fs::write(files[0], MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+	let mut previous = Vec::new();
+	assert!(record(&baseline_dir, files[0], &mut previous).is_ok());
+	assert!(record(&baseline_dir, files[0], &mut previous).is_ok());
+	assert!(record(&baseline_dir, files[1], &mut previous).is_ok());
+	assert!(!differs(&baseline_dir, files[0]).expect("The file exists; qed;"));
+	assert!(!differs(&baseline_dir, files[1]).expect("The file exists; qed;"));
+
+	restore(&previous);
+
+	// The first file has its first recorded hash back, while the second one isn't recorded
+	assert!(differs(&baseline_dir, files[0]).expect("The file exists; qed;"));
+	std::fs::write(files[0], ORIGINAL_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+	assert!(!differs(&baseline_dir, files[0]).expect("The file exists; qed;"));
+	assert!(differs(&baseline_dir, files[1]).expect("The file exists; qed;"));
+}
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::Rollback;
+use std::path::PathBuf;
+
+/// A builder configuring a [`Rollback`] instance through named methods, obtained through
+/// [`Rollback::builder`]. Every option left unset keeps the default of [`Rollback::default`].
+///
+/// The built instance can be configured further with the `with_*` methods of [`Rollback`].
+#[derive(Debug, Default, Clone)]
+pub struct RollbackBuilder<'a> {
+	note_capacity: usize,
+	new_files_capacity: usize,
+	new_dirs_capacity: usize,
+	temp_dir: Option<PathBuf>,
+	temp_file_prefix: Option<&'a str>,
+	temp_file_target_names: bool,
+	max_threads: Option<usize>,
+}
+
+impl<'a> RollbackBuilder<'a> {
+	/// Pre allocates memory for `capacity` noted files.
+	pub fn note_capacity(mut self, capacity: usize) -> Self {
+		self.note_capacity = capacity;
+		self
+	}
+
+	/// Pre allocates memory for `capacity` new files.
+	pub fn new_files_capacity(mut self, capacity: usize) -> Self {
+		self.new_files_capacity = capacity;
+		self
+	}
+
+	/// Pre allocates memory for `capacity` new directories.
+	pub fn new_dirs_capacity(mut self, capacity: usize) -> Self {
+		self.new_dirs_capacity = capacity;
+		self
+	}
+
+	/// The same as [`Rollback::with_temp_dir`].
+	pub fn temp_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+		self.temp_dir = Some(dir.into());
+		self
+	}
+
+	/// The same as [`Rollback::with_temp_file_prefix`].
+	pub fn temp_file_prefix(mut self, prefix: &'a str) -> Self {
+		self.temp_file_prefix = Some(prefix);
+		self
+	}
+
+	/// The same as [`Rollback::with_temp_file_target_names`].
+	pub fn temp_file_target_names(mut self) -> Self {
+		self.temp_file_target_names = true;
+		self
+	}
+
+	/// The same as [`Rollback::with_max_threads`].
+	pub fn max_threads(mut self, max_threads: usize) -> Self {
+		self.max_threads = Some(max_threads);
+		self
+	}
+
+	/// Builds the configured [`Rollback`] instance.
+	pub fn build(self) -> Rollback<'a> {
+		let mut rollback = Rollback::with_capacity(
+			self.note_capacity,
+			self.new_files_capacity,
+			self.new_dirs_capacity,
+		);
+		rollback.temp_dir = self.temp_dir;
+		rollback.temp_file_prefix = self.temp_file_prefix;
+		rollback.temp_file_target_names = self.temp_file_target_names;
+		rollback.max_threads = self.max_threads;
+		rollback
+	}
+}
@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use std::path::Path;
+
+#[test]
+fn build_without_options_works() {
+	let rollback = RollbackBuilder::default().build();
+	assert_eq!(rollback.noted.capacity(), 0);
+	assert_eq!(rollback.new_files.capacity(), 0);
+	assert_eq!(rollback.new_dirs.capacity(), 0);
+	assert!(rollback.temp_dir.is_none());
+	assert!(rollback.temp_file_prefix.is_none());
+	assert!(!rollback.temp_file_target_names);
+	assert!(rollback.max_threads.is_none());
+}
+
+#[test]
+fn build_with_options_works() {
+	let temp_dir = Path::new("temp_dir");
+	let rollback = Rollback::builder()
+		.note_capacity(1)
+		.new_files_capacity(2)
+		.new_dirs_capacity(3)
+		.temp_dir(temp_dir)
+		.temp_file_prefix("prefix")
+		.temp_file_target_names()
+		.max_threads(4)
+		.build();
+	assert!(rollback.noted.capacity() >= 1);
+	assert!(rollback.new_files.capacity() >= 2);
+	assert!(rollback.new_dirs.capacity() >= 3);
+	assert_eq!(rollback.temp_dir.as_deref(), Some(temp_dir));
+	assert_eq!(rollback.temp_file_prefix, Some("prefix"));
+	assert!(rollback.temp_file_target_names);
+	assert_eq!(rollback.max_threads, Some(4));
+}
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::Error;
+use std::{
+	io,
+	path::{Component, Path, PathBuf},
+};
+
+// Checks that a path resolves to a location under the root, so staging it cannot touch anything
+// outside of it. The path is reported as `reported` if it escapes the root.
+pub(crate) fn check(root: &Path, path: &Path, reported: &Path) -> Result<(), Error> {
+	let root = root.canonicalize().or_else(|_| std::path::absolute(root))?;
+	if resolve(path)?.starts_with(&root) {
+		Ok(())
+	} else {
+		Err(Error::OutsideRoot(format!("{}", reported.display())))
+	}
+}
+
+// The maximum number of symlinks followed while resolving a path, as the usual OS limit.
+const MAX_SYMLINKS: usize = 40;
+
+// Resolves where a path lands in the file system, as `resolve_parent` does. If the path is a
+// symlink, it's followed, even if it's dangling, as committing it may write through the link.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+	let mut resolved = resolve_parent(path)?;
+	for _ in 0..MAX_SYMLINKS {
+		let Ok(target) = std::fs::read_link(&resolved) else {
+			return Ok(resolved);
+		};
+		let parent = resolved.parent().expect("A symlink has a parent dir; qed;");
+		resolved = resolve_parent(&parent.join(target))?;
+	}
+	Err(io::Error::other("too many levels of symbolic links"))
+}
+
+// Resolves where a path lands in the file system: its parent dir is canonicalized up to its
+// deepest existing ancestor, and the missing components are applied on top. The final component
+// isn't followed.
+fn resolve_parent(path: &Path) -> io::Result<PathBuf> {
+	let path = std::path::absolute(path)?;
+	let (dir, name) = match path.file_name() {
+		Some(name) =>
+			(path.parent().expect("A path with a file name has a parent; qed;"), Some(name)),
+		None => (path.as_path(), None),
+	};
+	let existing = dir
+		.ancestors()
+		.find(|ancestor| ancestor.exists())
+		.expect("The file system root exists; qed;");
+	let mut resolved = existing.canonicalize()?;
+	for component in dir.strip_prefix(existing).expect("An ancestor is a prefix; qed;").components()
+	{
+		match component {
+			Component::ParentDir => {
+				resolved.pop();
+			},
+			Component::CurDir => (),
+			component => resolved.push(component),
+		}
+	}
+	if let Some(name) = name {
+		resolved.push(name);
+	}
+	Ok(resolved)
+}
@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use tempfile::TempDir;
+
+#[test]
+fn check_accepts_paths_under_root() {
+	let root = TempDir::new().expect("The dir can be created; qed;");
+	std::fs::create_dir(root.path().join("dir")).expect("The dir can be created; qed;");
+
+	assert!(check(root.path(), &root.path().join("dir/file.txt"), Path::new("file.txt")).is_ok());
+	assert!(check(root.path(), &root.path().join("missing/dir/file.txt"), Path::new("file.txt"))
+		.is_ok());
+	assert!(check(root.path(), &root.path().join("dir/../file.txt"), Path::new("file.txt")).is_ok());
+}
+
+#[test]
+fn check_rejects_paths_escaping_root_with_parent_dirs() {
+	let root = TempDir::new().expect("The dir can be created; qed;");
+	let path = root.path().join("../escaped.txt");
+
+	assert!(matches!(
+		check(root.path(), &path, &path),
+		Err(Error::OutsideRoot(reported)) if reported == path.to_string_lossy()
+	));
+	assert!(matches!(
+		check(root.path(), &root.path().join("missing/../../escaped"), &path),
+		Err(Error::OutsideRoot(_))
+	));
+}
+
+#[test]
+fn check_rejects_paths_escaping_root_through_symlinked_dirs() {
+	let root = TempDir::new().expect("The dir can be created; qed;");
+	let outside = TempDir::new().expect("The dir can be created; qed;");
+	let link = root.path().join("link");
+	std::os::unix::fs::symlink(outside.path(), &link).expect("The symlink can be created; qed;");
+
+	assert!(matches!(
+		check(root.path(), &link.join("file.txt"), &link),
+		Err(Error::OutsideRoot(_))
+	));
+}
+
+#[test]
+fn resolve_keeps_final_component() {
+	let root = TempDir::new().expect("The dir can be created; qed;");
+	let root_path = root.path().canonicalize().expect("The dir exists; qed;");
+
+	assert_eq!(
+		resolve(&root.path().join("missing/./file.txt")).expect("The path resolves; qed;"),
+		root_path.join("missing/file.txt")
+	);
+	assert_eq!(resolve(&root.path().join("dir/..")).expect("The path resolves; qed;"), root_path);
+}
+
+#[test]
+fn check_rejects_symlinks_leading_outside_root() {
+	let root = TempDir::new().expect("The dir can be created; qed;");
+	let outside = TempDir::new().expect("The dir can be created; qed;");
+	let target = outside.path().join("file.txt");
+	std::fs::write(&target, "content").expect("The file can be created; qed;");
+	let link = root.path().join("link");
+	let dangling = root.path().join("dangling");
+	let chained = root.path().join("chained");
+	std::os::unix::fs::symlink(&target, &link).expect("The symlink can be created; qed;");
+	std::os::unix::fs::symlink(outside.path().join("missing"), &dangling)
+		.expect("The symlink can be created; qed;");
+	std::os::unix::fs::symlink("link", &chained).expect("The symlink can be created; qed;");
+
+	assert!(matches!(check(root.path(), &link, &link), Err(Error::OutsideRoot(_))));
+	assert!(matches!(check(root.path(), &dangling, &dangling), Err(Error::OutsideRoot(_))));
+	assert!(matches!(check(root.path(), &chained, &chained), Err(Error::OutsideRoot(_))));
+}
+
+#[test]
+fn check_accepts_symlinks_inside_root() {
+	let root = TempDir::new().expect("The dir can be created; qed;");
+	let target = root.path().join("file.txt");
+	std::fs::write(&target, "content").expect("The file can be created; qed;");
+	let link = root.path().join("link");
+	std::os::unix::fs::symlink("file.txt", &link).expect("The symlink can be created; qed;");
+
+	assert!(check(root.path(), &link, &link).is_ok());
+}
+
+#[test]
+fn resolve_fails_with_symlink_loops() {
+	let root = TempDir::new().expect("The dir can be created; qed;");
+	let link = root.path().join("link");
+	std::os::unix::fs::symlink("link", &link).expect("The symlink can be created; qed;");
+
+	assert!(resolve(&link).is_err());
+}
@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::Error;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+// A backup of a whole directory tree, so the directory can be restored after removing it.
+#[derive(Debug)]
+pub(crate) struct DirBackup {
+	backup: TempDir,
+	original: PathBuf,
+}
+
+impl DirBackup {
+	pub(crate) fn new(original: &Path) -> Result<Self, Error> {
+		// The backup is restored by copying it, so it can live in the default temp dir. Keeping it
+		// out of the original parent dir also prevents backing it up if that dir is removed too.
+		let backup = tempfile::Builder::new().prefix(".fs_rollback-backup").tempdir()?;
+		copy_tree(original, backup.path())?;
+		Ok(Self { backup, original: original.to_path_buf() })
+	}
+
+	pub(crate) fn path(&self) -> &Path {
+		self.backup.path()
+	}
+
+	// Removes the backup without restoring it.
+	pub(crate) fn close(self) -> Result<(), Error> {
+		self.backup.close()?;
+		Ok(())
+	}
+
+	// Restores the entries of the original tree that don't exist, so a partially removed tree is
+	// completed. Existing entries are never overwritten, and the backup is kept so the original
+	// can be restored again.
+	pub(crate) fn restore(&self) -> Result<(), Error> {
+		copy_tree(self.backup.path(), &self.original)
+	}
+
+	// Restores the original tree exactly as it was backed up: the entries added afterwards are
+	// removed, and the rest are replaced by their backups. The backup is kept so the original can
+	// be restored again.
+	pub(crate) fn restore_exactly(&self) -> Result<(), Error> {
+		mirror_tree(self.backup.path(), &self.original)
+	}
+}
+
+// Copies the tree rooted at `from` into `to`, skipping the entries that already exist. The
+// permissions of each dir are copied after its content, as they may forbid writing into it.
+fn copy_tree(from: &Path, to: &Path) -> Result<(), Error> {
+	if to.symlink_metadata().is_err() {
+		std::fs::create_dir(to)?;
+	}
+
+	for entry in std::fs::read_dir(from)? {
+		let entry = entry?;
+		let target = to.join(entry.file_name());
+		let file_type = entry.file_type()?;
+		if file_type.is_dir() {
+			copy_tree(&entry.path(), &target)?;
+		} else if target.symlink_metadata().is_ok() {
+			continue;
+		} else if file_type.is_file() {
+			std::fs::copy(entry.path(), &target)?;
+		} else if file_type.is_symlink() {
+			copy_symlink(&entry.path(), &target)?;
+		} else {
+			return Err(Error::NotAFile(format!("{}", entry.path().display())));
+		}
+	}
+
+	std::fs::set_permissions(to, std::fs::metadata(from)?.permissions())?;
+	Ok(())
+}
+
+// Makes the tree rooted at `to` match the tree rooted at `from`. The entries of `to` missing in
+// `from` are removed, while the rest of entries are replaced by their copies in `from`, except
+// dirs, which are mirrored recursively. The permissions of each dir are copied after its content.
+fn mirror_tree(from: &Path, to: &Path) -> Result<(), Error> {
+	match to.symlink_metadata() {
+		Ok(metadata) if metadata.is_dir() =>
+			for entry in std::fs::read_dir(to)? {
+				let entry = entry?;
+				if from.join(entry.file_name()).symlink_metadata().is_err() {
+					remove_item(&entry.path())?;
+				}
+			},
+		Ok(_) => {
+			std::fs::remove_file(to)?;
+			std::fs::create_dir(to)?;
+		},
+		Err(_) => std::fs::create_dir(to)?,
+	}
+
+	for entry in std::fs::read_dir(from)? {
+		let entry = entry?;
+		let target = to.join(entry.file_name());
+		let file_type = entry.file_type()?;
+		if file_type.is_dir() {
+			mirror_tree(&entry.path(), &target)?;
+			continue;
+		} else if target.symlink_metadata().is_ok() {
+			remove_item(&target)?;
+		}
+
+		if file_type.is_file() {
+			std::fs::copy(entry.path(), &target)?;
+		} else if file_type.is_symlink() {
+			copy_symlink(&entry.path(), &target)?;
+		} else {
+			return Err(Error::NotAFile(format!("{}", entry.path().display())));
+		}
+	}
+
+	std::fs::set_permissions(to, std::fs::metadata(from)?.permissions())?;
+	Ok(())
+}
+
+// Removes an item of any kind. Symlinks are removed themselves, not their targets.
+fn remove_item(path: &Path) -> Result<(), Error> {
+	if path.symlink_metadata()?.is_dir() {
+		std::fs::remove_dir_all(path)?;
+	} else {
+		std::fs::remove_file(path)?;
+	}
+	Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> Result<(), Error> {
+	std::os::unix::fs::symlink(std::fs::read_link(from)?, to)?;
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, _to: &Path) -> Result<(), Error> {
+	Err(Error::NotAFile(format!("{}", from.display())))
+}
@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::{TestBuilder, ORIGINAL_BUILDER_FILE_CONTENT};
+use std::os::unix::fs::PermissionsExt;
+
+// Creates a tree with a nested dir, a file, an empty dir and a symlink inside the builder dir.
+fn create_tree(builder: &TestBuilder) -> PathBuf {
+	let dir = builder.get_temp_dir_path().join("tree");
+	std::fs::create_dir_all(dir.join("nested").join("empty"))
+		.expect("The dirs can be created; qed;");
+	std::fs::write(dir.join("nested").join("file.txt"), ORIGINAL_BUILDER_FILE_CONTENT)
+		.expect("The file can be created; qed;");
+	std::os::unix::fs::symlink("nested/file.txt", dir.join("link"))
+		.expect("The symlink can be created; qed;");
+	std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o750))
+		.expect("The dir exists; qed;");
+	dir
+}
+
+fn assert_is_tree(dir: &Path) {
+	assert_eq!(
+		std::fs::read_to_string(dir.join("nested").join("file.txt"))
+			.expect("The file exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+	assert!(dir.join("nested").join("empty").is_dir());
+	assert_eq!(
+		std::fs::read_link(dir.join("link")).expect("The symlink exists; qed;"),
+		Path::new("nested/file.txt")
+	);
+	assert_eq!(
+		std::fs::metadata(dir).expect("The dir exists; qed;").permissions().mode() & 0o777,
+		0o750
+	);
+}
+
+#[test]
+fn new_dir_backup_works() {
+	let builder = TestBuilder::new(Some(0));
+	let dir = create_tree(&builder);
+
+	let backup = DirBackup::new(&dir).expect("The backup should be created; qed;");
+
+	assert_eq!(backup.original, dir);
+	assert_is_tree(backup.path());
+}
+
+#[test]
+fn new_dir_backup_fails_if_original_doesnt_exist() {
+	let builder = TestBuilder::new(Some(0));
+	let result = DirBackup::new(&builder.get_temp_dir_path().join("missing"));
+
+	assert!(matches!(result, Err(Error::IO(err)) if err.kind() == std::io::ErrorKind::NotFound));
+}
+
+#[test]
+fn dir_backup_restore_works() {
+	let builder = TestBuilder::new(Some(0));
+	let dir = create_tree(&builder);
+	let backup = DirBackup::new(&dir).expect("The backup should be created; qed;");
+
+	std::fs::remove_dir_all(&dir).expect("The dir exists; qed;");
+	assert!(backup.restore().is_ok());
+
+	assert_is_tree(&dir);
+	// The backup is kept
+	assert_is_tree(backup.path());
+}
+
+#[test]
+fn dir_backup_restore_completes_partially_removed_trees() {
+	let builder = TestBuilder::new(Some(0));
+	let dir = create_tree(&builder);
+	let backup = DirBackup::new(&dir).expect("The backup should be created; qed;");
+
+	std::fs::remove_dir_all(dir.join("nested")).expect("The dir exists; qed;");
+	assert!(backup.restore().is_ok());
+
+	assert_is_tree(&dir);
+}
+
+#[test]
+fn dir_backup_restore_works_for_empty_dirs() {
+	let builder = TestBuilder::new(Some(0));
+	let dir = builder.get_temp_dir_path().join("empty");
+	std::fs::create_dir(&dir).expect("The dir can be created; qed;");
+	let backup = DirBackup::new(&dir).expect("The backup should be created; qed;");
+
+	std::fs::remove_dir(&dir).expect("The dir exists; qed;");
+	assert!(backup.restore().is_ok());
+
+	assert!(dir.is_dir());
+	assert_eq!(std::fs::read_dir(&dir).expect("The dir exists; qed;").count(), 0);
+}
+
+#[test]
+fn dir_backup_restore_exactly_works() {
+	let builder = TestBuilder::new(Some(0));
+	let dir = create_tree(&builder);
+	let backup = DirBackup::new(&dir).expect("The backup should be created; qed;");
+
+	std::fs::write(dir.join("nested").join("file.txt"), "modified").expect("The file exists; qed;");
+	std::fs::remove_dir(dir.join("nested").join("empty")).expect("The dir exists; qed;");
+	std::fs::remove_file(dir.join("link")).expect("The symlink exists; qed;");
+	std::fs::write(dir.join("added.txt"), "added").expect("The file can be created; qed;");
+	std::fs::create_dir_all(dir.join("added").join("nested"))
+		.expect("The dir can be created; qed;");
+	std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755))
+		.expect("The dir exists; qed;");
+	assert!(backup.restore_exactly().is_ok());
+
+	assert_is_tree(&dir);
+	assert!(!dir.join("added.txt").exists());
+	assert!(!dir.join("added").exists());
+	// The backup is kept
+	assert_is_tree(backup.path());
+}
+
+#[test]
+fn dir_backup_restore_exactly_replaces_items_of_another_kind() {
+	let builder = TestBuilder::new(Some(0));
+	let dir = create_tree(&builder);
+	let backup = DirBackup::new(&dir).expect("The backup should be created; qed;");
+
+	std::fs::remove_dir_all(dir.join("nested")).expect("The dir exists; qed;");
+	std::fs::write(dir.join("nested"), "file").expect("The file can be created; qed;");
+	std::fs::remove_file(dir.join("link")).expect("The symlink exists; qed;");
+	std::fs::create_dir(dir.join("link")).expect("The dir can be created; qed;");
+	assert!(backup.restore_exactly().is_ok());
+
+	assert_is_tree(&dir);
+}
+
+#[test]
+fn dir_backup_restore_exactly_recreates_removed_trees() {
+	let builder = TestBuilder::new(Some(0));
+	let dir = create_tree(&builder);
+	let backup = DirBackup::new(&dir).expect("The backup should be created; qed;");
+
+	std::fs::remove_dir_all(&dir).expect("The dir exists; qed;");
+	assert!(backup.restore_exactly().is_ok());
+
+	assert_is_tree(&dir);
+}
@@ -4,21 +4,493 @@
 mod tests;
 
 use crate::{
-	rollback::{backup::Backup, Rollback},
+	rollback::{
+		atomic::{
+			atomic_move_or_copy, create_atomically, link_or_copy_new, rename_within_fs,
+			replace_atomically, replace_with_permissions,
+		},
+		backup::Backup,
+		baseline, fs_root,
+		graph::{Graph, Operation},
+		journal,
+		lock::{self, LockKind},
+		noted::NotedFile,
+		options::CommitOptions,
+		phase::CommitPhase,
+		symlink, CommitCounts, CommitOutcome, FileTiming, LinkPolicy, Rollback, StagedKind,
+		VetoPolicy,
+	},
 	Error,
 };
 
+#[cfg(feature = "acl")]
+use crate::rollback::acl;
+#[cfg(unix)]
+use crate::rollback::umask::UmaskGuard;
 use std::{
-	fs::File,
-	sync::{Arc, Mutex},
+	borrow::Cow,
+	collections::{hash_map::Entry, HashMap},
+	ffi::OsStr,
+	fs::OpenOptions,
+	io::{self, ErrorKind},
+	panic::AssertUnwindSafe,
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Mutex,
+	},
+	time::{Instant, SystemTime, UNIX_EPOCH},
 };
+use tempfile::TempDir;
 
 impl Rollback<'_> {
-	pub(crate) fn rollback_new_dirs(&self) {
+	pub(crate) fn commit_order(&self) -> Result<Vec<Operation<'_>>, Error> {
+		Graph::new(self).topological_order()
+	}
+
+	pub(crate) fn commit_with_options(
+		&self,
+		options: &CommitOptions,
+	) -> Result<CommitOutcome, Error> {
+		let mut counts = CommitCounts {
+			noted_files: self.noted.len(),
+			new_files: self.new_files.len(),
+			new_dirs: self.new_dirs.len(),
+			new_symlinks: self.new_symlinks.len(),
+			renamed_files: self.renames.len(),
+			deleted_files: self.deleted.len(),
+			deleted_dirs: self.deleted_dirs.len(),
+			#[cfg(unix)]
+			dirs_metadata: self.dirs_metadata.len(),
+		};
+		if counts == CommitCounts::default() {
+			return Ok(CommitOutcome::Empty);
+		}
+
+		let order = self.commit_order()?;
+
+		if self.busy_check {
+			self.check_busy_files(&order)?;
+		}
+
+		#[cfg(unix)]
+		let _umask_guard = self.umask.map(UmaskGuard::new);
+
+		let mut backups = Vec::with_capacity(self.noted.capacity());
+		let mut rename_backups = Vec::with_capacity(self.renames.len());
+		let mut deleted_backups = Vec::with_capacity(self.deleted.len());
+		let mut committed_noted_files = 0;
+		let mut archived = Vec::new();
+		let mut baseline = Vec::new();
+		let steps = Graph::new(self).commit_steps(&CommitPhase::sequence(self.commit_order))?;
+
+		self.remove_completion_marker()?;
+
+		self.journal(|| "begin".to_owned()).map_err(|err| self.journal_error(&err))?;
+
+		for (index, (phase, operations)) in steps.iter().enumerate() {
+			#[cfg(feature = "tracing")]
+			tracing::debug!(?phase, "commit phase started");
+			let result = match phase {
+				CommitPhase::NotedFiles => match self.commit_noted_files(backups, options) {
+					Ok((computed_backups, committed)) => {
+						backups = computed_backups;
+						committed_noted_files = committed;
+						Ok(())
+					},
+					Err((err, computed_backups)) => {
+						backups = computed_backups;
+						Err(err)
+					},
+				},
+				CommitPhase::NewDirs => self.commit_new_dirs(operations, options),
+				CommitPhase::NewFiles => self.commit_new_files(operations, options),
+				CommitPhase::NewSymlinks => self.commit_new_symlinks(operations, options),
+				CommitPhase::Renames =>
+					self.commit_renames(operations, options, &mut rename_backups),
+				CommitPhase::DeletedFiles =>
+					self.commit_deleted_files(operations, options, &mut deleted_backups),
+				CommitPhase::DeletedDirs => self.commit_deleted_dirs(operations, options),
+				#[cfg(unix)]
+				CommitPhase::DirsMetadata => self.commit_dirs_metadata(operations, options),
+				CommitPhase::Verification => self.verify_commit(options),
+				CommitPhase::Baseline => self.commit_baseline(&mut baseline),
+				CommitPhase::Archive => self.commit_archive(&backups, &mut archived),
+				CommitPhase::CompletionMarker => self.commit_completion_marker(),
+			};
+
+			if let Err(err) = result {
+				#[cfg(feature = "tracing")]
+				tracing::warn!(?phase, error = %err, "commit phase failed, rolling back the commit");
+				// The journal is kept to diagnose the failure, so recording in it is best effort
+				// at this point.
+				let _ = self.journal(|| format!("failed {}", err));
+				// The failing phase may be partially committed, so it's rolled-back as well, unless
+				// it cleans up after itself.
+				let committed = if phase.cleans_up_on_failure() { index } else { index + 1 };
+				let mut unrestored = self.rollback_phases(
+					&steps[..committed],
+					backups,
+					&rename_backups,
+					&deleted_backups,
+					&baseline,
+					&archived,
+				);
+				// Noted dirs are restored once everything else is rolled-back, so they end up
+				// exactly as they were noted.
+				unrestored.extend(self.rollback_noted_dirs());
+				if !unrestored.is_empty() {
+					let unrestored = unrestored
+						.iter()
+						.map(|path| format!("{}", path.display()))
+						.collect::<Vec<_>>()
+						.join(", ");
+					#[cfg(feature = "tracing")]
+					tracing::error!(%unrestored, "rolling back the commit failed");
+					let _ = self.journal(|| format!("unrestored {}", unrestored));
+					return Err(Error::RollbackFailed(format!("{}", err), unrestored));
+				}
+				let _ = self.journal(|| "rolled-back".to_owned());
+				return Err(err);
+			}
+			#[cfg(feature = "tracing")]
+			tracing::debug!(?phase, "commit phase finished");
+		}
+
+		if let Some(journal) = self.journal.as_deref() {
+			// The commit succeeded, so failing to remove the journal doesn't change the outcome.
+			let _ = std::fs::remove_file(journal);
+		}
+
+		if let Some(report) = options.archived {
+			report.lock().expect("The commit threads cannot panic; qed;").extend(archived);
+		}
+
+		// Noted files vetoed by a file guard or untouched through their handles aren't committed.
+		counts.noted_files = committed_noted_files;
+		#[cfg(feature = "tracing")]
+		{
+			tracing::info!(?counts, "commit applied");
+			self.settled.store(true, std::sync::atomic::Ordering::Relaxed);
+		}
+		Ok(CommitOutcome::Applied { counts })
+	}
+
+	// Checks the preconditions of the staged operations in commit order, tracking the items that
+	// would be created so far, without touching the file system. See `Rollback::dry_run`.
+	pub(crate) fn validate_commit(&self) -> Result<(), Error> {
+		// The operations are checked in the order the commit steps follow.
+		let order: Vec<_> = Graph::new(self)
+			.commit_steps(&CommitPhase::sequence(self.commit_order))?
+			.into_iter()
+			.flat_map(|(_, operations)| operations)
+			.collect();
+		let commit_error = |path: &Path, err: &dyn std::fmt::Display| {
+			Error::Commit(format!("{}", path.display()), format!("{}", err))
+		};
+
+		for (original, noted) in self.noted.iter() {
+			// Links replaced by files only need to exist.
+			let metadata = match noted.link_target {
+				Some(_) => std::fs::symlink_metadata(self.fs_path(original)),
+				None => std::fs::metadata(self.fs_path(original)),
+			}
+			.map_err(|err| commit_error(original, &err))?;
+			if metadata.permissions().readonly() {
+				return Err(commit_error(original, &io::Error::from(ErrorKind::PermissionDenied)));
+			}
+		}
+
+		let mut new_dirs: Vec<PathBuf> = Vec::with_capacity(self.new_dirs.len());
+		let mut new_files = Vec::with_capacity(self.new_files.len());
+		let mut new_links: Vec<PathBuf> = Vec::with_capacity(self.new_symlinks.len());
+		// A new item can be created if its parent dir exists, if it'd be created by a new dir, or
+		// if it'd be reached through a new symlink, whose target isn't checked.
+		let parent_error =
+			|path: &Path, resolved: &Path, new_dirs: &[PathBuf], new_links: &[PathBuf]| {
+				let parent = resolved.parent().expect("The path is absolute; qed;");
+				match parent.symlink_metadata() {
+					Ok(_) => None,
+					Err(_) if new_dirs.iter().any(|dir| dir.starts_with(parent)) => None,
+					Err(_) if new_links.iter().any(|link| parent.starts_with(link)) => None,
+					Err(err) => Some(commit_error(path, &err)),
+				}
+			};
+
+		for operation in &order {
+			match operation {
+				Operation::NewDir(dir) => {
+					let fs_dir = self.fs_path(dir);
+					let resolved =
+						resolve_new_item(&fs_dir).map_err(|err| commit_error(dir, &err))?;
+					// Creating a dir also creates its parents, so they'd already exist as well.
+					if fs_dir.exists() || new_dirs.iter().any(|dir| dir.starts_with(&resolved)) {
+						return Err(Error::RepeatedNewDir(format!("{}", dir.display())));
+					} else if self.strict_new_dirs.contains(*dir) {
+						if let Some(err) = parent_error(dir, &resolved, &new_dirs, &new_links) {
+							return Err(err);
+						}
+					}
+					new_dirs.push(resolved);
+				},
+				Operation::NewFile(path) => {
+					let fs_path = self.fs_path(path);
+					let resolved =
+						resolve_new_item(&fs_path).map_err(|err| commit_error(path, &err))?;
+					if new_files.contains(&resolved) {
+						return Err(Error::RepeatedNewFile(format!("{}", path.display())));
+					} else if fs_path.symlink_metadata().is_ok() || resolved.exists() {
+						return Err(Error::NewItemAlreadyExists(format!("{}", resolved.display())));
+					} else if let Some(err) = parent_error(path, &resolved, &new_dirs, &new_links) {
+						return Err(err);
+					}
+					new_files.push(resolved);
+				},
+				Operation::NewSymlink(link) => {
+					let fs_link = self.fs_path(link);
+					let resolved =
+						resolve_new_item(&fs_link).map_err(|err| commit_error(link, &err))?;
+					if new_files.contains(&resolved) ||
+						fs_link.symlink_metadata().is_ok() ||
+						resolved.symlink_metadata().is_ok()
+					{
+						return Err(Error::NewItemAlreadyExists(format!("{}", link.display())));
+					} else if let Some(err) = parent_error(link, &resolved, &new_dirs, &new_links) {
+						return Err(err);
+					}
+					new_files.push(resolved.clone());
+					new_links.push(resolved);
+				},
+				Operation::Rename(from, to) => {
+					self.fs_path(from)
+						.symlink_metadata()
+						.map_err(|err| commit_error(from, &err))?;
+					let fs_to = self.fs_path(to);
+					if fs_to.symlink_metadata().is_ok() {
+						return Err(Error::NewItemAlreadyExists(format!("{}", to.display())));
+					}
+					let resolved =
+						resolve_new_item(&fs_to).map_err(|err| commit_error(from, &err))?;
+					if let Some(err) = parent_error(from, &resolved, &new_dirs, &new_links) {
+						return Err(err);
+					}
+				},
+				Operation::DeleteFile(path) | Operation::DeleteDir(path) => {
+					self.fs_path(path)
+						.symlink_metadata()
+						.map_err(|err| commit_error(path, &err))?;
+				},
+				_ => (),
+			}
+		}
+
+		Ok(())
+	}
+
+	// Commits the staged items into a mirror of the file system under the root, which stands for
+	// `/`, leaving the actual targets untouched. See `Rollback::dry_run_into_temp`.
+	pub(crate) fn commit_into_mirror(&self, root: &Path) -> io::Result<()> {
+		#[cfg(unix)]
+		let _umask_guard = self.umask.map(UmaskGuard::new);
+
+		// The dirs containing the staged items are mirrored if they exist in the file system, so
+		// the items can be created in the mirror only if they could be created in the file
+		// system.
+		let staged = self
+			.noted
+			.keys()
+			.chain(self.new_files.keys())
+			.chain(self.new_dirs.iter())
+			.chain(self.new_symlinks.keys())
+			.chain(self.renames.values());
+		for path in staged {
+			let fs_path = std::path::absolute(self.fs_path(path))?;
+			let mirrored = mirror_path(root, path)?;
+			if let (Some(fs_parent), Some(mirrored)) = (fs_path.parent(), mirrored.parent()) {
+				if fs_parent.is_dir() {
+					std::fs::create_dir_all(mirrored)?;
+				}
+			}
+		}
+
+		for dir in &self.new_dirs {
+			std::fs::create_dir_all(mirror_path(root, dir)?)?;
+		}
+
+		// Noted files are copied into the mirror before writing their staged content, so they
+		// keep their permissions.
+		for (original, noted) in self.noted.iter() {
+			let mirrored = mirror_path(root, original)?;
+			// Links replaced by files get the permissions recorded when they were noted.
+			match noted.link_target {
+				Some(_) => {
+					std::fs::write(&mirrored, self.read_overlay(original)?)?;
+					std::fs::set_permissions(&mirrored, noted.permissions.clone())?;
+				},
+				None => {
+					std::fs::copy(self.fs_path(original), &mirrored)?;
+					std::fs::write(&mirrored, self.read_overlay(original)?)?;
+				},
+			}
+		}
+
+		for (link, target) in &self.new_symlinks {
+			symlink::create(target, &mirror_path(root, link)?)?;
+		}
+
+		for (from, to) in &self.renames {
+			let mirrored = mirror_path(root, to)?;
+			OpenOptions::new().write(true).create_new(true).open(&mirrored)?;
+			std::fs::copy(self.fs_path(from), &mirrored)?;
+		}
+
+		for (path, temporal) in &self.new_files {
+			let mirrored = mirror_path(root, path)?;
+			OpenOptions::new().write(true).create_new(true).open(&mirrored)?;
+			std::fs::copy(temporal.path(), &mirrored)?;
+			#[cfg(unix)]
+			if let Some(mode) = self.new_file_mode(path) {
+				use std::os::unix::fs::PermissionsExt;
+				std::fs::set_permissions(&mirrored, std::fs::Permissions::from_mode(mode))?;
+			}
+		}
+
+		Ok(())
+	}
+
+	// The mode of a new file upon commit, if it's specified or derived from the umask.
+	#[cfg(unix)]
+	fn new_file_mode(&self, path: &Path) -> Option<u32> {
+		self.new_file_modes
+			.get(path)
+			.copied()
+			.or(self.umask.map(|umask| 0o666 & !umask))
+	}
+
+	// Checks that no noted file is busy before committing anything. Files are checked in commit
+	// order, so the reported file is deterministic.
+	fn check_busy_files(&self, order: &[Operation]) -> Result<(), Error> {
+		let noted_files = order.iter().filter_map(|operation| match operation {
+			Operation::NoteFile(original) => Some(original),
+			_ => None,
+		});
+		for original in noted_files {
+			match lock::is_busy(&self.fs_path(original)) {
+				Ok(false) => (),
+				Ok(true) => return Err(Error::FileBusy(format!("{}", original.display()))),
+				Err(err) =>
+					return Err(Error::Commit(format!("{}", original.display()), format!("{}", err))),
+			}
+		}
+		Ok(())
+	}
+
+	// Records an entry in the journal, if configured. The entry is computed lazily, so nothing is
+	// formatted if there's no journal.
+	fn journal<F: FnOnce() -> String>(&self, entry: F) -> Result<(), Error> {
+		match self.journal.as_deref() {
+			Some(journal) => journal::record(journal, &entry()),
+			None => Ok(()),
+		}
+	}
+
+	fn journal_error(&self, err: &Error) -> Error {
+		let journal = self.journal.as_deref().expect("Only journal failures are mapped; qed;");
+		Error::Commit(format!("{}", journal.display()), format!("{}", err))
+	}
+
+	// Rolls back the given steps in reverse order, each one undoing its phase for its operations.
+	// Renames and deleted files are undone from the backups taken while committing them, so undoing
+	// them again for another step of the same phase doesn't touch anything else. Returns the noted
+	// files that couldn't be restored, if any.
+	pub(crate) fn rollback_phases(
+		&self,
+		steps: &[(CommitPhase, Vec<Operation>)],
+		backups: Vec<Backup>,
+		rename_backups: &[(&Path, &Path, Option<Backup>)],
+		deleted_backups: &[Backup],
+		baseline: &[baseline::PreviousEntry],
+		archived: &[PathBuf],
+	) -> Vec<PathBuf> {
+		let mut backups = Some(backups);
+		let mut unrestored = Vec::new();
+		for (phase, operations) in steps.iter().rev() {
+			#[cfg(feature = "tracing")]
+			tracing::debug!(?phase, "rolling back commit phase");
+			match phase {
+				CommitPhase::NotedFiles =>
+					if let Some(backups) = backups.take() {
+						unrestored = self.rollback_noted_files(backups);
+					},
+				CommitPhase::NewDirs => self.rollback_new_dirs(operations),
+				CommitPhase::NewFiles => self.rollback_new_files(operations),
+				CommitPhase::NewSymlinks => self.rollback_new_symlinks(operations),
+				CommitPhase::Renames => self.rollback_renames(rename_backups),
+				CommitPhase::DeletedFiles => rollback_deleted_files(deleted_backups),
+				CommitPhase::DeletedDirs => self.rollback_deleted_dirs(operations),
+				#[cfg(unix)]
+				CommitPhase::DirsMetadata => self.rollback_dirs_metadata(operations),
+				CommitPhase::Baseline => baseline::restore(baseline),
+				CommitPhase::Archive => rollback_archive(archived),
+				CommitPhase::Verification | CommitPhase::CompletionMarker => (),
+			}
+		}
+		unrestored
+	}
+
+	// Restores the noted files from their backups. Returns the originals that couldn't be
+	// restored, so the failure can be reported instead of being silently ignored.
+	pub(crate) fn rollback_noted_files(&self, backups: Vec<Backup>) -> Vec<PathBuf> {
+		let mut unrestored = Vec::new();
+		for backup in backups {
+			#[cfg(feature = "acl")]
+			let original_acl = self
+				.noted
+				.iter()
+				.find(|(original, _)| self.fs_path(original) == backup.original())
+				.and_then(|(_, noted)| noted.acl.as_deref());
+			let original = backup.original().to_path_buf();
+			let restored = backup.rollback();
+			// The backup replaces the original file, so its ACL must be restored. Don't need to
+			// handle this result, as there's nothing else to do if it fails.
+			#[cfg(feature = "acl")]
+			if let (Ok(()), Some(original_acl)) = (&restored, original_acl) {
+				let _ = acl::apply(&original, original_acl);
+			}
+			if restored.is_err() {
+				unrestored.push(original);
+			}
+		}
+
+		// Replaced links are created again, while the links that haven't been replaced yet are
+		// untouched.
+		for (original, noted) in self.noted.iter() {
+			let Some(target) = &noted.link_target else {
+				continue;
+			};
+			let fs_link = self.fs_path(original);
+			let restored = match fs_link.symlink_metadata() {
+				Ok(metadata) if metadata.is_symlink() => continue,
+				Ok(_) => std::fs::remove_file(&fs_link),
+				Err(_) => Ok(()),
+			}
+			.and_then(|_| symlink::create(target, &fs_link));
+			if restored.is_err() {
+				unrestored.push(fs_link.into_owned());
+			}
+		}
+		unrestored
+	}
+
+	pub(crate) fn rollback_new_dirs(&self, order: &[Operation]) {
 		let mut handles = Vec::with_capacity(self.new_dirs.len());
+		let new_dirs = order.iter().filter_map(|operation| match operation {
+			Operation::NewDir(dir) => Some(dir),
+			_ => None,
+		});
 
-		for dir in self.new_dirs.iter() {
-			let dir = dir.to_path_buf();
+		for dir in new_dirs {
+			let dir = self.fs_path(dir).into_owned();
 			handles.push(std::thread::spawn(move || {
 				// Don't need to handle this result:
 				// - If it works: ✅
@@ -35,11 +507,15 @@ impl Rollback<'_> {
 		}
 	}
 
-	pub(crate) fn rollback_new_files(&self) {
+	pub(crate) fn rollback_new_files(&self, order: &[Operation]) {
 		let mut handles = Vec::with_capacity(self.new_files.len());
+		let new_files = order.iter().filter_map(|operation| match operation {
+			Operation::NewFile(file) => Some(file),
+			_ => None,
+		});
 
-		for file in self.new_files.keys() {
-			let file = file.to_path_buf();
+		for file in new_files {
+			let file = self.fs_path(file).into_owned();
 			handles.push(std::thread::spawn(move || {
 				// Don't need to handle this result:
 				// - If it works: ✅
@@ -56,110 +532,994 @@ impl Rollback<'_> {
 		}
 	}
 
+	pub(crate) fn rollback_new_symlinks(&self, order: &[Operation]) {
+		let new_symlinks = order.iter().filter_map(|operation| match operation {
+			Operation::NewSymlink(link) => Some(link),
+			_ => None,
+		});
+
+		for link in new_symlinks {
+			// Don't need to handle this result:
+			// - If it works: ✅
+			// - If it fails cause the symlink doesn't exist: ✅ as the funcion objective is to
+			//   delete it.
+			// - By construction there's not other possible error. If the symlink is created by the
+			//   commit flow, the commit flow can also delete it.
+			let _ = symlink::remove(&self.fs_path(link));
+		}
+	}
+
+	// Undoes the committed renames, which are paired with their backups if they were copied across
+	// file systems.
+	pub(crate) fn rollback_renames(&self, backups: &[(&Path, &Path, Option<Backup>)]) {
+		for (from, to, backup) in backups {
+			match backup {
+				Some(backup) => self.undo_rename(to, backup),
+				// Don't need to handle this result:
+				// - If it works: ✅
+				// - By construction there's not other possible error. The rename is only recorded
+				//   once it's done, and the current path cannot be reused by another operation.
+				None => {
+					let _ = std::fs::rename(self.fs_path(to), self.fs_path(from));
+				},
+			}
+		}
+	}
+
+	// Puts a renamed file back in its current path from its backup, and removes it from its new
+	// path.
+	fn undo_rename(&self, to: &Path, backup: &Backup) {
+		// Don't need to handle these results:
+		// - If they work: ✅
+		// - If restoring fails cause the file is still there: ✅ it hasn't been removed, or the
+		//   rename didn't even start.
+		// - If removing fails cause the new path doesn't exist: ✅ the rename didn't reach it.
+		// - By construction there's not other possible error. The new path didn't exist before the
+		//   rename, so the commit flow can also remove it.
+		let _ = backup.restore();
+		let _ = std::fs::remove_file(self.fs_path(to));
+	}
+
+	// Restores the noted dirs exactly as they were noted. Returns the dirs that couldn't be
+	// restored, so the failure can be reported.
+	pub(crate) fn rollback_noted_dirs(&self) -> Vec<PathBuf> {
+		let mut unrestored = Vec::new();
+		for (dir, backup) in &self.noted_dirs {
+			if backup.restore_exactly().is_err() {
+				unrestored.push(dir.to_path_buf());
+			}
+		}
+		unrestored
+	}
+
+	pub(crate) fn rollback_deleted_dirs(&self, order: &[Operation]) {
+		let backups = order.iter().filter_map(|operation| match operation {
+			Operation::DeleteDir(dir) => self.deleted_dirs.get(*dir),
+			_ => None,
+		});
+
+		for backup in backups {
+			// Don't need to handle this result:
+			// - If it works: ✅
+			// - If it fails cause the dir is inside another deleted dir that doesn't exist yet: ✅
+			//   restoring the containing dir restores the whole tree.
+			// - Otherwise, there's nothing else to do. The backup is kept, so it's deleted once the
+			//   instance is dropped.
+			let _ = backup.restore();
+		}
+	}
+
+	#[cfg(unix)]
+	pub(crate) fn rollback_dirs_metadata(&self, order: &[Operation]) {
+		let dirs_metadata = order.iter().filter_map(|operation| match operation {
+			Operation::DirMetadata(dir) => self.dirs_metadata.get_key_value(*dir),
+			_ => None,
+		});
+
+		for (dir, dir_metadata) in dirs_metadata {
+			// Don't need to handle this result:
+			// - If it works: ✅
+			// - If it fails cause the dir doesn't exist anymore: ✅ there's nothing to restore.
+			// - By construction there's not other possible error. If the metadata is changed by the
+			//   commit flow, the commit flow can also restore it.
+			let _ = dir_metadata.restore(&self.fs_path(dir));
+		}
+	}
+
 	pub(crate) fn commit_noted_files(
 		&self,
 		backups: Vec<Backup>,
-	) -> Result<Vec<Backup>, (Error, Vec<Backup>)> {
-		let mut handles = Vec::with_capacity(self.noted.len());
+		options: &CommitOptions,
+	) -> Result<(Vec<Backup>, usize), (Error, Vec<Backup>)> {
+		let backups = Mutex::new(backups);
+		// Each worker commits noted files in path order until there's none left or one of them
+		// fails. There's a worker per noted file unless the number of threads is capped, or a
+		// single one if the commit is sequential.
+		let mut noted: Vec<_> = self.noted.iter().collect();
+		noted.sort_by_key(|(original, _)| *original);
+		let queue = Mutex::new(noted.into_iter());
+		let max_threads = if options.sequential { Some(1) } else { self.max_threads };
+		let workers = max_threads
+			.map_or(self.noted.len(), |max_threads| max_threads.max(1))
+			.min(self.noted.len());
 
-		let mutex_backups = Arc::new(Mutex::new(backups));
+		// Keep track of all successfully created backups and return an error if something goes
+		// wrong in any thread. Each thread reports how many files it has committed.
+		let result = std::thread::scope(|scope| {
+			let handles: Vec<_> = (0..workers)
+				.map(|_| {
+					let (backups, queue) = (&backups, &queue);
+					scope.spawn(move || -> Result<usize, Error> {
+						let mut committed = 0;
+						loop {
+							// The queue must be released before committing the file.
+							let next = queue.lock().expect("The threads cannot panic; qed;").next();
+							match next {
+								Some((original, noted)) =>
+									if self.commit_noted_file(original, noted, backups, options)? {
+										committed += 1;
+									},
+								None => return Ok(committed),
+							}
+						}
+					})
+				})
+				.collect();
 
-		// Keep an Arc count in the main thread as we need to send back backups at the end of the
-		// process
-		let main_thread_backups_copy = Arc::clone(&mutex_backups);
+			let mut committed = 0;
+			let mut errors = Vec::new();
+			for handle in handles {
+				match handle.join().expect("The threads cannot panic; qed;") {
+					Ok(committed_by_worker) => committed += committed_by_worker,
+					Err(err) => errors.push(err),
+				}
+			}
+			// Each worker stops at its first failure, so every worker may report one.
+			match errors.len() {
+				0 => Ok(committed),
+				1 => Err(errors.remove(0)),
+				_ => Err(Error::Multiple(errors)),
+			}
+		});
 
-		// Keep track of all successfully created backups and return an error if something goes
-		// wrong in any thread.
-		for (original, temporal) in self.noted.iter() {
-			let original = original.to_path_buf();
-			let temporal = temporal.path().to_path_buf();
-			let mutex_backups = Arc::clone(&mutex_backups);
-			handles.push(std::thread::spawn(move || -> Result<(), Error> {
-				let backup = match Backup::new(&original) {
-					Ok(backup) => backup,
-					Err(err) => {
-						return Err(Error::Commit(
-							format!("{}", original.display()),
-							format!("{}", err),
-						));
-					},
+		let backups = backups
+			.into_inner()
+			.expect("At this point, all threads finished and threads cannot panic; qed;");
+
+		match result {
+			Ok(committed) => Ok((backups, committed)),
+			Err(err) => Err((err, backups)),
+		}
+	}
+
+	// Commits a noted file, returning whether it's been committed or skipped.
+	fn commit_noted_file(
+		&self,
+		original: &Path,
+		noted: &NotedFile,
+		backups: &Mutex<Vec<Backup>>,
+		options: &CommitOptions,
+	) -> Result<bool, Error> {
+		let commit_error = |err: Error| commit_failure(original, CommitPhase::NotedFiles, err);
+		// Symlinks are followed, so their target is modified while the link is kept, unless the
+		// link itself must be replaced.
+		let fs_original = match noted.link_target {
+			Some(_) => self.fs_path(original),
+			None =>
+				follow_symlink(self.fs_path(original)).map_err(|err| commit_error(err.into()))?,
+		};
+
+		// Files tracked through handles are committed only if they're dirty.
+		if noted.is_untouched() {
+			return Ok(false);
+		}
+
+		if let Some(file_guard) = &options.file_guard {
+			let metadata =
+				std::fs::symlink_metadata(&fs_original).map_err(|err| commit_error(err.into()))?;
+			if !(file_guard.guard)(original, &metadata) {
+				return match file_guard.policy {
+					VetoPolicy::Skip => Ok(false),
+					VetoPolicy::Abort => Err(Error::Vetoed(format!("{}", original.display()))),
 				};
+			}
+		}
 
-				let mut backups = mutex_backups.lock().expect("The threads cannot panic; qed;");
+		if let Some(file) = &noted.lock {
+			lock::lock(file, original, LockKind::Exclusive).map_err(commit_error)?;
+		}
 
-				backups.push(backup);
+		if self.modification_check &&
+			noted.is_modified(&fs_original).map_err(|err| commit_error(err.into()))?
+		{
+			return Err(Error::ConcurrentModification(format!("{}", original.display())));
+		}
 
-				if let Err(err) = std::fs::copy(temporal, &original) {
-					return Err(Error::Commit(
+		if let Some(transform) = &noted.transform {
+			let content = std::fs::read(&fs_original).map_err(|err| commit_error(err.into()))?;
+			// A poisoned mutex means that the transformation panicked in a
+			// previous commit attempt, which doesn't prevent calling it again.
+			let transform = transform.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+			let content = std::panic::catch_unwind(AssertUnwindSafe(|| transform(content)))
+				.map_err(|_| {
+					Error::Commit(
 						format!("{}", original.display()),
-						format!("{}", err),
-					));
-				}
-				Ok(())
-			}));
+						"the transformation panicked".to_owned(),
+					)
+				})?;
+			std::fs::write(noted.temp_file.path(), content)
+				.map_err(|err| commit_error(err.into()))?;
 		}
 
-		let mut result = Ok(());
-		for handle in handles {
-			let handle_result = handle.join().expect("The threads cannot panic; qed;");
-			if handle_result.is_err() {
-				result = handle_result;
-			}
+		#[cfg(feature = "mmap")]
+		if let Some(mmap) = &noted.mmap {
+			mmap.flush().map_err(|err| commit_error(err.into()))?;
 		}
 
-		let mut backups = main_thread_backups_copy
-			.lock()
-			.expect("At this point, this is the only reference to backups still alive and threads cannot panic; qed;");
+		if let Some(target) = &noted.link_target {
+			// The link is created again if the commit is rolled-back, so it isn't backed up.
+			self.journal(|| {
+				format!("intent replace_link {} target {}", fs_original.display(), target.display())
+			})
+			.map_err(commit_error)?;
+		} else if noted.disposable {
+			self.journal(|| format!("intent copy {} without backup", fs_original.display()))
+				.map_err(commit_error)?;
+		} else {
+			let backup = match self.backup_dir.as_deref() {
+				Some(backup_dir) => Backup::new_in(&fs_original, backup_dir),
+				None => Backup::new(&fs_original),
+			}
+			.map_err(commit_error)?;
+			let journal_entry = self.journal(|| {
+				format!("intent copy {} backup {}", fs_original.display(), backup.path().display())
+			});
 
-		// The only way to get backups back is to take its memory from the MutexGuard. It's fine, as
-		// there's not any remaining threads that can access this data
-		match result {
-			Ok(_) => Ok(std::mem::take(&mut *backups)),
-			Err(err) => Err((err, std::mem::take(&mut *backups))),
+			backups.lock().expect("The threads cannot panic; qed;").push(backup);
+
+			journal_entry.map_err(commit_error)?;
+		}
+		// Replacing the original is atomic, so it's the default. Copying into it is only done if
+		// the links must be preserved, or if the original is locked, as the lock is held on the
+		// original inode and replacing it would leave the committed file unlocked.
+		let replace = self.link_policy != Some(LinkPolicy::PreserveLinks) && noted.lock.is_none();
+		timed_copy(options, original, || {
+			if noted.link_target.is_some() {
+				replace_with_permissions(
+					noted.temp_file.path(),
+					&fs_original,
+					noted.permissions.clone(),
+				)?;
+				Ok(noted.temp_file.as_file().metadata()?.len())
+			} else if replace {
+				replace_atomically(noted.temp_file.path(), &fs_original)?;
+				Ok(noted.temp_file.as_file().metadata()?.len())
+			} else {
+				let copied = std::fs::copy(noted.temp_file.path(), &fs_original)?;
+				// Replacing the original keeps its current permissions, but copying into it
+				// applies the temporary file ones.
+				std::fs::set_permissions(&fs_original, noted.permissions.clone())?;
+				Ok(copied)
+			}
+		})
+		.map_err(commit_error)?;
+		if options.paranoid {
+			verify_and_sync(noted.temp_file.path(), &fs_original).map_err(commit_error)?;
+		} else if options.durable {
+			sync(&fs_original).map_err(commit_error)?;
 		}
+		#[cfg(feature = "acl")]
+		if let Some(original_acl) = &noted.acl {
+			acl::apply(&fs_original, original_acl).map_err(commit_error)?;
+		}
+		self.journal(|| format!("done copy {}", fs_original.display()))
+			.map_err(commit_error)?;
+		options.report_progress(original, StagedKind::NotedFile);
+		Ok(true)
 	}
 
-	pub(crate) fn commit_new_dirs(&self) -> Result<(), Error> {
+	pub(crate) fn commit_new_dirs(
+		&self,
+		order: &[Operation],
+		options: &CommitOptions,
+	) -> Result<(), Error> {
 		// Concurrency not possible cause two paths can be noted pointing to the same new dir.
 		// The only way to detect this is to check if the path already exists, for what concurrency
 		// may introduce race conditions.
-		for dir in self.new_dirs.iter() {
-			if dir.exists() {
+		let new_dirs = order.iter().filter_map(|operation| match operation {
+			Operation::NewDir(dir) => Some(dir),
+			_ => None,
+		});
+
+		for dir in new_dirs {
+			let fs_dir = self.fs_path(dir);
+			if fs_dir.exists() {
 				return Err(Error::RepeatedNewDir(format!("{}", dir.display())));
 			}
 
-			match std::fs::create_dir_all(dir) {
-				Ok(_) => (),
-				Err(err) => {
-					return Err(Error::Commit(format!("{}", dir.display()), format!("{}", err)));
-				},
+			// Strict dirs fail if their parent dir doesn't exist.
+			let create_dir = if self.strict_new_dirs.contains(*dir) {
+				std::fs::create_dir
+			} else {
+				std::fs::create_dir_all
+			};
+			let result = self
+				.journal(|| format!("intent create_dir {}", fs_dir.display()))
+				.and_then(|_| create_dir(&fs_dir).map_err(Error::from))
+				.and_then(|_| self.journal(|| format!("done create_dir {}", fs_dir.display())));
+			if let Err(err) = result {
+				return Err(commit_failure(dir, CommitPhase::NewDirs, err));
 			}
+			options.report_progress(dir, StagedKind::NewDir);
 		}
 
 		Ok(())
 	}
 
-	pub(crate) fn commit_new_files(&self) -> Result<(), Error> {
+	// If committing the new files fails, the files created so far are removed before returning,
+	// while the files that already existed are left untouched. Hence, this phase doesn't need to be
+	// rolled-back if it fails.
+	pub(crate) fn commit_new_files(
+		&self,
+		order: &[Operation],
+		options: &CommitOptions,
+	) -> Result<(), Error> {
+		let mut created = Vec::with_capacity(self.new_files.len());
+		let result = self.create_new_files(order, options, &mut created);
+		if result.is_err() {
+			for file in created {
+				// Don't need to handle this result, the file has just been created by this
+				// function, so it can be deleted as well.
+				let _ = std::fs::remove_file(file);
+			}
+		}
+		result
+	}
+
+	fn create_new_files(
+		&self,
+		order: &[Operation],
+		options: &CommitOptions,
+		created: &mut Vec<PathBuf>,
+	) -> Result<(), Error> {
 		// Concurrency not possible cause two paths can be noted pointing to the same new file.
-		// The only way to detect this is to check if the path already exists, for what concurrency
-		// may introduce race conditions.
-		for (path, temporal) in self.new_files.iter() {
-			if path.exists() {
+		// The only way to detect this is to keep track of the files processed so far, for what
+		// concurrency may introduce race conditions.
+		let new_files = order.iter().filter_map(|operation| match operation {
+			Operation::NewFile(path) => self.new_files.get_key_value(*path),
+			_ => None,
+		});
+
+		// Staging dirs are removed when dropped, once the staged files are linked into place or if
+		// something fails.
+		let mut staging_dirs: HashMap<PathBuf, TempDir> = HashMap::new();
+		let mut staged = Vec::with_capacity(self.new_files.len());
+		let mut resolved_paths = Vec::with_capacity(self.new_files.len());
+
+		for (path, temporal) in new_files {
+			let commit_error = |err: Error| commit_failure(path, CommitPhase::NewFiles, err);
+			// The temporary file renamed into place, if new files are created atomically.
+			let mut sibling = None;
+
+			// The parent dir may be a symlink, so the new file may end up somewhere else. Resolve
+			// the path to compare it against the files created so far and to check that it
+			// doesn't shadow an existing file.
+			let fs_path = self.fs_path(path);
+			let resolved = resolve_parent(&fs_path).map_err(|err| commit_error(err.into()))?;
+			if resolved_paths.contains(&resolved) {
 				return Err(Error::RepeatedNewFile(format!("{}", path.display())));
+			} else if fs_path.symlink_metadata().is_ok() || resolved.exists() {
+				return Err(Error::NewItemAlreadyExists(format!("{}", resolved.display())));
 			}
+			resolved_paths.push(resolved.clone());
 
-			match File::create(path) {
-				Ok(_) => (),
-				Err(err) => {
-					return Err(Error::Commit(format!("{}", path.display()), format!("{}", err)));
-				},
+			self.journal(|| format!("intent create_file {}", fs_path.display()))
+				.map_err(commit_error)?;
+
+			let destination = if self.stage_new_files {
+				let parent_dir = resolved.parent().expect("The path is resolved; qed;");
+				let staging_dir = match staging_dirs.entry(parent_dir.to_path_buf()) {
+					Entry::Occupied(entry) => entry.into_mut(),
+					Entry::Vacant(entry) => entry.insert(
+						tempfile::Builder::new()
+							.prefix(".fs_rollback-staging")
+							.tempdir_in(parent_dir)
+							.map_err(|err| commit_error(err.into()))?,
+					),
+				};
+				staging_dir.path().join(resolved.file_name().expect("The path is a file; qed;"))
+			} else if self.atomic_new_files {
+				let parent_dir = resolved.parent().expect("The path is resolved; qed;");
+				let file = tempfile::Builder::new()
+					.prefix(".fs_rollback-new")
+					.tempfile_in(parent_dir)
+					.map_err(|err| commit_error(err.into()))?;
+				sibling.insert(file).path().to_path_buf()
+			} else {
+				resolved.clone()
+			};
+
+			// Creating the file fails if it exists, so even if it's created by someone else after
+			// the previous check, it isn't overwritten. Temporary files renamed into place are
+			// checked when they're renamed instead.
+			if sibling.is_none() {
+				match OpenOptions::new().write(true).create_new(true).open(&destination) {
+					Ok(_) if !self.stage_new_files => created.push(resolved.clone()),
+					Ok(_) => (),
+					Err(err) if err.kind() == ErrorKind::AlreadyExists =>
+						return Err(Error::NewItemAlreadyExists(format!("{}", resolved.display()))),
+					Err(err) => return Err(commit_error(err.into())),
+				}
+			}
+
+			timed_copy(options, path, || Ok(std::fs::copy(temporal.path(), &destination)?))
+				.map_err(commit_error)?;
+			if options.paranoid {
+				verify_and_sync(temporal.path(), &destination).map_err(commit_error)?;
+			} else if options.durable {
+				sync(&destination).map_err(commit_error)?;
+			}
+
+			// Copying the temporary file also copies its permissions, so the specified mode or
+			// the umask must be explicitly honored.
+			#[cfg(unix)]
+			if let Some(mode) = self.new_file_mode(path) {
+				use std::os::unix::fs::PermissionsExt;
+				std::fs::set_permissions(&destination, std::fs::Permissions::from_mode(mode))
+					.map_err(|err| commit_error(err.into()))?;
+			}
+
+			// The file is fully written, so renaming it into place creates it atomically. Renaming
+			// fails if the file exists, so existing files aren't overwritten.
+			if let Some(file) = sibling {
+				match file.persist_noclobber(&resolved) {
+					Ok(_) => created.push(resolved.clone()),
+					Err(err) if err.error.kind() == ErrorKind::AlreadyExists =>
+						return Err(Error::NewItemAlreadyExists(format!("{}", resolved.display()))),
+					Err(err) => return Err(commit_error(err.error.into())),
+				}
+			}
+
+			if self.stage_new_files {
+				staged.push((path, fs_path, destination, resolved));
+			} else {
+				self.journal(|| format!("done create_file {}", fs_path.display()))
+					.map_err(commit_error)?;
+				options.report_progress(path, StagedKind::NewFile);
+			}
+		}
+
+		// Every staged file is fully written, so they can be linked into place. Linking fails if
+		// the file exists, so existing files aren't overwritten.
+		for (path, fs_path, staged_file, resolved) in staged {
+			let commit_error = |err: Error| commit_failure(path, CommitPhase::NewFiles, err);
+
+			match std::fs::hard_link(&staged_file, &resolved) {
+				Ok(_) => created.push(resolved),
+				Err(err) if err.kind() == ErrorKind::AlreadyExists =>
+					return Err(Error::NewItemAlreadyExists(format!("{}", resolved.display()))),
+				Err(err) => return Err(commit_error(err.into())),
+			}
+
+			self.journal(|| format!("done create_file {}", fs_path.display()))
+				.map_err(commit_error)?;
+			options.report_progress(path, StagedKind::NewFile);
+		}
+
+		Ok(())
+	}
+
+	#[cfg(unix)]
+	pub(crate) fn commit_dirs_metadata(
+		&self,
+		order: &[Operation],
+		options: &CommitOptions,
+	) -> Result<(), Error> {
+		let dirs_metadata = order.iter().filter_map(|operation| match operation {
+			Operation::DirMetadata(dir) => self.dirs_metadata.get_key_value(*dir),
+			_ => None,
+		});
+
+		for (dir, dir_metadata) in dirs_metadata {
+			let fs_dir = self.fs_path(dir);
+			let result = self
+				.journal(|| {
+					format!(
+						"intent set_metadata {} original mode {:o} uid {} gid {}",
+						fs_dir.display(),
+						dir_metadata.original_mode,
+						dir_metadata.original_uid,
+						dir_metadata.original_gid
+					)
+				})
+				.and_then(|_| dir_metadata.apply(&fs_dir).map_err(Error::from))
+				.and_then(|_| self.journal(|| format!("done set_metadata {}", fs_dir.display())));
+			if let Err(err) = result {
+				return Err(commit_failure(dir, CommitPhase::DirsMetadata, err));
+			}
+			options.report_progress(dir, StagedKind::DirMetadata);
+		}
+
+		Ok(())
+	}
+
+	// If committing the new symlinks fails, the symlinks created so far are removed before
+	// returning, while the items that already existed are left untouched. Hence, this phase doesn't
+	// need to be rolled-back if it fails.
+	pub(crate) fn commit_new_symlinks(
+		&self,
+		order: &[Operation],
+		options: &CommitOptions,
+	) -> Result<(), Error> {
+		let mut created = Vec::with_capacity(self.new_symlinks.len());
+		let result = self.create_new_symlinks(order, options, &mut created);
+		if result.is_err() {
+			for link in created {
+				// Don't need to handle this result, the symlink has just been created by this
+				// function, so it can be deleted as well.
+				let _ = symlink::remove(&link);
+			}
+		}
+		result
+	}
+
+	fn create_new_symlinks(
+		&self,
+		order: &[Operation],
+		options: &CommitOptions,
+		created: &mut Vec<PathBuf>,
+	) -> Result<(), Error> {
+		let new_symlinks = order.iter().filter_map(|operation| match operation {
+			Operation::NewSymlink(link) => self.new_symlinks.get_key_value(*link),
+			_ => None,
+		});
+
+		for (link, target) in new_symlinks {
+			let fs_link = self.fs_path(link);
+			// Existing items are never overwritten, so creating the symlink fails if its path
+			// exists.
+			if fs_link.symlink_metadata().is_ok() {
+				return Err(Error::NewItemAlreadyExists(format!("{}", link.display())));
+			}
+
+			let result = self
+				.journal(|| format!("intent symlink {} to {}", fs_link.display(), target.display()))
+				.and_then(|_| symlink::create(target, &fs_link).map_err(Error::from))
+				.and_then(|_| {
+					created.push(fs_link.to_path_buf());
+					self.journal(|| format!("done symlink {}", fs_link.display()))
+				});
+			if let Err(err) = result {
+				return Err(commit_failure(link, CommitPhase::NewSymlinks, err));
+			}
+			options.report_progress(link, StagedKind::NewSymlink);
+		}
+
+		Ok(())
+	}
+
+	// The committed renames are pushed into `backups` with their current and new paths. Renaming
+	// within a file system is atomic, so those renames are undone by renaming the files back and
+	// they don't need a backup. Files moved across file systems are copied instead, so they're
+	// backed up right before copying them and their backups are pushed as well. If committing the
+	// renames fails, the renames done so far are undone before returning. Hence, this phase
+	// doesn't need to be rolled-back if it fails.
+	pub(crate) fn commit_renames<'o>(
+		&self,
+		order: &[Operation<'o>],
+		options: &CommitOptions,
+		backups: &mut Vec<(&'o Path, &'o Path, Option<Backup>)>,
+	) -> Result<(), Error> {
+		let result = self.rename_files(order, options, backups);
+		if result.is_err() {
+			self.rollback_renames(backups);
+		}
+		result
+	}
+
+	fn rename_files<'o>(
+		&self,
+		order: &[Operation<'o>],
+		options: &CommitOptions,
+		backups: &mut Vec<(&'o Path, &'o Path, Option<Backup>)>,
+	) -> Result<(), Error> {
+		let renames = order.iter().filter_map(|operation| match operation {
+			Operation::Rename(from, to) => Some((*from, *to)),
+			_ => None,
+		});
+
+		for (from, to) in renames {
+			let (fs_from, fs_to) = (self.fs_path(from), self.fs_path(to));
+			// The new path is never overwritten. Checking it right before renaming narrows the
+			// window where someone else may create it.
+			if fs_to.symlink_metadata().is_ok() {
+				return Err(Error::NewItemAlreadyExists(format!("{}", to.display())));
+			}
+
+			let renamed = self
+				.journal(|| format!("intent rename {} to {}", fs_from.display(), fs_to.display()))
+				.and_then(|_| rename_within_fs(&fs_from, &fs_to))
+				.map_err(|err| commit_failure(from, CommitPhase::Renames, err))?;
+			if renamed {
+				backups.push((from, to, None));
+			} else {
+				self.copy_renamed_file(from, to, backups)
+					.map_err(|err| commit_failure(from, CommitPhase::Renames, err))?;
 			}
+			self.journal(|| format!("done rename {}", fs_from.display()))
+				.map_err(|err| commit_failure(from, CommitPhase::Renames, err))?;
+			options.report_progress(from, StagedKind::RenamedFile);
+		}
+
+		Ok(())
+	}
+
+	// Moves a file to a different file system by copying it, backing it up right before, so the
+	// backup holds the content it had when it was renamed.
+	fn copy_renamed_file<'o>(
+		&self,
+		from: &'o Path,
+		to: &'o Path,
+		backups: &mut Vec<(&'o Path, &'o Path, Option<Backup>)>,
+	) -> Result<(), Error> {
+		let (fs_from, fs_to) = (self.fs_path(from), self.fs_path(to));
+		let backup = Backup::new(&fs_from)?;
+		// A failed move may be partially done, so it's undone as well.
+		backups.push((from, to, Some(backup)));
+		let (_, _, backup) = backups.last().expect("The backup has just been pushed; qed;");
+		let backup = backup.as_ref().expect("The backup has just been pushed; qed;");
+		self.journal(|| {
+			format!(
+				"intent rename {} to {} backup {}",
+				fs_from.display(),
+				fs_to.display(),
+				backup.path().display()
+			)
+		})?;
+		atomic_move_or_copy(&fs_from, &fs_to)
+	}
 
-			if let Err(err) = std::fs::copy(temporal.path(), path) {
-				return Err(Error::Commit(format!("{}", path.display()), format!("{}", err)));
+	// The files are backed up right before deleting them, so the backups pushed into `backups`
+	// hold the content they had when they were deleted, even if it changed since they were staged.
+	pub(crate) fn commit_deleted_files(
+		&self,
+		order: &[Operation],
+		options: &CommitOptions,
+		backups: &mut Vec<Backup>,
+	) -> Result<(), Error> {
+		let deleted = order.iter().filter_map(|operation| match operation {
+			Operation::DeleteFile(path) => Some(*path),
+			_ => None,
+		});
+
+		for path in deleted {
+			let fs_path = self.fs_path(path);
+			let backup = Backup::new(&fs_path)
+				.map_err(|err| commit_failure(path, CommitPhase::DeletedFiles, err))?;
+			backups.push(backup);
+			let backup = backups.last().expect("The backup has just been pushed; qed;");
+			let result = self
+				.journal(|| {
+					format!(
+						"intent delete_file {} backup {}",
+						fs_path.display(),
+						backup.path().display()
+					)
+				})
+				.and_then(|_| std::fs::remove_file(&fs_path).map_err(Error::from))
+				.and_then(|_| self.journal(|| format!("done delete_file {}", fs_path.display())));
+			if let Err(err) = result {
+				return Err(commit_failure(path, CommitPhase::DeletedFiles, err));
 			}
+			options.report_progress(path, StagedKind::DeletedFile);
 		}
 
 		Ok(())
 	}
+
+	pub(crate) fn commit_deleted_dirs(
+		&self,
+		order: &[Operation],
+		options: &CommitOptions,
+	) -> Result<(), Error> {
+		let deleted_dirs = order.iter().filter_map(|operation| match operation {
+			Operation::DeleteDir(dir) => self.deleted_dirs.get_key_value(*dir),
+			_ => None,
+		});
+
+		for (dir, backup) in deleted_dirs {
+			let fs_dir = self.fs_path(dir);
+			let result = self
+				.journal(|| {
+					format!(
+						"intent delete_dir {} backup {}",
+						fs_dir.display(),
+						backup.path().display()
+					)
+				})
+				.and_then(|_| std::fs::remove_dir_all(&fs_dir).map_err(Error::from))
+				.and_then(|_| self.journal(|| format!("done delete_dir {}", fs_dir.display())));
+			if let Err(err) = result {
+				return Err(commit_failure(dir, CommitPhase::DeletedDirs, err));
+			}
+			options.report_progress(dir, StagedKind::DeletedDir);
+		}
+
+		Ok(())
+	}
+
+	// Records the committed files in the baseline dir, if any. The overwritten entries are pushed
+	// into `previous`, so they can be restored if the commit is rolled back.
+	pub(crate) fn commit_baseline(
+		&self,
+		previous: &mut Vec<baseline::PreviousEntry>,
+	) -> Result<(), Error> {
+		let Some(baseline_dir) = self.baseline_dir.as_deref() else {
+			return Ok(());
+		};
+
+		for path in self.noted.keys().chain(self.new_files.keys()) {
+			if let Err(err) = baseline::record(baseline_dir, &self.fs_path(path), previous) {
+				return Err(commit_failure(path, CommitPhase::Baseline, err));
+			}
+		}
+
+		Ok(())
+	}
+
+	// Checks that every new dir exists once everything's committed if the commit is paranoid,
+	// flushing to disk the dirs containing the committed items if it's paranoid or durable, so the
+	// changed entries survive a crash. Committed files are verified and flushed as they're
+	// committed, and if the commit is verified, they're read back again once everything's
+	// committed. Nothing's done for regular commits.
+	pub(crate) fn verify_commit(&self, options: &CommitOptions) -> Result<(), Error> {
+		if !options.paranoid && !options.durable && !options.verified {
+			return Ok(());
+		}
+
+		if options.verified {
+			// Files tracked through handles aren't committed if they're untouched.
+			let noted = self
+				.noted
+				.iter()
+				.filter(|(_, noted)| !noted.is_untouched())
+				.map(|(original, noted)| (original, noted.temp_file.path()));
+			let new_files = self.new_files.iter().map(|(path, temporal)| (path, temporal.path()));
+			for (path, staged) in noted.chain(new_files) {
+				verify_content(staged, &self.fs_path(path))
+					.map_err(|err| commit_failure(path, CommitPhase::Verification, err))?;
+			}
+		}
+
+		if options.paranoid {
+			for dir in self.new_dirs.iter() {
+				if !self.fs_path(dir).is_dir() {
+					return Err(Error::Commit(
+						format!("{}", dir.display()),
+						"the dir doesn't exist after committing it".to_owned(),
+					));
+				}
+			}
+		}
+
+		#[cfg(unix)]
+		{
+			let mut dirs: Vec<PathBuf> = self
+				.noted
+				.keys()
+				.chain(self.new_files.keys())
+				.chain(self.new_dirs.iter())
+				.chain(self.new_symlinks.keys())
+				.chain(self.renames.iter().flat_map(|(from, to)| [from, to]))
+				.chain(self.deleted.iter())
+				.chain(self.deleted_dirs.keys())
+				.filter_map(|path| {
+					let prefixed_path =
+						rustilities::paths::prefix_with_current_dir(self.fs_path(path));
+					prefixed_path.parent().map(Path::to_path_buf)
+				})
+				.chain(self.new_dirs.iter().map(|dir| self.fs_path(dir).into_owned()))
+				// The parent of a deleted item may have been deleted as well.
+				.filter(|dir| dir.is_dir())
+				.collect();
+			dirs.sort();
+			dirs.dedup();
+			for dir in dirs {
+				std::fs::File::open(&dir)
+					.and_then(|dir| dir.sync_all())
+					.map_err(|err| commit_failure(&dir, CommitPhase::Verification, err.into()))?;
+			}
+		}
+
+		Ok(())
+	}
+
+	// A marker left by a previous commit cannot signal the completion of this one.
+	fn remove_completion_marker(&self) -> Result<(), Error> {
+		let Some(marker) = self.completion_marker.as_deref() else {
+			return Ok(());
+		};
+
+		match std::fs::remove_file(marker) {
+			Err(err) if err.kind() != ErrorKind::NotFound =>
+				Err(commit_failure(marker, CommitPhase::CompletionMarker, err.into())),
+			_ => Ok(()),
+		}
+	}
+
+	// Keeps the backups of the committed noted files in the archive dir, if any, pushing the
+	// archived paths into `archived`. The backups are linked into the archive if possible, as
+	// they're dropped once the commit finishes anyway. Archived backups are never replaced: if a
+	// name is taken, e.g. by a concurrent commit, a new one is picked. If a backup cannot be
+	// archived, the ones archived so far are removed.
+	pub(crate) fn commit_archive(
+		&self,
+		backups: &[Backup],
+		archived: &mut Vec<PathBuf>,
+	) -> Result<(), Error> {
+		let Some(archive_dir) = self.archive_dir.as_deref() else {
+			return Ok(());
+		};
+
+		let mut backups: Vec<_> = backups.iter().collect();
+		backups.sort_by(|a, b| a.original().cmp(b.original()));
+
+		for backup in backups {
+			let file_name = backup
+				.original()
+				.file_name()
+				.expect("Noted files are files, so they have a name; qed;")
+				.to_string_lossy();
+			let result = loop {
+				let target = archive_dir.join(format!("{}-{}", archive_prefix(), file_name));
+				match link_or_copy_new(backup.path(), &target) {
+					Err(Error::IO(err)) if err.kind() == ErrorKind::AlreadyExists => continue,
+					result => break result.map(|_| target),
+				}
+			};
+			match result {
+				Ok(target) => archived.push(target),
+				Err(err) => {
+					rollback_archive(archived);
+					archived.clear();
+					return Err(commit_failure(backup.original(), CommitPhase::Archive, err));
+				},
+			}
+		}
+
+		Ok(())
+	}
+
+	pub(crate) fn commit_completion_marker(&self) -> Result<(), Error> {
+		let Some(marker) = self.completion_marker.as_deref() else {
+			return Ok(());
+		};
+
+		create_atomically(marker)
+			.map_err(|err| commit_failure(marker, CommitPhase::CompletionMarker, err))
+	}
+}
+
+// The error raised when committing a staged item fails in a commit phase. IO errors are kept as
+// the source, so their kind can be inspected.
+fn commit_failure(path: &Path, phase: CommitPhase, err: Error) -> Error {
+	match err {
+		Error::IO(source) => Error::CommitIo { path: path.to_path_buf(), phase, source },
+		err => Error::Commit(format!("{}", path.display()), format!("{}", err)),
+	}
+}
+
+// The prefix of an archived backup name: the current time in nanoseconds since the Unix epoch and
+// a counter, so the names are unique within the process even if the clock is coarse.
+fn archive_prefix() -> String {
+	static COUNTER: AtomicU64 = AtomicU64::new(0);
+	let timestamp = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|elapsed| elapsed.as_nanos())
+		.unwrap_or_default();
+	format!("{}-{}", timestamp, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+// Restores the deleted files from the backups taken while deleting them.
+pub(crate) fn rollback_deleted_files(backups: &[Backup]) {
+	for backup in backups {
+		// Don't need to handle this result:
+		// - If it works: ✅
+		// - If it fails cause the file still exists: ✅ it hasn't been deleted.
+		// - Otherwise, there's nothing else to do. The backup is removed once the commit finishes.
+		let _ = backup.restore();
+	}
+}
+
+// Removes the archived backups. Don't need to handle the results, as there's nothing else to do
+// if removing them fails.
+fn rollback_archive(archived: &[PathBuf]) {
+	for path in archived {
+		let _ = std::fs::remove_file(path);
+	}
+}
+
+// Checks that a committed file has the content of the file it has been copied from, flushing it
+// to disk.
+fn verify_and_sync(source: &Path, committed: &Path) -> Result<(), Error> {
+	sync(committed)?;
+	verify_content(source, committed)
+}
+
+// Checks that a committed file has the content of the file it has been copied from.
+fn verify_content(source: &Path, committed: &Path) -> Result<(), Error> {
+	if baseline::content_hash(source)? != baseline::content_hash(committed)? {
+		return Err(Error::IO(std::io::Error::other(
+			"the committed content doesn't match the staged content",
+		)));
+	}
+	Ok(())
+}
+
+// Flushes a committed file to disk.
+fn sync(committed: &Path) -> Result<(), Error> {
+	std::fs::File::open(committed)?.sync_all()?;
+	Ok(())
+}
+
+// Carries out a copy returning the number of bytes copied, recording how long it takes if the
+// options ask for it.
+fn timed_copy<F>(options: &CommitOptions, path: &Path, copy: F) -> Result<(), Error>
+where
+	F: FnOnce() -> Result<u64, Error>,
+{
+	let Some(timings) = options.timings else {
+		return copy().map(|_| ());
+	};
+
+	let start = Instant::now();
+	let bytes = copy()?;
+	let duration = start.elapsed();
+	timings.lock().expect("The threads cannot panic; qed;").push(FileTiming {
+		path: path.to_path_buf(),
+		bytes,
+		duration,
+	});
+	Ok(())
+}
+
+// Resolves the parent dir of a path, keeping its file name. The parent dir must exist.
+fn resolve_parent(path: &Path) -> std::io::Result<PathBuf> {
+	let prefixed_path = rustilities::paths::prefix_with_current_dir(path);
+	let parent_dir = prefixed_path.parent().expect("The path is a file and is prefixed; qed;");
+	let file_name = path.file_name().expect("The path is a file; qed;");
+	Ok(parent_dir.canonicalize()?.join(file_name))
+}
+
+// Resolves the path of an item to be created as `resolve_parent` does, or makes it absolute if its
+// parent dir doesn't exist yet.
+pub(crate) fn resolve_new_item(path: &Path) -> io::Result<PathBuf> {
+	match path.file_name() {
+		Some(_) => resolve_parent(path).or_else(|_| std::path::absolute(path)),
+		None => std::path::absolute(path),
+	}
+}
+
+// A file name made of characters safe to embed into another file name, truncated to a sane
+// length. Other characters are replaced by `_`.
+pub(crate) fn sanitize_file_name(name: &OsStr) -> String {
+	const MAX_LEN: usize = 64;
+	name.to_string_lossy()
+		.chars()
+		.take(MAX_LEN)
+		.map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+		.collect()
+}
+
+// The path of the file a symlink points to, or the path itself if it isn't a symlink.
+fn follow_symlink(path: Cow<'_, Path>) -> io::Result<Cow<'_, Path>> {
+	match path.symlink_metadata() {
+		Ok(metadata) if metadata.is_symlink() => Ok(Cow::Owned(std::fs::canonicalize(&path)?)),
+		_ => Ok(path),
+	}
+}
+
+// The path of an item inside a mirror of the file system rooted at the given dir.
+fn mirror_path(root: &Path, path: &Path) -> io::Result<PathBuf> {
+	Ok(fs_root::rebase(Some(root), &std::path::absolute(path)?).into_owned())
 }
@@ -4,7 +4,11 @@ use super::*;
 use crate::test_builder::{
 	TestBuilder, MODIFIED_BUILDER_FILE_CONTENT, ORIGINAL_BUILDER_FILE_CONTENT,
 };
-use std::path::Path;
+use std::{
+	fs::File,
+	os::unix::fs::{MetadataExt, PermissionsExt},
+	path::Path,
+};
 
 #[test]
 fn rollback_new_dirs_works() {
@@ -20,7 +24,7 @@ fn rollback_new_dirs_works() {
 		});
 
 		// The rollback is executed even if many dirs have not been created yet. That's good
-		rollback.rollback_new_dirs();
+		rollback.rollback_new_dirs(&rollback.commit_order().expect("The order is valid; qed;"));
 
 		// None of the new dirs exists, neither the one which was manually created
 		builder.new_dirs().iter().for_each(|dir_path| assert!(!dir_path.is_dir()));
@@ -41,18 +45,120 @@ fn rollback_new_files_works() {
 		});
 
 		// The rollback is executed even if many files have not been created yet. That's good
-		rollback.rollback_new_files();
+		rollback.rollback_new_files(&rollback.commit_order().expect("The order is valid; qed;"));
 
 		// None of the new files exists, neither the one which was manually created
 		builder.new_files().iter().for_each(|file_path| assert!(!file_path.is_file()));
 	});
 }
 
+#[test]
+fn rollback_noted_files_reports_the_files_that_cannot_be_restored() {
+	TestBuilder::new(Some(2)).execute(|builder, rollback| {
+		let backups = builder
+			.existing_files()
+			.iter()
+			.map(|file| Backup::new(file).expect("The backup should be created; qed;"))
+			.collect();
+		builder.existing_files().iter().for_each(|file| {
+			std::fs::write(file, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;")
+		});
+		// A file cannot be restored over a dir
+		let unrestorable = builder.existing_files()[1];
+		std::fs::remove_file(unrestorable).expect("The file exists; qed;");
+		std::fs::create_dir(unrestorable).expect("The dir can be created; qed;");
+
+		assert_eq!(rollback.rollback_noted_files(backups), [unrestorable]);
+		assert_eq!(
+			std::fs::read_to_string(builder.existing_files()[0]).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn rollback_deleted_files_works() {
+	TestBuilder::new(Some(2)).execute(|builder, _| {
+		let backups: Vec<_> = builder
+			.existing_files()
+			.iter()
+			.map(|file| Backup::new(file).expect("The backup should be created; qed;"))
+			.collect();
+		// Delete one of the files
+		std::fs::remove_file(builder.existing_files()[0]).expect("The file exists; qed;");
+
+		// The rollback is executed even if some files haven't been deleted yet
+		rollback_deleted_files(&backups);
+
+		builder.existing_files().iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			)
+		});
+	});
+}
+
+#[test]
+fn rollback_renames_works() {
+	TestBuilder::new(Some(2)).execute(|builder, rollback| {
+		let existing_files = builder.existing_files();
+		let new_files = builder.new_files();
+		// One file is renamed within the file system, while the other one is backed up as if it
+		// were copied across file systems.
+		let backups = vec![
+			(existing_files[0], new_files[0], None),
+			(
+				existing_files[1],
+				new_files[1],
+				Some(Backup::new(existing_files[1]).expect("The backup should be created; qed;")),
+			),
+		];
+		std::fs::rename(existing_files[0], new_files[0]).expect("The file exists; qed;");
+
+		// The rollback is executed even if some files haven't been renamed yet
+		rollback.rollback_renames(&backups);
+
+		existing_files.iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			)
+		});
+		new_files.iter().for_each(|file| assert!(!file.exists()));
+	});
+}
+
+#[test]
+fn rollback_deleted_dirs_works() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("dir");
+		let inner_dir = dir.join("inner");
+		std::fs::create_dir_all(&inner_dir).expect("The dirs can be created; qed;");
+		std::fs::write(inner_dir.join("file.txt"), ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		// Rebind rollback to accomplish with dir lifetime
+		let mut rollback = rollback;
+		rollback.delete_dir(&inner_dir).expect("The dir can be deleted; qed;");
+		rollback.delete_dir(&dir).expect("The dir can be deleted; qed;");
+		std::fs::remove_dir_all(&dir).expect("The dir exists; qed;");
+
+		rollback.rollback_deleted_dirs(&rollback.commit_order().expect("The order is valid; qed;"));
+
+		assert_eq!(
+			std::fs::read_to_string(inner_dir.join("file.txt")).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
 #[test]
 fn commit_noted_files_works_well() {
 	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
-		let backups = match rollback.commit_noted_files(Vec::with_capacity(builder.capacity())) {
-			Ok(backups) => backups,
+		let (backups, committed) = match rollback
+			.commit_noted_files(Vec::with_capacity(builder.capacity()), &CommitOptions::default())
+		{
+			Ok(output) => output,
 			_ => {
 				panic!("The call should be Ok");
 			},
@@ -68,6 +174,7 @@ fn commit_noted_files_works_well() {
 
 		// There's a backup for each noted file
 		assert_eq!(backups.len(), builder.capacity());
+		assert_eq!(committed, builder.capacity());
 	});
 }
 
@@ -76,21 +183,23 @@ fn commit_noted_files_fails_if_a_backup_cannot_be_created() {
 	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
 		// A backup cannot be created if the original has been deleted in the meanwhile
 		std::fs::remove_file(builder.existing_files()[0]).expect("The file exists; qed;");
-		let (error, backups) =
-			match rollback.commit_noted_files(Vec::with_capacity(builder.capacity())) {
-				Ok(_) => {
-					panic!("The call should be an error");
-				},
-				Err(output) => output,
-			};
+		let (error, backups) = match rollback
+			.commit_noted_files(Vec::with_capacity(builder.capacity()), &CommitOptions::default())
+		{
+			Ok(_) => {
+				panic!("The call should be an error");
+			},
+			Err(output) => output,
+		};
 
 		// The error is as expected
 		match error {
-			Error::Commit(item, err) => {
+			Error::CommitIo { path, phase, source } => {
 				// It says the original file doesn't exist => the backup wasn't created for that
 				// file
-				assert!(item == format!("{}", builder.existing_files()[0].display()));
-				assert!(err.contains("No such file or directory"));
+				assert_eq!(path, builder.existing_files()[0]);
+				assert_eq!(phase, CommitPhase::NotedFiles);
+				assert_eq!(source.kind(), ErrorKind::NotFound);
 			},
 			_ => panic!("Unexpected error"),
 		}
@@ -110,6 +219,40 @@ fn commit_noted_files_fails_if_a_backup_cannot_be_created() {
 	});
 }
 
+#[test]
+fn commit_noted_files_reports_every_failure() {
+	TestBuilder::new(Some(3)).with_noted_files().execute(|builder, rollback| {
+		let failing = &builder.existing_files()[..2];
+		for file in failing {
+			std::fs::remove_file(file).expect("The file exists; qed;");
+		}
+
+		let (error, _) = match rollback
+			.commit_noted_files(Vec::with_capacity(builder.capacity()), &CommitOptions::default())
+		{
+			Ok(_) => panic!("The call should be an error"),
+			Err(output) => output,
+		};
+
+		// Each noted file is committed by its own thread, so both failures are reported
+		let Error::Multiple(errors) = error else {
+			panic!("Unexpected error");
+		};
+		let mut items: Vec<_> = errors
+			.iter()
+			.map(|err| match err {
+				Error::CommitIo { path, .. } => format!("{}", path.display()),
+				_ => panic!("Unexpected error"),
+			})
+			.collect();
+		items.sort();
+		let mut expected: Vec<_> =
+			failing.iter().map(|file| format!("{}", file.display())).collect();
+		expected.sort();
+		assert_eq!(items, expected);
+	});
+}
+
 #[test]
 fn commit_noted_files_fails_if_a_noted_file_cannot_be_committed() {
 	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
@@ -121,20 +264,22 @@ fn commit_noted_files_fails_if_a_noted_file_cannot_be_committed() {
 		)
 		.expect("The file exists; qed;");
 
-		let (error, backups) =
-			match rollback.commit_noted_files(Vec::with_capacity(builder.capacity())) {
-				Ok(_) => {
-					panic!("The call should be an error");
-				},
-				Err(output) => output,
-			};
+		let (error, backups) = match rollback
+			.commit_noted_files(Vec::with_capacity(builder.capacity()), &CommitOptions::default())
+		{
+			Ok(_) => {
+				panic!("The call should be an error");
+			},
+			Err(output) => output,
+		};
 
 		// The error is as expected
 		match error {
-			Error::Commit(item, err) => {
+			Error::CommitIo { path, phase, source } => {
 				// The original file couldn't be committed
-				assert!(item == format!("{}", builder.existing_files()[0].display()));
-				assert!(err.contains("No such file or directory"));
+				assert_eq!(path, builder.existing_files()[0]);
+				assert_eq!(phase, CommitPhase::NotedFiles);
+				assert_eq!(source.kind(), ErrorKind::NotFound);
 			},
 			_ => panic!("Unexpected error"),
 		}
@@ -164,7 +309,12 @@ fn commit_new_dirs_works() {
 	TestBuilder::new(None).with_new_dirs().execute(|builder, rollback| {
 		builder.new_dirs().iter().for_each(|dir_path| assert!(!dir_path.is_dir()));
 
-		assert!(rollback.commit_new_dirs().is_ok());
+		assert!(rollback
+			.commit_new_dirs(
+				&rollback.commit_order().expect("The order is valid; qed;"),
+				&CommitOptions::default(),
+			)
+			.is_ok());
 
 		builder.new_dirs().iter().for_each(|dir_path| assert!(dir_path.is_dir()));
 	});
@@ -178,12 +328,16 @@ fn commit_new_dirs_fails_if_new_dirs_cannot_be_created() {
 		.execute(|builder, rollback| {
 			builder.new_dirs().iter().for_each(|dir_path| assert!(!dir_path.is_dir()));
 
-			match rollback.commit_new_dirs() {
-				Err(Error::Commit(_, err)) => {
+			match rollback.commit_new_dirs(
+				&rollback.commit_order().expect("The order is valid; qed;"),
+				&CommitOptions::default(),
+			) {
+				Err(Error::CommitIo { phase, source, .. }) => {
 					// No permissions in temp_dir => failure committing the dirs; Cannot ensure
 					// which one comes in the msg cause this runs concurrently and all of them
 					// failed
-					assert!(err.contains("Permission denied"));
+					assert_eq!(phase, CommitPhase::NewDirs);
+					assert_eq!(source.kind(), ErrorKind::PermissionDenied);
 				},
 				_ => panic!("Unexpected error"),
 			}
@@ -206,9 +360,14 @@ fn commit_new_dirs_fails_if_same_dir_noted_several_times() {
 		let refactored_path =
 			Path::new(path.file_name().expect("The path is a dir, so file_name exists; qed;"));
 
-		assert!(rollback.new_dir(&refactored_path).is_ok());
+		// The repeated dir is rejected when it's noted while its parent dir exists, so it's staged
+		// directly as if the parent dir didn't exist back then.
+		rollback.new_dirs.push(refactored_path.to_path_buf());
 
-		let result = rollback.commit_new_dirs();
+		let result = rollback.commit_new_dirs(
+			&rollback.commit_order().expect("The order is valid; qed;"),
+			&CommitOptions::default(),
+		);
 
 		std::env::set_current_dir(original_cwd)
 			.expect("The original_cwd should be able to be current_dir; qed;");
@@ -225,7 +384,12 @@ fn commit_new_files_works() {
 	TestBuilder::new(None).with_new_files().execute(|builder, rollback| {
 		builder.new_files().iter().for_each(|file_path| assert!(!file_path.is_file()));
 
-		assert!(rollback.commit_new_files().is_ok());
+		assert!(rollback
+			.commit_new_files(
+				&rollback.commit_order().expect("The order is valid; qed;"),
+				&CommitOptions::default(),
+			)
+			.is_ok());
 
 		builder.new_files().iter().for_each(|file_path| {
 			assert!(file_path.is_file());
@@ -250,9 +414,15 @@ fn commit_new_files_fails_if_same_file_noted_several_times() {
 		let refactored_path =
 			Path::new(path.file_name().expect("The path is a file, so file_name exists; qed;"));
 
-		assert!(rollback.new_file(&refactored_path).is_ok());
+		// The repeated file is rejected when it's noted while its parent dir exists, so it's
+		// staged directly as if the parent dir didn't exist back then.
+		let temp_file = std::sync::Arc::clone(&rollback.new_files[path]);
+		rollback.new_files.insert(refactored_path.to_path_buf(), temp_file);
 
-		let result = rollback.commit_new_files();
+		let result = rollback.commit_new_files(
+			&rollback.commit_order().expect("The order is valid; qed;"),
+			&CommitOptions::default(),
+		);
 
 		std::env::set_current_dir(original_cwd)
 			.expect("The original_cwd should be able to be current_dir; qed;");
@@ -272,12 +442,16 @@ fn commit_new_files_fails_if_new_files_cannot_be_created() {
 		.execute(|builder, rollback| {
 			builder.new_files().iter().for_each(|file_path| assert!(!file_path.is_file()));
 
-			match rollback.commit_new_files() {
-				Err(Error::Commit(_, err)) => {
+			match rollback.commit_new_files(
+				&rollback.commit_order().expect("The order is valid; qed;"),
+				&CommitOptions::default(),
+			) {
+				Err(Error::CommitIo { phase, source, .. }) => {
 					// No permissions in temp_dir => failure committing the files; cannot ensure
 					// which one comes in the message as this runs concurrently and all of them
 					// failed
-					assert!(err.contains("Permission denied"));
+					assert_eq!(phase, CommitPhase::NewFiles);
+					assert_eq!(source.kind(), ErrorKind::PermissionDenied);
 
 					// Files weren't created
 					builder.new_files().iter().for_each(|file_path| assert!(!file_path.is_file()));
@@ -300,35 +474,419 @@ fn commit_new_files_fails_if_the_temp_file_cannot_be_copied_to_the_new_file() {
 		)
 		.expect("The temporary file can be deleted; qed;");
 
-		match rollback.commit_new_files() {
-			Err(Error::Commit(item, err)) => {
+		match rollback.commit_new_files(
+			&rollback.commit_order().expect("The order is valid; qed;"),
+			&CommitOptions::default(),
+		) {
+			Err(Error::CommitIo { path, phase, source }) => {
 				// The temporary file was deleted for the first new file so it couldn't be
 				// created
-				assert!(item == format!("{}", builder.new_files()[0].display()));
-				assert!(err.contains("No such file or directory"));
+				assert_eq!(path, builder.new_files()[0]);
+				assert_eq!(phase, CommitPhase::NewFiles);
+				assert_eq!(source.kind(), ErrorKind::NotFound);
 			},
 			_ => panic!("Unexpected error"),
 		}
 
-		// The while the first file is created without content, other files may have been correctly
-		// created (maybe not all of them)
-		builder.new_files().iter().enumerate().for_each(|(index, file_path)| {
-			if index == 0 {
-				assert!(file_path.is_file());
-				assert_eq!(
-					std::fs::read_to_string(file_path)
-						.expect("The file exists and is readable; qed;"),
-					""
-				);
-			} else {
-				if file_path.is_file() {
-					assert_eq!(
-						std::fs::read_to_string(file_path)
-							.expect("The file exists and is readable; qed;"),
-						ORIGINAL_BUILDER_FILE_CONTENT
-					);
-				}
-			}
+		// The files created before the failure are removed
+		builder.new_files().iter().for_each(|file_path| assert!(!file_path.is_file()));
+	});
+}
+
+#[test]
+fn commit_renames_works() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let existing_files = builder.existing_files();
+		let new_files = builder.new_files();
+		existing_files.iter().zip(new_files.iter()).for_each(|(from, to)| {
+			rollback.rename(from, to).expect("The file can be renamed; qed;");
+		});
+
+		// The files are renamed when they're committed, so later changes are kept
+		std::fs::write(existing_files[0], MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("The file exists; qed;");
+		let order = rollback.commit_order().expect("The order is valid; qed;");
+		let mut backups = Vec::new();
+
+		assert!(rollback.commit_renames(&order, &CommitOptions::default(), &mut backups).is_ok());
+
+		existing_files.iter().for_each(|file| assert!(!file.exists()));
+		assert_eq!(
+			std::fs::read_to_string(new_files[1]).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::read_to_string(new_files[0]).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		// The files are renamed within the file system, so they aren't backed up
+		assert_eq!(backups.len(), new_files.len());
+		backups.iter().for_each(|(_, _, backup)| assert!(backup.is_none()));
+	});
+}
+
+#[test]
+fn commit_renames_undoes_the_renames_if_it_fails() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let existing_files = builder.existing_files();
+		let new_files = builder.new_files();
+		existing_files.iter().zip(new_files.iter()).for_each(|(from, to)| {
+			rollback.rename(from, to).expect("The file can be renamed; qed;");
+		});
+		// The last rename in the commit order cannot be committed as its new path exists now
+		std::fs::write(new_files[1], MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+
+		let order = rollback.commit_order().expect("The order is valid; qed;");
+		let mut backups = Vec::new();
+
+		match rollback.commit_renames(&order, &CommitOptions::default(), &mut backups) {
+			Err(Error::NewItemAlreadyExists(item)) =>
+				assert_eq!(item, format!("{}", new_files[1].display())),
+			_ => panic!("Unexpected error"),
+		}
+
+		existing_files.iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			)
+		});
+		assert!(!new_files[0].exists());
+		// The file created meanwhile is untouched
+		assert_eq!(
+			std::fs::read_to_string(new_files[1]).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn commit_deleted_files_works() {
+	TestBuilder::new(None).execute(|builder, mut rollback| {
+		builder.existing_files().iter().for_each(|file| {
+			rollback.delete_file(file).expect("The file can be deleted; qed;");
+		});
+		// The files are backed up when they're deleted, so later changes are kept
+		builder.existing_files().iter().for_each(|file| {
+			std::fs::write(file, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;")
+		});
+		let mut backups = Vec::new();
+
+		assert!(rollback
+			.commit_deleted_files(
+				&rollback.commit_order().expect("The order is valid; qed;"),
+				&CommitOptions::default(),
+				&mut backups,
+			)
+			.is_ok());
+
+		builder.existing_files().iter().for_each(|file| assert!(!file.exists()));
+		assert_eq!(backups.len(), builder.existing_files().len());
+		backups.iter().for_each(|backup| {
+			assert_eq!(backup.path().parent(), backup.original().parent());
+			assert_eq!(
+				std::fs::read_to_string(backup.path()).expect("The backup exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+		});
+	});
+}
+
+#[test]
+fn commit_deleted_files_fails_if_a_file_cannot_be_deleted() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.delete_file(path).expect("The file can be deleted; qed;");
+		std::fs::remove_file(path).expect("The file exists; qed;");
+
+		let mut backups = Vec::new();
+
+		match rollback.commit_deleted_files(
+			&rollback.commit_order().expect("The order is valid; qed;"),
+			&CommitOptions::default(),
+			&mut backups,
+		) {
+			Err(Error::CommitIo { path: item, phase, .. }) => {
+				assert_eq!(item, path);
+				assert_eq!(phase, CommitPhase::DeletedFiles);
+			},
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn commit_deleted_dirs_works() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("dir");
+		let inner_dir = dir.join("inner");
+		std::fs::create_dir_all(&inner_dir).expect("The dirs can be created; qed;");
+		// Rebind rollback to accomplish with dir lifetime
+		let mut rollback = rollback;
+		rollback.delete_dir(&dir).expect("The dir can be deleted; qed;");
+		rollback.delete_dir(&inner_dir).expect("The dir can be deleted; qed;");
+
+		let order = rollback.commit_order().expect("The order is valid; qed;");
+		// Nested dirs are deleted first
+		assert_eq!(order, vec![Operation::DeleteDir(&inner_dir), Operation::DeleteDir(&dir)]);
+		assert!(rollback.commit_deleted_dirs(&order, &CommitOptions::default()).is_ok());
+
+		assert!(!dir.exists());
+	});
+}
+
+#[test]
+fn commit_deleted_dirs_fails_if_a_dir_cannot_be_deleted() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("dir");
+		std::fs::create_dir(&dir).expect("The dir can be created; qed;");
+		// Rebind rollback to accomplish with dir lifetime
+		let mut rollback = rollback;
+		rollback.delete_dir(&dir).expect("The dir can be deleted; qed;");
+		std::fs::remove_dir(&dir).expect("The dir exists; qed;");
+
+		match rollback.commit_deleted_dirs(
+			&rollback.commit_order().expect("The order is valid; qed;"),
+			&CommitOptions::default(),
+		) {
+			Err(Error::CommitIo { path, phase, .. }) => {
+				assert_eq!(path, dir);
+				assert_eq!(phase, CommitPhase::DeletedDirs);
+			},
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn commit_baseline_works() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.execute(|builder, mut rollback| {
+			let baseline_dir = builder.get_temp_dir_path().join("baseline");
+
+			let mut previous = Vec::new();
+
+			// Without baseline, nothing's recorded
+			assert!(rollback.commit_baseline(&mut previous).is_ok());
+			assert!(!baseline_dir.exists());
+			assert!(previous.is_empty());
+
+			rollback.baseline_dir = Some(baseline_dir.clone());
+			assert!(rollback
+				.commit_new_files(
+					&rollback.commit_order().expect("The order is valid; qed;"),
+					&CommitOptions::default(),
+				)
+				.is_ok());
+			assert!(rollback.commit_baseline(&mut previous).is_ok());
+
+			// A baseline entry per committed file
+			assert_eq!(
+				std::fs::read_dir(&baseline_dir).expect("The baseline dir exists; qed;").count(),
+				2 * builder.capacity()
+			);
+			assert_eq!(previous.len(), 2 * builder.capacity());
+
+			// Restoring the previous entries removes the new ones
+			baseline::restore(&previous);
+			assert_eq!(
+				std::fs::read_dir(&baseline_dir).expect("The baseline dir exists; qed;").count(),
+				0
+			);
+		});
+}
+
+#[test]
+fn commit_baseline_fails_if_a_file_cannot_be_recorded() {
+	TestBuilder::new(None).with_new_files().execute(|builder, mut rollback| {
+		let baseline_dir = builder.get_temp_dir_path().join("baseline");
+		rollback.baseline_dir = Some(baseline_dir);
+
+		// The new files haven't been committed, so they cannot be recorded
+		match rollback.commit_baseline(&mut Vec::new()) {
+			Err(Error::CommitIo { phase, source, .. }) => {
+				assert_eq!(phase, CommitPhase::Baseline);
+				assert_eq!(source.kind(), ErrorKind::NotFound);
+			},
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn verify_commit_works() {
+	TestBuilder::new(None)
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let paranoid = CommitOptions { paranoid: true, ..Default::default() };
+			builder.new_dirs().iter().for_each(|dir| {
+				std::fs::create_dir_all(dir).expect("The dir can be created; qed;")
+			});
+
+			assert!(rollback.verify_commit(&paranoid).is_ok());
+		});
+}
+
+#[test]
+fn verify_commit_fails_if_a_new_dir_doesnt_exist() {
+	TestBuilder::new(None).with_new_dirs().execute(|builder, rollback| {
+		let paranoid = CommitOptions { paranoid: true, ..Default::default() };
+
+		// Nothing's verified unless the commit is paranoid
+		assert!(rollback.verify_commit(&CommitOptions::default()).is_ok());
+
+		match rollback.verify_commit(&paranoid) {
+			Err(Error::Commit(item, _)) => {
+				assert!(builder.new_dirs().iter().any(|dir| item == format!("{}", dir.display())))
+			},
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn verify_commit_reads_back_committed_files_if_the_commit_is_verified() {
+	TestBuilder::new(Some(1))
+		.with_noted_files()
+		.with_new_files()
+		.execute(|builder, rollback| {
+			let verified = CommitOptions { verified: true, ..Default::default() };
+			let noted = builder.existing_files()[0];
+			let new_file = builder.new_files()[0];
+			std::fs::write(noted, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+			std::fs::write(new_file, ORIGINAL_BUILDER_FILE_CONTENT)
+				.expect("The file can be created; qed;");
+
+			assert!(rollback.verify_commit(&verified).is_ok());
+
+			// Another writer modifies a committed file
+			std::fs::write(new_file, "concurrent write").expect("The file exists; qed;");
+
+			assert!(matches!(
+				rollback.verify_commit(&verified),
+				Err(Error::CommitIo { path, phase: CommitPhase::Verification, .. }) if path == new_file
+			));
+			// Nothing's read back unless the commit is verified
+			assert!(rollback.verify_commit(&CommitOptions::default()).is_ok());
 		});
+}
+
+#[test]
+fn verify_and_sync_fails_if_contents_differ() {
+	TestBuilder::new(Some(2)).execute(|builder, _| {
+		let [source, committed] = builder.existing_files()[..] else {
+			panic!("There's 2 existing files; qed;");
+		};
+		assert!(verify_and_sync(source, committed).is_ok());
+
+		std::fs::write(committed, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+		assert!(matches!(verify_and_sync(source, committed), Err(Error::IO(_))));
+	});
+}
+
+#[test]
+fn commit_completion_marker_works() {
+	TestBuilder::new(Some(0)).execute(|builder, mut rollback| {
+		let marker = builder.get_temp_dir_path().join(".committed");
+
+		// Without marker, nothing's created
+		assert!(rollback.commit_completion_marker().is_ok());
+		assert!(!marker.exists());
+
+		rollback.completion_marker = Some(marker.clone());
+		assert!(rollback.commit_completion_marker().is_ok());
+		assert!(marker.is_file());
+	});
+}
+
+#[test]
+fn commit_completion_marker_fails_if_the_marker_cannot_be_created() {
+	TestBuilder::new(Some(0)).execute(|builder, mut rollback| {
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		rollback.completion_marker = Some(marker.clone());
+
+		match rollback.commit_completion_marker() {
+			Err(Error::CommitIo { path, phase, source }) => {
+				assert_eq!(path, marker);
+				assert_eq!(phase, CommitPhase::CompletionMarker);
+				assert_eq!(source.kind(), ErrorKind::NotFound);
+			},
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn commit_dirs_metadata_works() {
+	TestBuilder::new(Some(0)).execute(|builder, mut rollback| {
+		let dir = builder.get_temp_dir_path();
+		rollback.note_dir_metadata(dir).expect("The dir should be noted; qed;");
+		rollback.set_dir_mode(dir, 0o700).expect("The dir is noted; qed;");
+
+		assert!(rollback
+			.commit_dirs_metadata(
+				&rollback.commit_order().expect("The order is valid; qed;"),
+				&CommitOptions::default(),
+			)
+			.is_ok());
+		assert_eq!(std::fs::metadata(dir).expect("The dir exists; qed;").mode() & 0o7777, 0o700);
+	});
+}
+
+#[test]
+fn commit_dirs_metadata_fails_if_metadata_cannot_be_changed() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let dir = builder.new_dirs()[0];
+		std::fs::create_dir(dir).expect("The dir should be created; qed;");
+		let mut rollback = rollback;
+		rollback.note_dir_metadata(dir).expect("The dir should be noted; qed;");
+		rollback.set_dir_mode(dir, 0o700).expect("The dir is noted; qed;");
+
+		// The dir doesn't exist anymore
+		std::fs::remove_dir(dir).expect("The dir should be removed; qed;");
+
+		match rollback.commit_dirs_metadata(
+			&rollback.commit_order().expect("The order is valid; qed;"),
+			&CommitOptions::default(),
+		) {
+			Err(Error::CommitIo { path, phase, source }) => {
+				assert_eq!(path, dir);
+				assert_eq!(phase, CommitPhase::DirsMetadata);
+				assert_eq!(source.kind(), ErrorKind::NotFound);
+			},
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn rollback_dirs_metadata_works() {
+	TestBuilder::new(Some(0)).execute(|builder, mut rollback| {
+		let dir = builder.get_temp_dir_path();
+		let original_mode = std::fs::metadata(dir).expect("The dir exists; qed;").mode() & 0o7777;
+		rollback.note_dir_metadata(dir).expect("The dir should be noted; qed;");
+
+		std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+			.expect("The permissions should be set; qed;");
+
+		rollback
+			.rollback_dirs_metadata(&rollback.commit_order().expect("The order is valid; qed;"));
+
+		assert_eq!(
+			std::fs::metadata(dir).expect("The dir exists; qed;").mode() & 0o7777,
+			original_mode
+		);
 	});
 }
+
+#[test]
+fn sanitize_file_name_works() {
+	assert_eq!(sanitize_file_name(OsStr::new("config.v2-final_1.toml")), "config.v2-final_1.toml");
+	assert_eq!(sanitize_file_name(OsStr::new("my file (1).txt")), "my_file__1_.txt");
+	assert_eq!(sanitize_file_name(OsStr::new(&"a".repeat(100))), "a".repeat(64));
+}
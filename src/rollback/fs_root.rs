@@ -0,0 +1,27 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use std::{
+	borrow::Cow,
+	path::{Component, Path, PathBuf},
+};
+
+// Rebases an absolute path onto the root, so the root stands for `/`. Relative paths, and every
+// path if there's no root, are returned untouched. This is pure path joining, so `..` components
+// may still escape the root.
+pub(crate) fn rebase<'p>(root: Option<&Path>, path: &'p Path) -> Cow<'p, Path> {
+	match root {
+		Some(root) if path.has_root() => Cow::Owned(
+			root.join(
+				path.components()
+					.filter(|component| {
+						!matches!(component, Component::Prefix(_) | Component::RootDir)
+					})
+					.collect::<PathBuf>(),
+			),
+		),
+		_ => Cow::Borrowed(path),
+	}
+}
@@ -0,0 +1,27 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+
+#[test]
+fn rebase_works_with_absolute_paths() {
+	let root = Path::new("/tmp/root");
+
+	assert_eq!(rebase(Some(root), Path::new("/etc/foo")), Path::new("/tmp/root/etc/foo"));
+	assert_eq!(rebase(Some(root), Path::new("/")), root);
+}
+
+#[test]
+fn rebase_keeps_relative_paths() {
+	let path = Path::new("etc/foo");
+
+	assert!(
+		matches!(rebase(Some(Path::new("/tmp/root")), path), Cow::Borrowed(rebased) if rebased == path)
+	);
+}
+
+#[test]
+fn rebase_keeps_paths_without_root() {
+	let path = Path::new("/etc/foo");
+
+	assert!(matches!(rebase(None, path), Cow::Borrowed(rebased) if rebased == path));
+}
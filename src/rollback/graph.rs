@@ -0,0 +1,296 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::{rollback::phase::CommitPhase, Error, Rollback};
+use std::{
+	collections::VecDeque,
+	fmt::Write,
+	path::{Path, PathBuf},
+};
+
+// An operation staged in a rollback. Operations are the nodes of the dependency graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation<'a> {
+	NoteFile(&'a Path),
+	NewDir(&'a Path),
+	NewFile(&'a Path),
+	NewSymlink(&'a Path),
+	// The current path and the new path of the renamed file.
+	Rename(&'a Path, &'a Path),
+	DeleteFile(&'a Path),
+	DeleteDir(&'a Path),
+	#[cfg(unix)]
+	DirMetadata(&'a Path),
+}
+
+impl<'a> Operation<'a> {
+	pub(crate) fn path(&self) -> &'a Path {
+		match self {
+			Self::NoteFile(path) |
+			Self::NewDir(path) |
+			Self::NewFile(path) |
+			Self::NewSymlink(path) |
+			Self::DeleteFile(path) |
+			Self::DeleteDir(path) |
+			Self::Rename(path, _) => path,
+			#[cfg(unix)]
+			Self::DirMetadata(path) => path,
+		}
+	}
+
+	fn kind(&self) -> &'static str {
+		match self {
+			Self::NoteFile(_) => "note_file",
+			Self::NewDir(_) => "new_dir",
+			Self::NewFile(_) => "new_file",
+			Self::NewSymlink(_) => "new_symlink",
+			Self::Rename(..) => "rename",
+			Self::DeleteFile(_) => "delete_file",
+			Self::DeleteDir(_) => "delete_dir",
+			#[cfg(unix)]
+			Self::DirMetadata(_) => "dir_metadata",
+		}
+	}
+
+	// The commit phase committing the operation.
+	pub(crate) fn phase(&self) -> CommitPhase {
+		match self {
+			Self::NoteFile(_) => CommitPhase::NotedFiles,
+			Self::NewDir(_) => CommitPhase::NewDirs,
+			Self::NewFile(_) => CommitPhase::NewFiles,
+			Self::NewSymlink(_) => CommitPhase::NewSymlinks,
+			Self::Rename(..) => CommitPhase::Renames,
+			Self::DeleteFile(_) => CommitPhase::DeletedFiles,
+			Self::DeleteDir(_) => CommitPhase::DeletedDirs,
+			#[cfg(unix)]
+			Self::DirMetadata(_) => CommitPhase::DirsMetadata,
+		}
+	}
+
+	// Checks if this operation must be committed after `other`.
+	fn depends_on(&self, other: &Self) -> bool {
+		let is_inside = |parent: &Path| self.path() != parent && self.path().starts_with(parent);
+		match (self, other) {
+			// Things inside a new dir need the dir to exist.
+			(Self::NewDir(_) | Self::NewFile(_) | Self::NewSymlink(_), Self::NewDir(dir)) =>
+				is_inside(dir),
+			// The new path of a renamed file may be inside a new dir.
+			(Self::Rename(_, to), Self::NewDir(dir)) => to != dir && to.starts_with(dir),
+			// New symlinks may point to dirs, so new items may be created through them.
+			(Self::NewDir(_) | Self::NewFile(_) | Self::NewSymlink(_), Self::NewSymlink(link)) =>
+				is_inside(link),
+			(Self::Rename(_, to), Self::NewSymlink(link)) => to != link && to.starts_with(link),
+			// A deleted dir is deleted after everything inside it, so nested deleted dirs are
+			// deleted before the dirs containing them.
+			(Self::DeleteDir(dir), _) => other.path() != *dir && other.path().starts_with(dir),
+			// Changing a dir metadata may restrict the access to its content, so everything
+			// inside the dir should be committed first.
+			#[cfg(unix)]
+			(Self::DirMetadata(dir), _) => other.path() != *dir && other.path().starts_with(dir),
+			_ => false,
+		}
+	}
+}
+
+// The dependency graph between the operations staged in a rollback. Nodes are sorted by kind and
+// path, so the graph, and hence the commit order, is deterministic.
+//
+// The commit is driven by the graph (see `Graph::commit_steps`): it follows the phase sequence
+// (see `CommitPhase::sequence`) as long as it honors the dependencies, and commits the operations
+// depending on an operation of a later phase in a further pass. Dependencies always go from an
+// operation on a dir, or on a symlink that may point to a dir, to the operations on the paths
+// nested inside it, so staging cannot produce a cycle: the cycle check is only a safeguard for the
+// graph invariants.
+pub(crate) struct Graph<'a> {
+	pub(crate) nodes: Vec<Operation<'a>>,
+	// edges[i] contains the nodes depending on the node i.
+	pub(crate) edges: Vec<Vec<usize>>,
+}
+
+impl<'a> Graph<'a> {
+	pub(crate) fn new(rollback: &'a Rollback<'_>) -> Self {
+		let sorted = |mut paths: Vec<&'a Path>| {
+			paths.sort();
+			paths
+		};
+
+		let mut nodes = Vec::new();
+		nodes.extend(
+			sorted(rollback.noted.keys().map(PathBuf::as_path).collect())
+				.into_iter()
+				.map(Operation::NoteFile),
+		);
+		nodes.extend(
+			sorted(rollback.new_dirs.iter().map(PathBuf::as_path).collect())
+				.into_iter()
+				.map(Operation::NewDir),
+		);
+		nodes.extend(
+			sorted(rollback.new_files.keys().map(PathBuf::as_path).collect())
+				.into_iter()
+				.map(Operation::NewFile),
+		);
+		nodes.extend(
+			sorted(rollback.new_symlinks.keys().map(PathBuf::as_path).collect())
+				.into_iter()
+				.map(Operation::NewSymlink),
+		);
+		nodes.extend(
+			sorted(rollback.renames.keys().map(PathBuf::as_path).collect())
+				.into_iter()
+				.map(|from| Operation::Rename(from, &rollback.renames[from])),
+		);
+		nodes.extend(
+			sorted(rollback.deleted.iter().map(PathBuf::as_path).collect())
+				.into_iter()
+				.map(Operation::DeleteFile),
+		);
+		nodes.extend(
+			sorted(rollback.deleted_dirs.keys().map(PathBuf::as_path).collect())
+				.into_iter()
+				.map(Operation::DeleteDir),
+		);
+		#[cfg(unix)]
+		nodes.extend(
+			sorted(rollback.dirs_metadata.keys().map(PathBuf::as_path).collect())
+				.into_iter()
+				.map(Operation::DirMetadata),
+		);
+
+		// Dependencies always involve a dir or a new symlink, so there's no need to check every
+		// pair of nodes.
+		let is_dir = |node: &Operation| {
+			!matches!(
+				node,
+				Operation::NoteFile(_) |
+					Operation::NewFile(_) |
+					Operation::Rename(..) |
+					Operation::DeleteFile(_)
+			)
+		};
+		let dirs: Vec<usize> = (0..nodes.len()).filter(|&index| is_dir(&nodes[index])).collect();
+
+		let mut edges = vec![Vec::new(); nodes.len()];
+		for &dir in dirs.iter() {
+			for (index, node) in nodes.iter().enumerate() {
+				if node.depends_on(&nodes[dir]) {
+					edges[dir].push(index);
+				} else if !is_dir(node) && nodes[dir].depends_on(node) {
+					edges[index].push(dir);
+				}
+			}
+		}
+
+		Self { nodes, edges }
+	}
+
+	// Graphviz DOT representation of the graph. Edges go from each operation to the operations
+	// depending on it.
+	pub(crate) fn to_dot(&self) -> String {
+		let mut dot = String::from("digraph rollback {\n");
+		for (index, node) in self.nodes.iter().enumerate() {
+			let path =
+				format!("{}", node.path().display()).replace('\\', "\\\\").replace('"', "\\\"");
+			// Writing to a String cannot fail; qed;
+			let _ = writeln!(dot, "\t{} [label=\"{} {}\"];", index, node.kind(), path);
+		}
+		for (index, dependents) in self.edges.iter().enumerate() {
+			for dependent in dependents {
+				let _ = writeln!(dot, "\t{} -> {};", index, dependent);
+			}
+		}
+		dot.push_str("}\n");
+		dot
+	}
+
+	// Sorts the operations so every operation comes after the operations it depends on. Ties are
+	// broken by the nodes order, so the result is deterministic.
+	pub(crate) fn topological_order(&self) -> Result<Vec<Operation<'a>>, Error> {
+		Ok(self.sorted_nodes()?.into_iter().map(|node| self.nodes[node]).collect())
+	}
+
+	// Splits the commit into steps, each one committing a phase of the sequence together with its
+	// operations, in topological order. The sequence is followed once with every operation whose
+	// dependencies are committed by earlier phases. The operations depending on an operation of
+	// a later phase, e.g. a new file inside a new symlink, are committed by a further pass over
+	// the sequence, which only includes the phases with operations left. The phases that don't
+	// commit operations, e.g. the verification, only run once at the end of the first pass.
+	pub(crate) fn commit_steps(
+		&self,
+		sequence: &[CommitPhase],
+	) -> Result<Vec<(CommitPhase, Vec<Operation<'a>>)>, Error> {
+		let rank = |phase: CommitPhase| {
+			sequence
+				.iter()
+				.position(|item| *item == phase)
+				.expect("Every operation phase is in the sequence; qed;")
+		};
+		let sorted = self.sorted_nodes()?;
+
+		// The pass committing each node: the pass of its latest dependency, or the next one if
+		// that dependency is committed by a later phase.
+		let mut passes = vec![0; self.nodes.len()];
+		for &node in sorted.iter() {
+			let phase = rank(self.nodes[node].phase());
+			for &dependent in self.edges[node].iter() {
+				let pass = passes[node] + usize::from(phase > rank(self.nodes[dependent].phase()));
+				passes[dependent] = passes[dependent].max(pass);
+			}
+		}
+
+		let last_pass = passes.iter().copied().max().unwrap_or_default();
+		let mut steps = Vec::new();
+		for pass in 0..=last_pass {
+			for phase in sequence.iter().filter(|phase| phase.commits_operations()) {
+				let operations: Vec<_> = sorted
+					.iter()
+					.filter(|&&node| passes[node] == pass && self.nodes[node].phase() == *phase)
+					.map(|&node| self.nodes[node])
+					.collect();
+				if pass == 0 || !operations.is_empty() {
+					steps.push((*phase, operations));
+				}
+			}
+		}
+		steps.extend(
+			sequence
+				.iter()
+				.filter(|phase| !phase.commits_operations())
+				.map(|phase| (*phase, Vec::new())),
+		);
+		Ok(steps)
+	}
+
+	// The indexes of the nodes in topological order.
+	fn sorted_nodes(&self) -> Result<Vec<usize>, Error> {
+		let mut in_degree = vec![0; self.nodes.len()];
+		self.edges.iter().flatten().for_each(|&node| in_degree[node] += 1);
+
+		let mut queue: VecDeque<usize> =
+			(0..self.nodes.len()).filter(|&node| in_degree[node] == 0).collect();
+		let mut order = Vec::with_capacity(self.nodes.len());
+
+		while let Some(node) = queue.pop_front() {
+			order.push(node);
+			for &dependent in self.edges[node].iter() {
+				in_degree[dependent] -= 1;
+				if in_degree[dependent] == 0 {
+					queue.push_back(dependent);
+				}
+			}
+		}
+
+		if order.len() < self.nodes.len() {
+			let cycle = (0..self.nodes.len())
+				.filter(|&node| in_degree[node] > 0)
+				.map(|node| format!("{}", self.nodes[node].path().display()))
+				.collect::<Vec<_>>()
+				.join(", ");
+			return Err(Error::CyclicOperations(cycle));
+		}
+
+		Ok(order)
+	}
+}
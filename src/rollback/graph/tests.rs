@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::TestBuilder;
+
+#[test]
+fn graph_new_works() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let dir = builder.new_dirs()[0];
+		let inner_dir = dir.join("inner");
+		let inner_file = inner_dir.join("file.txt");
+		let mut rollback = rollback;
+		// Insertion order doesn't matter, as long as new files are registered after their parent
+		rollback.new_dir(&inner_dir).expect("The dir should be added; qed;");
+		rollback.new_file(&inner_file).expect("The file should be added; qed;");
+		rollback.new_dir(dir).expect("The dir should be added; qed;");
+		rollback
+			.note_dir_metadata(builder.get_temp_dir_path())
+			.expect("The dir should be noted; qed;");
+
+		let graph = Graph::new(&rollback);
+
+		assert_eq!(
+			graph.nodes,
+			vec![
+				Operation::NoteFile(builder.existing_files()[0]),
+				Operation::NewDir(dir),
+				Operation::NewDir(&inner_dir),
+				Operation::NewFile(&inner_file),
+				Operation::DirMetadata(builder.get_temp_dir_path()),
+			]
+		);
+
+		// Everything's inside the dir whose metadata is changed.
+		assert_eq!(graph.edges[0], vec![4]);
+		assert_eq!(graph.edges[1], vec![2, 3, 4]);
+		assert_eq!(graph.edges[2], vec![3, 4]);
+		assert_eq!(graph.edges[3], vec![4]);
+		assert!(graph.edges[4].is_empty());
+	});
+}
+
+#[test]
+fn topological_order_works() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("b");
+		let inner_dir = dir.join("a");
+		let other_dir = builder.get_temp_dir_path().join("a");
+		let mut rollback = rollback;
+		rollback.new_dir(&inner_dir).expect("The dir should be added; qed;");
+		rollback.new_dir(&dir).expect("The dir should be added; qed;");
+		rollback.new_dir(&other_dir).expect("The dir should be added; qed;");
+
+		let order = Graph::new(&rollback).topological_order().expect("There's not any cycle; qed;");
+
+		assert_eq!(
+			order,
+			vec![
+				Operation::NewDir(&other_dir),
+				Operation::NewDir(&dir),
+				Operation::NewDir(&inner_dir),
+			]
+		);
+	});
+}
+
+#[test]
+fn topological_order_fails_if_there_is_a_cycle() {
+	let (a, b, c) = (Path::new("a"), Path::new("b"), Path::new("c"));
+	let graph = Graph {
+		nodes: vec![Operation::NewDir(a), Operation::NewDir(b), Operation::NewDir(c)],
+		edges: vec![vec![1], vec![2], vec![1]],
+	};
+
+	assert!(matches!(
+		graph.topological_order(),
+		Err(Error::CyclicOperations(cycle)) if cycle == "b, c"
+	));
+}
+
+#[test]
+fn to_dot_works() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let dir = builder.new_dirs()[0];
+		let file = dir.join("file.txt");
+		let mut rollback = rollback;
+		rollback.new_dir(dir).expect("The dir should be added; qed;");
+		rollback.new_file(&file).expect("The file should be added; qed;");
+
+		assert_eq!(
+			Graph::new(&rollback).to_dot(),
+			format!(
+				"digraph rollback {{\n\t0 [label=\"new_dir {}\"];\n\t1 [label=\"new_file {}\"];\n\t0 -> 1;\n}}\n",
+				dir.display(),
+				file.display()
+			)
+		);
+	});
+}
+
+#[test]
+fn to_dot_escapes_paths() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let file = builder.get_temp_dir_path().join("fi\"le\\.txt");
+		let mut rollback = rollback;
+		rollback.new_file(&file).expect("The file should be added; qed;");
+
+		assert!(Graph::new(&rollback).to_dot().contains("fi\\\"le\\\\.txt"));
+	});
+}
+
+#[test]
+fn commit_steps_follows_the_sequence() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let dir = builder.new_dirs()[0];
+		let file = dir.join("file.txt");
+		let mut rollback = rollback;
+		rollback.new_dir(dir).expect("The dir should be added; qed;");
+		rollback.new_file(&file).expect("The file should be added; qed;");
+		let sequence = CommitPhase::sequence(Default::default());
+
+		let steps = Graph::new(&rollback)
+			.commit_steps(&sequence)
+			.expect("There's not any cycle; qed;");
+
+		// Every phase runs once, in the sequence order
+		assert_eq!(steps.iter().map(|(phase, _)| *phase).collect::<Vec<_>>(), sequence);
+		assert_eq!(steps[0].1, vec![Operation::NoteFile(builder.existing_files()[0])]);
+		assert_eq!(steps[1].1, vec![Operation::NewDir(dir)]);
+		assert_eq!(steps[2].1, vec![Operation::NewFile(&file)]);
+		assert!(steps[3..].iter().all(|(_, operations)| operations.is_empty()));
+	});
+}
+
+#[test]
+fn commit_steps_commits_items_inside_new_symlinks_afterwards() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let link = builder.get_temp_dir_path().join("link");
+		let (dir, file) = (link.join("dir"), link.join("file.txt"));
+		let renamed = link.join("renamed.txt");
+		let mut rollback = rollback;
+		rollback.new_symlink(&link, "target").expect("The link should be added; qed;");
+		rollback.new_dir(&dir).expect("The dir should be added; qed;");
+		rollback.new_file(&file).expect("The file should be added; qed;");
+		rollback
+			.rename(builder.existing_files()[0], &renamed)
+			.expect("The file should be renamed; qed;");
+		let sequence = CommitPhase::sequence(Default::default());
+
+		let steps = Graph::new(&rollback)
+			.commit_steps(&sequence)
+			.expect("There's not any cycle; qed;");
+
+		// The items inside the link are committed by a second pass once the link exists, while
+		// the rename already comes after the link in the sequence
+		let first_pass = sequence.iter().filter(|phase| phase.commits_operations()).count();
+		assert_eq!(steps[3], (CommitPhase::NewSymlinks, vec![Operation::NewSymlink(&link)]));
+		assert_eq!(
+			steps[4],
+			(CommitPhase::Renames, vec![Operation::Rename(builder.existing_files()[0], &renamed)])
+		);
+		assert_eq!(
+			steps[first_pass..first_pass + 2],
+			[
+				(CommitPhase::NewDirs, vec![Operation::NewDir(&dir)]),
+				(CommitPhase::NewFiles, vec![Operation::NewFile(&file)]),
+			]
+		);
+		assert_eq!(steps[first_pass + 2].0, CommitPhase::Verification);
+		assert_eq!(steps.len(), sequence.len() + 2);
+	});
+}
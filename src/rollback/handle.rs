@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use std::{
+	fs::File,
+	io::{self, Read, Seek, SeekFrom, Write},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+};
+
+/// A handle to the temporary file of a noted file, obtained through
+/// [`Rollback::get_noted_handle`](crate::Rollback::get_noted_handle). It can be read, written and
+/// seeked as a [`File`], while it tracks whether something has been written through it, so the
+/// rollback knows if the noted file has been actually modified.
+#[derive(Debug)]
+pub struct NotedHandle {
+	file: File,
+	dirty: Arc<AtomicBool>,
+}
+
+impl NotedHandle {
+	pub(crate) fn new(file: File, dirty: Arc<AtomicBool>) -> Self {
+		Self { file, dirty }
+	}
+
+	/// Truncates or extends the noted file, as [`File::set_len`] does. This counts as a write.
+	pub fn set_len(&self, size: u64) -> io::Result<()> {
+		self.file.set_len(size)?;
+		self.dirty.store(true, Ordering::SeqCst);
+		Ok(())
+	}
+
+	/// Checks if something has been written to the noted file through any of its handles.
+	pub fn is_dirty(&self) -> bool {
+		self.dirty.load(Ordering::SeqCst)
+	}
+}
+
+impl Read for NotedHandle {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.file.read(buf)
+	}
+}
+
+impl Write for NotedHandle {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let written = self.file.write(buf)?;
+		if written > 0 {
+			self.dirty.store(true, Ordering::SeqCst);
+		}
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.file.flush()
+	}
+}
+
+impl Seek for NotedHandle {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		self.file.seek(pos)
+	}
+}
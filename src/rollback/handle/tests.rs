@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use tempfile::NamedTempFile;
+
+fn handle() -> (NamedTempFile, NotedHandle) {
+	let temp_file = NamedTempFile::new().expect("The temp file can be created; qed;");
+	let file = temp_file.reopen().expect("The temp file exists; qed;");
+	(temp_file, NotedHandle::new(file, Arc::new(AtomicBool::new(false))))
+}
+
+#[test]
+fn write_marks_the_handle_as_dirty() {
+	let (temp_file, mut handle) = handle();
+	assert!(!handle.is_dirty());
+
+	handle.write_all(b"Hello world!").expect("The handle is writable; qed;");
+
+	assert!(handle.is_dirty());
+	assert_eq!(
+		std::fs::read_to_string(temp_file.path()).expect("The temp file exists; qed;"),
+		"Hello world!"
+	);
+}
+
+#[test]
+fn set_len_marks_the_handle_as_dirty() {
+	let (temp_file, handle) = handle();
+	std::fs::write(temp_file.path(), "Hello world!").expect("The temp file exists; qed;");
+
+	handle.set_len(5).expect("The handle is writable; qed;");
+
+	assert!(handle.is_dirty());
+	assert_eq!(
+		std::fs::read_to_string(temp_file.path()).expect("The temp file exists; qed;"),
+		"Hello"
+	);
+}
+
+#[test]
+fn read_and_seek_dont_mark_the_handle_as_dirty() {
+	let (temp_file, mut handle) = handle();
+	std::fs::write(temp_file.path(), "Hello world!").expect("The temp file exists; qed;");
+
+	let mut content = String::new();
+	handle.read_to_string(&mut content).expect("The handle is readable; qed;");
+	handle.seek(SeekFrom::Start(0)).expect("The handle is seekable; qed;");
+	handle.write_all(b"").expect("The handle is writable; qed;");
+
+	assert_eq!(content, "Hello world!");
+	assert!(!handle.is_dirty());
+}
+
+#[test]
+fn handles_share_the_dirty_flag() {
+	let (temp_file, mut handle) = handle();
+	let other = NotedHandle::new(
+		temp_file.reopen().expect("The temp file exists; qed;"),
+		Arc::clone(&handle.dirty),
+	);
+
+	handle.write_all(b"Hello world!").expect("The handle is writable; qed;");
+
+	assert!(other.is_dirty());
+}
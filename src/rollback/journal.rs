@@ -0,0 +1,17 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::Error;
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+// Appends an entry to the journal, creating it if needed. The entry is flushed to disk before
+// returning, so it survives a crash happening right after. Each entry is written at once, so
+// entries recorded concurrently don't interleave.
+pub(crate) fn record(journal: &Path, entry: &str) -> Result<(), Error> {
+	let mut file = OpenOptions::new().create(true).append(true).open(journal)?;
+	file.write_all(format!("{}\n", entry).as_bytes())?;
+	file.sync_data()?;
+	Ok(())
+}
@@ -0,0 +1,26 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::TestBuilder;
+
+#[test]
+fn record_works() {
+	let builder = TestBuilder::new(Some(0));
+	let journal = builder.get_temp_dir_path().join("journal");
+
+	assert!(record(&journal, "first entry").is_ok());
+	assert!(record(&journal, "second entry").is_ok());
+
+	assert_eq!(
+		std::fs::read_to_string(&journal).expect("The journal exists; qed;"),
+		"first entry\nsecond entry\n"
+	);
+}
+
+#[test]
+fn record_fails_if_the_journal_cannot_be_created() {
+	let builder = TestBuilder::new(Some(0));
+	let journal = builder.get_temp_dir_path().join("missing").join("journal");
+
+	assert!(matches!(record(&journal, "entry"), Err(Error::IO(_))));
+}
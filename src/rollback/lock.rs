@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::Error;
+use std::{fs::File, path::Path};
+
+// The Windows error returned when a file is open by another process without sharing access.
+#[cfg(windows)]
+const ERROR_SHARING_VIOLATION: i32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockKind {
+	Shared,
+	Exclusive,
+}
+
+// Acquires an advisory lock on the file, waiting until it's available. Acquiring an exclusive lock
+// on a file already locked as shared by the same handle upgrades the lock.
+#[cfg(unix)]
+pub(crate) fn lock(file: &File, path: &Path, kind: LockKind) -> Result<(), Error> {
+	use std::os::fd::AsRawFd;
+
+	let operation = match kind {
+		LockKind::Shared => libc::LOCK_SH,
+		LockKind::Exclusive => libc::LOCK_EX,
+	};
+
+	// SAFETY: The file descriptor is valid as long as the file is alive.
+	if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+		return Ok(());
+	}
+
+	let err = std::io::Error::last_os_error();
+	match err.raw_os_error() {
+		Some(libc::ENOLCK) | Some(libc::EOPNOTSUPP) | Some(libc::EINVAL) =>
+			Err(Error::LockUnsupported(format!("{}", path.display()))),
+		_ => Err(err.into()),
+	}
+}
+
+#[cfg(not(unix))]
+pub(crate) fn lock(_file: &File, path: &Path, _kind: LockKind) -> Result<(), Error> {
+	Err(Error::LockUnsupported(format!("{}", path.display())))
+}
+
+// Checks if the file is exclusively held by someone else, by trying to acquire a shared advisory
+// lock without waiting. Shared locks held on the file, including the ones held by a rollback
+// through note_file_locked, don't make the file busy. If the file system doesn't support locking,
+// there's no way to know, so the file isn't considered busy.
+#[cfg(unix)]
+pub(crate) fn is_busy(path: &Path) -> Result<bool, Error> {
+	use std::os::fd::AsRawFd;
+
+	let file = File::open(path)?;
+	// SAFETY: The file descriptor is valid as long as the file is alive. The probing lock is
+	// released as soon as the file is dropped.
+	if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) } == 0 {
+		return Ok(false);
+	}
+
+	let err = std::io::Error::last_os_error();
+	match err.raw_os_error() {
+		Some(libc::EWOULDBLOCK) => Ok(true),
+		Some(libc::ENOLCK) | Some(libc::EOPNOTSUPP) | Some(libc::EINVAL) => Ok(false),
+		_ => Err(err.into()),
+	}
+}
+
+// Checks if the file is open by another process without sharing access, by trying to open it
+// without sharing it.
+#[cfg(windows)]
+pub(crate) fn is_busy(path: &Path) -> Result<bool, Error> {
+	use std::os::windows::fs::OpenOptionsExt;
+
+	match std::fs::OpenOptions::new().read(true).write(true).share_mode(0).open(path) {
+		Ok(_) => Ok(false),
+		Err(err) if err.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(true),
+		Err(err) => Err(err.into()),
+	}
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn is_busy(_path: &Path) -> Result<bool, Error> {
+	Ok(false)
+}
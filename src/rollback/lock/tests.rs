@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::TestBuilder;
+use std::os::fd::AsRawFd;
+
+// Checks if the file can be exclusively locked through a different handle without waiting.
+fn is_exclusively_lockable(path: &Path) -> bool {
+	let file = File::open(path).expect("The file exists; qed;");
+	unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 }
+}
+
+// Checks if the file can be locked as shared through a different handle without waiting.
+fn is_shared_lockable(path: &Path) -> bool {
+	let file = File::open(path).expect("The file exists; qed;");
+	unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) == 0 }
+}
+
+#[test]
+fn shared_lock_works() {
+	let builder = TestBuilder::new(Some(1));
+	let path = builder.existing_files()[0];
+	let file = File::open(path).expect("The file exists; qed;");
+
+	assert!(lock(&file, path, LockKind::Shared).is_ok());
+
+	assert!(is_shared_lockable(path));
+	assert!(!is_exclusively_lockable(path));
+
+	// The lock is released as soon as the handle is dropped
+	drop(file);
+	assert!(is_exclusively_lockable(path));
+}
+
+#[test]
+fn exclusive_lock_upgrades_shared_lock() {
+	let builder = TestBuilder::new(Some(1));
+	let path = builder.existing_files()[0];
+	let file = File::open(path).expect("The file exists; qed;");
+
+	assert!(lock(&file, path, LockKind::Shared).is_ok());
+	assert!(lock(&file, path, LockKind::Exclusive).is_ok());
+
+	assert!(!is_shared_lockable(path));
+}
+
+#[test]
+fn is_busy_works() {
+	let builder = TestBuilder::new(Some(1));
+	let path = builder.existing_files()[0];
+
+	assert!(!is_busy(path).expect("The file exists; qed;"));
+
+	// Shared locks don't make the file busy
+	let file = File::open(path).expect("The file exists; qed;");
+	lock(&file, path, LockKind::Shared).expect("The file should be locked; qed;");
+	assert!(!is_busy(path).expect("The file exists; qed;"));
+
+	lock(&file, path, LockKind::Exclusive).expect("The file should be locked; qed;");
+	assert!(is_busy(path).expect("The file exists; qed;"));
+}
+
+#[test]
+fn is_busy_fails_if_the_file_doesnt_exist() {
+	assert!(matches!(is_busy(Path::new("some/unexisting/path/file.txt")), Err(Error::IO(_))));
+}
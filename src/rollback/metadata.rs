@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::Error;
+use std::{
+	fs::Permissions,
+	os::unix::fs::{MetadataExt, PermissionsExt},
+	path::Path,
+};
+
+// The metadata of a noted directory, or of a file whose permissions are noted. The original
+// metadata is kept so it can be restored if needed, together with the staged changes.
+#[derive(Debug)]
+pub(crate) struct DirMetadata {
+	pub(crate) original_mode: u32,
+	pub(crate) original_uid: u32,
+	pub(crate) original_gid: u32,
+	pub(crate) mode: Option<u32>,
+	pub(crate) uid: Option<u32>,
+	pub(crate) gid: Option<u32>,
+}
+
+impl DirMetadata {
+	pub(crate) fn new(dir: &Path) -> Result<Self, Error> {
+		let metadata = std::fs::metadata(dir)?;
+		Ok(Self {
+			original_mode: metadata.mode() & 0o7777,
+			original_uid: metadata.uid(),
+			original_gid: metadata.gid(),
+			mode: None,
+			uid: None,
+			gid: None,
+		})
+	}
+
+	// Applies the staged changes to the directory. Ownership is changed first, as chown may clear
+	// some mode bits.
+	pub(crate) fn apply(&self, dir: &Path) -> std::io::Result<()> {
+		if self.uid.is_some() || self.gid.is_some() {
+			std::os::unix::fs::chown(dir, self.uid, self.gid)?;
+		}
+
+		if let Some(mode) = self.mode {
+			std::fs::set_permissions(dir, Permissions::from_mode(mode))?;
+		}
+
+		Ok(())
+	}
+
+	// Restores the original metadata of the directory. Only the ownership is restored if it was
+	// staged to change, as changing it may require privileges.
+	pub(crate) fn restore(&self, dir: &Path) -> std::io::Result<()> {
+		if self.uid.is_some() || self.gid.is_some() {
+			std::os::unix::fs::chown(dir, Some(self.original_uid), Some(self.original_gid))?;
+		}
+
+		std::fs::set_permissions(dir, Permissions::from_mode(self.original_mode))
+	}
+}
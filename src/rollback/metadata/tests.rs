@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::TestBuilder;
+
+fn mode(path: &Path) -> u32 {
+	std::fs::metadata(path).expect("The path exists; qed;").mode() & 0o7777
+}
+
+#[test]
+fn new_dir_metadata_works() {
+	let builder = TestBuilder::new(Some(0));
+	let dir = builder.get_temp_dir_path();
+	let metadata = std::fs::metadata(dir).expect("The dir exists; qed;");
+
+	let dir_metadata = DirMetadata::new(dir).expect("The metadata should be read; qed;");
+
+	assert_eq!(dir_metadata.original_mode, metadata.mode() & 0o7777);
+	assert_eq!(dir_metadata.original_uid, metadata.uid());
+	assert_eq!(dir_metadata.original_gid, metadata.gid());
+	assert!(dir_metadata.mode.is_none());
+	assert!(dir_metadata.uid.is_none());
+	assert!(dir_metadata.gid.is_none());
+}
+
+#[test]
+fn new_dir_metadata_fails_if_the_dir_doesnt_exist() {
+	let builder = TestBuilder::new(Some(1));
+
+	assert!(matches!(
+		DirMetadata::new(builder.new_dirs()[0]),
+		Err(Error::IO(err)) if err.kind() == std::io::ErrorKind::NotFound
+	));
+}
+
+#[test]
+fn apply_and_restore_work() {
+	let builder = TestBuilder::new(Some(1));
+	let dir = builder.new_dirs()[0];
+	std::fs::create_dir(dir).expect("The dir should be created; qed;");
+	std::fs::set_permissions(dir, Permissions::from_mode(0o755))
+		.expect("The permissions should be set; qed;");
+
+	let mut dir_metadata = DirMetadata::new(dir).expect("The metadata should be read; qed;");
+	dir_metadata.mode = Some(0o700);
+	// Changing the owner to the current one is always allowed
+	dir_metadata.uid = Some(dir_metadata.original_uid);
+
+	assert!(dir_metadata.apply(dir).is_ok());
+	assert_eq!(mode(dir), 0o700);
+
+	assert!(dir_metadata.restore(dir).is_ok());
+	assert_eq!(mode(dir), 0o755);
+}
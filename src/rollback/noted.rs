@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::Error;
+use std::{
+	fmt,
+	fs::{File, Metadata, Permissions},
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Mutex,
+	},
+	time::SystemTime,
+};
+use tempfile::NamedTempFile;
+
+// A transformation computing the new content of a noted file from its original content.
+pub(crate) type Transform = Box<dyn Fn(Vec<u8>) -> Vec<u8> + Send>;
+
+// A file noted to be modified.
+pub(crate) struct NotedFile {
+	// The temporary file where changes are applied. As it's included in the struct, it lives as
+	// long as the rollback does.
+	pub(crate) temp_file: NamedTempFile,
+	// A handle to the original file holding an advisory lock on it, if the file was noted as
+	// locked. The lock is released when the handle is dropped.
+	pub(crate) lock: Option<File>,
+	// A transformation applied to the original content upon commit, if the file was noted as
+	// lazy. The mutex allows sharing the transformation among the commit threads.
+	pub(crate) transform: Option<Mutex<Transform>>,
+	// Whether something has been written through the handles of the file, if any handle has been
+	// requested. Tracked files are only committed if they're dirty.
+	pub(crate) dirty: Option<Arc<AtomicBool>>,
+	// The permissions of the original file when it was noted, which are applied to it upon
+	// commit, as copying the temporary file would apply the temporary file ones.
+	pub(crate) permissions: Permissions,
+	// The modification time and the size of the original file when it was noted, if they're
+	// known, so modifications made by someone else can be detected upon commit.
+	pub(crate) stamp: Option<(SystemTime, u64)>,
+	// The number of hard links of the original file when it was noted.
+	#[cfg(unix)]
+	pub(crate) nlink: u64,
+	// Whether the original content is disposable, so the file isn't backed up upon commit.
+	pub(crate) disposable: bool,
+	// Whether the original was a symlink when it was noted. Its target is modified upon commit,
+	// while the link is kept.
+	pub(crate) symlinked: bool,
+	// The target of the original symlink, if the link itself is replaced by a regular file upon
+	// commit. The link is created again if the commit is rolled-back.
+	pub(crate) link_target: Option<PathBuf>,
+	// The ACL of the original file when it was noted, if it must be preserved.
+	#[cfg(feature = "acl")]
+	pub(crate) acl: Option<Vec<u8>>,
+	// A memory map of the temporary file, if the file was noted as mapped.
+	#[cfg(feature = "mmap")]
+	pub(crate) mmap: Option<memmap2::MmapMut>,
+}
+
+impl NotedFile {
+	pub(crate) fn new(temp_file: NamedTempFile, permissions: Permissions) -> Self {
+		Self {
+			temp_file,
+			permissions,
+			stamp: None,
+			lock: None,
+			transform: None,
+			dirty: None,
+			#[cfg(unix)]
+			nlink: 1,
+			disposable: false,
+			symlinked: false,
+			link_target: None,
+			#[cfg(feature = "acl")]
+			acl: None,
+			#[cfg(feature = "mmap")]
+			mmap: None,
+		}
+	}
+
+	// Discards the changes applied to the temporary file, copying the original file again.
+	pub(crate) fn reset(&mut self, original: &Path) -> Result<(), Error> {
+		let metadata = std::fs::metadata(original)?;
+		self.permissions = metadata.permissions();
+		self.stamp = stamp(&metadata);
+		// Lazily noted files read the original upon commit.
+		if self.transform.is_some() {
+			return Ok(());
+		}
+
+		// The original size may have changed, so the map must be dropped before copying and
+		// created again afterwards.
+		#[cfg(feature = "mmap")]
+		let mapped = self.mmap.take().is_some();
+
+		std::fs::copy(original, self.temp_file.path())?;
+		if let Some(dirty) = &self.dirty {
+			dirty.store(false, Ordering::SeqCst);
+		}
+
+		#[cfg(feature = "mmap")]
+		if mapped {
+			// SAFETY: The temporary file is owned by the rollback and isn't modified by the crate
+			// while it's mapped. External modifications are documented as a caller concern.
+			self.mmap = Some(unsafe { memmap2::MmapMut::map_mut(self.temp_file.as_file()) }?);
+		}
+
+		Ok(())
+	}
+
+	// Yields the temporary file holding the new content, releasing the lock on the original if
+	// any. The content of lazily noted files is computed from the original.
+	pub(crate) fn into_temp_file(self, original: &Path) -> Result<NamedTempFile, Error> {
+		if let Some(transform) = self.transform {
+			let content = std::fs::read(original)?;
+			let transform = transform.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+			std::fs::write(self.temp_file.path(), transform(content))?;
+		}
+
+		// The map must be flushed and dropped before handing over the mapped file.
+		#[cfg(feature = "mmap")]
+		if let Some(mmap) = self.mmap {
+			mmap.flush()?;
+		}
+
+		Ok(self.temp_file)
+	}
+
+	// Removes the temporary file, releasing the lock on the original if any.
+	pub(crate) fn close(self) -> Result<(), Error> {
+		// The map must be dropped before removing the mapped file.
+		#[cfg(feature = "mmap")]
+		drop(self.mmap);
+		self.temp_file.close()?;
+		Ok(())
+	}
+
+	// Checks if the original file has been modified since it was noted. Lazily noted files read
+	// the original upon commit, so they're never considered modified.
+	pub(crate) fn is_modified(&self, original: &Path) -> std::io::Result<bool> {
+		match self.stamp {
+			Some(noted_stamp) if self.transform.is_none() =>
+				Ok(stamp(&std::fs::metadata(original)?) != Some(noted_stamp)),
+			_ => Ok(false),
+		}
+	}
+
+	// Checks if the file is tracked through handles and nothing has been written through them.
+	pub(crate) fn is_untouched(&self) -> bool {
+		self.dirty.as_ref().is_some_and(|dirty| !dirty.load(Ordering::SeqCst))
+	}
+
+	pub(crate) fn is_mapped(&self) -> bool {
+		#[cfg(feature = "mmap")]
+		return self.mmap.is_some();
+		#[cfg(not(feature = "mmap"))]
+		false
+	}
+}
+
+// The modification time and the size of a file, if its modification time is available.
+pub(crate) fn stamp(metadata: &Metadata) -> Option<(SystemTime, u64)> {
+	metadata.modified().ok().map(|modified| (modified, metadata.len()))
+}
+
+impl fmt::Debug for NotedFile {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut debug = f.debug_struct("NotedFile");
+		debug
+			.field("temp_file", &self.temp_file)
+			.field("lock", &self.lock)
+			.field("transform", &self.transform.as_ref().map(|_| "Fn(Vec<u8>) -> Vec<u8>"))
+			.field("dirty", &self.dirty)
+			.field("permissions", &self.permissions)
+			.field("stamp", &self.stamp)
+			.field("disposable", &self.disposable);
+		#[cfg(unix)]
+		debug.field("nlink", &self.nlink);
+		#[cfg(feature = "acl")]
+		debug.field("acl", &self.acl);
+		#[cfg(feature = "mmap")]
+		debug.field("mmap", &self.mmap);
+		debug.finish()
+	}
+}
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::rollback::{FileTiming, StagedKind, VetoPolicy};
+use std::{
+	fs::Metadata,
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+// A guard deciding whether a noted file can be committed, together with the policy applied when
+// it vetoes a file.
+pub(crate) struct FileGuard<'g> {
+	pub(crate) policy: VetoPolicy,
+	pub(crate) guard: &'g (dyn Fn(&Path, &Metadata) -> bool + Sync),
+}
+
+// A callback notified after each item is committed. The calls are serialized, so the callback
+// isn't called concurrently even if the items are committed from several threads.
+pub(crate) struct Progress<'g> {
+	lock: Mutex<()>,
+	callback: &'g (dyn Fn(&Path, StagedKind) + Sync),
+}
+
+impl<'g> Progress<'g> {
+	pub(crate) fn new(callback: &'g (dyn Fn(&Path, StagedKind) + Sync)) -> Self {
+		Self { lock: Mutex::new(()), callback }
+	}
+}
+
+// Options tweaking how a commit is carried out.
+#[derive(Default)]
+pub(crate) struct CommitOptions<'g> {
+	pub(crate) file_guard: Option<FileGuard<'g>>,
+	// Where the time spent copying each file is collected, if it must be measured. The commit
+	// threads share it.
+	pub(crate) timings: Option<&'g Mutex<Vec<FileTiming>>>,
+	// Where the paths of the archived backups are collected, if they must be reported.
+	pub(crate) archived: Option<&'g Mutex<Vec<PathBuf>>>,
+	// Whether every committed item is verified and flushed to disk.
+	pub(crate) paranoid: bool,
+	// Whether every committed item is flushed to disk, without verifying it.
+	pub(crate) durable: bool,
+	// Whether the committed files are read back once everything's committed and compared against
+	// their staged content.
+	pub(crate) verified: bool,
+	// Whether noted files are committed one by one in path order, so the first failure is
+	// deterministic.
+	pub(crate) sequential: bool,
+	// Where each committed item is reported, if the progress must be tracked.
+	pub(crate) progress: Option<Progress<'g>>,
+}
+
+impl CommitOptions<'_> {
+	// Reports a committed item to the progress callback, if any.
+	pub(crate) fn report_progress(&self, path: &Path, kind: StagedKind) {
+		if let Some(progress) = &self.progress {
+			// A poisoned lock means that the callback panicked in another thread, which doesn't
+			// prevent calling it again.
+			let _guard = progress.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+			(progress.callback)(path, kind);
+		}
+	}
+}
@@ -0,0 +1,113 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use std::{
+	fs::{File, Metadata},
+	io,
+	os::unix::fs::MetadataExt,
+};
+
+// Gives a file the owner and group from the metadata. Changing the owner requires privileges, so
+// this fails with `PermissionDenied` if the process cannot keep them.
+pub(crate) fn keep_owner(metadata: &Metadata, file: &File) -> io::Result<()> {
+	let current = file.metadata()?;
+	if current.uid() == metadata.uid() && current.gid() == metadata.gid() {
+		return Ok(());
+	}
+	std::os::unix::fs::fchown(file, Some(metadata.uid()), Some(metadata.gid()))
+}
+
+// Copies the extended attributes of a file into another one, which includes SELinux labels and
+// POSIX ACLs. Setting some namespaces requires privileges, so the attributes the process isn't
+// allowed to set, or the target file system doesn't support, are skipped.
+#[cfg(target_os = "linux")]
+pub(crate) fn copy_xattrs(from: &File, to: &File) -> io::Result<()> {
+	use std::os::fd::AsRawFd;
+
+	for name in list_xattrs(from)? {
+		let Some(value) = get_xattr(from, &name)? else {
+			continue;
+		};
+		// SAFETY: The file descriptor is valid as long as the file is alive, the name is a valid C
+		// string and the value pointer is valid for its length.
+		let result = unsafe {
+			libc::fsetxattr(to.as_raw_fd(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0)
+		};
+		if result != 0 {
+			let err = io::Error::last_os_error();
+			match err.raw_os_error() {
+				Some(libc::EPERM) | Some(libc::EACCES) | Some(libc::ENOTSUP) => (),
+				_ => return Err(err),
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn copy_xattrs(_from: &File, _to: &File) -> io::Result<()> {
+	Ok(())
+}
+
+// The names of the extended attributes of a file. Empty if the file system doesn't support them.
+#[cfg(target_os = "linux")]
+fn list_xattrs(file: &File) -> io::Result<Vec<std::ffi::CString>> {
+	use std::os::fd::AsRawFd;
+
+	let names = read_sized(|buffer, size| {
+		// SAFETY: The file descriptor is valid as long as the file is alive, and the buffer is
+		// valid for `size` bytes.
+		unsafe { libc::flistxattr(file.as_raw_fd(), buffer.cast(), size) }
+	});
+	let names = match names {
+		Ok(names) => names,
+		Err(err) if err.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+		Err(err) => return Err(err),
+	};
+	Ok(names
+		.split(|byte| *byte == 0)
+		.filter(|name| !name.is_empty())
+		.map(|name| std::ffi::CString::new(name).expect("The names are split by nul bytes; qed;"))
+		.collect())
+}
+
+// The value of an extended attribute of a file, if it still exists.
+#[cfg(target_os = "linux")]
+fn get_xattr(file: &File, name: &std::ffi::CStr) -> io::Result<Option<Vec<u8>>> {
+	use std::os::fd::AsRawFd;
+
+	let value = read_sized(|buffer, size| {
+		// SAFETY: The file descriptor is valid as long as the file is alive, the name is a valid C
+		// string and the buffer is valid for `size` bytes.
+		unsafe { libc::fgetxattr(file.as_raw_fd(), name.as_ptr(), buffer.cast(), size) }
+	});
+	match value {
+		Ok(value) => Ok(Some(value)),
+		Err(err) if err.raw_os_error() == Some(libc::ENODATA) => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+// Reads a value through a call that reports the needed size if it's given an empty buffer, as
+// the xattr syscalls do. The size is asked again if the value grows between both calls.
+#[cfg(target_os = "linux")]
+fn read_sized(call: impl Fn(*mut u8, usize) -> libc::ssize_t) -> io::Result<Vec<u8>> {
+	loop {
+		let size = call(std::ptr::null_mut(), 0);
+		if size < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let mut buffer = vec![0; size as usize];
+		let read = call(buffer.as_mut_ptr(), buffer.len());
+		if read >= 0 {
+			buffer.truncate(read as usize);
+			return Ok(buffer);
+		}
+		let err = io::Error::last_os_error();
+		if err.raw_os_error() != Some(libc::ERANGE) {
+			return Err(err);
+		}
+	}
+}
@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::TestBuilder;
+
+// Sets an extended attribute in the user namespace, returning false if the file system doesn't
+// support them.
+#[cfg(target_os = "linux")]
+fn set_user_xattr(file: &File, name: &str, value: &[u8]) -> bool {
+	use std::os::fd::AsRawFd;
+
+	let name = std::ffi::CString::new(name).expect("The name has no nul bytes; qed;");
+	// SAFETY: The file descriptor is valid, the name is a valid C string and the value pointer
+	// is valid for its length.
+	unsafe {
+		libc::fsetxattr(file.as_raw_fd(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) == 0
+	}
+}
+
+#[test]
+fn keep_owner_keeps_the_owner_and_group() {
+	let builder = TestBuilder::new(Some(2));
+	let original = builder.existing_files()[0];
+	let replacement = File::open(builder.existing_files()[1]).expect("The file exists; qed;");
+	// Giving files away requires privileges
+	if std::os::unix::fs::chown(original, Some(65534), Some(65534)).is_err() {
+		return;
+	}
+	let metadata = std::fs::metadata(original).expect("The file exists; qed;");
+
+	assert!(keep_owner(&metadata, &replacement).is_ok());
+
+	let replaced = replacement.metadata().expect("The file exists; qed;");
+	assert_eq!((replaced.uid(), replaced.gid()), (65534, 65534));
+}
+
+#[test]
+fn keep_owner_does_nothing_if_the_owner_matches() {
+	let builder = TestBuilder::new(Some(2));
+	let metadata = std::fs::metadata(builder.existing_files()[0]).expect("The file exists; qed;");
+	let replacement = File::open(builder.existing_files()[1]).expect("The file exists; qed;");
+
+	assert!(keep_owner(&metadata, &replacement).is_ok());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn copy_xattrs_works() {
+	let builder = TestBuilder::new(Some(2));
+	let from = File::open(builder.existing_files()[0]).expect("The file exists; qed;");
+	let to = File::open(builder.existing_files()[1]).expect("The file exists; qed;");
+	if !set_user_xattr(&from, "user.fs_rollback", b"value") {
+		return;
+	}
+
+	assert!(copy_xattrs(&from, &to).is_ok());
+
+	let name = std::ffi::CString::new("user.fs_rollback").expect("The name has no nul bytes; qed;");
+	assert_eq!(
+		get_xattr(&to, &name).expect("The attribute can be read; qed;"),
+		Some(b"value".to_vec())
+	);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn list_xattrs_is_empty_for_plain_files() {
+	let builder = TestBuilder::new(Some(1));
+	let file = File::open(builder.existing_files()[0]).expect("The file exists; qed;");
+
+	let names = list_xattrs(&file).expect("The attributes can be listed; qed;");
+
+	assert!(names.iter().all(|name| !name.to_bytes().starts_with(b"user.")));
+}
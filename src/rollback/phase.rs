@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use crate::rollback::CommitOrder;
+use std::fmt;
+
+/// The phases a commit goes through, reported by [`Error::CommitIo`](crate::Error::CommitIo).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitPhase {
+	/// Noted files are written.
+	NotedFiles,
+	/// New dirs are created.
+	NewDirs,
+	/// New files are created.
+	NewFiles,
+	/// New symbolic links are created.
+	NewSymlinks,
+	/// Files are renamed.
+	Renames,
+	/// Files are deleted.
+	DeletedFiles,
+	/// Dirs are deleted.
+	DeletedDirs,
+	/// The metadata of dirs, and the permissions of files, are modified.
+	#[cfg(unix)]
+	DirsMetadata,
+	/// Committed items are verified and flushed to disk, if the commit asks for it.
+	Verification,
+	/// The hashes of committed files are recorded in the baseline dir, if any.
+	Baseline,
+	/// The backups of noted files are archived, if an archive dir is configured.
+	Archive,
+	/// The completion marker is created, if any.
+	CompletionMarker,
+}
+
+impl fmt::Display for CommitPhase {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let phase = match self {
+			Self::NotedFiles => "noted files",
+			Self::NewDirs => "new dirs",
+			Self::NewFiles => "new files",
+			Self::NewSymlinks => "new symlinks",
+			Self::Renames => "renames",
+			Self::DeletedFiles => "deleted files",
+			Self::DeletedDirs => "deleted dirs",
+			#[cfg(unix)]
+			Self::DirsMetadata => "dirs metadata",
+			Self::Verification => "verification",
+			Self::Baseline => "baseline",
+			Self::Archive => "archive",
+			Self::CompletionMarker => "completion marker",
+		};
+		f.write_str(phase)
+	}
+}
+
+impl CommitPhase {
+	// The phases a commit goes through following the given order. Rolling back a commit must walk
+	// the phases in reverse.
+	pub(crate) fn sequence(order: CommitOrder) -> Vec<CommitPhase> {
+		let mut phases = match order {
+			CommitOrder::NotedFirst =>
+				vec![CommitPhase::NotedFiles, CommitPhase::NewDirs, CommitPhase::NewFiles],
+			CommitOrder::DirsFirst =>
+				vec![CommitPhase::NewDirs, CommitPhase::NotedFiles, CommitPhase::NewFiles],
+		};
+		phases.push(CommitPhase::NewSymlinks);
+		phases.push(CommitPhase::Renames);
+		phases.push(CommitPhase::DeletedFiles);
+		phases.push(CommitPhase::DeletedDirs);
+		#[cfg(unix)]
+		phases.push(CommitPhase::DirsMetadata);
+		phases.push(CommitPhase::Verification);
+		phases.push(CommitPhase::Baseline);
+		phases.push(CommitPhase::Archive);
+		phases.push(CommitPhase::CompletionMarker);
+		phases
+	}
+
+	// Whether the phase commits staged operations, as opposed to the phases acting on the commit
+	// as a whole once every operation is committed.
+	pub(crate) fn commits_operations(self) -> bool {
+		!matches!(
+			self,
+			CommitPhase::Verification |
+				CommitPhase::Baseline |
+				CommitPhase::Archive |
+				CommitPhase::CompletionMarker
+		)
+	}
+
+	// Whether the phase undoes its own changes if it fails.
+	pub(crate) fn cleans_up_on_failure(self) -> bool {
+		matches!(
+			self,
+			CommitPhase::NewFiles |
+				CommitPhase::NewSymlinks |
+				CommitPhase::Renames |
+				CommitPhase::Archive
+		)
+	}
+}
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+
+#[test]
+fn sequence_noted_first_works() {
+	let phases = CommitPhase::sequence(CommitOrder::NotedFirst);
+	assert_eq!(phases[..3], [CommitPhase::NotedFiles, CommitPhase::NewDirs, CommitPhase::NewFiles]);
+	assert_eq!(
+		phases[phases.len() - 3..],
+		[CommitPhase::Baseline, CommitPhase::Archive, CommitPhase::CompletionMarker]
+	);
+}
+
+#[test]
+fn sequence_dirs_first_works() {
+	let phases = CommitPhase::sequence(CommitOrder::DirsFirst);
+	assert_eq!(phases[..3], [CommitPhase::NewDirs, CommitPhase::NotedFiles, CommitPhase::NewFiles]);
+	assert_eq!(
+		phases[phases.len() - 3..],
+		[CommitPhase::Baseline, CommitPhase::Archive, CommitPhase::CompletionMarker]
+	);
+}
+
+#[test]
+fn sequence_creates_symlinks_renames_and_deletes_files_after_creating_items() {
+	for order in [CommitOrder::NotedFirst, CommitOrder::DirsFirst] {
+		let phases = CommitPhase::sequence(order);
+		assert_eq!(
+			phases[3..7],
+			[
+				CommitPhase::NewSymlinks,
+				CommitPhase::Renames,
+				CommitPhase::DeletedFiles,
+				CommitPhase::DeletedDirs
+			]
+		);
+	}
+}
+
+#[cfg(unix)]
+#[test]
+fn sequence_commits_dirs_metadata_after_creating_items() {
+	for order in [CommitOrder::NotedFirst, CommitOrder::DirsFirst] {
+		let phases = CommitPhase::sequence(order);
+		assert_eq!(phases[7], CommitPhase::DirsMetadata);
+	}
+}
+
+#[test]
+fn sequence_verifies_everything_before_recording_the_commit() {
+	for order in [CommitOrder::NotedFirst, CommitOrder::DirsFirst] {
+		let phases = CommitPhase::sequence(order);
+		assert_eq!(
+			phases[phases.len() - 4..],
+			[
+				CommitPhase::Verification,
+				CommitPhase::Baseline,
+				CommitPhase::Archive,
+				CommitPhase::CompletionMarker
+			]
+		);
+	}
+}
+
+#[test]
+fn only_new_files_new_symlinks_renames_and_archive_clean_up_on_failure() {
+	for phase in CommitPhase::sequence(CommitOrder::NotedFirst) {
+		assert_eq!(
+			phase.cleans_up_on_failure(),
+			matches!(
+				phase,
+				CommitPhase::NewFiles |
+					CommitPhase::NewSymlinks |
+					CommitPhase::Renames |
+					CommitPhase::Archive
+			)
+		);
+	}
+}
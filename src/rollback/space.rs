@@ -0,0 +1,27 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use std::{ffi::CString, io, mem::MaybeUninit, os::unix::ffi::OsStrExt, path::Path};
+
+// The number of bytes available to unprivileged users in the file system containing the path.
+pub(crate) fn available(path: &Path) -> io::Result<u64> {
+	let path = CString::new(path.as_os_str().as_bytes())?;
+	let mut stats = MaybeUninit::<libc::statvfs>::uninit();
+	// SAFETY: The path is a valid C string, and the stats are only read if statvfs fills them.
+	if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	// SAFETY: statvfs succeeded, so the stats are initialized.
+	let stats = unsafe { stats.assume_init() };
+	// The field types depend on the platform.
+	#[allow(clippy::unnecessary_cast)]
+	Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+// The nearest ancestor of an absolute path that exists, including the path itself. That's the
+// dir whose file system would hold the item once it's created.
+pub(crate) fn existing_ancestor(path: &Path) -> Option<&Path> {
+	path.ancestors().find(|ancestor| ancestor.symlink_metadata().is_ok())
+}
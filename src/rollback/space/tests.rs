@@ -0,0 +1,29 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::TestBuilder;
+
+#[test]
+fn available_works() {
+	let builder = TestBuilder::new(Some(0));
+
+	assert!(available(builder.get_temp_dir_path()).is_ok());
+}
+
+#[test]
+fn available_fails_if_path_doesnt_exist() {
+	let builder = TestBuilder::new(Some(0));
+	let result = available(&builder.get_temp_dir_path().join("missing"));
+
+	assert!(matches!(result, Err(err) if err.kind() == io::ErrorKind::NotFound));
+}
+
+#[test]
+fn existing_ancestor_works() {
+	let builder = TestBuilder::new(Some(1));
+	let dir = builder.get_temp_dir_path();
+	let file = builder.existing_files()[0];
+
+	assert_eq!(existing_ancestor(file), Some(file));
+	assert_eq!(existing_ancestor(&dir.join("missing").join("file.txt")), Some(dir));
+}
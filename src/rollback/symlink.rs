@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+use std::{io, path::Path};
+
+// Creates a symlink pointing to the target, which is written as is. Relative targets are resolved
+// from the parent dir of the symlink. Creating the symlink fails if its path already exists.
+#[cfg(unix)]
+pub(crate) fn create(target: &Path, link: &Path) -> io::Result<()> {
+	std::os::unix::fs::symlink(target, link)
+}
+
+// Windows distinguishes between file and dir symlinks, so the kind follows the target. Targets
+// that don't exist get file symlinks.
+#[cfg(windows)]
+pub(crate) fn create(target: &Path, link: &Path) -> io::Result<()> {
+	let resolved = match link.parent() {
+		Some(parent) => parent.join(target),
+		None => target.to_path_buf(),
+	};
+	if resolved.is_dir() {
+		std::os::windows::fs::symlink_dir(target, link)
+	} else {
+		std::os::windows::fs::symlink_file(target, link)
+	}
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn create(_target: &Path, _link: &Path) -> io::Result<()> {
+	Err(io::ErrorKind::Unsupported.into())
+}
+
+// Removes a symlink, leaving its target untouched. Windows dir symlinks are removed as dirs.
+pub(crate) fn remove(link: &Path) -> io::Result<()> {
+	std::fs::remove_file(link).or_else(|err| match cfg!(windows) {
+		true => std::fs::remove_dir(link),
+		false => Err(err),
+	})
+}
@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+use crate::test_builder::{TestBuilder, ORIGINAL_BUILDER_FILE_CONTENT};
+
+#[test]
+fn create_works() {
+	let builder = TestBuilder::new(Some(1));
+	let target = builder.existing_files()[0];
+	let link = builder.get_temp_dir_path().join("link");
+
+	assert!(create(Path::new(target.file_name().expect("The path is a file; qed;")), &link).is_ok());
+
+	assert!(link.symlink_metadata().expect("The link exists; qed;").is_symlink());
+	assert_eq!(
+		std::fs::read_to_string(&link).expect("The link points to the file; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+}
+
+#[test]
+fn create_fails_if_link_exists() {
+	let builder = TestBuilder::new(Some(2));
+	let (target, link) = (builder.existing_files()[0], builder.existing_files()[1]);
+
+	assert!(matches!(create(target, link), Err(err) if err.kind() == io::ErrorKind::AlreadyExists));
+	assert!(!link.symlink_metadata().expect("The file exists; qed;").is_symlink());
+}
+
+#[test]
+fn remove_keeps_the_target() {
+	let builder = TestBuilder::new(Some(1));
+	let target = builder.existing_files()[0];
+	let link = builder.get_temp_dir_path().join("link");
+	create(target, &link).expect("The link can be created; qed;");
+
+	assert!(remove(&link).is_ok());
+
+	assert!(link.symlink_metadata().is_err());
+	assert!(target.is_file());
+}
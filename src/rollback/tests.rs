@@ -1,21 +1,397 @@
 // SPDX-License-Identifier: GPL-3.0
 
 use super::*;
+use crate::test_builder::TestBuilder;
+
+// Checks that every option of the rollback is the default one and nothing is staged, regardless
+// of the capacity.
+fn assert_default(rollback: &Rollback) {
+	assert!(rollback.noted.is_empty());
+	assert!(rollback.new_files.is_empty());
+	assert!(rollback.new_file_modes.is_empty());
+	assert!(rollback.new_dirs.is_empty());
+	assert!(rollback.strict_new_dirs.is_empty());
+	assert!(rollback.new_symlinks.is_empty());
+	assert!(rollback.renames.is_empty());
+	assert!(rollback.deleted.is_empty());
+	assert!(rollback.history.is_empty());
+	assert!(rollback.deleted_dirs.is_empty());
+	assert!(rollback.noted_dirs.is_empty());
+	assert!(rollback.dirs_metadata.is_empty());
+	assert!(rollback.baseline_dir.is_none());
+	assert!(rollback.umask.is_none());
+	assert_eq!(rollback.commit_order, CommitOrder::NotedFirst);
+	assert!(rollback.journal.is_none());
+	assert!(!rollback.busy_check);
+	assert!(!rollback.modification_check);
+	assert!(!rollback.hard_link_check);
+	assert!(rollback.deduplicated_contents.is_none());
+	assert!(!rollback.stage_new_files);
+	assert!(!rollback.atomic_new_files);
+	assert!(rollback.completion_marker.is_none());
+	assert!(rollback.fs_root.is_none());
+	assert!(rollback.base_dir.is_none());
+	assert!(rollback.confined_root.is_none());
+	assert!(rollback.link_policy.is_none());
+	assert!(rollback.max_threads.is_none());
+	assert!(rollback.temp_dir.is_none());
+	assert!(rollback.temp_file_prefix.is_none());
+	assert!(!rollback.temp_file_target_names);
+	assert!(rollback.backup_dir.is_none());
+	assert!(rollback.archive_dir.is_none());
+	#[cfg(feature = "tracing")]
+	assert!(!rollback.settled.load(Ordering::Relaxed));
+}
 
 #[test]
 fn rollback_new_works() {
 	let rollback = Rollback::default();
 
-	assert!(rollback.noted.is_empty() && rollback.noted.capacity() == 0);
-	assert!(rollback.new_files.is_empty() && rollback.new_files.capacity() == 0);
-	assert!(rollback.new_dirs.is_empty() && rollback.new_dirs.capacity() == 0);
+	assert_default(&rollback);
+	assert_eq!(rollback.noted.capacity(), 0);
+	assert_eq!(rollback.new_files.capacity(), 0);
+	assert_eq!(rollback.new_dirs.capacity(), 0);
 }
 
 #[test]
 fn rollback_with_capacity_works() {
 	let rollback = Rollback::with_capacity(1, 2, 3);
 
+	assert_default(&rollback);
+	assert!(rollback.noted.capacity() >= 1);
+	assert!(rollback.new_files.capacity() >= 2);
+	assert_eq!(rollback.new_dirs.capacity(), 3);
+}
+
+#[test]
+fn reserve_works() {
+	let mut rollback = Rollback::default();
+	rollback.reserve_noted(1);
+	rollback.reserve_new_files(2);
+	rollback.reserve_new_dirs(3);
+
 	assert!(rollback.noted.is_empty() && rollback.noted.capacity() >= 1);
 	assert!(rollback.new_files.is_empty() && rollback.new_files.capacity() >= 2);
-	assert!(rollback.new_dirs.is_empty() && rollback.new_dirs.capacity() == 3);
+	assert!(rollback.new_dirs.is_empty() && rollback.new_dirs.capacity() >= 3);
+}
+
+#[test]
+fn clear_keeps_the_capacity() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let mut rollback = rollback.with_busy_check();
+			let temp_file = rollback
+				.get_noted_file(builder.existing_files()[0])
+				.expect("The file is noted; qed;")
+				.to_path_buf();
+			let capacities = (
+				rollback.noted.capacity(),
+				rollback.new_files.capacity(),
+				rollback.new_dirs.capacity(),
+			);
+
+			rollback.clear();
+
+			assert!(rollback.is_empty());
+			assert!(rollback.history.is_empty());
+			assert!(!temp_file.exists());
+			assert_eq!(
+				(
+					rollback.noted.capacity(),
+					rollback.new_files.capacity(),
+					rollback.new_dirs.capacity()
+				),
+				capacities
+			);
+			// The configuration is kept
+			assert!(rollback.busy_check);
+		});
+}
+
+#[test]
+fn with_commit_order_works() {
+	let rollback = Rollback::default().with_commit_order(CommitOrder::DirsFirst);
+
+	assert_eq!(rollback.commit_order, CommitOrder::DirsFirst);
+}
+
+#[test]
+fn with_journal_works() {
+	let journal = Path::new("journal");
+	let rollback = Rollback::default().with_journal(journal);
+
+	assert_eq!(rollback.journal.as_deref(), Some(journal));
+}
+
+#[test]
+fn with_busy_check_works() {
+	let rollback = Rollback::default().with_busy_check();
+
+	assert!(rollback.busy_check);
+}
+
+#[test]
+fn with_modification_check_works() {
+	let rollback = Rollback::default().with_modification_check();
+
+	assert!(rollback.modification_check);
+}
+
+#[test]
+fn with_hard_link_check_works() {
+	let rollback = Rollback::default().with_hard_link_check();
+
+	assert!(rollback.hard_link_check);
+}
+
+#[test]
+fn with_deduplicated_new_files_works() {
+	let rollback = Rollback::default().with_deduplicated_new_files();
+
+	assert!(rollback
+		.deduplicated_contents
+		.as_ref()
+		.is_some_and(|contents| contents.is_empty()));
+}
+
+#[test]
+fn with_staged_new_files_works() {
+	let rollback = Rollback::default().with_staged_new_files();
+
+	assert!(rollback.stage_new_files);
+}
+
+#[test]
+fn with_atomic_new_files_works() {
+	let rollback = Rollback::default().with_atomic_new_files();
+
+	assert!(rollback.atomic_new_files);
+}
+
+#[test]
+fn with_link_policy_works() {
+	let rollback = Rollback::default().with_link_policy(LinkPolicy::BreakLinks);
+
+	assert_eq!(rollback.link_policy, Some(LinkPolicy::BreakLinks));
+}
+
+#[test]
+fn with_max_threads_works() {
+	let rollback = Rollback::default().with_max_threads(8);
+
+	assert_eq!(rollback.max_threads, Some(8));
+}
+
+#[test]
+fn with_temp_dir_works() {
+	let dir = Path::new("staging");
+	let rollback = Rollback::default().with_temp_dir(dir);
+
+	assert_eq!(rollback.temp_dir.as_deref(), Some(dir));
+}
+
+#[test]
+fn with_temp_file_prefix_works() {
+	let rollback = Rollback::default().with_temp_file_prefix("fs_rollback_");
+
+	assert_eq!(rollback.temp_file_prefix, Some("fs_rollback_"));
+}
+
+#[test]
+fn with_temp_file_target_names_works() {
+	let rollback = Rollback::default().with_temp_file_target_names();
+
+	assert!(rollback.temp_file_target_names);
+}
+
+#[test]
+fn with_backup_dir_works() {
+	let dir = Path::new("backups");
+	let rollback = Rollback::default().with_backup_dir(dir);
+
+	assert_eq!(rollback.backup_dir.as_deref(), Some(dir));
+}
+
+#[test]
+fn with_archive_dir_works() {
+	let dir = Path::new("archive");
+	let rollback = Rollback::default().with_archive_dir(dir);
+
+	assert_eq!(rollback.archive_dir.as_deref(), Some(dir));
+}
+
+#[test]
+fn with_completion_marker_works() {
+	let marker = Path::new(".committed");
+	let rollback = Rollback::default().with_completion_marker(marker);
+
+	assert_eq!(rollback.completion_marker.as_deref(), Some(marker));
+}
+
+#[test]
+fn with_fs_root_works() {
+	let root = Path::new("root");
+	let rollback = Rollback::default().with_fs_root(root);
+
+	assert_eq!(rollback.fs_root.as_deref(), Some(root));
+}
+
+#[test]
+fn rooted_at_works() {
+	let base = Path::new("/tmp/base");
+	let rollback = Rollback::default().rooted_at(base);
+
+	assert_eq!(rollback.base_dir.as_deref(), Some(base));
+}
+
+#[test]
+fn confined_to_works() {
+	let root = Path::new("/tmp/root");
+	let rollback = Rollback::default().confined_to(root);
+
+	assert_eq!(rollback.confined_root.as_deref(), Some(root));
+}
+
+#[test]
+fn rooted_at_makes_relative_base_absolute() {
+	let rollback = Rollback::default().rooted_at(PathBuf::from("base"));
+
+	assert_eq!(rollback.base_dir, Some(std::env::current_dir().unwrap().join("base")));
+}
+
+#[test]
+fn with_baseline_dir_works() {
+	let baseline_dir = Path::new("baseline");
+	let rollback = Rollback::default().with_baseline_dir(baseline_dir);
+
+	assert_eq!(rollback.baseline_dir.as_deref(), Some(baseline_dir));
+}
+
+#[test]
+fn path_builders_accept_owned_paths() {
+	let dir = PathBuf::from("dir");
+	// The paths are built on the fly, so they're dropped once the instance is configured
+	let rollback = Rollback::default()
+		.with_journal(dir.join("journal"))
+		.with_temp_dir(dir.join("staging"))
+		.with_backup_dir(dir.join("backups"))
+		.with_archive_dir(dir.join("archive"))
+		.with_completion_marker(dir.join(".committed"))
+		.with_fs_root(dir.join("root"))
+		.with_baseline_dir(dir.join("baseline"));
+
+	assert_eq!(rollback.journal, Some(dir.join("journal")));
+	assert_eq!(rollback.temp_dir, Some(dir.join("staging")));
+	assert_eq!(rollback.backup_dir, Some(dir.join("backups")));
+	assert_eq!(rollback.archive_dir, Some(dir.join("archive")));
+	assert_eq!(rollback.completion_marker, Some(dir.join(".committed")));
+	assert_eq!(rollback.fs_root, Some(dir.join("root")));
+	assert_eq!(rollback.baseline_dir, Some(dir.join("baseline")));
+}
+
+#[test]
+fn with_umask_works() {
+	let rollback = Rollback::default().with_umask(0o027);
+
+	assert_eq!(rollback.umask, Some(0o027));
+}
+
+// Another representation of a path inside the builder temp dir, going through its parent.
+fn refactored_path(builder: &TestBuilder, path: &Path) -> PathBuf {
+	let dir = builder.get_temp_dir_path();
+	dir.join("..")
+		.join(dir.file_name().expect("The path is a dir, so file_name exists; qed;"))
+		.join(path.file_name().expect("The path is inside the dir, so file_name exists; qed;"))
+}
+
+#[test]
+fn new_dir_detects_repeated_new_dirs() {
+	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, mut rollback| {
+		let refactored_path = refactored_path(builder, builder.new_dirs()[0]);
+
+		assert!(matches!(rollback.new_dir(&refactored_path), Err(Error::RepeatedNewDir(_))));
+		assert_eq!(rollback.new_dirs_len(), 1);
+	});
+}
+
+#[test]
+fn commit_detects_repeated_new_dirs() {
+	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, mut rollback| {
+		let dir = builder.new_dirs()[0];
+		// The parent dir doesn't exist yet, so the paths cannot be resolved when they're noted.
+		let path = dir.join("inner");
+		rollback.new_dir(&path).expect("The dir should be noted; qed;");
+		rollback
+			.new_dir(refactored_path(builder, dir).join("inner"))
+			.expect("The dir should be noted; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::RepeatedNewDir(_))));
+		assert!(!dir.exists());
+	});
+}
+
+#[test]
+fn dry_run_detects_repeated_new_dirs() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let dir = builder.new_dirs()[0];
+		let path = dir.join("inner");
+		rollback.new_dir(&path).expect("The dir should be noted; qed;");
+		rollback
+			.new_dir(refactored_path(builder, dir).join("inner"))
+			.expect("The dir should be noted; qed;");
+		std::fs::create_dir(dir).expect("The dir can be created; qed;");
+
+		assert!(matches!(rollback.dry_run(), Err(Error::RepeatedNewDir(_))));
+		assert!(!path.exists());
+	});
+}
+
+#[test]
+fn new_file_detects_repeated_new_files() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, mut rollback| {
+		let refactored_path = refactored_path(builder, builder.new_files()[0]);
+
+		assert!(matches!(rollback.new_file(&refactored_path), Err(Error::RepeatedNewFile(_))));
+		assert_eq!(rollback.new_files_len(), 1);
+	});
+}
+
+#[test]
+fn commit_detects_repeated_new_files() {
+	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, mut rollback| {
+		let dir = builder.new_dirs()[0];
+		// The parent dir doesn't exist yet, so the paths cannot be resolved when they're noted.
+		let path = dir.join("file.txt");
+		rollback.new_file(&path).expect("The file should be noted; qed;");
+		rollback
+			.new_file(refactored_path(builder, dir).join("file.txt"))
+			.expect("The file should be noted; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::RepeatedNewFile(_))));
+		assert!(!path.exists());
+		assert!(!dir.exists());
+	});
+}
+
+#[test]
+fn dry_run_detects_repeated_new_files() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let dir = builder.new_dirs()[0];
+		let path = dir.join("file.txt");
+		// The parent dir doesn't exist yet, so the paths cannot be resolved when they're noted.
+		// It's created by other means before the dry run.
+		rollback.new_dir(dir).expect("The dir should be noted; qed;");
+		rollback.new_file(&path).expect("The file should be noted; qed;");
+		rollback
+			.new_file(refactored_path(builder, dir).join("file.txt"))
+			.expect("The file should be noted; qed;");
+		rollback.forget(dir);
+		std::fs::create_dir(dir).expect("The dir can be created; qed;");
+
+		assert!(matches!(rollback.dry_run(), Err(Error::RepeatedNewFile(_))));
+		assert!(!path.exists());
+	});
 }
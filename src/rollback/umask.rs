@@ -0,0 +1,27 @@
+// SPDX-License-Identifier: GPL-3.0
+
+#[cfg(all(test, not(feature = "integration-tests")))]
+mod tests;
+
+// Sets the process umask while the guard is alive, restoring the previous one as soon as it goes
+// out of scope.
+pub(crate) struct UmaskGuard {
+	previous: libc::mode_t,
+}
+
+impl UmaskGuard {
+	pub(crate) fn new(umask: u32) -> Self {
+		// SAFETY: umask cannot fail, it just swaps the process file mode creation mask.
+		let previous = unsafe { libc::umask(umask as libc::mode_t) };
+		Self { previous }
+	}
+}
+
+impl Drop for UmaskGuard {
+	fn drop(&mut self) {
+		// SAFETY: umask cannot fail, it just swaps the process file mode creation mask.
+		unsafe {
+			libc::umask(self.previous);
+		}
+	}
+}
@@ -0,0 +1,24 @@
+// SPDX-License-Identifier: GPL-3.0
+
+use super::*;
+
+// Reads the current umask without modifying it
+fn current_umask() -> libc::mode_t {
+	unsafe {
+		let umask = libc::umask(0);
+		libc::umask(umask);
+		umask
+	}
+}
+
+#[test]
+fn umask_guard_works() {
+	let original_umask = current_umask();
+
+	{
+		let _guard = UmaskGuard::new(0o077);
+		assert_eq!(current_umask(), 0o077);
+	}
+
+	assert_eq!(current_umask(), original_umask);
+}
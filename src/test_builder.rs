@@ -1,11 +1,9 @@
 // SPDX-License-Identifier: GPL-3.0
 
 use crate::Rollback;
-use std::{
-	fs::Permissions,
-	os::unix::fs::PermissionsExt,
-	path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::{fs::Permissions, os::unix::fs::PermissionsExt};
 use tempfile::{NamedTempFile, TempDir};
 
 pub const ORIGINAL_BUILDER_FILE_CONTENT: &str = "Hello world";
@@ -182,25 +180,51 @@ impl TestBuilder {
 		}
 
 		if self.with_read_only_dir {
-			std::fs::set_permissions(self.tempdir.path(), Permissions::from_mode(0o555))
-				.expect("temp dir permissions should be configurable; qed;");
+			set_read_only(self.tempdir.path());
 		}
 
 		if self.with_read_only_temp_dir {
 			std::env::set_var("TMPDIR", self.tempdir.path());
 			std::env::set_var("TEMP", self.tempdir.path());
+			std::env::set_var("TMP", self.tempdir.path());
 
-			std::fs::set_permissions(self.tempdir.path(), Permissions::from_mode(0o555))
-				.expect("temp dir permissions should be configurable; qed;");
+			set_read_only(self.tempdir.path());
 		}
 
 		if self.with_permissionless_files {
-			self.existing_files.iter().for_each(|file| {
-				std::fs::set_permissions(file, Permissions::from_mode(0o000))
-					.expect("File permissions should be configurable; qed;");
-			});
+			self.existing_files.iter().for_each(|file| remove_permissions(file.path()));
 		}
 
 		test(self, rollback);
 	}
 }
+
+// Makes a dir read only. On Unix its content can be listed and traversed but not modified, while
+// on Windows it gets the read-only attribute.
+fn set_read_only(path: &Path) {
+	#[cfg(unix)]
+	let permissions = Permissions::from_mode(0o555);
+	#[cfg(not(unix))]
+	let permissions = {
+		let mut permissions = std::fs::metadata(path).expect("The path exists; qed;").permissions();
+		permissions.set_readonly(true);
+		permissions
+	};
+	std::fs::set_permissions(path, permissions)
+		.expect("temp dir permissions should be configurable; qed;");
+}
+
+// Removes every permission from a file. Windows doesn't have permission bits, so the closest it
+// gets is the read-only attribute.
+fn remove_permissions(path: &Path) {
+	#[cfg(unix)]
+	let permissions = Permissions::from_mode(0o000);
+	#[cfg(not(unix))]
+	let permissions = {
+		let mut permissions = std::fs::metadata(path).expect("The path exists; qed;").permissions();
+		permissions.set_readonly(true);
+		permissions
+	};
+	std::fs::set_permissions(path, permissions)
+		.expect("File permissions should be configurable; qed;");
+}
@@ -4,9 +4,21 @@
 
 use fs_rollback::{
 	test_builder::{TestBuilder, MODIFIED_BUILDER_FILE_CONTENT, ORIGINAL_BUILDER_FILE_CONTENT},
-	Error,
+	CommitCounts, CommitOrder, CommitOutcome, CommitPhase, CommitReport, Error, LinkPolicy,
+	ReNotePolicy, Rollback, StagedKind, VetoPolicy,
 };
-use std::{fs::File, io::ErrorKind, path::Path};
+use std::{
+	fs::File,
+	io::{ErrorKind, Write},
+	os::{
+		fd::AsRawFd,
+		unix::fs::{MetadataExt, PermissionsExt},
+	},
+	path::Path,
+};
+
+// Some content including NULs and invalid UTF-8.
+const BINARY_CONTENT: &[u8] = &[0x00, 0xff, 0xfe, 0x00, 0xc3, 0x28, 0x0a, 0x00, 0x80];
 
 #[test]
 fn note_file_works() {
@@ -25,12 +37,101 @@ fn note_file_works() {
 	});
 }
 
+#[test]
+fn note_file_reports_changes_against_the_baseline() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		let baseline_dir = builder.get_temp_dir_path().join("baseline");
+
+		// Without baseline, the file is always considered as changed
+		assert!(rollback.note_file(path).expect("The file should be noted; qed;"));
+
+		// The file isn't part of the baseline yet, so it's considered as changed
+		let mut rollback = Rollback::default().with_baseline_dir(&baseline_dir);
+		assert!(rollback.note_file(path).expect("The file should be noted; qed;"));
+		rollback.commit().expect("The rollback should be committed; qed;");
+
+		// The file is unchanged since the last commit
+		let mut rollback = Rollback::default().with_baseline_dir(&baseline_dir);
+		assert!(!rollback.note_file(path).expect("The file should be noted; qed;"));
+		std::fs::write(
+			rollback.get_noted_file(path).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+		rollback.commit().expect("The rollback should be committed; qed;");
+
+		// The committed file changed, so the baseline was updated
+		let mut rollback = Rollback::default().with_baseline_dir(&baseline_dir);
+		assert!(!rollback.note_file(path).expect("The file should be noted; qed;"));
+
+		// The file changes outside the rollback
+		std::fs::write(path, ORIGINAL_BUILDER_FILE_CONTENT).expect("The file is writable; qed;");
+		let mut rollback = Rollback::default().with_baseline_dir(&baseline_dir);
+		assert!(rollback.note_file(path).expect("The file should be noted; qed;"));
+	});
+}
+
+#[test]
+fn note_files_works() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		assert!(rollback.note_files(builder.existing_files()).is_ok());
+
+		builder
+			.existing_files()
+			.iter()
+			.for_each(|file| assert!(rollback.is_noted(file)));
+	});
+}
+
+#[test]
+fn note_files_discards_the_noted_files_if_one_fails() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let missing = builder.get_temp_dir_path().join("missing.txt");
+		let mut files: Vec<_> =
+			builder.existing_files().into_iter().map(Path::to_path_buf).collect();
+		files.insert(1, missing.clone());
+
+		match rollback.note_files(&files) {
+			Err(Error::NotAFile(item)) => assert_eq!(item, format!("{}", missing.display())),
+			_ => panic!("Unexpected error"),
+		}
+		assert!(rollback.is_empty());
+	});
+}
+
+#[test]
+fn note_file_works_with_empty_and_binary_files() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let empty_file = builder.get_temp_dir_path().join("empty");
+		let binary_file = builder.get_temp_dir_path().join("binary");
+		std::fs::write(&empty_file, []).expect("The file should be created; qed;");
+		std::fs::write(&binary_file, BINARY_CONTENT).expect("The file should be created; qed;");
+
+		let mut rollback = rollback;
+		rollback.note_file(&empty_file).expect("The file should be noted; qed;");
+		rollback.note_file(&binary_file).expect("The file should be noted; qed;");
+
+		// The temporary files are exact copies of the originals
+		assert!(std::fs::read(
+			rollback.get_noted_file(&empty_file).expect("The file is noted; qed;")
+		)
+		.expect("The file should be readable; qed;")
+		.is_empty());
+		assert_eq!(
+			std::fs::read(rollback.get_noted_file(&binary_file).expect("The file is noted; qed;"))
+				.expect("The file should be readable; qed;"),
+			BINARY_CONTENT
+		);
+	});
+}
+
 #[test]
 fn note_file_fails_if_provided_path_isnt_file() {
 	TestBuilder::new(Some(0)).execute(|_, mut rollback| {
 		let some_path = "some/path";
 
-		match rollback.note_file(some_path.as_ref()) {
+		match rollback.note_file::<&Path>(some_path.as_ref()) {
 			Err(Error::NotAFile(item)) => assert_eq!(item, format!("{}", some_path)),
 			_ => panic!("Unexpected error"),
 		}
@@ -106,325 +207,5535 @@ fn note_file_fails_if_original_file_cannot_be_copied() {
 		});
 }
 
+// Checks if the file can be locked through a different handle without waiting.
+fn is_lockable(path: &Path, operation: i32) -> bool {
+	let file = File::open(path).expect("The file exists; qed;");
+	unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) == 0 }
+}
+
 #[test]
-fn new_file_works() {
+fn note_file_locked_works() {
 	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
-		let path = builder.new_files()[0];
-		assert!(rollback.new_file(path).is_ok());
-		assert!(rollback.get_new_file(path).is_some());
+		let path = builder.existing_files()[0];
+		assert!(rollback.note_file_locked(path).is_ok());
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			std::fs::read_to_string(
+				rollback.get_noted_file(path).expect("The file is noted; qed;")
+			)
+			.expect("The file exists; qed;")
+		);
+
+		// The original is locked as shared
+		assert!(is_lockable(path, libc::LOCK_SH));
+		assert!(!is_lockable(path, libc::LOCK_EX));
+
+		// The lock is released as soon as the rollback is dropped
+		drop(rollback);
+		assert!(is_lockable(path, libc::LOCK_EX));
 	});
 }
 
 #[test]
-fn new_file_fails_if_path_already_exists() {
+fn note_file_locked_fails_if_provided_path_isnt_file() {
 	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
-		let path = builder.existing_files()[0];
-		match rollback.new_file(path) {
-			Err(Error::NewItemAlreadyExists(item)) =>
-				assert_eq!(format!("{}", path.display()), item),
+		let path = builder.new_files()[0];
+		match rollback.note_file_locked(path) {
+			Err(Error::NotAFile(item)) => assert_eq!(item, format!("{}", path.display())),
 			_ => panic!("Unexpected error"),
 		}
 	});
 }
 
 #[test]
-fn new_file_fails_if_path_already_noted() {
-	TestBuilder::new(Some(1)).with_new_files().execute(|builder, mut rollback| {
-		let path = builder.new_files()[0];
-		match rollback.new_file(path) {
-			Err(Error::AlreadyNoted(item)) => assert_eq!(format!("{}", path.display()), item),
+fn note_file_locked_fails_if_provided_path_is_already_noted() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		match rollback.note_file_locked(path) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(item, format!("{}", path.display())),
 			_ => panic!("Unexpected error"),
 		}
 	});
 }
 
 #[test]
-fn new_file_fails_if_path_cannot_be_a_file() {
+fn temp_files_are_named_as_configured() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let noted = builder.existing_files()[0];
+		let new_file = builder.get_temp_dir_path().join("new file.txt");
+		// Rebind rollback to accomplish with new_file lifetime
+		let mut rollback =
+			rollback.with_temp_file_prefix("fs_rollback_").with_temp_file_target_names();
+		rollback.note_file(noted).expect("The file can be noted; qed;");
+		rollback.new_file(&new_file).expect("The file can be added; qed;");
+
+		let name = |path: &Path| {
+			path.file_name()
+				.expect("The path is a file; qed;")
+				.to_string_lossy()
+				.into_owned()
+		};
+		let noted_name = name(rollback.get_noted_file(noted).expect("The file is noted; qed;"));
+		let new_file_name =
+			name(rollback.get_new_file(&new_file).expect("The file is staged; qed;"));
+		assert!(noted_name.starts_with("fs_rollback_"));
+		assert!(noted_name.ends_with(&format!("-{}", name(noted))));
+		assert!(new_file_name.starts_with("fs_rollback_"));
+		assert!(new_file_name.ends_with("-new_file.txt"));
+
+		// The names don't affect the commit
+		assert!(rollback.commit().is_ok());
+		assert!(new_file.is_file());
+	});
+}
+
+#[test]
+fn commit_modifies_the_target_of_noted_symlinks_keeping_the_link() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let target = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::os::unix::fs::symlink(target, &link).expect("The link can be created; qed;");
+		// Rebind rollback to accomplish with link lifetime
+		let mut rollback = rollback;
+		rollback.note_file(&link).expect("The file can be noted; qed;");
+		std::fs::write(
+			rollback.get_noted_file(&link).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file is writable; qed;");
+
+		assert_eq!(rollback.symlinked_files(), vec![link.as_path()]);
+		assert!(rollback.commit().is_ok());
+		assert!(link.symlink_metadata().expect("The link exists; qed;").is_symlink());
+		assert_eq!(
+			std::fs::read_to_string(target).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn symlinked_files_is_empty_if_no_symlink_is_noted() {
+	TestBuilder::new(Some(2)).with_noted_files().execute(|_, rollback| {
+		assert!(rollback.symlinked_files().is_empty());
+	});
+}
+
+#[test]
+fn note_symlink_works() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let target = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::os::unix::fs::symlink(target, &link).expect("The link can be created; qed;");
+		let mut permissions =
+			std::fs::metadata(target).expect("The file exists; qed;").permissions();
+		permissions.set_mode(0o640);
+		std::fs::set_permissions(target, permissions).expect("The file is writable; qed;");
+		// Rebind rollback to accomplish with link lifetime
+		let mut rollback = rollback;
+
+		assert!(rollback.note_symlink(&link).is_ok());
+		let temp_file = rollback.get_noted_file(&link).expect("The link is noted; qed;");
+		assert_eq!(
+			std::fs::read_to_string(temp_file).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		std::fs::write(temp_file, MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("The file is writable; qed;");
+		// The link itself is noted, not its target
+		assert!(rollback.symlinked_files().is_empty());
+
+		assert!(rollback.commit().is_ok());
+		let metadata = link.symlink_metadata().expect("The file exists; qed;");
+		assert!(metadata.is_file());
+		assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+		assert_eq!(
+			std::fs::read_to_string(&link).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::read_to_string(target).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn note_symlink_works_for_dangling_links() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::os::unix::fs::symlink("missing.txt", &link).expect("The link can be created; qed;");
+		// Rebind rollback to accomplish with link lifetime
+		let mut rollback = rollback;
+
+		assert!(rollback.note_symlink(&link).is_ok());
+		let temp_file = rollback.get_noted_file(&link).expect("The link is noted; qed;");
+		assert_eq!(std::fs::read(temp_file).expect("The file exists; qed;"), b"");
+		std::fs::write(temp_file, MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("The file is writable; qed;");
+
+		assert!(rollback.commit().is_ok());
+		assert_eq!(
+			std::fs::read_to_string(&link).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert!(!builder.get_temp_dir_path().join("missing.txt").exists());
+	});
+}
+
+#[test]
+fn note_symlink_fails_if_path_isnt_a_symlink() {
 	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
-		let path = builder.new_dirs()[0];
-		match rollback.new_file(path) {
-			Err(Error::NotAFile(item)) => assert_eq!(format!("{}", path.display()), item),
-			_ => panic!("Unexpected error"),
-		}
+		let path = builder.existing_files()[0];
+
+		assert!(matches!(
+			rollback.note_symlink(path),
+			Err(Error::NotASymlink(err_path)) if err_path == format!("{}", path.display())
+		));
 	});
 }
 
 #[test]
-fn new_file_fails_if_it_cannot_create_temp_file() {
-	// Save original tempdir locations as this test will modify them.
-	let original_temp_dir = std::env::var("TEMP").unwrap_or_default(); // Windows
-	let original_tmpdir = std::env::var("TMPDIR").unwrap_or_default(); // UNIX
+fn note_symlink_fails_if_link_is_already_noted() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::os::unix::fs::symlink(builder.existing_files()[0], &link)
+			.expect("The link can be created; qed;");
+		// Rebind rollback to accomplish with link lifetime
+		let mut rollback = rollback;
+		rollback.note_symlink(&link).expect("The link can be noted; qed;");
 
-	TestBuilder::new(Some(1))
-		.with_read_only_temp_dir()
-		.execute(|builder, mut rollback| {
-			let result = rollback.new_file(builder.new_files()[0]);
+		assert!(matches!(rollback.note_symlink(&link), Err(Error::AlreadyNoted(_))));
+	});
+}
 
-			// Reset original tempdir locations before asserting, so we're safe in case of panic
-			std::env::set_var("TMPDIR", original_tmpdir.clone());
-			std::env::set_var("TEMP", original_temp_dir.clone());
+#[test]
+fn commit_restores_replaced_symlinks_if_it_fails() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let target = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link.txt");
+		let dir = builder.get_temp_dir_path().join("dir");
+		let new_file = dir.join("file.txt");
+		std::os::unix::fs::symlink(target, &link).expect("The link can be created; qed;");
+		std::fs::create_dir(&dir).expect("The dir can be created; qed;");
+		// Rebind rollback to accomplish with link and new_file lifetime
+		let mut rollback = rollback;
+		rollback.note_symlink(&link).expect("The link can be noted; qed;");
+		rollback.new_file(&new_file).expect("The file can be added; qed;");
+		// The new file cannot be created once its parent dir is removed
+		std::fs::remove_dir(&dir).expect("The dir can be removed; qed;");
 
-			match result {
-				Err(Error::IO(err)) => assert_eq!(err.kind(), ErrorKind::PermissionDenied),
-				_ => panic!("Unexpected error"),
-			}
-		});
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+		assert!(link.symlink_metadata().expect("The link exists; qed;").is_symlink());
+		assert_eq!(std::fs::read_link(&link).expect("The link exists; qed;"), target);
+	});
 }
 
 #[test]
-fn new_dir_works() {
+fn note_file_if_exists_works() {
 	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
-		let path = builder.new_dirs()[0];
-		assert!(rollback.new_dir(path).is_ok());
+		let path = builder.existing_files()[0];
+
+		assert!(matches!(rollback.note_file_if_exists(path), Ok(true)));
+		assert!(rollback.get_noted_file(path).is_some());
 	});
 }
 
 #[test]
-fn new_dir_fails_if_path_already_exists() {
+fn note_file_if_exists_skips_missing_files() {
 	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
-		let path = builder.new_dirs()[0];
-		std::fs::create_dir_all(path).expect("The directory should be created; qed;");
-		match rollback.new_dir(path) {
-			Err(Error::NewItemAlreadyExists(item)) =>
-				assert_eq!(format!("{}", path.display()), item),
-			_ => panic!("Unexpected error"),
-		}
+		let path = builder.new_files()[0];
+
+		assert!(matches!(rollback.note_file_if_exists(path), Ok(false)));
+		assert!(rollback.get_noted_file(path).is_none());
+		assert!(matches!(rollback.commit(), Ok(CommitOutcome::Empty)));
 	});
 }
 
 #[test]
-fn new_dir_fails_if_path_already_noted() {
-	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, mut rollback| {
-		let path = builder.new_dirs()[0];
-		match rollback.new_dir(path) {
-			Err(Error::AlreadyNoted(item)) => assert_eq!(format!("{}", path.display()), item),
+fn note_file_if_exists_fails_if_path_isnt_a_file() {
+	TestBuilder::new(Some(0)).execute(|builder, mut rollback| {
+		let dir = builder.get_temp_dir_path();
+
+		assert!(matches!(
+			rollback.note_file_if_exists(dir),
+			Err(Error::NotAFile(path)) if path == format!("{}", dir.display())
+		));
+	});
+}
+
+#[test]
+fn note_file_lazy_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		assert!(rollback.note_file_lazy(path, Box::new(|content| content)).is_ok());
+		// There's no temporary file to modify
+		assert!(rollback.get_noted_file(path).is_none());
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn note_file_lazy_fails_if_provided_path_isnt_file() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.get_temp_dir_path();
+		match rollback.note_file_lazy(path, Box::new(|content| content)) {
+			Err(Error::NotAFile(item)) => assert_eq!(item, format!("{}", path.display())),
 			_ => panic!("Unexpected error"),
 		}
 	});
 }
 
 #[test]
-fn new_dir_fails_if_path_cannot_be_a_dir() {
+fn note_file_truncate_works() {
 	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
-		let path = builder.new_files()[0];
-		match rollback.new_dir(path) {
-			Err(Error::NotADir(item)) => assert_eq!(format!("{}", path.display()), item),
+		let path = builder.existing_files()[0];
+		assert!(rollback.note_file_truncate(path).is_ok());
+		// The temporary file starts empty
+		let temp_file = rollback.get_noted_file(path).expect("The file is noted; qed;");
+		assert_eq!(std::fs::read_to_string(temp_file).expect("The file exists; qed;"), "");
+
+		std::fs::write(temp_file, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+		assert!(rollback.commit().is_ok());
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn note_file_truncate_fails_if_provided_path_is_already_noted() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		match rollback.note_file_truncate(path) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(item, format!("{}", path.display())),
 			_ => panic!("Unexpected error"),
 		}
 	});
 }
 
 #[test]
-fn get_noted_file_works() {
-	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
-		assert!(rollback.get_noted_file(builder.existing_files()[0]).is_some());
-		assert!(rollback.get_noted_file::<&Path>("something".as_ref()).is_none());
+fn commit_restores_files_noted_truncated_if_it_fails() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		assert!(rollback.note_file_truncate(path).is_ok());
+		std::fs::write(
+			rollback.get_noted_file(path).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file exists; qed;");
+
+		// Deleting a temporary file means that rollback cannot commit the related new file.
+		std::fs::remove_file(
+			rollback
+				.get_new_file(builder.new_files()[0])
+				.expect("The file exists for this rollback; qed;"),
+		)
+		.expect("This should be possible; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
 	});
 }
 
 #[test]
-fn get_noted_file_works_with_different_path_representation() {
-	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+fn note_file_with_policy_notes_the_file_if_it_isnt_noted_yet() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
 		let path = builder.existing_files()[0];
+		assert!(rollback.note_file_with_policy(path, ReNotePolicy::Error).is_ok());
+		let noted_file = rollback.get_noted_file(path).expect("The file is noted; qed;");
+		assert_eq!(
+			std::fs::read_to_string(noted_file).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
 
-		let original_cwd = std::env::current_dir().expect("The current dir is the crate dir; qed;");
-
-		std::env::set_current_dir(builder.get_temp_dir_path())
-			.expect("The tempdir should be able to be current_dir; qed;");
+#[test]
+fn note_file_with_policy_keeps_the_noted_file_content() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.note_file(path).expect("The file can be noted; qed;");
+		let noted_file =
+			rollback.get_noted_file(path).expect("The file is noted; qed;").to_path_buf();
+		std::fs::write(&noted_file, "Edited").expect("The file exists; qed;");
+		std::fs::write(path, "Changed").expect("The file exists; qed;");
 
-		let refactored_path =
-			Path::new(path.file_name().expect("The path is a file, so file_name exists; qed;"));
+		assert!(rollback.note_file_with_policy(path, ReNotePolicy::Keep).is_ok());
+		assert_eq!(std::fs::read_to_string(&noted_file).expect("The file exists; qed;"), "Edited");
+	});
+}
 
-		let noted_file = rollback.get_noted_file(path);
-		let noted_file_refactored_path = rollback.get_noted_file(&refactored_path);
+#[test]
+fn note_file_with_policy_resets_the_noted_file_content() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.note_file(path).expect("The file can be noted; qed;");
+		let noted_file =
+			rollback.get_noted_file(path).expect("The file is noted; qed;").to_path_buf();
+		std::fs::write(&noted_file, "Edited").expect("The file exists; qed;");
+		std::fs::write(path, "Changed").expect("The file exists; qed;");
 
-		std::env::set_current_dir(original_cwd)
-			.expect("The original_cwd should be able to be current_dir; qed;");
+		assert!(rollback.note_file_with_policy(path, ReNotePolicy::Reset).is_ok());
+		assert_eq!(std::fs::read_to_string(&noted_file).expect("The file exists; qed;"), "Changed");
 
-		assert!(noted_file_refactored_path.is_some());
-		assert_eq!(noted_file, noted_file_refactored_path);
+		rollback.commit().expect("The commit works; qed;");
+		assert_eq!(std::fs::read_to_string(path).expect("The file exists; qed;"), "Changed");
 	});
 }
 
 #[test]
-fn get_new_file_works() {
-	TestBuilder::new(Some(1)).with_new_files().execute(|builder, rollback| {
-		assert!(rollback.get_new_file(builder.new_files()[0]).is_some());
-		assert!(rollback.get_new_file::<&Path>("something".as_ref()).is_none());
+fn note_file_with_policy_fails_if_file_is_already_noted_and_policy_is_error() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.note_file(path).expect("The file can be noted; qed;");
+		match rollback.note_file_with_policy(path, ReNotePolicy::Error) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(item, format!("{}", path.display())),
+			_ => panic!("Unexpected error"),
+		}
 	});
 }
 
+#[cfg(feature = "mmap")]
 #[test]
-fn commit_works() {
-	TestBuilder::new(None)
-		.with_noted_files()
-		.with_new_files()
-		.with_new_dirs()
-		.execute(|builder, rollback| {
-			builder.existing_files().iter().for_each(|file| {
-				assert_eq!(
-					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
-					ORIGINAL_BUILDER_FILE_CONTENT
-				)
-			});
+fn note_file_with_policy_remaps_mapped_files_upon_reset() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.note_file_mmap(path).expect("The file can be noted; qed;");
+		std::fs::write(path, "A longer content than the original one")
+			.expect("The file exists; qed;");
 
-			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
-			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		assert!(rollback.note_file_with_policy(path, ReNotePolicy::Reset).is_ok());
+		let map = rollback.get_noted_file_mmap(path).expect("The file is mapped; qed;");
+		assert_eq!(map, b"A longer content than the original one");
+	});
+}
 
-			assert!(rollback.commit().is_ok());
+#[test]
+fn note_file_fails_if_file_is_hard_linked_and_hard_link_check_is_enabled() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::fs::hard_link(path, &link).expect("The link can be created; qed;");
+		let mut rollback = rollback.with_hard_link_check();
 
-			builder.existing_files().iter().for_each(|file| {
-				assert_eq!(
-					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
-					MODIFIED_BUILDER_FILE_CONTENT
-				)
-			});
+		match rollback.note_file(path) {
+			Err(Error::HardLinked(item)) => assert_eq!(item, format!("{}", path.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
 
+#[test]
+fn note_file_fails_if_hard_links_to_the_same_file_are_noted() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::fs::hard_link(path, &link).expect("The link can be created; qed;");
+		let mut rollback = rollback;
+
+		assert!(rollback.note_file(path).is_ok());
+		match rollback.note_file(&link) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(item, format!("{}", link.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn commit_replaces_noted_files_atomically_by_default() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::fs::hard_link(path, &link).expect("The link can be created; qed;");
+		let inode = std::fs::metadata(path).expect("The file exists; qed;").ino();
+		let mut permissions = std::fs::metadata(path).expect("The file exists; qed;").permissions();
+		permissions.set_mode(0o640);
+		std::fs::set_permissions(path, permissions).expect("The file is writable; qed;");
+		let entries = std::fs::read_dir(builder.get_temp_dir_path())
+			.expect("The dir exists; qed;")
+			.count();
+
+		assert!(rollback.commit().is_ok());
+		// The original has been replaced by a new file keeping its permissions, while the old
+		// file is only reachable through the link
+		let metadata = std::fs::metadata(path).expect("The file exists; qed;");
+		assert_ne!(metadata.ino(), inode);
+		assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::read_to_string(&link).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		// No replacement is left next to the original
+		assert_eq!(
+			std::fs::read_dir(builder.get_temp_dir_path())
+				.expect("The dir exists; qed;")
+				.count(),
+			entries
+		);
+	});
+}
+
+#[test]
+fn commit_with_preserve_links_policy_changes_the_content_seen_through_hard_links() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::fs::hard_link(path, &link).expect("The link can be created; qed;");
+		let inode = std::fs::metadata(path).expect("The file exists; qed;").ino();
+		let rollback = rollback.with_link_policy(LinkPolicy::PreserveLinks);
+
+		assert!(rollback.commit().is_ok());
+		assert_eq!(std::fs::metadata(path).expect("The file exists; qed;").ino(), inode);
+		assert_eq!(
+			std::fs::read_to_string(&link).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn commit_with_break_links_policy_keeps_the_original_content_in_hard_links() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::fs::hard_link(path, &link).expect("The link can be created; qed;");
+		let rollback = rollback.with_link_policy(LinkPolicy::BreakLinks);
+
+		assert!(rollback.commit().is_ok());
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::read_to_string(&link).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(std::fs::metadata(&link).expect("The file exists; qed;").nlink(), 1);
+	});
+}
+
+#[test]
+fn hard_linked_files_works() {
+	TestBuilder::new(Some(2)).execute(|builder, rollback| {
+		let linked = builder.existing_files()[0];
+		let unlinked = builder.existing_files()[1];
+		let link = builder.get_temp_dir_path().join("link.txt");
+		std::fs::hard_link(linked, &link).expect("The link can be created; qed;");
+		let mut rollback = rollback;
+
+		rollback.note_file(linked).expect("The file can be noted; qed;");
+		rollback.note_file(unlinked).expect("The file can be noted; qed;");
+
+		assert_eq!(rollback.hard_linked_files(), vec![linked]);
+	});
+}
+
+#[test]
+fn forget_works() {
+	TestBuilder::new(Some(3))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let noted = builder.existing_files()[0];
+			let new_file = builder.new_files()[0];
+			let new_dir = builder.new_dirs()[0];
+			let temp_file =
+				rollback.get_noted_file(noted).expect("The file is noted; qed;").to_path_buf();
+			let mut rollback = rollback;
+
+			assert!(rollback.forget(noted));
+			assert!(rollback.forget(new_file));
+			assert!(rollback.forget(new_dir));
+			// Nothing's staged for them anymore
+			assert!(!rollback.forget(noted));
+			assert!(!rollback.is_noted(noted));
+			assert!(!rollback.is_new_file(new_file));
+			assert!(!rollback.is_new_dir(new_dir));
+			assert!(!temp_file.exists());
+
+			assert!(rollback.commit().is_ok());
+			assert_eq!(
+				std::fs::read_to_string(noted).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			assert!(!new_file.exists());
+			assert!(!new_dir.exists());
+			// The other operations are committed
+			assert_eq!(
+				std::fs::read_to_string(builder.existing_files()[1])
+					.expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+			assert!(builder.new_files()[1].exists());
+			assert!(builder.new_dirs()[1].exists());
+		});
+}
+
+#[test]
+fn clear_works() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, mut rollback| {
+			rollback.clear();
+			assert!(rollback.is_empty());
+
+			// The instance can be reused
+			let noted = builder.existing_files()[0];
+			rollback.note_file(noted).expect("The file can be noted again; qed;");
+			std::fs::write(
+				rollback.get_noted_file(noted).expect("The file is noted; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT,
+			)
+			.expect("The file exists; qed;");
+			assert!(rollback.commit().is_ok());
+			assert_eq!(
+				std::fs::read_to_string(noted).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+			// The cleared operations aren't committed
+			assert_eq!(
+				std::fs::read_to_string(builder.existing_files()[1])
+					.expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			builder.new_files().iter().for_each(|file| assert!(!file.exists()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.exists()));
+		});
+}
+
+#[test]
+fn forget_works_for_deletions() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.delete_file(path).expect("The file can be deleted; qed;");
+		assert_eq!(rollback.get_deleted_file(path), Some(path));
+
+		assert!(rollback.forget(path));
+		assert!(rollback.get_deleted_file(path).is_none());
+		assert!(path.is_file());
+		assert!(rollback.is_empty());
+		assert!(!rollback.forget(builder.new_files()[0]));
+	});
+}
+
+#[test]
+fn rollback_to_works() {
+	TestBuilder::new(Some(3)).execute(|builder, mut rollback| {
+		let (kept, noted, deleted) =
+			(builder.existing_files()[0], builder.existing_files()[1], builder.existing_files()[2]);
+		let (new_file, new_dir) = (builder.new_files()[0], builder.new_dirs()[0]);
+		rollback.note_file(kept).expect("The file can be noted; qed;");
+		let savepoint = rollback.savepoint();
+		rollback.note_file(noted).expect("The file can be noted; qed;");
+		rollback.new_file(new_file).expect("The file can be noted; qed;");
+		rollback.new_dir(new_dir).expect("The dir can be noted; qed;");
+		rollback.delete_file(deleted).expect("The file can be deleted; qed;");
+		let temp_files = [rollback.get_noted_file(noted), rollback.get_new_file(new_file)]
+			.map(|path| path.expect("The path is staged; qed;").to_path_buf());
+		let later_savepoint = rollback.savepoint();
+
+		rollback.rollback_to(savepoint);
+
+		assert!(rollback.get_deleted_file(deleted).is_none());
+		assert!(rollback.is_noted(kept));
+		assert_eq!(rollback.pending_count(), 1);
+		assert!(temp_files.iter().all(|path| !path.exists()));
+		// Rolling back to a savepoint taken after the current one does nothing
+		rollback.rollback_to(later_savepoint);
+		assert_eq!(rollback.pending_count(), 1);
+	});
+}
+
+#[test]
+fn merge_works() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let (noted, deleted) = (builder.existing_files()[0], builder.existing_files()[1]);
+		let (new_file, new_dir) = (builder.new_files()[0], builder.new_dirs()[0]);
+		rollback.note_file(noted).expect("The file can be noted; qed;");
+		std::fs::write(
+			rollback.get_noted_file(noted).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+
+		let mut other = Rollback::default();
+		other.new_file(new_file).expect("The file can be noted; qed;");
+		other.new_dir(new_dir).expect("The dir can be noted; qed;");
+		other.delete_file(deleted).expect("The file can be deleted; qed;");
+		let temp_file = other.get_new_file(new_file).expect("The file is new; qed;").to_path_buf();
+		std::fs::write(&temp_file, MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("The file should be writable; qed;");
+
+		assert!(rollback.merge(other).is_ok());
+
+		// The temporary files are kept alive
+		assert_eq!(rollback.get_new_file(new_file), Some(temp_file.as_path()));
+		assert!(temp_file.is_file());
+		assert_eq!(rollback.pending_count(), 4);
+
+		assert!(rollback.commit().is_ok());
+		assert_eq!(
+			std::fs::read_to_string(noted).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::read_to_string(new_file).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert!(new_dir.is_dir());
+		assert!(!deleted.exists());
+	});
+}
+
+#[test]
+fn merge_fails_if_operations_collide() {
+	TestBuilder::new(Some(1))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let dir = builder.get_temp_dir_path();
+			let refactored = |path: &Path| {
+				dir.join("..")
+					.join(dir.file_name().expect("The path is a dir, so file_name exists; qed;"))
+					.join(path.file_name().expect("The path is inside the dir; qed;"))
+			};
+			let noted = refactored(builder.existing_files()[0]);
+			let new_file = refactored(builder.new_files()[0]);
+			let new_dir = refactored(builder.new_dirs()[0]);
+			// Rebind rollback to accomplish with the refactored paths lifetime
+			let mut rollback = rollback;
+
+			let mut other = Rollback::default();
+			other.note_file(&noted).expect("The file can be noted; qed;");
+			assert!(matches!(rollback.merge(other), Err(Error::AlreadyNoted(_))));
+
+			let mut other = Rollback::default();
+			other.new_file(&new_file).expect("The file can be noted; qed;");
+			assert!(matches!(rollback.merge(other), Err(Error::RepeatedNewFile(_))));
+
+			let mut other = Rollback::default();
+			other.new_dir(&new_dir).expect("The dir can be noted; qed;");
+			assert!(matches!(rollback.merge(other), Err(Error::RepeatedNewDir(_))));
+
+			// Nothing has been merged
+			assert_eq!(rollback.pending_count(), 3);
+		});
+}
+
+#[test]
+fn staging_owned_paths_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let dir = builder.get_temp_dir_path();
+		// The paths are dropped right after staging them
+		for index in 0..3 {
+			rollback
+				.new_file(dir.join(format!("owned_{}.txt", index)))
+				.expect("The file can be noted; qed;");
+		}
+		rollback
+			.note_file(
+				dir.join(
+					builder.existing_files()[0].file_name().expect("The path is a file; qed;"),
+				),
+			)
+			.expect("The file can be noted; qed;");
+		assert_eq!(rollback.pending_count(), 4);
+
+		std::fs::write(
+			rollback.get_new_file(dir.join("owned_1.txt")).expect("The file is new; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+
+		assert!(rollback.commit().is_ok());
+		for index in 0..3 {
+			assert!(dir.join(format!("owned_{}.txt", index)).is_file());
+		}
+		assert_eq!(
+			std::fs::read_to_string(dir.join("owned_1.txt")).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn path_iterators_work() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			fn sorted(mut paths: Vec<&Path>) -> Vec<&Path> {
+				paths.sort();
+				paths
+			}
+
+			assert_eq!(sorted(rollback.noted_paths().collect()), sorted(builder.existing_files()));
+			assert_eq!(sorted(rollback.new_file_paths().collect()), sorted(builder.new_files()));
+			assert_eq!(rollback.new_dir_paths().collect::<Vec<_>>(), builder.new_dirs());
+			// The iterators can be requested again
+			assert_eq!(rollback.noted_paths().count(), 2);
+		});
+}
+
+#[test]
+fn entry_iterators_work() {
+	TestBuilder::new(Some(2)).with_new_files().execute(|builder, mut rollback| {
+		let [noted, lazy] = builder.existing_files()[..] else {
+			panic!("The builder has 2 existing files");
+		};
+		rollback.note_file(noted).expect("The file can be noted; qed;");
+		rollback
+			.note_file_lazy(lazy, Box::new(|content| content))
+			.expect("The file can be noted; qed;");
+
+		// Lazily noted files don't have a temporary file
+		assert_eq!(
+			rollback.noted_entries().collect::<Vec<_>>(),
+			vec![(noted, rollback.get_noted_file(noted).expect("The file is noted; qed;"))]
+		);
+
+		let mut new_file_entries = rollback.new_file_entries().collect::<Vec<_>>();
+		new_file_entries.sort();
+		let mut expected = builder
+			.new_files()
+			.into_iter()
+			.map(|path| (path, rollback.get_new_file(path).expect("The file is new; qed;")))
+			.collect::<Vec<_>>();
+		expected.sort();
+		assert_eq!(new_file_entries, expected);
+	});
+}
+
+#[test]
+fn len_accessors_work() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.execute(|builder, rollback| {
+			let mut rollback = rollback;
+			rollback.new_dir(builder.new_dirs()[0]).expect("The dir can be noted; qed;");
+
+			assert_eq!(rollback.noted_len(), 2);
+			assert_eq!(rollback.new_files_len(), 2);
+			assert_eq!(rollback.new_dirs_len(), 1);
+			assert_eq!(rollback.pending_count(), 5);
+			assert!(!rollback.is_empty());
+
+			rollback
+				.note_dir_metadata(builder.get_temp_dir_path())
+				.expect("The dir can be noted; qed;");
+			assert_eq!(rollback.pending_count(), 6);
+		});
+}
+
+#[test]
+fn pending_count_matches_staged_paths_sorted() {
+	TestBuilder::new(Some(1))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let dir = builder.get_temp_dir_path().join("dir");
+			std::fs::create_dir(&dir).expect("The dir can be created; qed;");
+			// Rebind rollback to accomplish with dir lifetime
+			let mut rollback = rollback;
+			rollback.note_dir(&dir).expect("The dir should be noted; qed;");
+			rollback
+				.note_dir_metadata(builder.get_temp_dir_path())
+				.expect("The dir can be noted; qed;");
+
+			assert_eq!(rollback.pending_count(), 5);
+			assert_eq!(rollback.pending_count(), rollback.staged_paths_sorted().len());
+		});
+}
+
+#[test]
+fn is_empty_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		assert!(rollback.is_empty());
+		assert_eq!(rollback.pending_count(), 0);
+
+		rollback
+			.delete_file(builder.existing_files()[0])
+			.expect("The file can be deleted; qed;");
+		assert!(!rollback.is_empty());
+		assert_eq!(rollback.pending_count(), 1);
+	});
+}
+
+#[test]
+fn is_noted_works() {
+	TestBuilder::new(Some(2)).with_noted_files().execute(|builder, rollback| {
+		let noted = builder.existing_files()[0];
+		let dir = builder.get_temp_dir_path();
+		let refactored_path = dir
+			.join("..")
+			.join(dir.file_name().expect("The path is a dir, so file_name exists; qed;"))
+			.join(noted.file_name().expect("The path is a file, so file_name exists; qed;"));
+
+		assert!(rollback.is_noted(noted));
+		assert!(rollback.is_noted(&refactored_path));
+		assert!(!rollback.is_noted(builder.new_files()[0]));
+		assert!(!rollback.is_noted(dir.join("missing.txt")));
+	});
+}
+
+#[test]
+fn is_new_file_and_is_new_dir_work() {
+	TestBuilder::new(Some(1))
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let new_file = builder.new_files()[0];
+			let new_dir = builder.new_dirs()[0];
+
+			assert!(rollback.is_new_file(new_file));
+			assert!(!rollback.is_new_file(new_dir));
+			assert!(rollback.is_new_dir(new_dir));
+			assert!(!rollback.is_new_dir(new_file));
+			assert!(!rollback.is_new_file(builder.existing_files()[0]));
+		});
+}
+
+#[test]
+fn get_writer_works() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, mut rollback| {
+		let noted = builder.existing_files()[0];
+		let new_file = builder.new_files()[0];
+		rollback.note_file(noted).expect("The file can be noted; qed;");
+
+		for path in [noted, new_file] {
+			let mut writer = rollback.get_writer(path).expect("The file is staged; qed;");
+			// The previous content is truncated
+			writer.write_all(b"Bye").expect("The file is writable; qed;");
+		}
+
+		assert!(rollback.commit().is_ok());
+		for path in [noted, new_file] {
+			assert_eq!(std::fs::read_to_string(path).expect("The file exists; qed;"), "Bye");
+		}
+	});
+}
+
+#[test]
+fn get_writer_returns_none_if_file_cannot_be_written() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let lazy = builder.existing_files()[0];
+		rollback
+			.note_file_lazy(lazy, Box::new(|content| content))
+			.expect("The file can be noted; qed;");
+
+		assert!(rollback.get_writer(lazy).is_none());
+		assert!(rollback.get_writer(builder.existing_files()[1]).is_none());
+		assert!(rollback.get_writer(builder.new_files()[0]).is_none());
+	});
+}
+
+#[test]
+fn get_writer_returns_none_for_deduplicated_files() {
+	TestBuilder::new(Some(2)).execute(|builder, rollback| {
+		let mut rollback = rollback.with_deduplicated_new_files();
+		let [first, second] = builder.new_files()[..] else {
+			panic!("There's 2 new files; qed;");
+		};
+		let mut reader = BINARY_CONTENT;
+		assert!(rollback.new_file_from_reader(first, &mut reader).is_ok());
+		let mut reader = BINARY_CONTENT;
+		assert!(rollback.new_file_from_reader(second, &mut reader).is_ok());
+
+		// Truncating the shared temporary file would rewrite both files
+		assert!(rollback.get_writer(first).is_none());
+		assert!(rollback.get_writer(second).is_none());
+
+		assert!(rollback.commit().is_ok());
+		assert_eq!(std::fs::read(first).expect("The file exists; qed;"), BINARY_CONTENT);
+		assert_eq!(std::fs::read(second).expect("The file exists; qed;"), BINARY_CONTENT);
+	});
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn get_writer_returns_none_for_mapped_files() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.note_file_mmap(path).expect("The file can be noted; qed;");
+
+		assert!(rollback.get_writer(path).is_none());
+	});
+}
+
+#[test]
+fn read_staged_works() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.execute(|builder, rollback| {
+			let [noted, lazy] = builder.existing_files()[..] else {
+				panic!("The builder has 2 existing files");
+			};
+			assert_eq!(
+				rollback.read_staged(noted).expect("The file is noted; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT.as_bytes()
+			);
+			assert_eq!(
+				rollback.read_staged(builder.new_files()[0]).expect("The file is new; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT.as_bytes()
+			);
+
+			// Noted files are matched through other path representations
+			let refactored_path = builder
+				.get_temp_dir_path()
+				.join(".")
+				.join(noted.file_name().expect("The path is a file, so file_name exists; qed;"));
+			assert_eq!(
+				rollback.read_staged(&refactored_path).expect("The file is noted; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT.as_bytes()
+			);
+
+			let mut rollback = rollback;
+			rollback.forget(lazy);
+			rollback
+				.note_file_lazy(lazy, Box::new(|content| content))
+				.expect("The file can be noted; qed;");
+			for path in
+				[lazy, builder.new_files()[1].parent().expect("The file has a parent; qed;")]
+			{
+				match rollback.read_staged(path) {
+					Err(Error::NotNoted(item)) => assert_eq!(item, format!("{}", path.display())),
+					_ => panic!("Unexpected error"),
+				}
+			}
+		});
+}
+
+#[test]
+fn append_to_noted_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.note_file(path).expect("The file can be noted; qed;");
+
+		assert!(rollback.append_to_noted(path, b" Appended").is_ok());
+		assert!(rollback.append_to_noted(path, b" twice").is_ok());
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			format!("{} Appended twice", ORIGINAL_BUILDER_FILE_CONTENT)
+		);
+	});
+}
+
+#[test]
+fn append_to_noted_marks_tracked_files_as_written() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.note_file(path).expect("The file can be noted; qed;");
+		let _handle = rollback.get_noted_handle(path).expect("The file is noted; qed;");
+
+		assert!(rollback.append_to_noted(path, b" Appended").is_ok());
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			format!("{} Appended", ORIGINAL_BUILDER_FILE_CONTENT)
+		);
+	});
+}
+
+#[test]
+fn append_to_noted_fails_if_file_isnt_noted() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		match rollback.append_to_noted(path, b" Appended") {
+			Err(Error::NotNoted(item)) => assert_eq!(item, format!("{}", path.display())),
+			_ => panic!("Unexpected error"),
+		}
+
+		let lazy = builder.existing_files()[1];
+		rollback
+			.note_file_lazy(lazy, Box::new(|content| content))
+			.expect("The file can be noted; qed;");
+		assert!(matches!(rollback.append_to_noted(lazy, b" Appended"), Err(Error::NotNoted(_))));
+		assert!(matches!(
+			rollback.append_to_noted(builder.new_files()[0], b" Appended"),
+			Err(Error::NotNoted(_))
+		));
+	});
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn append_to_noted_fails_for_mapped_files() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.note_file_mmap(path).expect("The file can be noted; qed;");
+
+		assert!(matches!(rollback.append_to_noted(path, b" Appended"), Err(Error::NotNoted(_))));
+	});
+}
+
+#[test]
+fn get_noted_handle_fails_if_file_isnt_noted() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		match rollback.get_noted_handle(path) {
+			Err(Error::NotNoted(item)) => assert_eq!(item, format!("{}", path.display())),
+			_ => panic!("Unexpected error"),
+		}
+
+		rollback
+			.note_file_lazy(path, Box::new(|content| content))
+			.expect("The file can be noted; qed;");
+		assert!(matches!(rollback.get_noted_handle(path), Err(Error::NotNoted(_))));
+	});
+}
+
+#[test]
+fn commit_skips_noted_files_untouched_through_their_handles() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let written = builder.existing_files()[0];
+		let untouched = builder.existing_files()[1];
+		rollback.note_file(written).expect("The file can be noted; qed;");
+		rollback.note_file(untouched).expect("The file can be noted; qed;");
+		let modified = std::fs::metadata(untouched)
+			.and_then(|metadata| metadata.modified())
+			.expect("The file exists; qed;");
+
+		let mut handle = rollback.get_noted_handle(written).expect("The file is noted; qed;");
+		handle.set_len(0).expect("The handle is writable; qed;");
+		handle
+			.write_all(MODIFIED_BUILDER_FILE_CONTENT.as_bytes())
+			.expect("The handle is writable; qed;");
+		assert!(handle.is_dirty());
+		let handle = rollback.get_noted_handle(untouched).expect("The file is noted; qed;");
+		assert!(!handle.is_dirty());
+		// Changes applied by other means than the handles are ignored for tracked files.
+		std::fs::write(
+			rollback.get_noted_file(untouched).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file exists; qed;");
+
+		match rollback.commit() {
+			Ok(CommitOutcome::Applied { counts }) => assert_eq!(counts.noted_files, 1),
+			_ => panic!("Unexpected outcome"),
+		}
+
+		assert_eq!(
+			std::fs::read_to_string(written).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::read_to_string(untouched).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::metadata(untouched)
+				.and_then(|metadata| metadata.modified())
+				.expect("The file exists; qed;"),
+			modified
+		);
+	});
+}
+
+#[test]
+fn note_file_no_backup_works() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let disposable = builder.existing_files()[0];
+		let backed_up = builder.existing_files()[1];
+		assert!(rollback.note_file_no_backup(disposable).is_ok());
+		assert!(rollback.note_file(backed_up).is_ok());
+		for file in [disposable, backed_up] {
+			std::fs::write(
+				rollback.get_noted_file(file).expect("The file is noted; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT,
+			)
+			.expect("The file exists; qed;");
+		}
+
+		match rollback.commit() {
+			Ok(CommitOutcome::Applied { counts }) => assert_eq!(counts.noted_files, 2),
+			_ => panic!("Unexpected outcome"),
+		}
+
+		for file in [disposable, backed_up] {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+		}
+	});
+}
+
+#[test]
+fn commit_doesnt_restore_files_noted_without_backup() {
+	TestBuilder::new(Some(2)).with_new_files().execute(|builder, mut rollback| {
+		let disposable = builder.existing_files()[0];
+		let backed_up = builder.existing_files()[1];
+		assert!(rollback.note_file_no_backup(disposable).is_ok());
+		assert!(rollback.note_file(backed_up).is_ok());
+		for file in [disposable, backed_up] {
+			std::fs::write(
+				rollback.get_noted_file(file).expect("The file is noted; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT,
+			)
+			.expect("The file exists; qed;");
+		}
+
+		// Deleting a temporary file means that rollback cannot commit the related new file.
+		std::fs::remove_file(
+			rollback
+				.get_new_file(builder.new_files()[0])
+				.expect("The file exists for this rollback; qed;"),
+		)
+		.expect("This should be possible; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+		assert_eq!(
+			std::fs::read_to_string(disposable).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::read_to_string(backed_up).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn read_overlay_works() {
+	TestBuilder::new(Some(3)).with_new_files().execute(|builder, mut rollback| {
+		let noted = builder.existing_files()[0];
+		let lazy = builder.existing_files()[1];
+		let untouched = builder.existing_files()[2];
+		let new_file = builder.new_files()[0];
+		rollback.note_file(noted).expect("The file can be noted; qed;");
+		rollback
+			.note_file_lazy(lazy, Box::new(|content| content.repeat(2)))
+			.expect("The file can be noted; qed;");
+		rollback.note_file(untouched).expect("The file can be noted; qed;");
+		let _handle = rollback.get_noted_handle(untouched).expect("The file is noted; qed;");
+		std::fs::write(
+			rollback.get_noted_file(noted).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file exists; qed;");
+		std::fs::write(
+			rollback.get_new_file(new_file).expect("The file is new; qed;"),
+			BINARY_CONTENT,
+		)
+		.expect("The file exists; qed;");
+		let not_staged = builder.get_temp_dir_path().join("not_staged.txt");
+		std::fs::write(&not_staged, "Not staged").expect("The file can be created; qed;");
+
+		let read = |path: &Path| rollback.read_overlay(path).expect("The file is readable; qed;");
+		assert_eq!(read(noted), MODIFIED_BUILDER_FILE_CONTENT.as_bytes());
+		assert_eq!(read(lazy), ORIGINAL_BUILDER_FILE_CONTENT.repeat(2).as_bytes());
+		assert_eq!(read(untouched), ORIGINAL_BUILDER_FILE_CONTENT.as_bytes());
+		assert_eq!(read(new_file), BINARY_CONTENT);
+		assert_eq!(read(&not_staged), b"Not staged");
+		// The file system isn't affected
+		assert_eq!(
+			std::fs::read_to_string(noted).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn read_overlay_fails_if_file_doesnt_exist() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let missing = builder.get_temp_dir_path().join("missing.txt");
+		assert!(matches!(
+			rollback.read_overlay(missing),
+			Err(err) if err.kind() == ErrorKind::NotFound
+		));
+	});
+}
+
+#[test]
+fn dry_run_into_temp_works() {
+	TestBuilder::new(Some(1))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let noted = builder.existing_files()[0];
+			let new_dir = builder.new_dirs()[0];
+			let nested_file = new_dir.join("nested.txt");
+			// Rebind rollback to accomplish with nested_file lifetime
+			let mut rollback = rollback;
+			rollback.new_file(&nested_file).expect("The file can be noted; qed;");
+
+			let tree = rollback.dry_run_into_temp().expect("The dry run succeeds; qed;");
+			let mirrored = |path: &Path| {
+				tree.path().join(path.strip_prefix("/").expect("The path is absolute; qed;"))
+			};
+
+			assert_eq!(
+				std::fs::read_to_string(mirrored(noted)).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+			assert_eq!(
+				std::fs::read_to_string(mirrored(builder.new_files()[0]))
+					.expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			assert!(mirrored(new_dir).is_dir());
+			assert!(mirrored(&nested_file).is_file());
+
+			// The actual targets are untouched
+			assert_eq!(
+				std::fs::read_to_string(noted).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			assert!(!builder.new_files()[0].exists());
+			assert!(!new_dir.exists());
+
+			// The tree is thrown away, while the rollback can still be committed
+			let tree_path = tree.path().to_path_buf();
+			drop(tree);
+			assert!(!tree_path.exists());
+			assert!(rollback.commit().is_ok());
+			assert_eq!(
+				std::fs::read_to_string(noted).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+			assert!(nested_file.is_file());
+		});
+}
+
+#[test]
+fn commit_into_works() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let noted = builder.existing_files()[0];
+			let renamed = builder.existing_files()[1];
+			let rename_target = builder.get_temp_dir_path().join("renamed.txt");
+			let root = builder.get_temp_dir_path().join("preview");
+			// Rebind rollback to accomplish with rename_target and root lifetime
+			let mut rollback = rollback;
+			rollback.forget(renamed);
+			rollback.rename(renamed, &rename_target).expect("The file can be renamed; qed;");
+
+			assert!(rollback.commit_into(&root).is_ok());
+			let previewed = |path: &Path| {
+				root.join(path.strip_prefix("/").expect("The path is absolute; qed;"))
+			};
+
+			assert_eq!(
+				std::fs::read_to_string(previewed(noted)).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+			assert_eq!(
+				std::fs::read_to_string(previewed(builder.new_files()[0]))
+					.expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			assert!(previewed(builder.new_dirs()[0]).is_dir());
+			assert_eq!(
+				std::fs::read_to_string(previewed(&rename_target)).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+
+			// The actual targets are untouched
+			assert_eq!(
+				std::fs::read_to_string(noted).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			assert!(renamed.is_file());
+			assert!(!rename_target.exists());
+			assert!(builder.new_files().into_iter().all(|path| !path.exists()));
+			assert!(builder.new_dirs().into_iter().all(|path| !path.exists()));
+		});
+}
+
+#[test]
+fn commit_into_fails_if_an_item_already_exists_in_the_tree() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, rollback| {
+		let new_file = builder.new_files()[0];
+		let root = builder.get_temp_dir_path().join("preview");
+		let previewed = root.join(new_file.strip_prefix("/").expect("The path is absolute; qed;"));
+		std::fs::create_dir_all(previewed.parent().expect("The path has a parent; qed;"))
+			.expect("The dir can be created; qed;");
+		std::fs::write(&previewed, "previous").expect("The file can be created; qed;");
+
+		assert!(matches!(
+			rollback.commit_into(&root),
+			Err(Error::IO(err)) if err.kind() == ErrorKind::AlreadyExists
+		));
+		// The existing item isn't overwritten
+		assert_eq!(std::fs::read_to_string(&previewed).expect("The file exists; qed;"), "previous");
+		assert!(!new_file.exists());
+	});
+}
+
+#[test]
+fn dry_run_into_temp_fails_if_new_file_parent_dir_doesnt_exist() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let removed_dir = builder.get_temp_dir_path().join("removed");
+		let new_file = removed_dir.join("file.txt");
+		std::fs::create_dir(&removed_dir).expect("The dir can be created; qed;");
+		// Rebind rollback to accomplish with new_file lifetime
+		let mut rollback = rollback;
+		rollback.new_file(&new_file).expect("The file can be noted; qed;");
+		// The parent dir is removed after noting the file
+		std::fs::remove_dir(&removed_dir).expect("The dir exists; qed;");
+
+		assert!(matches!(
+			rollback.dry_run_into_temp(),
+			Err(err) if err.kind() == ErrorKind::NotFound
+		));
+	});
+}
+
+#[test]
+fn dry_run_works() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let nested_file = builder.new_dirs()[0].join("file.txt");
+			let rename_target = builder.new_dirs()[1].join("renamed.txt");
+			// Rebind rollback to accomplish with nested_file and rename_target lifetime
+			let mut rollback = rollback;
+			rollback.new_file(&nested_file).expect("The file can be noted; qed;");
+			rollback.forget(builder.existing_files()[1]);
+			rollback
+				.rename(builder.existing_files()[1], &rename_target)
+				.expect("The file can be renamed; qed;");
+
+			assert!(rollback.dry_run().is_ok());
+
+			// Nothing's committed
+			assert!(builder.new_dirs().into_iter().all(|dir| !dir.exists()));
+			assert!(builder.new_files().into_iter().all(|file| !file.exists()));
+			assert_eq!(
+				std::fs::read_to_string(builder.existing_files()[0])
+					.expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			assert!(builder.existing_files()[1].exists());
+			// And the rollback can still be committed
+			assert!(rollback.commit().is_ok());
+			assert!(nested_file.is_file());
+			assert!(rename_target.is_file());
+		});
+}
+
+#[test]
+fn dry_run_fails_if_noted_file_cannot_be_written() {
+	TestBuilder::new(Some(2)).with_noted_files().execute(|builder, rollback| {
+		let missing = builder.existing_files()[0];
+		let read_only = builder.existing_files()[1];
+		std::fs::remove_file(missing).expect("The file exists; qed;");
+		match rollback.dry_run() {
+			Err(Error::Commit(item, _)) => assert_eq!(item, format!("{}", missing.display())),
+			_ => panic!("Unexpected error"),
+		}
+
+		std::fs::write(missing, ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		std::fs::set_permissions(read_only, std::fs::Permissions::from_mode(0o444))
+			.expect("The file exists; qed;");
+		match rollback.dry_run() {
+			Err(Error::Commit(item, _)) => assert_eq!(item, format!("{}", read_only.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn dry_run_fails_if_new_file_parent_dir_doesnt_exist() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let removed_dir = builder.get_temp_dir_path().join("removed");
+		let new_file = removed_dir.join("file.txt");
+		std::fs::create_dir(&removed_dir).expect("The dir can be created; qed;");
+		// Rebind rollback to accomplish with new_file lifetime
+		let mut rollback = rollback;
+		rollback.new_file(&new_file).expect("The file can be noted; qed;");
+		// The parent dir is removed after noting the file
+		std::fs::remove_dir(&removed_dir).expect("The dir exists; qed;");
+
+		match rollback.dry_run() {
+			Err(Error::Commit(item, _)) => assert_eq!(item, format!("{}", new_file.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn dry_run_fails_if_new_items_already_exist() {
+	TestBuilder::new(Some(1))
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let new_file = builder.new_files()[0];
+			std::fs::write(new_file, ORIGINAL_BUILDER_FILE_CONTENT)
+				.expect("The file can be created; qed;");
+			assert!(matches!(rollback.dry_run(), Err(Error::NewItemAlreadyExists(_))));
+
+			std::fs::remove_file(new_file).expect("The file exists; qed;");
+			std::fs::create_dir(builder.new_dirs()[0]).expect("The dir can be created; qed;");
+			assert!(matches!(rollback.dry_run(), Err(Error::RepeatedNewDir(_))));
+		});
+}
+
+#[test]
+fn dry_run_fails_if_renamed_or_deleted_items_changed() {
+	TestBuilder::new(Some(2)).execute(|builder, rollback| {
+		let renamed = builder.existing_files()[0];
+		let deleted = builder.existing_files()[1];
+		let rename_target = builder.get_temp_dir_path().join("renamed.txt");
+		// Rebind rollback to accomplish with rename_target lifetime
+		let mut rollback = rollback;
+		rollback.rename(renamed, &rename_target).expect("The file can be renamed; qed;");
+		rollback.delete_file(deleted).expect("The file can be deleted; qed;");
+
+		std::fs::write(&rename_target, ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		match rollback.dry_run() {
+			Err(Error::NewItemAlreadyExists(item)) =>
+				assert_eq!(item, format!("{}", rename_target.display())),
+			_ => panic!("Unexpected error"),
+		}
+
+		std::fs::remove_file(&rename_target).expect("The file exists; qed;");
+		std::fs::remove_file(deleted).expect("The file exists; qed;");
+		match rollback.dry_run() {
+			Err(Error::Commit(item, _)) => assert_eq!(item, format!("{}", deleted.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+// Holds an exclusive advisory lock on the file through a different handle until it's dropped.
+fn lock_exclusively(path: &Path) -> File {
+	let file = File::open(path).expect("The file exists; qed;");
+	assert_eq!(unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) }, 0);
+	file
+}
+
+#[test]
+fn note_file_fails_if_file_is_busy_and_busy_check_is_enabled() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let mut rollback = rollback.with_busy_check();
+		let _lock = lock_exclusively(path);
+
+		match rollback.note_file(path) {
+			Err(Error::FileBusy(item)) => assert_eq!(item, format!("{}", path.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn note_file_ignores_busy_files_if_busy_check_is_disabled() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		let _lock = lock_exclusively(path);
+
+		assert!(rollback.note_file(path).is_ok());
+	});
+}
+
+#[test]
+fn new_file_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.new_files()[0];
+		assert!(rollback.new_file(path).is_ok());
+		assert!(rollback.get_new_file(path).is_some());
+	});
+}
+
+#[test]
+fn new_file_fails_if_path_already_exists() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		match rollback.new_file(path) {
+			Err(Error::NewItemAlreadyExists(item)) => {
+				assert_eq!(format!("{}", path.display()), item)
+			},
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn new_file_fails_if_path_already_noted() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, mut rollback| {
+		let path = builder.new_files()[0];
+		match rollback.new_file(path) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(format!("{}", path.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn new_file_fails_if_path_cannot_be_a_file() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("dir/");
+		let parent = builder.get_temp_dir_path().join("dir").join("..");
+		// Rebind rollback to accomplish with dir lifetime
+		let mut rollback = rollback;
+		for path in [Path::new(""), &dir, &parent] {
+			match rollback.new_file(path) {
+				Err(Error::NotAFile(item)) => assert_eq!(format!("{}", path.display()), item),
+				_ => panic!("Unexpected error"),
+			}
+		}
+	});
+}
+
+#[test]
+fn new_file_works_without_extension() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let makefile = builder.get_temp_dir_path().join("Makefile");
+		let dotfile = builder.get_temp_dir_path().join(".gitignore");
+		// Rebind rollback to accomplish with makefile lifetime
+		let mut rollback = rollback;
+
+		assert!(rollback.new_file(&makefile).is_ok());
+		assert!(rollback.new_file(&dotfile).is_ok());
+
+		assert!(rollback.commit().is_ok());
+		assert!(makefile.is_file());
+		assert!(dotfile.is_file());
+	});
+}
+
+#[test]
+fn new_file_fails_if_parent_dir_doesnt_exist() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.new_dirs()[0].join("file.txt");
+		match rollback.new_file(&path) {
+			Err(Error::MissingParentDir(item)) => assert_eq!(format!("{}", path.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+		assert!(!rollback.is_new_file(&path));
+	});
+}
+
+#[test]
+fn new_file_works_if_parent_dir_is_created_by_a_new_dir() {
+	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, rollback| {
+		let dir = builder.new_dirs()[0];
+		let nested_dir = dir.join("nested");
+		let temp_dir = builder.get_temp_dir_path();
+		let refactored_dir = temp_dir
+			.join("..")
+			.join(temp_dir.file_name().expect("The path is a dir, so file_name exists; qed;"))
+			.join(dir.file_name().expect("The path is a dir, so file_name exists; qed;"));
+		// Rebind rollback to accomplish with nested_dir lifetime
+		let mut rollback = rollback;
+		rollback.new_dir(&nested_dir).expect("The dir should be noted; qed;");
+
+		// The new dirs create their parents, even if they're given in another representation
+		assert!(rollback.new_file(dir.join("file.txt")).is_ok());
+		assert!(rollback.new_file(refactored_dir.join("other.txt")).is_ok());
+		assert!(rollback.new_file(nested_dir.join("file.txt")).is_ok());
+		assert!(rollback.commit().is_ok());
+		assert!(nested_dir.join("file.txt").is_file());
+	});
+}
+
+#[test]
+fn new_file_with_parents_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let dir = builder.new_dirs()[0];
+		let path = dir.join("nested").join("file.txt");
+		let sibling = dir.join("nested").join("sibling.txt");
+		let existing_parent = builder.get_temp_dir_path().join("file.txt");
+
+		assert!(rollback.new_file_with_parents(&path).is_ok());
+		assert!(rollback.new_file_with_parents(&sibling).is_ok());
+		assert!(rollback.new_file_with_parents(&existing_parent).is_ok());
+		// The parent dir is registered just once, and only if it doesn't exist
+		assert_eq!(rollback.new_dir_paths().collect::<Vec<_>>(), [dir.join("nested")]);
+
+		assert!(rollback.commit().is_ok());
+		assert!(path.is_file());
+		assert!(sibling.is_file());
+		assert!(existing_parent.is_file());
+	});
+}
+
+#[test]
+fn new_file_with_parents_doesnt_register_the_parent_dir_if_the_file_fails() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		// The path cannot name a file, so it fails once the parent dir has been registered
+		let path = builder.new_dirs()[0].join("file/");
+
+		assert!(matches!(rollback.new_file_with_parents(&path), Err(Error::NotAFile(_))));
+		assert!(rollback.new_dir_paths().next().is_none());
+		assert!(rollback.is_empty());
+	});
+}
+
+#[test]
+fn new_file_fails_if_it_cannot_create_temp_file() {
+	// Save original tempdir locations as this test will modify them.
+	let original_temp_dir = std::env::var("TEMP").unwrap_or_default(); // Windows
+	let original_tmpdir = std::env::var("TMPDIR").unwrap_or_default(); // UNIX
+
+	TestBuilder::new(Some(1))
+		.with_read_only_temp_dir()
+		.execute(|builder, mut rollback| {
+			let result = rollback.new_file(builder.new_files()[0]);
+
+			// Reset original tempdir locations before asserting, so we're safe in case of panic
+			std::env::set_var("TMPDIR", original_tmpdir.clone());
+			std::env::set_var("TEMP", original_temp_dir.clone());
+
+			match result {
+				Err(Error::IO(err)) => assert_eq!(err.kind(), ErrorKind::PermissionDenied),
+				_ => panic!("Unexpected error"),
+			}
+		});
+}
+
+#[test]
+fn new_file_from_reader_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.new_files()[0];
+		let mut reader = BINARY_CONTENT;
+		assert!(rollback.new_file_from_reader(path, &mut reader).is_ok());
+		assert_eq!(
+			std::fs::read(rollback.get_new_file(path).expect("The file is noted; qed;"))
+				.expect("The file exists; qed;"),
+			BINARY_CONTENT
+		);
+	});
+}
+
+#[test]
+fn new_file_from_reader_fails_if_path_already_exists() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		let mut reader = BINARY_CONTENT;
+		match rollback.new_file_from_reader(path, &mut reader) {
+			Err(Error::NewItemAlreadyExists(item)) => {
+				assert_eq!(format!("{}", path.display()), item)
+			},
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn new_file_from_reader_fails_if_reader_fails() {
+	struct FailingReader;
+
+	impl std::io::Read for FailingReader {
+		fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+			Err(std::io::Error::new(ErrorKind::ConnectionReset, "The connection was reset"))
+		}
+	}
+
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.new_files()[0];
+		match rollback.new_file_from_reader(path, &mut FailingReader) {
+			Err(Error::IO(err)) => assert_eq!(err.kind(), ErrorKind::ConnectionReset),
+			_ => panic!("Unexpected error"),
+		}
+		// The file isn't registered
+		assert!(rollback.get_new_file(path).is_none());
+	});
+}
+
+#[test]
+fn new_file_from_reader_leaves_history_unchanged_if_reader_fails() {
+	struct FailingReader;
+
+	impl std::io::Read for FailingReader {
+		fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+			Err(std::io::Error::new(ErrorKind::ConnectionReset, "The connection was reset"))
+		}
+	}
+
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let (staged, failing) = (builder.new_files()[0], builder.new_files()[1]);
+		let initial = rollback.savepoint();
+		rollback.new_file(staged).expect("The file should be added; qed;");
+		let savepoint = rollback.savepoint();
+
+		assert!(rollback.new_file_from_reader(failing, &mut FailingReader).is_err());
+
+		// Nothing is left in the history, so the savepoints keep matching the staged operations
+		assert_eq!(rollback.savepoint(), savepoint);
+		rollback.rollback_to(initial);
+		assert!(rollback.is_empty());
+	});
+}
+
+#[test]
+fn copy_file_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let (src, dst) = (builder.existing_files()[0], builder.new_files()[0]);
+		assert!(rollback.copy_file(src, dst).is_ok());
+		// The content is copied right away
+		std::fs::write(src, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+		assert!(!dst.exists());
+
+		assert_eq!(
+			rollback.commit().expect("The commit should succeed; qed;"),
+			CommitOutcome::Applied { counts: CommitCounts { new_files: 1, ..Default::default() } }
+		);
+		assert_eq!(
+			std::fs::read_to_string(dst).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn copy_file_fails_if_src_isnt_a_file_or_dst_exists() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let dst = builder.new_files()[0];
+		match rollback.copy_file(builder.get_temp_dir_path(), dst) {
+			Err(Error::NotAFile(item)) =>
+				assert_eq!(item, format!("{}", builder.get_temp_dir_path().display())),
+			_ => panic!("Unexpected error"),
+		}
+		match rollback.copy_file(builder.existing_files()[0], builder.existing_files()[1]) {
+			Err(Error::NewItemAlreadyExists(item)) =>
+				assert_eq!(item, format!("{}", builder.existing_files()[1].display())),
+			_ => panic!("Unexpected error"),
+		}
+		assert!(rollback.is_empty());
+	});
+}
+
+#[test]
+fn new_file_from_reader_deduplicates_identical_contents() {
+	TestBuilder::new(Some(3)).execute(|builder, rollback| {
+		let mut rollback = rollback.with_deduplicated_new_files();
+		let [first, second, third] = builder.new_files()[..] else {
+			panic!("There's 3 new files; qed;");
+		};
+		let mut reader = BINARY_CONTENT;
+		assert!(rollback.new_file_from_reader(first, &mut reader).is_ok());
+		let mut reader = BINARY_CONTENT;
+		assert!(rollback.new_file_from_reader(second, &mut reader).is_ok());
+		let mut reader = ORIGINAL_BUILDER_FILE_CONTENT.as_bytes();
+		assert!(rollback.new_file_from_reader(third, &mut reader).is_ok());
+
+		let temp_file = |path| rollback.get_new_file(path).expect("The file is new; qed;");
+		assert_eq!(temp_file(first), temp_file(second));
+		assert_ne!(temp_file(first), temp_file(third));
+
+		assert!(rollback.commit().is_ok());
+
+		// Each new file is independent
+		assert_eq!(std::fs::read(first).expect("The file exists; qed;"), BINARY_CONTENT);
+		assert_eq!(std::fs::read(second).expect("The file exists; qed;"), BINARY_CONTENT);
+		assert_eq!(
+			std::fs::read_to_string(third).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert!(!same_file::is_same_file(first, second).expect("The files exist; qed;"));
+	});
+}
+
+#[test]
+fn new_file_from_reader_doesnt_deduplicate_contents_by_default() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		for path in builder.new_files() {
+			let mut reader = BINARY_CONTENT;
+			assert!(rollback.new_file_from_reader(path, &mut reader).is_ok());
+		}
+
+		assert_ne!(
+			rollback.get_new_file(builder.new_files()[0]),
+			rollback.get_new_file(builder.new_files()[1])
+		);
+	});
+}
+
+#[test]
+fn new_dir_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.new_dirs()[0];
+		assert!(rollback.new_dir(path).is_ok());
+	});
+}
+
+#[test]
+fn new_dir_fails_if_path_already_exists() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.new_dirs()[0];
+		std::fs::create_dir_all(path).expect("The directory should be created; qed;");
+		match rollback.new_dir(path) {
+			Err(Error::NewItemAlreadyExists(item)) => {
+				assert_eq!(format!("{}", path.display()), item)
+			},
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn new_dir_fails_if_path_already_noted() {
+	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, mut rollback| {
+		let path = builder.new_dirs()[0];
+		match rollback.new_dir(path) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(format!("{}", path.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn new_dir_fails_if_path_cannot_be_a_dir() {
+	TestBuilder::new(Some(1)).execute(|_, mut rollback| {
+		let path = Path::new("");
+		match rollback.new_dir(path) {
+			Err(Error::NotADir(item)) => assert_eq!(format!("{}", path.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn new_dir_works_with_extension() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("assets.v2");
+		// Rebind rollback to accomplish with dir lifetime
+		let mut rollback = rollback;
+
+		assert!(rollback.new_dir(&dir).is_ok());
+		assert!(rollback.commit().is_ok());
+		assert!(dir.is_dir());
+	});
+}
+
+#[test]
+fn sorted_new_dirs_works() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let root = builder.get_temp_dir_path();
+		let [a, b, a_c, a_c_d, b_e] = ["a", "b", "a/c", "a/c/d", "b/e"].map(|dir| root.join(dir));
+		// Rebind rollback to accomplish with the dirs lifetime
+		let mut rollback = rollback;
+		for dir in [&a_c_d, &b_e, &a, &a_c, &b] {
+			rollback.new_dir(dir).expect("The dir can be added; qed;");
+		}
+
+		assert_eq!(
+			rollback.sorted_new_dirs(),
+			vec![a.as_path(), b.as_path(), a_c.as_path(), b_e.as_path(), a_c_d.as_path()]
+		);
+		// Creating them in order never misses a parent dir
+		rollback
+			.sorted_new_dirs()
+			.into_iter()
+			.for_each(|dir| std::fs::create_dir(dir).expect("The parent dir exists; qed;"));
+	});
+}
+
+#[test]
+fn sorted_new_dirs_is_empty_if_there_are_no_new_dirs() {
+	assert!(Rollback::default().sorted_new_dirs().is_empty());
+}
+
+#[test]
+fn new_dirs_bulk_works() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		assert!(rollback.new_dirs_bulk(builder.new_dirs()).is_ok());
+
+		builder.new_dirs().iter().for_each(|dir| assert!(rollback.is_new_dir(dir)));
+		assert!(rollback.commit().is_ok());
+		builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+	});
+}
+
+#[test]
+fn new_dirs_bulk_discards_the_new_dirs_if_one_fails() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let existing = builder.get_temp_dir_path();
+		let dirs = [builder.new_dirs()[0], existing, builder.new_dirs()[1]];
+
+		match rollback.new_dirs_bulk(dirs) {
+			Err(Error::NewItemAlreadyExists(item)) =>
+				assert_eq!(item, format!("{}", existing.display())),
+			_ => panic!("Unexpected error"),
+		}
+		assert!(rollback.is_empty());
+	});
+}
+
+#[test]
+fn new_dir_strict_works() {
+	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, mut rollback| {
+		// The parent dir may be another new dir
+		let dir = builder.new_dirs()[0].join("strict");
+		assert!(rollback.new_dir_strict(&dir).is_ok());
+		assert!(rollback.dry_run().is_ok());
+
+		assert!(rollback.commit().is_ok());
+		assert!(dir.is_dir());
+	});
+}
+
+#[test]
+fn new_dir_strict_fails_upon_commit_if_parent_doesnt_exist() {
+	TestBuilder::new(Some(2)).with_new_dirs().execute(|builder, mut rollback| {
+		let missing_parent = builder.get_temp_dir_path().join("missing");
+		let dir = missing_parent.join("strict");
+		assert!(rollback.new_dir_strict(&dir).is_ok());
+
+		assert!(
+			matches!(rollback.dry_run(), Err(Error::Commit(item, _)) if item == format!("{}", dir.display()))
+		);
+		match rollback.commit() {
+			Err(Error::CommitIo { path, .. }) => assert_eq!(path, dir),
+			_ => panic!("Unexpected error"),
+		}
+		// The parent isn't created, and the other new dirs are rolled-back
+		assert!(!missing_parent.exists());
+		builder.new_dirs().iter().for_each(|dir| assert!(!dir.exists()));
+	});
+}
+
+#[test]
+fn rename_works() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let from = builder.existing_files()[0];
+		let dir = builder.new_dirs()[0];
+		let to = dir.join("renamed.txt");
+		// Rebind rollback to accomplish with to lifetime
+		let mut rollback = rollback;
+		rollback.new_dir(dir).expect("The dir can be noted; qed;");
+		assert!(rollback.rename(from, &to).is_ok());
+
+		// The file isn't renamed yet, but it's seen at its new path through the overlay
+		assert_eq!(rollback.get_renamed_file(from), Some(to.as_path()));
+		assert!(from.is_file());
+		assert!(!to.exists());
+		assert_eq!(
+			rollback.read_overlay(&to).expect("The file is readable; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT.as_bytes()
+		);
+		assert!(matches!(
+			rollback.read_overlay(from),
+			Err(err) if err.kind() == ErrorKind::NotFound
+		));
+
+		// The new path is inside a new dir, so the dir is created first
+		assert_eq!(
+			rollback.commit().expect("The commit should succeed; qed;"),
+			CommitOutcome::Applied {
+				counts: CommitCounts { new_dirs: 1, renamed_files: 1, ..Default::default() }
+			}
+		);
+		assert!(!from.exists());
+		assert_eq!(
+			std::fs::read_to_string(&to).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		// The backup doesn't linger on disk
+		assert_eq!(
+			std::fs::read_dir(builder.get_temp_dir_path())
+				.expect("The dir exists; qed;")
+				.count(),
+			1
+		);
+	});
+}
+
+#[test]
+fn rename_fails_if_from_isnt_a_file() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let from = builder.new_files()[0];
+		match rollback.rename(from, builder.new_files()[1]) {
+			Err(Error::NotAFile(item)) => assert_eq!(item, format!("{}", from.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn rename_fails_if_to_already_exists() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let to = builder.existing_files()[1];
+		match rollback.rename(builder.existing_files()[0], to) {
+			Err(Error::NewItemAlreadyExists(item)) => assert_eq!(item, format!("{}", to.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn rename_fails_if_paths_are_already_staged() {
+	TestBuilder::new(Some(3)).execute(|builder, mut rollback| {
+		let [noted, from, other] = builder.existing_files()[..] else {
+			panic!("The builder has 3 files");
+		};
+		let [to, new_file, _] = builder.new_files()[..] else {
+			panic!("The builder has 3 new files");
+		};
+		rollback.note_file(noted).expect("The file can be noted; qed;");
+		rollback.new_file(new_file).expect("The file can be noted; qed;");
+		rollback.rename(from, to).expect("The file can be renamed; qed;");
+
+		assert!(matches!(rollback.rename(noted, to), Err(Error::AlreadyNoted(_))));
+		assert!(matches!(rollback.rename(from, to), Err(Error::AlreadyNoted(_))));
+		assert!(matches!(rollback.note_file(from), Err(Error::AlreadyNoted(_))));
+		assert!(matches!(rollback.delete_file(from), Err(Error::AlreadyNoted(_))));
+		match rollback.rename(other, to) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(item, format!("{}", to.display())),
+			_ => panic!("Unexpected error"),
+		}
+		assert!(matches!(rollback.rename(other, new_file), Err(Error::AlreadyNoted(_))));
+	});
+}
+
+#[test]
+fn commit_restores_renamed_files_if_it_fails() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let from = builder.existing_files()[0];
+		let to = builder.new_files()[0];
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		let mut rollback = rollback.with_completion_marker(&marker);
+		rollback.rename(from, to).expect("The file can be renamed; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+		assert_eq!(
+			std::fs::read_to_string(from).expect("The file is restored; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert!(!to.exists());
+	});
+}
+
+#[test]
+fn commit_restores_renamed_files_as_they_were_when_renamed() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let from = builder.existing_files()[0];
+		let to = builder.new_files()[0];
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		let mut rollback = rollback.with_completion_marker(&marker);
+		rollback.rename(from, to).expect("The file can be renamed; qed;");
+		// The file isn't backed up when it's staged
+		assert_eq!(
+			std::fs::read_dir(builder.get_temp_dir_path())
+				.expect("The dir exists; qed;")
+				.count(),
+			1
+		);
+		// The file changes after it's staged
+		std::fs::write(from, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+		assert_eq!(
+			std::fs::read_to_string(from).expect("The file is restored; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert!(!to.exists());
+		// The backup doesn't linger on disk
+		assert_eq!(
+			std::fs::read_dir(builder.get_temp_dir_path())
+				.expect("The dir exists; qed;")
+				.count(),
+			1
+		);
+	});
+}
+
+#[test]
+fn commit_renames_files_within_the_file_system_without_copying_them() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let inode = |path: &Path| std::fs::metadata(path).expect("The file exists; qed;").ino();
+		let inodes: Vec<_> = builder.existing_files().iter().map(|file| inode(file)).collect();
+		builder
+			.existing_files()
+			.iter()
+			.zip(builder.new_files().iter())
+			.for_each(|(from, to)| {
+				rollback.rename(from, to).expect("The file can be renamed; qed;");
+			});
+
+		assert!(rollback.commit().is_ok());
+
+		builder.existing_files().iter().for_each(|file| assert!(!file.exists()));
+		// The files themselves are moved, and no backup is left behind
+		assert_eq!(builder.new_files().iter().map(|file| inode(file)).collect::<Vec<_>>(), inodes);
+		assert_eq!(
+			std::fs::read_dir(builder.get_temp_dir_path())
+				.expect("The dir exists; qed;")
+				.count(),
+			2
+		);
+	});
+}
+
+#[test]
+fn commit_renames_files_within_the_file_system_back_if_it_fails() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let from = builder.existing_files()[0];
+		let to = builder.new_files()[0];
+		let inode = std::fs::metadata(from).expect("The file exists; qed;").ino();
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		let mut rollback = rollback.with_completion_marker(&marker);
+		rollback.rename(from, to).expect("The file can be renamed; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+		// The file itself is renamed back
+		assert_eq!(std::fs::metadata(from).expect("The file is restored; qed;").ino(), inode);
+		assert!(!to.exists());
+	});
+}
+
+#[test]
+fn new_symlink_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let target = builder.existing_files()[0];
+		let dir = builder.new_dirs()[0];
+		let link = dir.join("link");
+		let relative_target = Path::new("..")
+			.join(target.file_name().expect("The path is a file, so file_name exists; qed;"));
+		rollback.new_dir(dir).expect("The dir can be noted; qed;");
+		assert!(rollback.new_symlink(&link, &relative_target).is_ok());
+
+		// The link isn't created yet
+		assert_eq!(rollback.get_new_symlink(&link), Some(relative_target.as_path()));
+		assert!(link.symlink_metadata().is_err());
+
+		// The link is inside a new dir, so the dir is created first
+		assert_eq!(
+			rollback.commit().expect("The commit should succeed; qed;"),
+			CommitOutcome::Applied {
+				counts: CommitCounts { new_dirs: 1, new_symlinks: 1, ..Default::default() }
+			}
+		);
+		assert!(link.symlink_metadata().expect("The link exists; qed;").is_symlink());
+		assert_eq!(std::fs::read_link(&link).expect("The link exists; qed;"), relative_target);
+		assert_eq!(
+			std::fs::read_to_string(&link).expect("The link points to the file; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn commit_creates_new_items_inside_new_symlinks() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let target = builder.get_temp_dir_path().join("target");
+		std::fs::create_dir(&target).expect("The dir can be created; qed;");
+		let link = builder.get_temp_dir_path().join("link");
+		let (dir, file) = (link.join("dir"), link.join("file.txt"));
+		// Rebind rollback to accomplish with link lifetime
+		let mut rollback = rollback;
+		rollback.new_symlink(&link, "target").expect("The link can be noted; qed;");
+		rollback.new_dir(&dir).expect("The dir can be noted; qed;");
+		rollback.new_file(&file).expect("The file can be noted; qed;");
+		std::fs::write(
+			rollback.get_new_file(&file).expect("The file is new; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+
+		// The new items are created once the link exists
+		assert!(rollback.dry_run().is_ok());
+		assert!(rollback.commit().is_ok());
+
+		assert!(target.join("dir").is_dir());
+		assert_eq!(
+			std::fs::read_to_string(target.join("file.txt")).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn commit_removes_new_items_inside_new_symlinks_if_it_fails() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let target = builder.get_temp_dir_path().join("target");
+		std::fs::create_dir(&target).expect("The dir can be created; qed;");
+		let link = builder.get_temp_dir_path().join("link");
+		let (dir, file) = (link.join("dir"), link.join("file.txt"));
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		let mut rollback = rollback.with_completion_marker(&marker);
+		rollback.new_symlink(&link, "target").expect("The link can be noted; qed;");
+		rollback.new_dir(&dir).expect("The dir can be noted; qed;");
+		rollback.new_file(&file).expect("The file can be noted; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+		assert!(link.symlink_metadata().is_err());
+		assert_eq!(std::fs::read_dir(&target).expect("The dir exists; qed;").count(), 0);
+	});
+}
+
+#[test]
+fn new_symlink_fails_if_link_already_exists_or_is_staged() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let target = builder.existing_files()[0];
+		let [link, new_file] = builder.new_files()[..] else {
+			panic!("The builder has 2 new files");
+		};
+		rollback.new_file(new_file).expect("The file can be noted; qed;");
+		rollback.new_symlink(link, target).expect("The link can be noted; qed;");
+
+		match rollback.new_symlink(builder.existing_files()[1], target) {
+			Err(Error::NewItemAlreadyExists(item)) =>
+				assert_eq!(item, format!("{}", builder.existing_files()[1].display())),
+			_ => panic!("Unexpected error"),
+		}
+		assert!(matches!(rollback.new_symlink(link, target), Err(Error::AlreadyNoted(_))));
+		assert!(matches!(rollback.new_symlink(new_file, target), Err(Error::AlreadyNoted(_))));
+		assert!(matches!(rollback.new_file(link), Err(Error::AlreadyNoted(_))));
+		assert!(matches!(
+			rollback.rename(builder.existing_files()[1], link),
+			Err(Error::AlreadyNoted(_))
+		));
+	});
+}
+
+#[test]
+fn commit_removes_new_symlinks_if_it_fails() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let target = builder.existing_files()[0];
+		let link = builder.new_files()[0];
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		let mut rollback = rollback.with_completion_marker(&marker);
+		rollback.new_symlink(link, target).expect("The link can be noted; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+		assert!(link.symlink_metadata().is_err());
+		assert_eq!(
+			std::fs::read_to_string(target).expect("The target is untouched; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn delete_file_works() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		assert!(rollback.delete_file(path).is_ok());
+
+		// The file isn't deleted nor backed up yet
+		assert_eq!(rollback.get_deleted_file(path), Some(path));
+		assert!(path.is_file());
+		assert_eq!(
+			std::fs::read_dir(builder.get_temp_dir_path())
+				.expect("The dir exists; qed;")
+				.count(),
+			1
+		);
+		assert!(matches!(
+			rollback.read_overlay(path),
+			Err(err) if err.kind() == ErrorKind::NotFound
+		));
+
+		assert!(rollback.commit().is_ok());
+		assert!(!path.exists());
+		// The backup taken upon commit is removed once the commit finishes
+		assert_eq!(
+			std::fs::read_dir(builder.get_temp_dir_path())
+				.expect("The dir exists; qed;")
+				.count(),
+			0
+		);
+	});
+}
+
+#[test]
+fn delete_file_fails_if_path_isnt_a_file() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.new_files()[0];
+		match rollback.delete_file(path) {
+			Err(Error::NotAFile(item)) => assert_eq!(item, format!("{}", path.display())),
+			_ => panic!("Unexpected error"),
+		}
+		assert!(rollback.get_deleted_file(path).is_none());
+	});
+}
+
+#[test]
+fn delete_file_fails_if_file_is_already_noted() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		match rollback.delete_file(path) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(item, format!("{}", path.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn note_file_fails_if_file_is_going_to_be_deleted() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.delete_file(path).expect("The file can be deleted; qed;");
+
+		assert!(matches!(rollback.delete_file(path), Err(Error::AlreadyNoted(_))));
+		match rollback.note_file(path) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(item, format!("{}", path.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn commit_restores_deleted_files_if_it_fails() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		let mut rollback = rollback.with_completion_marker(&marker);
+		rollback.delete_file(path).expect("The file can be deleted; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file is restored; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		// The backup doesn't linger on disk
+		assert_eq!(
+			std::fs::read_dir(builder.get_temp_dir_path())
+				.expect("The dir exists; qed;")
+				.count(),
+			1
+		);
+	});
+}
+
+#[test]
+fn commit_restores_deleted_files_as_they_were_when_deleted() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		let mut rollback = rollback.with_completion_marker(&marker);
+		rollback.delete_file(path).expect("The file can be deleted; qed;");
+		// The file changes after it's staged
+		std::fs::write(path, MODIFIED_BUILDER_FILE_CONTENT).expect("The file exists; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file is restored; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn delete_dir_works() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("dir");
+		let empty_dir = builder.get_temp_dir_path().join("empty");
+		std::fs::create_dir_all(dir.join("inner")).expect("The dirs can be created; qed;");
+		std::fs::write(dir.join("inner").join("file.txt"), ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		std::fs::create_dir(&empty_dir).expect("The dir can be created; qed;");
+		// Rebind rollback to accomplish with dir lifetime
+		let mut rollback = rollback;
+		assert!(rollback.delete_dir(&dir).is_ok());
+		assert!(rollback.delete_dir(&empty_dir).is_ok());
+
+		// The tree is backed up, but it isn't deleted yet
+		let backup = rollback.get_deleted_dir(&dir).expect("The dir is deleted; qed;");
+		assert_eq!(
+			std::fs::read_to_string(backup.join("inner").join("file.txt"))
+				.expect("The backup exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert!(dir.is_dir());
+		assert!(matches!(
+			rollback.read_overlay(dir.join("inner").join("file.txt")),
+			Err(err) if err.kind() == ErrorKind::NotFound
+		));
+
+		let backup = backup.to_path_buf();
+		assert_eq!(
+			rollback.commit().expect("The commit should succeed; qed;"),
+			CommitOutcome::Applied {
+				counts: CommitCounts { deleted_dirs: 2, ..Default::default() }
+			}
+		);
+		assert!(!dir.exists());
+		assert!(!empty_dir.exists());
+		assert!(!backup.exists());
+	});
+}
+
+#[test]
+fn delete_dir_fails_if_path_isnt_a_dir() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let file = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link");
+		std::os::unix::fs::symlink(builder.get_temp_dir_path(), &link)
+			.expect("The symlink can be created; qed;");
+		// Rebind rollback to accomplish with link lifetime
+		let mut rollback = rollback;
+
+		for path in [file, &link, builder.new_dirs()[0]] {
+			match rollback.delete_dir(path) {
+				Err(Error::NotADir(item)) => assert_eq!(item, format!("{}", path.display())),
+				_ => panic!("Unexpected error"),
+			}
+		}
+	});
+}
+
+#[test]
+fn delete_dir_fails_if_dir_is_already_deleted() {
+	TestBuilder::new(Some(0)).execute(|builder, mut rollback| {
+		let dir = builder.get_temp_dir_path();
+		rollback.delete_dir(dir).expect("The dir can be deleted; qed;");
+
+		match rollback.delete_dir(dir) {
+			Err(Error::AlreadyNoted(item)) => assert_eq!(item, format!("{}", dir.display())),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn commit_restores_deleted_dirs_if_it_fails() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("dir");
+		std::fs::create_dir_all(dir.join("inner")).expect("The dirs can be created; qed;");
+		std::fs::write(dir.join("inner").join("file.txt"), ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		let mut rollback = rollback.with_completion_marker(&marker);
+		rollback.delete_dir(&dir).expect("The dir can be deleted; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+		assert_eq!(
+			std::fs::read_to_string(dir.join("inner").join("file.txt"))
+				.expect("The file is restored; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn note_dir_works() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("dir");
+		let file = dir.join("file.txt");
+		let new_file = dir.join("new.txt");
+		std::fs::create_dir(&dir).expect("The dir can be created; qed;");
+		std::fs::write(&file, ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		// Rebind rollback to accomplish with dir lifetime
+		let mut rollback = rollback;
+		assert!(rollback.note_dir(&dir).is_ok());
+		rollback.note_file(&file).expect("The file should be noted; qed;");
+		rollback.new_file(&new_file).expect("The file should be new; qed;");
+		std::fs::write(
+			rollback.get_noted_file(&file).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file exists; qed;");
+
+		// Noting the dir doesn't add any operation to the commit
+		assert_eq!(
+			rollback.commit().expect("The commit should succeed; qed;"),
+			CommitOutcome::Applied {
+				counts: CommitCounts { noted_files: 1, new_files: 1, ..Default::default() }
+			}
+		);
+		assert_eq!(
+			std::fs::read_to_string(&file).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert!(new_file.is_file());
+	});
+}
+
+#[test]
+fn note_dir_fails_if_path_isnt_a_dir() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let file = builder.existing_files()[0];
+		let link = builder.get_temp_dir_path().join("link");
+		std::os::unix::fs::symlink(builder.get_temp_dir_path(), &link)
+			.expect("The symlink can be created; qed;");
+		// Rebind rollback to accomplish with link lifetime
+		let mut rollback = rollback;
+
+		for path in [file, &link, builder.new_dirs()[0]] {
+			match rollback.note_dir(path) {
+				Err(Error::NotADir(item)) => assert_eq!(item, format!("{}", path.display())),
+				_ => panic!("Unexpected error"),
+			}
+		}
+	});
+}
+
+#[test]
+fn note_dir_fails_if_dir_already_noted() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path();
+		let refactored_path = dir.join(".");
+		// Rebind rollback to accomplish with refactored_path lifetime
+		let mut rollback = rollback;
+		rollback.note_dir(dir).expect("The dir should be noted; qed;");
+
+		match rollback.note_dir(&refactored_path) {
+			Err(Error::AlreadyNoted(item)) =>
+				assert_eq!(format!("{}", refactored_path.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn commit_restores_noted_dirs_exactly_if_it_fails() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path().join("dir");
+		let file = dir.join("file.txt");
+		let deleted = dir.join("inner").join("deleted.txt");
+		let new_file = dir.join("new.txt");
+		let added = dir.join("added.txt");
+		std::fs::create_dir_all(dir.join("inner")).expect("The dirs can be created; qed;");
+		std::fs::write(&file, ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		std::fs::write(&deleted, ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+		let mut rollback = rollback.with_completion_marker(&marker);
+		rollback.note_dir(&dir).expect("The dir should be noted; qed;");
+		rollback.note_file(&file).expect("The file should be noted; qed;");
+		rollback.new_file(&new_file).expect("The file should be new; qed;");
+		rollback.delete_file(&deleted).expect("The file should be deleted; qed;");
+		std::fs::write(
+			rollback.get_noted_file(&file).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file exists; qed;");
+		// Changes applied by other means are reverted as well
+		std::fs::write(&added, ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+		assert_eq!(
+			std::fs::read_to_string(&file).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::read_to_string(&deleted).expect("The file is restored; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert!(!new_file.exists());
+		assert!(!added.exists());
+		assert_eq!(std::fs::read_dir(&dir).expect("The dir exists; qed;").count(), 2);
+	});
+}
+
+#[test]
+fn note_dir_metadata_works() {
+	TestBuilder::new(Some(0)).execute(|builder, mut rollback| {
+		let dir = builder.get_temp_dir_path();
+		assert!(rollback.note_dir_metadata(dir).is_ok());
+		assert!(rollback.set_dir_mode(dir, 0o700).is_ok());
+		assert!(rollback.set_dir_owner(dir, None, None).is_ok());
+	});
+}
+
+#[test]
+fn note_dir_metadata_fails_if_path_isnt_a_dir() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		match rollback.note_dir_metadata(path) {
+			Err(Error::NotADir(item)) => assert_eq!(format!("{}", path.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn note_dir_metadata_fails_if_dir_already_noted() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path();
+		let refactored_path = dir.join(".");
+		// Rebind rollback to accomplish with refactored_path lifetime
+		let mut rollback = rollback;
+		rollback.note_dir_metadata(dir).expect("The dir should be noted; qed;");
+
+		match rollback.note_dir_metadata(&refactored_path) {
+			Err(Error::AlreadyNoted(item)) =>
+				assert_eq!(format!("{}", refactored_path.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn set_dir_mode_fails_if_dir_isnt_noted() {
+	TestBuilder::new(Some(0)).execute(|builder, mut rollback| {
+		let dir = builder.get_temp_dir_path();
+		match rollback.set_dir_mode(dir, 0o700) {
+			Err(Error::NotNoted(item)) => assert_eq!(format!("{}", dir.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+		match rollback.set_dir_owner(dir, None, None) {
+			Err(Error::NotNoted(item)) => assert_eq!(format!("{}", dir.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn note_permissions_fails_if_path_isnt_a_file() {
+	TestBuilder::new(Some(0)).execute(|builder, mut rollback| {
+		let dir = builder.get_temp_dir_path();
+		match rollback.note_permissions(dir, 0o600) {
+			Err(Error::NotAFile(item)) => assert_eq!(format!("{}", dir.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn note_permissions_fails_if_permissions_already_noted() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let refactored_path = builder
+			.get_temp_dir_path()
+			.join(".")
+			.join(path.file_name().expect("The path is a file, so file_name exists; qed;"));
+		// Rebind rollback to accomplish with refactored_path lifetime
+		let mut rollback = rollback;
+		rollback.note_permissions(path, 0o600).expect("The file should be noted; qed;");
+
+		match rollback.note_permissions(&refactored_path, 0o600) {
+			Err(Error::AlreadyNoted(item)) =>
+				assert_eq!(format!("{}", refactored_path.display()), item),
+			_ => panic!("Unexpected error"),
+		}
+	});
+}
+
+#[test]
+fn get_noted_file_works() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		assert!(rollback.get_noted_file(builder.existing_files()[0]).is_some());
+		assert!(rollback.get_noted_file::<&Path>("something".as_ref()).is_none());
+	});
+}
+
+#[test]
+fn get_noted_file_works_with_different_path_representation() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+
+		let original_cwd = std::env::current_dir().expect("The current dir is the crate dir; qed;");
+
+		std::env::set_current_dir(builder.get_temp_dir_path())
+			.expect("The tempdir should be able to be current_dir; qed;");
+
+		let refactored_path =
+			Path::new(path.file_name().expect("The path is a file, so file_name exists; qed;"));
+
+		let noted_file = rollback.get_noted_file(path);
+		let noted_file_refactored_path = rollback.get_noted_file(&refactored_path);
+
+		std::env::set_current_dir(original_cwd)
+			.expect("The original_cwd should be able to be current_dir; qed;");
+
+		assert!(noted_file_refactored_path.is_some());
+		assert_eq!(noted_file, noted_file_refactored_path);
+	});
+}
+
+#[test]
+fn get_new_file_works() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, rollback| {
+		assert!(rollback.get_new_file(builder.new_files()[0]).is_some());
+		assert!(rollback.get_new_file::<&Path>("something".as_ref()).is_none());
+	});
+}
+
+#[test]
+fn get_new_file_works_with_different_path_representation() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, rollback| {
+		let path = builder.new_files()[0];
+
+		let original_cwd = std::env::current_dir().expect("The current dir is the crate dir; qed;");
+
+		std::env::set_current_dir(builder.get_temp_dir_path())
+			.expect("The tempdir should be able to be current_dir; qed;");
+
+		let refactored_path =
+			Path::new(path.file_name().expect("The path is a file, so file_name exists; qed;"));
+
+		let new_file = rollback.get_new_file(path);
+		let new_file_refactored_path = rollback.get_new_file(refactored_path);
+
+		std::env::set_current_dir(original_cwd)
+			.expect("The original_cwd should be able to be current_dir; qed;");
+
+		assert!(new_file_refactored_path.is_some());
+		assert_eq!(new_file, new_file_refactored_path);
+	});
+}
+
+#[test]
+fn get_new_file_only_matches_the_same_path_if_its_parent_dir_does_not_exist() {
+	TestBuilder::new(None).execute(|builder, mut rollback| {
+		let missing_dir = builder.get_temp_dir_path().join("missing");
+		let path = missing_dir.join("new.txt");
+		rollback.new_dir(&missing_dir).expect("The dir should be new; qed;");
+		rollback.new_file(&path).expect("The file should be new; qed;");
+
+		assert!(rollback.get_new_file(&path).is_some());
+		assert!(rollback
+			.get_new_file(missing_dir.join("..").join("missing").join("new.txt"))
+			.is_none());
+	});
+}
+
+#[test]
+fn staged_paths_sorted_works() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, mut rollback| {
+			let dir = builder.get_temp_dir_path();
+			rollback.note_dir_metadata(dir).expect("The dir should be noted; qed;");
+
+			let mut expected = vec![(dir.to_path_buf(), StagedKind::DirMetadata)];
+			expected.extend(
+				builder
+					.existing_files()
+					.iter()
+					.map(|path| (path.to_path_buf(), StagedKind::NotedFile)),
+			);
+			expected.extend(
+				builder.new_files().iter().map(|path| (path.to_path_buf(), StagedKind::NewFile)),
+			);
+			expected.extend(
+				builder.new_dirs().iter().map(|path| (path.to_path_buf(), StagedKind::NewDir)),
+			);
+			expected.sort_by(|a, b| a.0.cmp(&b.0));
+
+			assert_eq!(rollback.staged_paths_sorted(), expected);
+			// The output is reproducible
+			assert_eq!(rollback.staged_paths_sorted(), rollback.staged_paths_sorted());
+		});
+}
+
+#[test]
+fn to_dot_works() {
+	TestBuilder::new(Some(1))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let dot = rollback.to_dot();
+
+			assert!(dot.starts_with("digraph rollback {"));
+			builder
+				.existing_files()
+				.iter()
+				.for_each(|file| assert!(dot.contains(&format!("note_file {}", file.display()))));
+			builder
+				.new_files()
+				.iter()
+				.for_each(|file| assert!(dot.contains(&format!("new_file {}", file.display()))));
+			builder
+				.new_dirs()
+				.iter()
+				.for_each(|dir| assert!(dot.contains(&format!("new_dir {}", dir.display()))));
+		});
+}
+
+#[test]
+fn commit_works() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+
+			assert!(rollback.commit().is_ok());
+
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				)
+			});
+
+			builder.new_files().iter().for_each(|file| {
+				assert!(file.is_file());
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_returns_empty_outcome_if_nothing_is_staged() {
+	TestBuilder::new(None).execute(|builder, rollback| {
+		let journal = builder.get_temp_dir_path().join("journal");
+		let rollback = rollback.with_journal(&journal);
+
+		assert_eq!(
+			rollback.commit().expect("The commit should succeed; qed;"),
+			CommitOutcome::Empty
+		);
+		// Nothing has been done
+		assert!(!journal.exists());
+	});
+}
+
+#[test]
+fn commit_returns_the_applied_counts() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|_, rollback| {
+			assert_eq!(
+				rollback.commit().expect("The commit should succeed; qed;"),
+				CommitOutcome::Applied {
+					counts: CommitCounts {
+						noted_files: 2,
+						new_files: 2,
+						new_dirs: 2,
+						new_symlinks: 0,
+						renamed_files: 0,
+						deleted_files: 0,
+						deleted_dirs: 0,
+						dirs_metadata: 0
+					}
+				}
+			);
+		});
+}
+
+#[test]
+fn commit_fails_and_rollbacks_if_noted_file_cannot_be_committed() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+
+			// Deleting an existing file means that rollback cannot commit that file.
+			let removed_file = builder
+				.existing_files()
+				.last()
+				.expect("There's existing_files; qed;")
+				.to_path_buf();
+			std::fs::remove_file(&removed_file).expect("This should be possible; qed;");
+
+			match rollback.commit() {
+				Err(Error::CommitIo { path, source, .. }) => {
+					assert_eq!(path, removed_file);
+					assert_eq!(source.kind(), ErrorKind::NotFound);
+				},
+				_ => panic!("Unexpected error"),
+			}
+
+			// The fs wasn't affected
+			builder.existing_files().iter().enumerate().for_each(|(index, file)| {
+				if index < builder.capacity() - 1 {
+					assert_eq!(
+						std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+						ORIGINAL_BUILDER_FILE_CONTENT
+					)
+				}
+			});
+
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_fails_and_rollbacks_if_new_dir_cannot_be_committed() {
+	TestBuilder::new(None)
+		.with_new_files()
+		.with_new_dirs()
+		.with_read_only_dir()
+		.execute(|builder, rollback| {
+			// Note a file (cannot build it with builder cause the commit will fail because of this
+			// file as it's in a read_only dir). Rebind rollback to accomplish with existing_file
+			// lifetime
+			let mut rollback = rollback;
+			let tempdir = tempfile::tempdir().expect("Tempdir should be created");
+			let existing_file = tempdir.path().join("file.txt");
+
+			File::create(&existing_file).expect("File should be created; qed;");
+			std::fs::write(&existing_file, ORIGINAL_BUILDER_FILE_CONTENT)
+				.expect("File should be writable; qed;");
+			rollback.note_file(&existing_file).expect("File should be noted; qed;");
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+
+			match rollback.commit() {
+				Err(Error::CommitIo { source, .. }) => {
+					// No permissions in temp_dir => failure committing the dirs; Cannot ensure
+					// which one comes in the msg cause this runs concurrently and all of them
+					// failed
+					assert_eq!(source.kind(), ErrorKind::PermissionDenied);
+				},
+				_ => panic!("Unexpected error"),
+			}
+
+			// The fs wasn't affected
+			assert_eq!(
+				std::fs::read_to_string(&existing_file).expect("File should be readable; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_fails_and_rollbacks_if_new_file_cannot_be_committed() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+
+			// Deleting a temporary file means that rollback cannot commit the related new file.
+			let uncommitted_file =
+				builder.new_files().last().expect("There's new_files; qed;").to_path_buf();
+
+			std::fs::remove_file(
+				rollback
+					.get_new_file(&uncommitted_file)
+					.expect("The file exists for this rollback; qed;"),
+			)
+			.expect("This should be possible; qed;");
+
+			match rollback.commit() {
+				Err(Error::CommitIo { path, source, .. }) => {
+					assert_eq!(path, uncommitted_file);
+					assert_eq!(source.kind(), ErrorKind::NotFound);
+				},
+				_ => panic!("Unexpected error"),
+			}
+
+			// The fs wasn't affected
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_works_with_dirs_first_order() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let rollback = rollback.with_commit_order(CommitOrder::DirsFirst);
+
+			assert!(rollback.commit().is_ok());
+
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_with_dirs_first_order_rollbacks_new_dirs_if_noted_file_cannot_be_committed() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let rollback = rollback.with_commit_order(CommitOrder::DirsFirst);
+
+			// Deleting an existing file means that rollback cannot commit that file, which happens
+			// once the new dirs are already created.
+			let removed_file = builder
+				.existing_files()
+				.last()
+				.expect("There's existing_files; qed;")
+				.to_path_buf();
+			std::fs::remove_file(&removed_file).expect("This should be possible; qed;");
+
+			match rollback.commit() {
+				Err(Error::CommitIo { path, .. }) => {
+					assert_eq!(path, removed_file);
+				},
+				_ => panic!("Unexpected error"),
+			}
+
+			// The fs wasn't affected
+			builder.existing_files().iter().enumerate().for_each(|(index, file)| {
+				if index < builder.capacity() - 1 {
+					assert_eq!(
+						std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+						ORIGINAL_BUILDER_FILE_CONTENT
+					)
+				}
+			});
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_removes_the_journal_on_success() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let journal = builder.get_temp_dir_path().join("journal");
+			let rollback = rollback.with_journal(&journal);
+
+			assert!(rollback.commit().is_ok());
+
+			assert!(!journal.exists());
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				)
+			});
+		});
+}
+
+#[test]
+fn commit_creates_the_completion_marker_on_success() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let marker = builder.get_temp_dir_path().join(".committed");
+			let rollback = rollback.with_completion_marker(&marker);
+
+			assert!(rollback.commit().is_ok());
+
+			assert!(marker.is_file());
+			builder.new_files().iter().for_each(|file| assert!(file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_removes_the_stale_completion_marker_on_failure() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.execute(|builder, rollback| {
+			let marker = builder.get_temp_dir_path().join(".committed");
+			std::fs::write(&marker, "").expect("The marker can be created; qed;");
+			let rollback = rollback.with_completion_marker(&marker);
+
+			// Deleting a temporary file means that rollback cannot commit the related new file.
+			std::fs::remove_file(
+				rollback
+					.get_new_file(builder.new_files()[0])
+					.expect("The file exists for this rollback; qed;"),
+			)
+			.expect("This should be possible; qed;");
+
+			assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+			assert!(!marker.exists());
+		});
+}
+
+#[test]
+fn commit_rollbacks_if_the_completion_marker_cannot_be_created() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let marker = builder.get_temp_dir_path().join("missing").join(".committed");
+			let rollback = rollback.with_completion_marker(&marker);
+
+			match rollback.commit() {
+				Err(Error::CommitIo { path, .. }) => assert_eq!(path, marker),
+				_ => panic!("Unexpected error"),
+			}
+
+			// The fs wasn't affected
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+			assert!(!marker.exists());
+		});
+}
+
+#[test]
+fn commit_rebases_absolute_paths_onto_the_fs_root() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let root = builder.get_temp_dir_path();
+		std::fs::create_dir(root.join("etc")).expect("The dir can be created; qed;");
+		std::fs::write(root.join("etc/foo"), ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		let mut rollback = rollback.with_fs_root(root);
+
+		let noted_file = Path::new("/etc/foo");
+		let new_dir = Path::new("/etc/app");
+		let new_file = Path::new("/etc/app/config.toml");
+		assert!(rollback.note_file(noted_file).is_ok());
+		assert!(rollback.new_dir(new_dir).is_ok());
+		assert!(rollback.new_file(new_file).is_ok());
+		std::fs::write(
+			rollback.get_noted_file(noted_file).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file exists; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(
+			std::fs::read_to_string(root.join("etc/foo")).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert!(root.join("etc/app").is_dir());
+		assert!(root.join("etc/app/config.toml").is_file());
+	});
+}
+
+#[test]
+fn commit_rollbacks_rebased_paths_on_failure() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let root = builder.get_temp_dir_path();
+		std::fs::create_dir(root.join("etc")).expect("The dir can be created; qed;");
+		std::fs::write(root.join("etc/foo"), ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		let mut rollback = rollback.with_fs_root(root);
+
+		let noted_file = Path::new("/etc/foo");
+		let new_dir = Path::new("/etc/app");
+		let new_file = Path::new("/var/app/config.toml");
+		std::fs::create_dir_all(root.join("var/app")).expect("The dir can be created; qed;");
+		assert!(rollback.note_file(noted_file).is_ok());
+		assert!(rollback.new_dir(new_dir).is_ok());
+		assert!(rollback.new_file(new_file).is_ok());
+		// The parent dir is removed after noting the file, so it cannot be committed.
+		std::fs::remove_dir(root.join("var/app")).expect("The dir exists; qed;");
+		std::fs::write(
+			rollback.get_noted_file(noted_file).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file exists; qed;");
+
+		match rollback.commit() {
+			Err(Error::CommitIo { path, .. }) => assert_eq!(path, new_file),
+			_ => panic!("Unexpected error"),
+		}
+
+		assert_eq!(
+			std::fs::read_to_string(root.join("etc/foo")).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert!(!root.join("etc/app").exists());
+	});
+}
+
+#[test]
+fn commit_creates_relative_paths_under_the_base_dir() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let base = builder.get_temp_dir_path();
+		let mut rollback = rollback.rooted_at(base.to_path_buf());
+
+		assert!(rollback.new_dir("app").is_ok());
+		assert!(rollback.new_file("app/config.toml").is_ok());
+		assert_eq!(rollback.new_dir_paths().collect::<Vec<_>>(), vec![base.join("app")]);
+		assert!(rollback.is_new_file("app/config.toml"));
+		assert!(rollback.is_new_file(base.join("app/config.toml")));
+		std::fs::write(
+			rollback.get_new_file("app/config.toml").expect("The file is new; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file exists; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		assert!(base.join("app").is_dir());
+		assert_eq!(
+			std::fs::read_to_string(base.join("app/config.toml")).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn note_file_works_with_relative_paths_under_the_base_dir() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let original = builder.existing_files()[0];
+		let base = original.parent().expect("The file has a parent; qed;");
+		let relative = original.file_name().expect("The file has a name; qed;");
+		let mut rollback = rollback.rooted_at(base.to_path_buf());
+
+		assert!(rollback.note_file(relative).is_ok());
+		assert!(rollback.is_noted(original));
+		std::fs::write(
+			rollback.get_noted_file(relative).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file exists; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(
+			std::fs::read_to_string(original).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn staging_rejects_paths_outside_the_base_dir() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let base = builder.get_temp_dir_path().join("base");
+		std::fs::create_dir(&base).expect("The dir can be created; qed;");
+		let mut rollback = rollback.rooted_at(base);
+		let outside = builder.get_temp_dir_path().join("outside.txt");
+
+		assert!(matches!(
+			rollback.new_file("../escaped.txt"),
+			Err(Error::OutsideBaseDir(path)) if path == "../escaped.txt"
+		));
+		assert!(matches!(
+			rollback.new_dir("app/../../escaped"),
+			Err(Error::OutsideBaseDir(path)) if path == "app/../../escaped"
+		));
+		assert!(matches!(
+			rollback.new_file(&outside),
+			Err(Error::OutsideBaseDir(path)) if path == outside.to_string_lossy()
+		));
+		assert!(rollback.is_empty());
+		assert!(!builder.get_temp_dir_path().join("escaped.txt").exists());
+	});
+}
+
+#[test]
+fn confined_rollback_commits_paths_under_the_root() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let root = builder.get_temp_dir_path();
+		let original = builder.existing_files()[0];
+		let new_dir = root.join("app");
+		let new_file = root.join("config.toml");
+		let mut rollback = rollback.confined_to(root.to_path_buf());
+
+		assert!(rollback.note_file(original).is_ok());
+		assert!(rollback.new_dir(&new_dir).is_ok());
+		assert!(rollback.new_file(&new_file).is_ok());
+
+		assert!(rollback.commit().is_ok());
+
+		assert!(new_dir.is_dir());
+		assert!(root.join("config.toml").is_file());
+	});
+}
+
+#[test]
+fn confined_rollback_rejects_paths_escaping_the_root() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let root = builder.get_temp_dir_path().join("root");
+		std::fs::create_dir(&root).expect("The dir can be created; qed;");
+		let outside = builder.get_temp_dir_path().join("outside");
+		std::fs::create_dir(&outside).expect("The dir can be created; qed;");
+		std::os::unix::fs::symlink(&outside, root.join("link"))
+			.expect("The symlink can be created; qed;");
+		std::fs::write(outside.join("foo"), ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file can be created; qed;");
+		let mut rollback = rollback.confined_to(root.clone());
+
+		let escaping_file = root.join("../escaped.txt");
+		assert!(matches!(
+			rollback.new_file(&escaping_file),
+			Err(Error::OutsideRoot(path)) if path == escaping_file.to_string_lossy()
+		));
+		assert!(matches!(rollback.new_dir(root.join("link/app")), Err(Error::OutsideRoot(_))));
+		assert!(matches!(rollback.note_file(root.join("link/foo")), Err(Error::OutsideRoot(_))));
+		// A symlink inside the root pointing to a file outside of it is written through
+		std::os::unix::fs::symlink(outside.join("foo"), root.join("file_link"))
+			.expect("The symlink can be created; qed;");
+		assert!(matches!(rollback.note_file(root.join("file_link")), Err(Error::OutsideRoot(_))));
+		assert!(rollback.is_empty());
+
+		assert!(rollback.commit().is_ok());
+		assert!(!builder.get_temp_dir_path().join("escaped.txt").exists());
+		assert!(!outside.join("app").exists());
+	});
+}
+
+// Checks that there isn't any staging dir left in the dir.
+fn has_staging_dirs(dir: &Path) -> bool {
+	std::fs::read_dir(dir).expect("The dir exists; qed;").any(|entry| {
+		entry
+			.expect("The entry is readable; qed;")
+			.file_name()
+			.to_string_lossy()
+			.starts_with(".fs_rollback-staging")
+	})
+}
+
+#[test]
+fn commit_works_with_staged_new_files() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let rollback = rollback.with_staged_new_files();
+			let new_files: Vec<_> = builder
+				.new_files()
+				.iter()
+				.map(|file| {
+					let temp_file = rollback.get_new_file(file).expect("The file is new; qed;");
+					std::fs::write(temp_file, MODIFIED_BUILDER_FILE_CONTENT)
+						.expect("The file exists; qed;");
+					file.to_path_buf()
+				})
+				.collect();
+
+			assert!(rollback.commit().is_ok());
+
+			new_files.iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file exists; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				);
+				assert!(!has_staging_dirs(file.parent().expect("The file has a parent; qed;")));
+			});
+		});
+}
+
+#[test]
+fn commit_doesnt_create_any_staged_new_file_if_one_cannot_be_written() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let rollback = rollback.with_staged_new_files();
+
+			// Deleting a temporary file means that rollback cannot commit the related new file.
+			let uncommitted_file = *builder.new_files().last().expect("There's new_files; qed;");
+			std::fs::remove_file(
+				rollback
+					.get_new_file(uncommitted_file)
+					.expect("The file exists for this rollback; qed;"),
+			)
+			.expect("This should be possible; qed;");
+
+			assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			assert!(!has_staging_dirs(builder.get_temp_dir_path()));
+		});
+}
+
+fn has_atomic_new_files(dir: &Path) -> bool {
+	std::fs::read_dir(dir).expect("The dir exists; qed;").any(|entry| {
+		entry
+			.expect("The entry is readable; qed;")
+			.file_name()
+			.to_string_lossy()
+			.starts_with(".fs_rollback-new")
+	})
+}
+
+#[test]
+fn commit_works_with_atomic_new_files() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let rollback = rollback.with_atomic_new_files();
+			let new_files: Vec<_> = builder
+				.new_files()
+				.iter()
+				.map(|file| {
+					let temp_file = rollback.get_new_file(file).expect("The file is new; qed;");
+					std::fs::write(temp_file, MODIFIED_BUILDER_FILE_CONTENT)
+						.expect("The file exists; qed;");
+					file.to_path_buf()
+				})
+				.collect();
+
+			assert!(rollback.commit().is_ok());
+
+			new_files.iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file exists; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				);
+				assert!(!has_atomic_new_files(file.parent().expect("The file has a parent; qed;")));
+			});
+		});
+}
+
+#[test]
+fn commit_removes_atomic_new_files_if_one_cannot_be_written() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let rollback = rollback.with_atomic_new_files();
+
+			// Deleting a temporary file means that rollback cannot commit the related new file.
+			let uncommitted_file = *builder.new_files().last().expect("There's new_files; qed;");
+			std::fs::remove_file(
+				rollback
+					.get_new_file(uncommitted_file)
+					.expect("The file exists for this rollback; qed;"),
+			)
+			.expect("This should be possible; qed;");
+
+			assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+			builder.new_files().iter().for_each(|file| {
+				assert!(!file.is_file());
+				assert!(!has_atomic_new_files(file.parent().expect("The file has a parent; qed;")));
+			});
+		});
+}
+
+#[test]
+fn commit_with_atomic_new_files_doesnt_overwrite_existing_files() {
+	TestBuilder::new(None).with_new_files().execute(|builder, rollback| {
+		let rollback = rollback.with_atomic_new_files();
+
+		// The new file is created by someone else after being noted.
+		let new_file = *builder.new_files().first().expect("There's new_files; qed;");
+		std::fs::write(new_file, "existing").expect("The dir exists; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::NewItemAlreadyExists(..))));
+
+		assert_eq!(std::fs::read_to_string(new_file).expect("The file exists; qed;"), "existing");
+	});
+}
+
+#[test]
+fn commit_fails_and_rollbacks_if_a_noted_file_is_modified_concurrently() {
+	TestBuilder::new(Some(3)).with_noted_files().execute(|builder, rollback| {
+		let rollback = rollback.with_modification_check().with_max_threads(1);
+		let modified = builder.existing_files()[2];
+		std::fs::write(modified, "Modified by someone else").expect("The file exists; qed;");
+
+		match rollback.commit() {
+			Err(Error::ConcurrentModification(item)) =>
+				assert_eq!(item, format!("{}", modified.display())),
+			_ => panic!("Unexpected error"),
+		}
+
+		// The concurrent modification is kept, and the other noted files are rolled-back
+		assert_eq!(
+			std::fs::read_to_string(modified).expect("The file exists; qed;"),
+			"Modified by someone else"
+		);
+		builder.existing_files()[..2].iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			)
+		});
+	});
+}
+
+#[test]
+fn commit_overwrites_concurrent_modifications_without_modification_check() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let modified = builder.existing_files()[0];
+		std::fs::write(modified, "Modified by someone else").expect("The file exists; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(
+			std::fs::read_to_string(modified).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn commit_accepts_concurrent_modifications_of_reset_noted_files() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let mut rollback = rollback.with_modification_check();
+		let modified = builder.existing_files()[0];
+		std::fs::write(modified, "Modified by someone else").expect("The file exists; qed;");
+		rollback
+			.note_file_with_policy(modified, ReNotePolicy::Reset)
+			.expect("The file can be reset; qed;");
+
+		assert!(rollback.commit().is_ok());
+	});
+}
+
+#[test]
+fn try_commit_with_modification_check_can_be_retried_after_a_rollback() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.execute(|builder, rollback| {
+			let mut rollback = rollback.with_modification_check();
+			let new_file = builder.new_files()[0];
+			// An existing new file makes the commit fail once the noted files are committed
+			std::fs::write(new_file, "").expect("The dir exists; qed;");
+
+			assert!(matches!(rollback.try_commit(), Err(Error::NewItemAlreadyExists(..))));
+
+			std::fs::remove_file(new_file).expect("The file exists; qed;");
+			assert!(rollback.try_commit().is_ok());
+
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file exists; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				)
+			});
+		});
+}
+
+#[test]
+fn commit_with_max_threads_works() {
+	// A cap of 0 threads still uses one thread.
+	for max_threads in [0, 1, 3] {
+		TestBuilder::new(Some(10)).with_noted_files().execute(|builder, rollback| {
+			assert!(rollback.with_max_threads(max_threads).commit().is_ok());
+
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				)
+			});
+		});
+	}
+}
+
+#[test]
+fn commit_with_max_threads_fails_and_rollbacks() {
+	TestBuilder::new(Some(10)).with_noted_files().execute(|builder, rollback| {
+		let rollback = rollback.with_max_threads(3);
+		// Deleting an existing file means that rollback cannot commit that file.
+		std::fs::remove_file(builder.existing_files()[5]).expect("This should be possible; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+		builder
+			.existing_files()
+			.iter()
+			.enumerate()
+			.filter(|(index, _)| *index != 5)
+			.for_each(|(_, file)| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+	});
+}
+
+#[test]
+fn commit_sequential_works() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			assert!(rollback.commit_sequential().is_ok());
+
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_sequential_reports_the_first_failing_file() {
+	TestBuilder::new(Some(5)).with_noted_files().execute(|builder, rollback| {
+		// Deleting existing files means that rollback cannot commit those files.
+		let mut removed = builder.existing_files()[1..4].to_vec();
+		removed.iter().for_each(|file| {
+			std::fs::remove_file(file).expect("This should be possible; qed;");
+		});
+		removed.sort();
+
+		match rollback.commit_sequential() {
+			Err(Error::CommitIo { path, .. }) => assert_eq!(path, removed[0]),
+			_ => panic!("Unexpected error"),
+		}
+
+		builder
+			.existing_files()
+			.iter()
+			.filter(|file| !removed.contains(file))
+			.for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+	});
+}
+
+#[test]
+fn commit_preserves_noted_files_permissions() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let (path, lazy) = (builder.existing_files()[0], builder.existing_files()[1]);
+		for file in [path, lazy] {
+			std::fs::set_permissions(file, std::fs::Permissions::from_mode(0o755))
+				.expect("The permissions should be set; qed;");
+		}
+		rollback.note_file(path).expect("The file should be noted; qed;");
+		rollback
+			.note_file_lazy(lazy, Box::new(|content| content))
+			.expect("The file should be noted; qed;");
+		let temp_file = rollback.get_noted_file(path).expect("The file is noted; qed;");
+		std::fs::write(temp_file, MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("The file should be writable; qed;");
+		std::fs::set_permissions(temp_file, std::fs::Permissions::from_mode(0o600))
+			.expect("The permissions should be set; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		for file in [path, lazy] {
+			assert_eq!(
+				std::fs::metadata(file).expect("The file exists; qed;").permissions().mode() &
+					0o777,
+				0o755
+			);
+		}
+	});
+}
+
+#[test]
+fn commit_with_temp_dir_works() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let new_file = builder.new_files()[0];
+		let temp_dir = tempfile::tempdir().expect("Tempdir should be created");
+		let mut rollback = rollback.with_temp_dir(temp_dir.path());
+		rollback.forget(new_file);
+		rollback.note_file(path).expect("The file should be noted; qed;");
+		rollback.new_file(new_file).expect("The file should be noted; qed;");
+
+		let noted_temp_file = rollback.get_noted_file(path).expect("The file is noted; qed;");
+		let new_temp_file = rollback.get_new_file(new_file).expect("The file is new; qed;");
+		assert!(noted_temp_file.starts_with(temp_dir.path()));
+		assert!(new_temp_file.starts_with(temp_dir.path()));
+		std::fs::write(noted_temp_file, MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("The file should be writable; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert!(new_file.is_file());
+	});
+}
+
+#[test]
+fn commit_with_backup_dir_works() {
+	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
+		let backup_dir = tempfile::tempdir().expect("Tempdir should be created");
+		let rollback = rollback.with_backup_dir(backup_dir.path());
+
+		assert!(rollback.commit().is_ok());
+
+		builder.existing_files().iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			)
+		});
+		// The backups are removed once the commit finishes
+		assert_eq!(std::fs::read_dir(backup_dir.path()).expect("The dir exists; qed;").count(), 0);
+		assert!(Rollback::recover(backup_dir.path())
+			.expect("The dir can be read; qed;")
+			.is_empty());
+	});
+}
+
+#[test]
+fn commit_with_backup_dir_removes_the_backups_if_commit_fails() {
+	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
+		let backup_dir = tempfile::tempdir().expect("Tempdir should be created");
+		let panicking_file = backup_dir.path().join("file.txt");
+		std::fs::write(&panicking_file, ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("File should be writable; qed;");
+		let mut rollback = rollback.with_backup_dir(backup_dir.path());
+		rollback
+			.note_file_lazy(&panicking_file, Box::new(|_| panic!("Transformation failed")))
+			.expect("The file should be noted; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::Commit(..))));
+
+		builder.existing_files().iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			)
+		});
+		// Only the panicking file is left in the dir
+		assert_eq!(std::fs::read_dir(backup_dir.path()).expect("The dir exists; qed;").count(), 1);
+	});
+}
+
+#[test]
+fn commit_with_archive_dir_keeps_the_backups() {
+	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
+		let archive_dir = tempfile::tempdir().expect("Tempdir should be created");
+		let rollback = rollback.with_archive_dir(archive_dir.path());
+
+		let report = rollback.commit_report().expect("The commit works; qed;");
+
+		assert_eq!(report.archived.len(), builder.existing_files().len());
+		report.archived.iter().for_each(|archived| {
+			assert_eq!(archived.parent(), Some(archive_dir.path()));
+			assert_eq!(
+				std::fs::read_to_string(archived).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+		});
+		assert_eq!(
+			std::fs::read_dir(archive_dir.path()).expect("The dir exists; qed;").count(),
+			report.archived.len()
+		);
+		builder.existing_files().iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			)
+		});
+	});
+}
+
+#[test]
+fn commit_with_archive_dir_doesnt_replace_previous_archives() {
+	TestBuilder::new(Some(1)).execute(|builder, _| {
+		let archive_dir = tempfile::tempdir().expect("Tempdir should be created");
+		let file = builder.existing_files()[0];
+		let contents = ["first", "second", "third"];
+
+		// Several commits of the same file in a row, likely within the same second
+		let archived: Vec<_> = contents
+			.iter()
+			.flat_map(|content| {
+				let mut rollback = Rollback::default().with_archive_dir(archive_dir.path());
+				rollback.note_file(file).expect("The file can be noted; qed;");
+				std::fs::write(
+					rollback.get_noted_file(file).expect("The file is noted; qed;"),
+					content,
+				)
+				.expect("The file should be writable; qed;");
+				rollback.commit_report().expect("The commit works; qed;").archived
+			})
+			.collect();
+
+		assert_eq!(
+			std::fs::read_dir(archive_dir.path()).expect("The dir exists; qed;").count(),
+			contents.len()
+		);
+		// Each archive keeps the content the file had before its own commit
+		let previous = [ORIGINAL_BUILDER_FILE_CONTENT, contents[0], contents[1]];
+		archived.iter().zip(previous).for_each(|(archived, previous)| {
+			assert_eq!(std::fs::read_to_string(archived).expect("The file exists; qed;"), previous);
+		});
+	});
+}
+
+#[test]
+fn commit_fails_and_rollbacks_if_backups_cannot_be_archived() {
+	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
+		let archive_dir = tempfile::tempdir().expect("Tempdir should be created");
+		let missing_dir = archive_dir.path().join("missing");
+		let rollback = rollback.with_archive_dir(&missing_dir);
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+		builder.existing_files().iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			)
+		});
+		assert!(!missing_dir.exists());
+	});
+}
+
+#[test]
+fn commit_keeps_the_baseline_if_backups_cannot_be_archived() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		let baseline_dir = builder.get_temp_dir_path().join("baseline");
+		let missing_dir = builder.get_temp_dir_path().join("missing");
+		let mut rollback = rollback.with_baseline_dir(&baseline_dir);
+		rollback.note_file(path).expect("The file should be noted; qed;");
+		rollback.commit().expect("The rollback should be committed; qed;");
+
+		let mut rollback = Rollback::default()
+			.with_archive_dir(&missing_dir)
+			.with_baseline_dir(&baseline_dir);
+		assert!(!rollback.note_file(path).expect("The file should be noted; qed;"));
+		std::fs::write(
+			rollback.get_noted_file(path).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+
+		assert!(matches!(
+			rollback.commit(),
+			Err(Error::CommitIo { phase: CommitPhase::Archive, .. })
+		));
+
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		// The baseline still records the original content
+		let mut rollback = Rollback::default().with_baseline_dir(&baseline_dir);
+		assert!(!rollback.note_file(path).expect("The file should be noted; qed;"));
+	});
+}
+
+#[test]
+fn commit_verified_works() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			assert!(rollback.commit_verified().is_ok());
+
+			builder.existing_files().into_iter().for_each(|path| {
+				assert_eq!(
+					std::fs::read_to_string(path).expect("The file exists; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().into_iter().for_each(|path| {
+				assert_eq!(
+					std::fs::read_to_string(path).expect("The file exists; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+		});
+}
+
+#[test]
+fn commit_paranoid_works() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			assert!(rollback.commit_paranoid().is_ok());
+
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file exists; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_durable_works() {
+	TestBuilder::new(Some(3))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let (renamed, deleted) = (builder.existing_files()[1], builder.existing_files()[2]);
+			let rename_target = builder.get_temp_dir_path().join("renamed.txt");
+			// Rebind rollback to accomplish with rename_target lifetime
+			let mut rollback = rollback;
+			rollback.forget(renamed);
+			rollback.forget(deleted);
+			rollback.rename(renamed, &rename_target).expect("The file can be renamed; qed;");
+			rollback.delete_file(deleted).expect("The file can be deleted; qed;");
+
+			assert!(rollback.commit_durable().is_ok());
+
+			assert_eq!(
+				std::fs::read_to_string(builder.existing_files()[0])
+					.expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+			builder.new_files().iter().for_each(|file| assert!(file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+			assert!(!renamed.exists() && rename_target.is_file());
+			assert!(!deleted.exists());
+		});
+}
+
+#[test]
+fn commit_with_progress_reports_each_committed_item() {
+	TestBuilder::new(Some(3))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let mut rollback = rollback;
+			let [.., deleted] = builder.existing_files()[..] else {
+				panic!("The builder has 3 existing files");
+			};
+			rollback.forget(deleted);
+			rollback.delete_file(deleted).expect("The file can be deleted; qed;");
+			let expected = rollback.staged_paths_sorted();
+
+			let reported = std::sync::Mutex::new(Vec::new());
+			assert!(rollback
+				.commit_with_progress(|path, kind| reported
+					.lock()
+					.expect("The callback cannot panic; qed;")
+					.push((path.to_path_buf(), kind)))
+				.is_ok());
+
+			let mut reported = reported.into_inner().expect("The callback cannot panic; qed;");
+			reported.sort();
+			assert_eq!(reported, expected);
+			assert!(!deleted.exists());
+		});
+}
+
+#[test]
+fn commit_report_measures_each_copied_file() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
 			builder.new_files().iter().for_each(|file| {
-				assert!(file.is_file());
+				std::fs::write(
+					rollback.get_new_file(file).expect("The file is new; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT,
+				)
+				.expect("The file exists; qed;");
+			});
+
+			let report = rollback.commit_report().expect("The commit works; qed;");
+			let CommitReport { outcome, timings, new_dirs, archived } = &report;
+
+			assert!(matches!(outcome, CommitOutcome::Applied { .. }));
+			let mut expected_paths: Vec<_> = builder
+				.existing_files()
+				.into_iter()
+				.chain(builder.new_files())
+				.map(Path::to_path_buf)
+				.collect();
+			expected_paths.sort();
+			assert_eq!(
+				timings.iter().map(|timing| timing.path.clone()).collect::<Vec<_>>(),
+				expected_paths
+			);
+			timings.iter().for_each(|timing| {
+				assert_eq!(timing.bytes, MODIFIED_BUILDER_FILE_CONTENT.len() as u64)
+			});
+			assert_eq!(
+				report.total_bytes(),
+				(timings.len() * MODIFIED_BUILDER_FILE_CONTENT.len()) as u64
+			);
+			let mut expected_dirs: Vec<_> =
+				builder.new_dirs().into_iter().map(Path::to_path_buf).collect();
+			expected_dirs.sort();
+			assert_eq!(*new_dirs, expected_dirs);
+			// Nothing's archived unless an archive dir is configured
+			assert!(archived.is_empty());
+		});
+}
+
+#[test]
+fn commit_keeps_the_journal_on_failure() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let journal = builder.get_temp_dir_path().join("journal");
+			let rollback = rollback.with_journal(&journal);
+
+			// Deleting a temporary file means that rollback cannot commit the related new file.
+			let uncommitted_file =
+				builder.new_files().last().expect("There's new_files; qed;").to_path_buf();
+			std::fs::remove_file(
+				rollback
+					.get_new_file(&uncommitted_file)
+					.expect("The file exists for this rollback; qed;"),
+			)
+			.expect("This should be possible; qed;");
+
+			assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+			let entries = std::fs::read_to_string(&journal).expect("The journal exists; qed;");
+			let entries: Vec<_> = entries.lines().collect();
+			assert_eq!(entries.first(), Some(&"begin"));
+			builder.existing_files().iter().for_each(|file| {
+				assert!(entries.contains(&format!("done copy {}", file.display()).as_str()));
+			});
+			assert!(entries
+				.contains(&format!("intent create_file {}", uncommitted_file.display()).as_str()));
+			assert!(entries[entries.len() - 2].starts_with("failed"));
+			assert_eq!(entries.last(), Some(&"rolled-back"));
+
+			// The fs wasn't affected
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_fails_if_the_journal_cannot_be_written() {
+	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
+		let journal = builder.get_temp_dir_path().join("missing").join("journal");
+		let rollback = rollback.with_journal(&journal);
+
+		match rollback.commit() {
+			Err(Error::Commit(item, _)) => assert_eq!(item, format!("{}", journal.display())),
+			_ => panic!("Unexpected error"),
+		}
+
+		// The fs wasn't affected
+		builder.existing_files().iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			)
+		});
+	});
+}
+
+#[test]
+fn commit_fails_early_if_a_noted_file_is_busy() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let rollback = rollback.with_busy_check();
+			let busy_file = builder.existing_files()[0];
+			let _lock = lock_exclusively(busy_file);
+
+			match rollback.commit() {
+				Err(Error::FileBusy(item)) => assert_eq!(item, format!("{}", busy_file.display())),
+				_ => panic!("Unexpected error"),
+			}
+
+			// The fs wasn't affected
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_works_with_busy_check_and_locked_files() {
+	TestBuilder::new(None).execute(|builder, rollback| {
+		let mut rollback = rollback.with_busy_check();
+		builder.existing_files().iter().for_each(|file| {
+			rollback.note_file_locked(file).expect("The file should be noted; qed;");
+		});
+
+		// The files locked by the rollback itself aren't busy
+		assert!(rollback.commit().is_ok());
+	});
+}
+
+#[test]
+fn commit_fails_if_a_new_file_shadows_an_existing_file_through_a_symlinked_parent() {
+	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, rollback| {
+		let shared_dir = builder.get_temp_dir_path().join("shared");
+		let existing_file = shared_dir.join("file.txt");
+		let link = builder.get_temp_dir_path().join("link");
+		let shadowing_file = link.join("file.txt");
+		std::fs::create_dir(&shared_dir).expect("The dir should be created; qed;");
+		std::fs::write(&existing_file, ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("The file should be writable; qed;");
+
+		std::fs::create_dir(&link).expect("The dir should be created; qed;");
+
+		// Rebind rollback to accomplish with shadowing_file lifetime
+		let mut rollback = rollback;
+		rollback.new_file(&shadowing_file).expect("The file should be noted; qed;");
+		std::fs::write(
+			rollback.get_new_file(&shadowing_file).expect("The file is noted; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+		// The parent dir is replaced by a symlink after noting the new file
+		std::fs::remove_dir(&link).expect("The dir exists; qed;");
+		std::os::unix::fs::symlink(&shared_dir, &link)
+			.expect("The symlink should be created; qed;");
+
+		match rollback.commit() {
+			Err(Error::NewItemAlreadyExists(item)) => assert_eq!(
+				item,
+				format!(
+					"{}",
+					existing_file.canonicalize().expect("The file exists; qed;").display()
+				)
+			),
+			_ => panic!("Unexpected error"),
+		}
+
+		// The existing file isn't clobbered nor deleted, and everything is rolled-back
+		assert_eq!(
+			std::fs::read_to_string(&existing_file).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+	});
+}
+
+#[test]
+fn commit_fails_if_a_new_file_is_a_dangling_symlink() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, rollback| {
+		let new_file = builder.new_files()[0];
+		let target = builder.get_temp_dir_path().join("target.txt");
+		std::os::unix::fs::symlink(&target, new_file).expect("The symlink should be created; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::NewItemAlreadyExists(_))));
+
+		// The symlink target isn't created and the symlink is kept
+		assert!(!target.exists());
+		assert!(new_file.symlink_metadata().is_ok());
+	});
+}
+
+#[test]
+fn commit_doesnt_delete_new_files_created_by_someone_else() {
+	TestBuilder::new(Some(2)).with_new_files().execute(|builder, rollback| {
+		let external_file = builder.new_files()[1];
+		std::fs::write(external_file, MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("The file should be writable; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::NewItemAlreadyExists(_))));
+
+		assert_eq!(
+			std::fs::read_to_string(external_file).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert!(!builder.new_files()[0].exists());
+	});
+}
+
+#[test]
+fn commit_records_new_files_in_the_baseline() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let path = builder.new_files()[0];
+		let baseline_dir = builder.get_temp_dir_path().join("baseline");
+		let mut rollback = rollback.with_baseline_dir(&baseline_dir);
+
+		rollback.new_file(path).expect("The file should be added; qed;");
+		std::fs::write(
+			rollback.get_new_file(path).expect("The file is added; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+
+		assert!(!baseline_dir.exists());
+
+		rollback.commit().expect("The rollback should be committed; qed;");
+
+		assert!(baseline_dir.is_dir());
+
+		let mut rollback = Rollback::default().with_baseline_dir(&baseline_dir);
+		assert!(!rollback.note_file(path).expect("The file should be noted; qed;"));
+	});
+}
+
+#[test]
+fn commit_honors_umask() {
+	TestBuilder::new(None)
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let umask = |mask| unsafe { libc::umask(mask) };
+			let original_umask = umask(0o022);
+
+			let result = rollback.with_umask(0o027).commit();
+
+			// The umask is restored after the commit
+			let current_umask = umask(original_umask);
+			assert_eq!(current_umask, 0o022);
+			assert!(result.is_ok());
+
+			builder.new_files().iter().for_each(|file| {
+				let mode =
+					std::fs::metadata(file).expect("The file exists; qed;").permissions().mode();
+				assert_eq!(mode & 0o777, 0o640);
+			});
+			builder.new_dirs().iter().for_each(|dir| {
+				let mode =
+					std::fs::metadata(dir).expect("The dir exists; qed;").permissions().mode();
+				assert_eq!(mode & 0o777, 0o750);
+			});
+		});
+}
+
+#[test]
+fn commit_applies_new_files_modes() {
+	TestBuilder::new(Some(0)).execute(|builder, rollback| {
+		let script = builder.get_temp_dir_path().join("script.sh");
+		let config = builder.get_temp_dir_path().join("config.toml");
+		// Rebind rollback to accomplish with script and config lifetime
+		let mut rollback = rollback;
+		assert!(rollback.new_file_with_mode(&script, 0o755).is_ok());
+		assert!(rollback.new_file_with_mode(&config, 0o640).is_ok());
+		std::fs::write(
+			rollback.get_new_file(&script).expect("The file is new; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(
+			std::fs::read_to_string(&script).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			std::fs::metadata(&script).expect("The file exists; qed;").permissions().mode() & 0o777,
+			0o755
+		);
+		assert_eq!(
+			std::fs::metadata(&config).expect("The file exists; qed;").permissions().mode() & 0o777,
+			0o640
+		);
+	});
+}
+
+#[test]
+fn new_file_with_mode_fails_if_path_exists() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+
+		assert!(matches!(
+			rollback.new_file_with_mode(path, 0o755),
+			Err(Error::NewItemAlreadyExists(_))
+		));
+		assert!(!rollback.is_new_file(path));
+	});
+}
+
+#[test]
+fn commit_changes_dirs_metadata() {
+	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, mut rollback| {
+		let dir = builder.get_temp_dir_path();
+		let uid = std::fs::metadata(dir).expect("The dir exists; qed;").uid();
+		rollback.note_dir_metadata(dir).expect("The dir should be noted; qed;");
+		rollback.set_dir_mode(dir, 0o750).expect("The dir is noted; qed;");
+		rollback.set_dir_owner(dir, Some(uid), None).expect("The dir is noted; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		let metadata = std::fs::metadata(dir).expect("The dir exists; qed;");
+		assert_eq!(metadata.mode() & 0o7777, 0o750);
+		assert_eq!(metadata.uid(), uid);
+		builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+	});
+}
+
+#[test]
+fn commit_fails_and_rollbacks_if_dir_metadata_cannot_be_committed() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, rollback| {
+		let dir = builder.get_temp_dir_path();
+		let original_mode = std::fs::metadata(dir).expect("The dir exists; qed;").mode() & 0o7777;
+		let removed_dir = dir.join("removed");
+		std::fs::create_dir(&removed_dir).expect("The dir should be created; qed;");
+
+		let mut rollback = rollback;
+		rollback.note_dir_metadata(dir).expect("The dir should be noted; qed;");
+		rollback.note_dir_metadata(&removed_dir).expect("The dir should be noted; qed;");
+		rollback.set_dir_mode(dir, 0o700).expect("The dir is noted; qed;");
+		rollback.set_dir_mode(&removed_dir, 0o700).expect("The dir is noted; qed;");
+
+		// A removed dir's metadata cannot be committed
+		std::fs::remove_dir(&removed_dir).expect("The dir should be removed; qed;");
+
+		match rollback.commit() {
+			Err(Error::CommitIo { path, source, .. }) => {
+				assert_eq!(path, removed_dir);
+				assert_eq!(source.kind(), ErrorKind::NotFound);
+			},
+			_ => panic!("Unexpected error"),
+		}
+
+		// The fs wasn't affected
+		assert_eq!(
+			std::fs::metadata(dir).expect("The dir exists; qed;").mode() & 0o7777,
+			original_mode
+		);
+		builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+	});
+}
+
+#[test]
+fn commit_changes_file_permissions() {
+	TestBuilder::new(Some(2)).with_noted_files().execute(|builder, mut rollback| {
+		let (noted, untouched) = (builder.existing_files()[0], builder.existing_files()[1]);
+		rollback.forget(untouched);
+		rollback.note_permissions(noted, 0o640).expect("The file should be noted; qed;");
+		rollback
+			.note_permissions(untouched, 0o600)
+			.expect("The file should be noted; qed;");
+
+		assert!(rollback.contains_conflicts().is_none());
+		assert!(rollback.commit().is_ok());
+
+		let metadata = std::fs::metadata(noted).expect("The file exists; qed;");
+		assert_eq!(metadata.mode() & 0o7777, 0o640);
+		assert_eq!(
+			std::fs::read_to_string(noted).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		let metadata = std::fs::metadata(untouched).expect("The file exists; qed;");
+		assert_eq!(metadata.mode() & 0o7777, 0o600);
+		assert_eq!(
+			std::fs::read_to_string(untouched).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn commit_fails_and_rollbacks_if_file_permissions_cannot_be_committed() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let noted = builder.existing_files()[0];
+		let original_mode =
+			std::fs::metadata(noted).expect("The file exists; qed;").mode() & 0o7777;
+		let removed = builder.get_temp_dir_path().join("zz_removed");
+		std::fs::write(&removed, []).expect("The file should be created; qed;");
+
+		let mut rollback = rollback;
+		rollback.note_permissions(noted, 0o600).expect("The file should be noted; qed;");
+		rollback
+			.note_permissions(&removed, 0o600)
+			.expect("The file should be noted; qed;");
+
+		// A removed file's permissions cannot be committed
+		std::fs::remove_file(&removed).expect("The file should be removed; qed;");
+
+		match rollback.commit() {
+			Err(Error::CommitIo { path, source, .. }) => {
+				assert_eq!(path, removed);
+				assert_eq!(source.kind(), ErrorKind::NotFound);
+			},
+			_ => panic!("Unexpected error"),
+		}
+
+		// The fs wasn't affected
+		let metadata = std::fs::metadata(noted).expect("The file exists; qed;");
+		assert_eq!(metadata.mode() & 0o7777, original_mode);
+		assert_eq!(
+			std::fs::read_to_string(noted).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+	});
+}
+
+#[test]
+fn commit_works_with_empty_and_binary_files() {
+	TestBuilder::new(Some(2)).execute(|builder, rollback| {
+		let empty_file = builder.get_temp_dir_path().join("empty");
+		let binary_file = builder.get_temp_dir_path().join("binary");
+		std::fs::write(&empty_file, []).expect("The file should be created; qed;");
+		std::fs::write(&binary_file, BINARY_CONTENT).expect("The file should be created; qed;");
+		let (new_empty_file, new_binary_file) = (builder.new_files()[0], builder.new_files()[1]);
+
+		let mut rollback = rollback;
+		rollback.note_file(&empty_file).expect("The file should be noted; qed;");
+		rollback.note_file(&binary_file).expect("The file should be noted; qed;");
+		rollback.new_file(new_empty_file).expect("The file should be added; qed;");
+		rollback.new_file(new_binary_file).expect("The file should be added; qed;");
+
+		// The empty file becomes binary and the binary file becomes empty
+		std::fs::write(
+			rollback.get_noted_file(&empty_file).expect("The file is noted; qed;"),
+			BINARY_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+		std::fs::write(rollback.get_noted_file(&binary_file).expect("The file is noted; qed;"), [])
+			.expect("The file should be writable; qed;");
+		std::fs::write(
+			rollback.get_new_file(new_binary_file).expect("The file is added; qed;"),
+			BINARY_CONTENT,
+		)
+		.expect("The file should be writable; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(std::fs::read(&empty_file).expect("The file exists; qed;"), BINARY_CONTENT);
+		assert!(std::fs::read(&binary_file).expect("The file exists; qed;").is_empty());
+		assert!(std::fs::read(new_empty_file).expect("The file exists; qed;").is_empty());
+		assert_eq!(std::fs::read(new_binary_file).expect("The file exists; qed;"), BINARY_CONTENT);
+	});
+}
+
+#[test]
+fn commit_fails_and_rollbacks_binary_files() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let binary_file = builder.get_temp_dir_path().join("binary");
+		std::fs::write(&binary_file, BINARY_CONTENT).expect("The file should be created; qed;");
+		let new_file = builder.new_files()[0];
+
+		let mut rollback = rollback;
+		rollback.note_file(&binary_file).expect("The file should be noted; qed;");
+		rollback.new_file(new_file).expect("The file should be added; qed;");
+		std::fs::write(rollback.get_noted_file(&binary_file).expect("The file is noted; qed;"), [])
+			.expect("The file should be writable; qed;");
+
+		// The new file cannot be committed without its temporary file
+		std::fs::remove_file(rollback.get_new_file(new_file).expect("The file is added; qed;"))
+			.expect("The file should be removed; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+		// The binary file is restored byte by byte
+		assert_eq!(std::fs::read(&binary_file).expect("The file exists; qed;"), BINARY_CONTENT);
+		assert!(!new_file.exists());
+	});
+}
+
+#[test]
+fn commit_works_with_locked_files() {
+	TestBuilder::new(None).execute(|builder, mut rollback| {
+		builder.existing_files().iter().for_each(|file| {
+			rollback.note_file_locked(file).expect("The file should be noted; qed;");
+			std::fs::write(
+				rollback.get_noted_file(file).expect("The file is noted; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT,
+			)
+			.expect("The file should be writable; qed;");
+		});
+
+		assert!(rollback.commit().is_ok());
+
+		builder.existing_files().iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+			// The lock is released after commit
+			assert!(is_lockable(file, libc::LOCK_EX));
+		});
+	});
+}
+
+#[test]
+fn commit_keeps_locked_files_locked_while_they_are_committed() {
+	TestBuilder::new(None).execute(|builder, mut rollback| {
+		let inodes: Vec<u64> = builder
+			.existing_files()
+			.iter()
+			.map(|file| {
+				rollback.note_file_locked(file).expect("The file should be noted; qed;");
+				std::fs::write(
+					rollback.get_noted_file(file).expect("The file is noted; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT,
+				)
+				.expect("The file should be writable; qed;");
+				std::fs::metadata(file).expect("The file exists; qed;").ino()
+			})
+			.collect();
+
+		// Each file is reported once committed, while it's still locked
+		assert!(rollback
+			.commit_with_progress(|path, _| {
+				assert_eq!(
+					std::fs::read_to_string(path).expect("The file exists; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				);
+				assert!(!is_lockable(path, libc::LOCK_EX));
+				assert!(!is_lockable(path, libc::LOCK_SH));
+			})
+			.is_ok());
+
+		// The files are written in place, so the locked inodes are the committed ones
+		builder.existing_files().iter().zip(inodes).for_each(|(file, inode)| {
+			assert_eq!(std::fs::metadata(file).expect("The file exists; qed;").ino(), inode);
+			assert!(is_lockable(file, libc::LOCK_EX));
+		});
+	});
+}
+
+#[test]
+fn commit_keeps_the_owner_of_replaced_files() {
+	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
+		// Giving files away requires privileges
+		if builder
+			.existing_files()
+			.iter()
+			.any(|file| std::os::unix::fs::chown(file, Some(65534), Some(65534)).is_err())
+		{
+			return;
+		}
+		let inodes: Vec<u64> = builder
+			.existing_files()
+			.iter()
+			.map(|file| std::fs::metadata(file).expect("The file exists; qed;").ino())
+			.collect();
+
+		assert!(rollback.commit().is_ok());
+
+		// The files are replaced, but they keep their owner and group
+		builder.existing_files().iter().zip(inodes).for_each(|(file, inode)| {
+			let metadata = std::fs::metadata(file).expect("The file exists; qed;");
+			assert_ne!(metadata.ino(), inode);
+			assert_eq!((metadata.uid(), metadata.gid()), (65534, 65534));
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+		});
+	});
+}
+
+#[test]
+fn commit_applies_lazy_transformations_to_the_freshest_content() {
+	TestBuilder::new(None).execute(|builder, mut rollback| {
+		builder.existing_files().iter().for_each(|file| {
+			rollback
+				.note_file_lazy(
+					file,
+					Box::new(|mut content| {
+						content.extend_from_slice(b" transformed");
+						content
+					}),
+				)
+				.expect("The file should be noted; qed;");
+			// The file is modified after noting it
+			std::fs::write(file, MODIFIED_BUILDER_FILE_CONTENT)
+				.expect("The file should be writable; qed;");
+		});
+
+		assert!(rollback.commit().is_ok());
+
+		builder.existing_files().iter().for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				format!("{} transformed", MODIFIED_BUILDER_FILE_CONTENT)
+			);
+		});
+	});
+}
+
+#[test]
+fn commit_fails_and_rollbacks_if_a_lazy_transformation_panics() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			// Rebind rollback to accomplish with panicking_file lifetime
+			let mut rollback = rollback;
+			let tempdir = tempfile::tempdir().expect("Tempdir should be created");
+			let panicking_file = tempdir.path().join("file.txt");
+			std::fs::write(&panicking_file, ORIGINAL_BUILDER_FILE_CONTENT)
+				.expect("File should be writable; qed;");
+			rollback
+				.note_file_lazy(&panicking_file, Box::new(|_| panic!("Transformation failed")))
+				.expect("The file should be noted; qed;");
+
+			match rollback.commit() {
+				Err(Error::Commit(item, err)) => {
+					assert_eq!(item, format!("{}", panicking_file.display()));
+					assert!(err.contains("panicked"));
+				},
+				_ => panic!("Unexpected error"),
+			}
+
+			// The fs wasn't affected
+			assert_eq!(
+				std::fs::read_to_string(&panicking_file).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file exists; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_with_file_guard_skips_vetoed_files() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.execute(|builder, rollback| {
+			let vetoed = builder.existing_files()[0];
+
+			let result = rollback.commit_with_file_guard(VetoPolicy::Skip, |path, metadata| {
+				// The guard receives the current metadata of the original
+				assert_eq!(metadata.len(), ORIGINAL_BUILDER_FILE_CONTENT.len() as u64);
+				path != vetoed
+			});
+
+			// The vetoed file isn't counted
+			match result {
+				Ok(CommitOutcome::Applied { counts }) =>
+					assert_eq!(counts.noted_files, builder.capacity() - 1),
+				_ => panic!("Unexpected outcome"),
+			}
+
+			// The vetoed file is untouched while the rest is committed
+			builder.existing_files().iter().for_each(|file| {
+				let expected = if *file == vetoed {
+					ORIGINAL_BUILDER_FILE_CONTENT
+				} else {
+					MODIFIED_BUILDER_FILE_CONTENT
+				};
+				assert_eq!(std::fs::read_to_string(file).expect("The file exists; qed;"), expected);
+			});
+			builder.new_files().iter().for_each(|file| assert!(file.is_file()));
+		});
+}
+
+#[test]
+fn commit_with_file_guard_aborts_if_a_file_is_vetoed() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let vetoed = builder.existing_files()[0];
+
+			match rollback.commit_with_file_guard(VetoPolicy::Abort, |path, _| path != vetoed) {
+				Err(Error::Vetoed(item)) => assert_eq!(item, format!("{}", vetoed.display())),
+				_ => panic!("Unexpected error"),
+			}
+
+			// The fs wasn't affected
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file exists; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_doesnt_leave_backups_behind_if_an_original_is_removed_mid_commit() {
+	TestBuilder::new(None).with_noted_files().execute(|builder, rollback| {
+		let removed_file = builder.existing_files()[0];
+		let dir = removed_file.parent().expect("The file has a parent; qed;");
+		let entries = || {
+			let mut entries: Vec<_> = std::fs::read_dir(dir)
+				.expect("The dir exists; qed;")
+				.map(|entry| entry.expect("The entry is readable; qed;").path())
+				.collect();
+			entries.sort();
+			entries
+		};
+		let entries_before_commit = entries();
+
+		// The guard runs right before each noted file is backed up.
+		let result = rollback.commit_with_file_guard(VetoPolicy::Abort, |path, _| {
+			if path == removed_file {
+				std::fs::remove_file(path).expect("The file exists; qed;");
+			}
+			true
+		});
+		assert!(matches!(result, Err(Error::CommitIo { .. })));
+
+		// Only the removed file is missing, there's not any stray backup
+		let mut expected_entries = entries_before_commit;
+		expected_entries.retain(|entry| entry != removed_file);
+		assert_eq!(entries(), expected_entries);
+		builder.existing_files().iter().skip(1).for_each(|file| {
+			assert_eq!(
+				std::fs::read_to_string(file).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			)
+		});
+	});
+}
+
+#[test]
+fn commit_with_transaction_retry_recovers_from_transient_failures() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			// Lock a noted file, so the first attempt fails, and release it before the next one.
+			let rollback = rollback.with_busy_check();
+			let lock = lock_exclusively(builder.existing_files()[0]);
+			let releaser = std::thread::spawn(move || {
+				std::thread::sleep(std::time::Duration::from_millis(100));
+				drop(lock);
+			});
+
+			assert!(rollback
+				.commit_with_transaction_retry(5, std::time::Duration::from_millis(300))
+				.is_ok());
+			releaser.join().expect("The thread cannot panic; qed;");
+
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_with_transaction_retry_fails_and_rollbacks_if_attempts_are_exhausted() {
+	TestBuilder::new(None)
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, rollback| {
+			let rollback = rollback.with_busy_check();
+			let busy_file = builder.existing_files()[0];
+			let _lock = lock_exclusively(busy_file);
+
+			match rollback.commit_with_transaction_retry(3, std::time::Duration::from_millis(10)) {
+				Err(Error::FileBusy(item)) => assert_eq!(item, format!("{}", busy_file.display())),
+				_ => panic!("Unexpected error"),
+			}
+
+			// The fs wasn't affected
+			builder.existing_files().iter().for_each(|file| {
+				assert_eq!(
+					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				)
+			});
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+		});
+}
+
+#[test]
+fn commit_with_transaction_retry_doesnt_retry_non_retryable_errors() {
+	TestBuilder::new(Some(1)).with_new_dirs().execute(|builder, mut rollback| {
+		let dir = builder.new_dirs()[0];
+		let temp_dir = builder.get_temp_dir_path();
+		// The parent dir doesn't exist yet, so the collision is detected upon commit.
+		let refactored_path = temp_dir
+			.join("..")
+			.join(temp_dir.file_name().expect("The path is a dir, so file_name exists; qed;"))
+			.join(dir.file_name().expect("The path is a dir, so file_name exists; qed;"))
+			.join("file.txt");
+		rollback.new_file(dir.join("file.txt")).expect("The file should be noted; qed;");
+		rollback.new_file(&refactored_path).expect("The file should be noted; qed;");
+
+		let backoff = std::time::Duration::from_secs(60);
+		let start = std::time::Instant::now();
+		assert!(matches!(
+			rollback.commit_with_transaction_retry(3, backoff),
+			Err(Error::RepeatedNewFile(_))
+		));
+		assert!(start.elapsed() < backoff);
+	});
+}
+
+#[test]
+fn commit_with_transaction_retry_doesnt_retry_io_errors_that_arent_transient() {
+	TestBuilder::new(Some(1)).with_noted_files().execute(|builder, rollback| {
+		let removed_file = builder.existing_files()[0];
+		std::fs::remove_file(removed_file).expect("This should be possible; qed;");
+
+		let backoff = std::time::Duration::from_secs(60);
+		let start = std::time::Instant::now();
+		match rollback.commit_with_transaction_retry(3, backoff) {
+			Err(Error::CommitIo { path, source, .. }) => {
+				assert_eq!(path, removed_file);
+				assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+			},
+			_ => panic!("Unexpected error"),
+		}
+		assert!(start.elapsed() < backoff);
+	});
+}
+
+#[test]
+fn try_commit_can_be_retried_after_a_failure() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, mut rollback| {
+			let removed_file = builder.existing_files()[1];
+			std::fs::remove_file(removed_file).expect("This should be possible; qed;");
+
+			match rollback.try_commit() {
+				Err(Error::CommitIo { path, .. }) => assert_eq!(path, removed_file),
+				_ => panic!("Unexpected error"),
+			}
+			// The fs wasn't affected, while the staged operations are kept
+			assert_eq!(
+				std::fs::read_to_string(builder.existing_files()[0])
+					.expect("The file should be readable; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
+			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
+			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+			assert_eq!(rollback.pending_count(), 6);
+
+			// Once the failing condition is fixed, the commit succeeds
+			std::fs::write(removed_file, ORIGINAL_BUILDER_FILE_CONTENT)
+				.expect("File should be writable; qed;");
+			assert_eq!(
+				rollback.try_commit().expect("The commit should succeed; qed;"),
+				CommitOutcome::Applied {
+					counts: CommitCounts {
+						noted_files: 2,
+						new_files: 2,
+						new_dirs: 2,
+						..Default::default()
+					}
+				}
+			);
+			builder.existing_files().iter().for_each(|file| {
 				assert_eq!(
 					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
-					ORIGINAL_BUILDER_FILE_CONTENT
+					MODIFIED_BUILDER_FILE_CONTENT
 				)
 			});
+			builder.new_files().iter().for_each(|file| assert!(file.is_file()));
 			builder.new_dirs().iter().for_each(|dir| assert!(dir.is_dir()));
+
+			// The staged operations are discarded, so the instance can be reused
+			assert!(rollback.is_empty());
+			assert!(matches!(rollback.try_commit(), Ok(CommitOutcome::Empty)));
+			rollback
+				.note_file(builder.existing_files()[0])
+				.expect("The file can be noted; qed;");
+			assert_eq!(rollback.noted_len(), 1);
 		});
 }
 
 #[test]
-fn commit_fails_and_rollbacks_if_noted_file_cannot_be_committed() {
-	TestBuilder::new(None)
+fn abort_works() {
+	TestBuilder::new(Some(3))
 		.with_noted_files()
 		.with_new_files()
 		.with_new_dirs()
 		.execute(|builder, rollback| {
-			builder.existing_files().iter().for_each(|file| {
+			let dir = builder.get_temp_dir_path().join("dir");
+			std::fs::create_dir(&dir).expect("The dir can be created; qed;");
+			let deleted = builder.existing_files()[1];
+			let renamed = builder.existing_files()[2];
+			let rename_target = builder.get_temp_dir_path().join("renamed.txt");
+			// Rebind rollback to accomplish with dir and rename_target lifetime
+			let mut rollback = rollback;
+			rollback.forget(deleted);
+			rollback.forget(renamed);
+			rollback.delete_file(deleted).expect("The file can be deleted; qed;");
+			rollback.rename(renamed, &rename_target).expect("The file can be renamed; qed;");
+			rollback.delete_dir(&dir).expect("The dir can be deleted; qed;");
+
+			let temp_files = [
+				rollback.get_noted_file(builder.existing_files()[0]),
+				rollback.get_new_file(builder.new_files()[0]),
+				rollback.get_deleted_dir(&dir),
+			]
+			.map(|path| path.expect("The path is staged; qed;").to_path_buf());
+
+			assert!(rollback.abort().is_ok());
+
+			// Every temporary file and backup is removed, while the fs is untouched
+			assert!(temp_files.iter().all(|path| !path.exists()));
+			builder.existing_files().into_iter().for_each(|path| {
 				assert_eq!(
-					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
+					std::fs::read_to_string(path).expect("The file exists; qed;"),
 					ORIGINAL_BUILDER_FILE_CONTENT
 				)
 			});
+			assert!(builder.new_files().into_iter().all(|path| !path.exists()));
+			assert!(builder.new_dirs().into_iter().all(|path| !path.exists()));
+			assert!(!rename_target.exists());
+			assert!(dir.is_dir());
+		});
+}
 
-			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
-			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+#[test]
+fn abort_works_for_empty_rollbacks() {
+	assert!(Rollback::default().abort().is_ok());
+}
 
-			// Deleting an existing file means that rollback cannot commit that file.
-			let removed_file = builder
-				.existing_files()
-				.last()
-				.expect("There's existing_files; qed;")
-				.to_path_buf();
-			std::fs::remove_file(&removed_file).expect("This should be possible; qed;");
+#[test]
+fn into_parts_works() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|builder, mut rollback| {
+			let lazy = builder.existing_files()[1];
+			rollback.forget(lazy);
+			rollback
+				.note_file_lazy(lazy, Box::new(|_| MODIFIED_BUILDER_FILE_CONTENT.into()))
+				.expect("The file can be noted; qed;");
 
-			match rollback.commit() {
-				Err(Error::Commit(item, err)) => {
-					assert_eq!(item, format!("{}", removed_file.display()));
-					assert!(err.contains("No such file or directory"));
-				},
-				_ => panic!("Unexpected error"),
-			}
+			let (noted, new_files, new_dirs) =
+				rollback.into_parts().expect("The parts can be taken; qed;");
 
-			// The fs wasn't affected
-			builder.existing_files().iter().enumerate().for_each(|(index, file)| {
-				if index < builder.capacity() - 1 {
-					assert_eq!(
-						std::fs::read_to_string(file).expect("The file should be readable; qed;"),
-						ORIGINAL_BUILDER_FILE_CONTENT
-					)
-				}
+			assert_eq!(noted.len(), 2);
+			builder.existing_files().into_iter().for_each(|path| {
+				let temp_file = noted.get(path).expect("The file is noted; qed;");
+				assert_eq!(
+					std::fs::read_to_string(temp_file.path()).expect("The file exists; qed;"),
+					MODIFIED_BUILDER_FILE_CONTENT
+				);
+				// The fs is untouched
+				assert_eq!(
+					std::fs::read_to_string(path).expect("The file exists; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				);
 			});
+			assert_eq!(new_files.len(), 2);
+			builder.new_files().into_iter().for_each(|path| {
+				let temp_file = new_files.get(path).expect("The file is new; qed;");
+				assert_eq!(
+					std::fs::read_to_string(temp_file.path()).expect("The file exists; qed;"),
+					ORIGINAL_BUILDER_FILE_CONTENT
+				);
+				assert!(!path.exists());
+			});
+			assert_eq!(new_dirs, builder.new_dirs());
+			assert!(builder.new_dirs().into_iter().all(|path| !path.exists()));
 
-			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
-			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+			// The temporary files are owned by the caller, so they can be persisted
+			let (path, temp_file) = new_files.into_iter().next().expect("There's new files; qed;");
+			temp_file.persist(&path).expect("The file can be persisted; qed;");
+			assert_eq!(
+				std::fs::read_to_string(&path).expect("The file exists; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT
+			);
 		});
 }
 
 #[test]
-fn commit_fails_and_rollbacks_if_new_dir_cannot_be_committed() {
-	TestBuilder::new(None)
+fn into_parts_gives_deduplicated_new_files_their_own_temp_file() {
+	TestBuilder::new(Some(2)).execute(|builder, rollback| {
+		let mut rollback = rollback.with_deduplicated_new_files();
+		builder.new_files().into_iter().for_each(|path| {
+			rollback
+				.new_file_from_reader(path, &mut MODIFIED_BUILDER_FILE_CONTENT.as_bytes())
+				.expect("The file can be new; qed;");
+		});
+
+		let (_, new_files, _) = rollback.into_parts().expect("The parts can be taken; qed;");
+
+		let temp_files: Vec<_> = new_files.values().map(|temp_file| temp_file.path()).collect();
+		assert_eq!(temp_files.len(), 2);
+		assert_ne!(temp_files[0], temp_files[1]);
+		temp_files.into_iter().for_each(|temp_file| {
+			assert_eq!(
+				std::fs::read_to_string(temp_file).expect("The file exists; qed;"),
+				MODIFIED_BUILDER_FILE_CONTENT
+			);
+		});
+	});
+}
+
+#[test]
+fn contains_conflicts_is_none_for_consistent_rollbacks() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
 		.with_new_files()
 		.with_new_dirs()
-		.with_read_only_dir()
 		.execute(|builder, rollback| {
-			// Note a file (cannot build it with builder cause the commit will fail because of this
-			// file as it's in a read_only dir). Rebind rollback to accomplish with existing_file
-			// lifetime
+			let new_file = builder.new_dirs()[0].join("file.txt");
+			// Rebind rollback to accomplish with new_file lifetime
 			let mut rollback = rollback;
-			let tempdir = tempfile::tempdir().expect("Tempdir should be created");
-			let existing_file = tempdir.path().join("file.txt");
+			rollback.new_file(&new_file).expect("The file can be added; qed;");
 
-			File::create(&existing_file).expect("File should be created; qed;");
-			std::fs::write(&existing_file, ORIGINAL_BUILDER_FILE_CONTENT)
-				.expect("File should be writable; qed;");
-			rollback.note_file(&existing_file).expect("File should be noted; qed;");
-			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
-			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+			assert!(rollback.contains_conflicts().is_none());
+		});
+}
 
-			match rollback.commit() {
-				Err(Error::Commit(_, err)) => {
-					// No permissions in temp_dir => failure committing the dirs; Cannot ensure
-					// which one comes in the msg cause this runs concurrently and all of them
-					// failed
-					assert!(err.contains("Permission denied"));
-				},
-				_ => panic!("Unexpected error"),
-			}
+#[test]
+fn contains_conflicts_detects_paths_staged_in_several_categories() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.new_files()[0];
+		rollback.new_dir(path).expect("The dir can be added; qed;");
+		rollback.new_file(path).expect("The file can be added; qed;");
 
-			// The fs wasn't affected
+		assert_eq!(
+			rollback.contains_conflicts(),
+			Some(vec![(path.to_path_buf(), path.to_path_buf())])
+		);
+	});
+}
+
+#[test]
+fn contains_conflicts_detects_items_inside_files() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let file = builder.new_files()[0];
+		let dir = file.join("dir");
+		let nested_dir = dir.join("nested");
+		// Rebind rollback to accomplish with dir and nested_dir lifetime
+		let mut rollback = rollback;
+		rollback.new_file(file).expect("The file can be added; qed;");
+		rollback.new_dir(&dir).expect("The dir can be added; qed;");
+		rollback.new_dir(&nested_dir).expect("The dir can be added; qed;");
+
+		// Only the file conflicts, as dirs can contain other items
+		assert_eq!(
+			rollback.contains_conflicts(),
+			Some(vec![(file.to_path_buf(), dir.clone()), (file.to_path_buf(), nested_dir.clone())])
+		);
+	});
+}
+
+#[test]
+fn contains_conflicts_detects_rename_targets() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let target = builder.get_temp_dir_path().join("renamed.txt");
+		// Rebind rollback to accomplish with target lifetime
+		let mut rollback = rollback;
+		rollback
+			.rename(builder.existing_files()[0], &target)
+			.expect("The file can be renamed; qed;");
+		rollback.new_file(&target).expect("The file can be added; qed;");
+
+		assert_eq!(rollback.contains_conflicts(), Some(vec![(target.clone(), target.clone())]));
+	});
+}
+
+#[test]
+fn estimate_required_space_works() {
+	TestBuilder::new(Some(2))
+		.with_noted_files()
+		.with_new_files()
+		.with_new_dirs()
+		.execute(|_, rollback| {
+			// The builder writes the modified content into noted files and the original content
+			// into new files, while new dirs don't require space
 			assert_eq!(
-				std::fs::read_to_string(&existing_file).expect("File should be readable; qed;"),
-				ORIGINAL_BUILDER_FILE_CONTENT
+				rollback.estimate_required_space(),
+				2 * (MODIFIED_BUILDER_FILE_CONTENT.len() + ORIGINAL_BUILDER_FILE_CONTENT.len())
+					as u64
 			);
-			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
-			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
 		});
 }
 
 #[test]
-fn commit_fails_and_rollbacks_if_new_file_cannot_be_committed() {
-	TestBuilder::new(None)
+fn estimate_required_space_uses_original_content_of_lazily_noted_files() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback
+			.note_file_lazy(path, Box::new(|content| content))
+			.expect("The file can be noted; qed;");
+
+		assert_eq!(rollback.estimate_required_space(), ORIGINAL_BUILDER_FILE_CONTENT.len() as u64);
+	});
+}
+
+#[test]
+fn estimate_required_space_is_zero_for_empty_rollbacks() {
+	assert_eq!(Rollback::default().estimate_required_space(), 0);
+}
+
+#[test]
+fn check_free_space_works() {
+	TestBuilder::new(Some(2))
 		.with_noted_files()
 		.with_new_files()
-		.with_new_dirs()
 		.execute(|builder, rollback| {
-			builder.existing_files().iter().for_each(|file| {
-				assert_eq!(
-					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
-					ORIGINAL_BUILDER_FILE_CONTENT
-				)
-			});
+			let dir = builder.get_temp_dir_path().join("dir");
+			let new_file = dir.join("file.txt");
+			// Rebind rollback to accomplish with new_file lifetime
+			let mut rollback = rollback;
+			rollback.new_file_with_parents(&new_file).expect("The file can be added; qed;");
+			std::fs::write(
+				rollback.get_new_file(&new_file).expect("The file is staged; qed;"),
+				ORIGINAL_BUILDER_FILE_CONTENT,
+			)
+			.expect("The file is writable; qed;");
 
-			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
-			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
+			assert!(rollback.check_free_space().is_ok());
+		});
+}
 
-			// Deleting a temporary file means that rollback cannot commit the related new file.
-			let uncommitted_file =
-				builder.new_files().last().expect("There's new_files; qed;").to_path_buf();
+#[test]
+fn check_free_space_fails_if_a_file_system_doesnt_have_enough_space() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.new_files()[0];
+		rollback.new_file(path).expect("The file can be added; qed;");
+		// A sparse file way bigger than the space available
+		let size = 1 << 43;
+		File::options()
+			.write(true)
+			.open(rollback.get_new_file(path).expect("The file is staged; qed;"))
+			.and_then(|file| file.set_len(size))
+			.expect("The file can be resized; qed;");
 
-			std::fs::remove_file(
-				rollback
-					.get_new_file(&uncommitted_file)
-					.expect("The file exists for this rollback; qed;"),
-			)
-			.expect("This should be possible; qed;");
+		assert!(matches!(
+			rollback.check_free_space(),
+			Err(Error::InsufficientSpace(_, required, available)) if required == size && available < size
+		));
+		// Nothing is committed
+		assert!(!path.exists());
+	});
+}
 
-			match rollback.commit() {
-				Err(Error::Commit(item, err)) => {
-					assert_eq!(item, format!("{}", uncommitted_file.display()));
-					assert!(err.contains("No such file or directory"));
-				},
-				_ => panic!("Unexpected error"),
-			}
+#[cfg(feature = "mmap")]
+#[test]
+fn note_file_mmap_leaves_history_unchanged_if_mapping_fails() {
+	// Mapping is forced to fail by limiting the address space, which affects the whole process,
+	// so the test runs again in a child process.
+	const CHILD_ENV: &str = "FS_ROLLBACK_MMAP_CHILD";
+	if std::env::var_os(CHILD_ENV).is_none() {
+		let status = std::process::Command::new(
+			std::env::current_exe().expect("The test binary exists; qed;"),
+		)
+		.args(["note_file_mmap_leaves_history_unchanged_if_mapping_fails", "--exact"])
+		.env(CHILD_ENV, "1")
+		.status()
+		.expect("The test binary can be run; qed;");
+		assert!(status.success());
+		return;
+	}
 
-			// The fs wasn't affected
-			builder.existing_files().iter().for_each(|file| {
-				assert_eq!(
-					std::fs::read_to_string(file).expect("The file should be readable; qed;"),
-					ORIGINAL_BUILDER_FILE_CONTENT
-				)
-			});
+	const FILE_SIZE: u64 = 64 << 20;
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let (noted, mapped) = (builder.existing_files()[0], builder.existing_files()[1]);
+		std::fs::write(mapped, vec![1; FILE_SIZE as usize]).expect("The file is writable; qed;");
+		let initial = rollback.savepoint();
+		rollback.note_file(noted).expect("The file should be noted; qed;");
+		let savepoint = rollback.savepoint();
 
-			builder.new_files().iter().for_each(|file| assert!(!file.is_file()));
-			builder.new_dirs().iter().for_each(|dir| assert!(!dir.is_dir()));
-		});
+		// Leave room for noting the file, but not for mapping it
+		let statm = std::fs::read_to_string("/proc/self/statm").expect("statm exists; qed;");
+		let pages: u64 = statm
+			.split_whitespace()
+			.next()
+			.and_then(|pages| pages.parse().ok())
+			.expect("statm starts with the size in pages; qed;");
+		let size = pages * unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+		let limit = libc::rlimit { rlim_cur: size + FILE_SIZE / 2, rlim_max: libc::RLIM_INFINITY };
+		assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) }, 0);
+
+		let result = rollback.note_file_mmap(mapped);
+
+		let limit = libc::rlimit { rlim_cur: libc::RLIM_INFINITY, rlim_max: libc::RLIM_INFINITY };
+		assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) }, 0);
+
+		assert!(matches!(result, Err(Error::IO(err)) if err.raw_os_error() == Some(libc::ENOMEM)));
+		// Nothing is left in the history, so the savepoints keep matching the staged operations
+		assert!(!rollback.is_noted(mapped));
+		assert_eq!(rollback.savepoint(), savepoint);
+		rollback.rollback_to(initial);
+		assert!(rollback.is_empty());
+	});
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn note_file_mmap_works() {
+	TestBuilder::new(Some(2)).execute(|builder, mut rollback| {
+		let mapped = builder.existing_files()[0];
+		let unmapped = builder.existing_files()[1];
+		assert!(rollback.note_file_mmap(mapped).is_ok());
+		assert!(rollback.note_file(unmapped).is_ok());
+
+		assert_eq!(
+			rollback.get_noted_file_mmap(mapped).expect("The file is mapped; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT.as_bytes()
+		);
+		assert!(rollback.get_noted_file_mmap(unmapped).is_none());
+	});
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn commit_replaces_mapped_files_atomically() {
+	TestBuilder::new(Some(1)).execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		let original_inode = std::fs::metadata(path).expect("The file exists; qed;").ino();
+		rollback.note_file_mmap(path).expect("The file should be noted; qed;");
+
+		let mmap = rollback.get_noted_file_mmap(path).expect("The file is mapped; qed;");
+		mmap[0] = b'#';
+
+		assert!(rollback.commit().is_ok());
+
+		let mut expected = ORIGINAL_BUILDER_FILE_CONTENT.as_bytes().to_vec();
+		expected[0] = b'#';
+		assert_eq!(std::fs::read(path).expect("The file exists; qed;"), expected);
+		// The file has been replaced rather than overwritten
+		assert_ne!(std::fs::metadata(path).expect("The file exists; qed;").ino(), original_inode);
+	});
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn commit_fails_and_rollbacks_mapped_files() {
+	TestBuilder::new(Some(1)).with_new_files().execute(|builder, mut rollback| {
+		let path = builder.existing_files()[0];
+		rollback.note_file_mmap(path).expect("The file should be noted; qed;");
+		rollback.get_noted_file_mmap(path).expect("The file is mapped; qed;")[0] = b'#';
+
+		// Deleting a temporary file means that rollback cannot commit the related new file.
+		let uncommitted_file = builder.new_files()[0];
+		std::fs::remove_file(
+			rollback
+				.get_new_file(uncommitted_file)
+				.expect("The file exists for this rollback; qed;"),
+		)
+		.expect("This should be possible; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+		// The fs wasn't affected
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert!(!uncommitted_file.is_file());
+	});
+}
+
+// A raw POSIX ACL, as stored in the `system.posix_acl_access` extended attribute, granting read
+// access to the user with uid 1000 besides the usual entries.
+#[cfg(feature = "acl")]
+fn raw_acl() -> Vec<u8> {
+	const UNDEFINED_ID: u32 = u32::MAX;
+	let entries: [(u16, u16, u32); 5] = [
+		(0x01, 6, UNDEFINED_ID), // Owner
+		(0x02, 4, 1000),         // Named user
+		(0x04, 4, UNDEFINED_ID), // Owning group
+		(0x10, 4, UNDEFINED_ID), // Mask
+		(0x20, 4, UNDEFINED_ID), // Others
+	];
+	let mut acl = 2u32.to_le_bytes().to_vec();
+	for (tag, permissions, id) in entries {
+		acl.extend(tag.to_le_bytes());
+		acl.extend(permissions.to_le_bytes());
+		acl.extend(id.to_le_bytes());
+	}
+	acl
+}
+
+#[cfg(feature = "acl")]
+const ACL_XATTR: &str = "system.posix_acl_access";
+
+#[cfg(feature = "acl")]
+#[test]
+fn commit_preserves_acls() {
+	TestBuilder::new(Some(1)).execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		xattr::set(path, ACL_XATTR, &raw_acl()).expect("The ACL should be set; qed;");
+		let mut rollback = rollback.with_preserved_acls();
+		rollback.note_file(path).expect("The file should be noted; qed;");
+		let temp_file = rollback.get_noted_file(path).expect("The file is noted; qed;");
+		std::fs::write(temp_file, MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("The file should be writable; qed;");
+		// Loosening the temporary file permissions would change the ACL mask upon commit
+		std::fs::set_permissions(temp_file, std::fs::Permissions::from_mode(0o677))
+			.expect("The permissions should be set; qed;");
+
+		assert!(rollback.commit().is_ok());
+
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(xattr::get(path, ACL_XATTR).expect("The file exists; qed;"), Some(raw_acl()));
+	});
+}
+
+#[cfg(feature = "acl")]
+#[test]
+fn commit_restores_acls_on_rollback() {
+	TestBuilder::new(Some(2)).with_noted_files().execute(|builder, rollback| {
+		let path = builder.existing_files()[0];
+		// Rebind rollback to accomplish with acl_file lifetime
+		let mut rollback = rollback.with_preserved_acls();
+		let tempdir = tempfile::tempdir().expect("Tempdir should be created");
+		let acl_file = tempdir.path().join("file.txt");
+		std::fs::write(&acl_file, ORIGINAL_BUILDER_FILE_CONTENT)
+			.expect("File should be writable; qed;");
+		xattr::set(&acl_file, ACL_XATTR, &raw_acl()).expect("The ACL should be set; qed;");
+		rollback.note_file(&acl_file).expect("The file should be noted; qed;");
+
+		// Deleting an existing file means that rollback cannot commit that file.
+		std::fs::remove_file(path).expect("This should be possible; qed;");
+
+		assert!(matches!(rollback.commit(), Err(Error::CommitIo { .. })));
+
+		// The backup replaced the file, but the ACL is restored
+		assert_eq!(
+			std::fs::read_to_string(&acl_file).expect("The file exists; qed;"),
+			ORIGINAL_BUILDER_FILE_CONTENT
+		);
+		assert_eq!(
+			xattr::get(&acl_file, ACL_XATTR).expect("The file exists; qed;"),
+			Some(raw_acl())
+		);
+	});
+}
+
+#[cfg(feature = "tokio")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+	tokio::runtime::Builder::new_current_thread()
+		.build()
+		.expect("The runtime should be built; qed;")
+		.block_on(future)
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn commit_async_works() {
+	let tempdir = tempfile::tempdir().expect("Tempdir should be created");
+	let noted = tempdir.path().join("noted.txt");
+	let new = tempdir.path().join("new.txt");
+	std::fs::write(&noted, ORIGINAL_BUILDER_FILE_CONTENT).expect("File should be writable; qed;");
+	let mut rollback = Rollback::default();
+	rollback.note_file(&noted).expect("The file should be noted; qed;");
+	rollback.new_file(&new).expect("The file should be new; qed;");
+	for path in [&noted, &new] {
+		let staged = rollback.get_noted_file(path).or_else(|| rollback.get_new_file(path));
+		std::fs::write(staged.expect("The file is staged; qed;"), MODIFIED_BUILDER_FILE_CONTENT)
+			.expect("File should be writable; qed;");
+	}
+
+	assert!(matches!(block_on(rollback.commit_async()), Ok(CommitOutcome::Applied { .. })));
+
+	for path in [&noted, &new] {
+		assert_eq!(
+			std::fs::read_to_string(path).expect("The file exists; qed;"),
+			MODIFIED_BUILDER_FILE_CONTENT
+		);
+	}
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn commit_async_rollbacks_if_it_fails() {
+	let tempdir = tempfile::tempdir().expect("Tempdir should be created");
+	let noted = tempdir.path().join("noted.txt");
+	let removed_dir = tempdir.path().join("removed");
+	let new = removed_dir.join("new.txt");
+	std::fs::write(&noted, ORIGINAL_BUILDER_FILE_CONTENT).expect("File should be writable; qed;");
+	std::fs::create_dir(&removed_dir).expect("The dir can be created; qed;");
+	let mut rollback = Rollback::default();
+	rollback.note_file(&noted).expect("The file should be noted; qed;");
+	std::fs::write(
+		rollback.get_noted_file(&noted).expect("The file is noted; qed;"),
+		MODIFIED_BUILDER_FILE_CONTENT,
+	)
+	.expect("File should be writable; qed;");
+	rollback.new_file(&new).expect("The file should be new; qed;");
+	// The new file's parent dir is removed, so it cannot be committed.
+	std::fs::remove_dir(&removed_dir).expect("The dir exists; qed;");
+
+	assert!(matches!(block_on(rollback.commit_async()), Err(Error::CommitIo { .. })));
+
+	assert_eq!(
+		std::fs::read_to_string(&noted).expect("The file exists; qed;"),
+		ORIGINAL_BUILDER_FILE_CONTENT
+	);
+	assert!(!new.exists());
 }